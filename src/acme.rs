@@ -0,0 +1,215 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, OrderStatus,
+};
+use log::{debug, error, info, warn};
+use rcgen::{CertificateParams, KeyPair};
+use tokio::process::Command;
+use tokio::time::sleep;
+
+use crate::tls::CertificateResolver;
+
+/// Certificates are renewed when they expire within this period.
+const RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const RENEWAL_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+const ORDER_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const ORDER_POLL_ATTEMPTS: usize = 60;
+
+pub type AcmeError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Obtains and renews the FTPS certificate for a domain from an ACME CA using DNS-01 challenges.
+///
+/// The DNS records are published by a user-supplied hook, called as
+/// `<hook> set <record name> <value>` before validation and `<hook> clear <record name> <value>`
+/// afterwards, so any DNS provider can be scripted.
+#[derive(Debug, Clone)]
+pub struct AcmeManager {
+    pub domain: String,
+    pub contact_email: Option<String>,
+    pub directory_url: String,
+    pub dns_hook: PathBuf,
+    /// Keeps the account credentials, certificate and key between restarts.
+    pub state_dir: PathBuf,
+}
+
+impl AcmeManager {
+    pub fn cert_path(&self) -> PathBuf {
+        self.state_dir.join("cert.pem")
+    }
+
+    pub fn key_path(&self) -> PathBuf {
+        self.state_dir.join("key.pem")
+    }
+
+    fn account_path(&self) -> PathBuf {
+        self.state_dir.join("account.json")
+    }
+
+    /// Obtain a certificate unless a valid one is already stored. Returns whether a new
+    /// certificate was issued.
+    pub async fn ensure_certificate(&self) -> Result<bool, AcmeError> {
+        match expires_in(&self.cert_path()) {
+            Ok(remaining) if remaining > RENEW_BEFORE => {
+                debug!(
+                    "Certificate for {} is valid for {} more days",
+                    self.domain,
+                    remaining.as_secs() / 86400
+                );
+                return Ok(false);
+            }
+            Ok(_) => info!("Certificate for {} expires soon, renewing", self.domain),
+            Err(e) => info!("Requesting certificate for {} ({e})", self.domain),
+        }
+        self.issue().await?;
+        Ok(true)
+    }
+
+    async fn account(&self) -> Result<Account, AcmeError> {
+        if let Ok(credentials) = std::fs::read_to_string(self.account_path()) {
+            let credentials: AccountCredentials = serde_json::from_str(&credentials)?;
+            return Ok(Account::from_credentials(credentials).await?);
+        }
+
+        let contact = self
+            .contact_email
+            .as_ref()
+            .map(|email| format!("mailto:{email}"));
+        let contact: Vec<&str> = contact.iter().map(String::as_str).collect();
+        let (account, credentials) = Account::create(
+            &NewAccount {
+                contact: &contact,
+                terms_of_service_agreed: true,
+                only_return_existing: false,
+            },
+            &self.directory_url,
+            None,
+        )
+        .await?;
+        std::fs::write(self.account_path(), serde_json::to_string(&credentials)?)?;
+        info!("Registered ACME account at {}", self.directory_url);
+        Ok(account)
+    }
+
+    async fn issue(&self) -> Result<(), AcmeError> {
+        std::fs::create_dir_all(&self.state_dir)?;
+        let account = self.account().await?;
+        let mut order = account
+            .new_order(&NewOrder {
+                identifiers: &[Identifier::Dns(self.domain.clone())],
+            })
+            .await?;
+
+        let mut records = Vec::new();
+        for authorization in order.authorizations().await? {
+            if authorization.status == AuthorizationStatus::Valid {
+                continue;
+            }
+            let challenge = authorization
+                .challenges
+                .iter()
+                .find(|c| c.r#type == ChallengeType::Dns01)
+                .ok_or("ACME server offered no DNS-01 challenge")?;
+            let record = format!("_acme-challenge.{}", self.domain);
+            let value = order.key_authorization(challenge).dns_value();
+            self.run_hook("set", &record, &value).await?;
+            records.push((record, value, challenge.url.clone()));
+        }
+
+        let result = self.validate_and_finalize(&mut order, &records).await;
+        for (record, value, _) in &records {
+            if let Err(e) = self.run_hook("clear", record, value).await {
+                warn!("Failed to remove DNS record {record}: {e}");
+            }
+        }
+        let (certificate, key) = result?;
+
+        // Write the key first so a reload never pairs a new certificate with an old key.
+        write_private(&self.key_path(), key.as_bytes())?;
+        std::fs::write(self.cert_path(), certificate)?;
+        info!("Issued certificate for {}", self.domain);
+        Ok(())
+    }
+
+    async fn validate_and_finalize(
+        &self,
+        order: &mut instant_acme::Order,
+        records: &[(String, String, String)],
+    ) -> Result<(String, String), AcmeError> {
+        for (_, _, url) in records {
+            order.set_challenge_ready(url).await?;
+        }
+
+        let mut status = order.refresh().await?.status;
+        for _ in 0..ORDER_POLL_ATTEMPTS {
+            if matches!(status, OrderStatus::Ready | OrderStatus::Invalid) {
+                break;
+            }
+            sleep(ORDER_POLL_INTERVAL).await;
+            status = order.refresh().await?.status;
+        }
+        if status != OrderStatus::Ready {
+            return Err(format!("ACME order ended in state {status:?}").into());
+        }
+
+        let key = KeyPair::generate()?;
+        let csr = CertificateParams::new(vec![self.domain.clone()])?.serialize_request(&key)?;
+        order.finalize(csr.der()).await?;
+
+        for _ in 0..ORDER_POLL_ATTEMPTS {
+            if let Some(certificate) = order.certificate().await? {
+                return Ok((certificate, key.serialize_pem()));
+            }
+            sleep(ORDER_POLL_INTERVAL).await;
+        }
+        Err("ACME server did not issue the certificate in time".into())
+    }
+
+    async fn run_hook(&self, action: &str, record: &str, value: &str) -> Result<(), AcmeError> {
+        debug!("Running DNS hook: {action} {record}");
+        let status = Command::new(&self.dns_hook)
+            .args([action, record, value])
+            .status()
+            .await?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("DNS hook '{action} {record}' failed with {status}").into())
+        }
+    }
+}
+
+/// Periodically renew the certificate and swap a new one into `resolver`.
+pub async fn renewal_loop(manager: AcmeManager, resolver: Arc<CertificateResolver>) {
+    loop {
+        sleep(RENEWAL_CHECK_INTERVAL).await;
+        match manager.ensure_certificate().await {
+            Ok(true) => resolver.reload(&manager.cert_path(), &manager.key_path()),
+            Ok(false) => {}
+            Err(e) => error!("Failed to renew certificate for {}: {e}", manager.domain),
+        }
+    }
+}
+
+/// Time until the certificate stored at `path` expires.
+fn expires_in(path: &Path) -> Result<Duration, AcmeError> {
+    let pem = std::fs::read(path)?;
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&pem)?;
+    let certificate = pem.parse_x509()?;
+    let not_after = certificate.validity().not_after.timestamp();
+    let now = chrono::Utc::now().timestamp();
+    Ok(Duration::from_secs(
+        not_after.saturating_sub(now).max(0) as u64
+    ))
+}
+
+fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(path)?, content)
+}
@@ -1,35 +1,119 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
 use async_trait::async_trait;
 use libunftp::auth::{AuthenticationError, Authenticator, Credentials, UserDetail};
+#[cfg(feature = "ftps")]
+use log::debug;
 use log::{info, warn};
 
 use crate::health::PaperlessHealth;
+use crate::users::{UserConfig, UserSettings};
 
-#[derive(Debug)]
-pub struct User;
+#[derive(Debug, Default)]
+pub struct User {
+    pub username: String,
+    pub settings: UserSettings,
+    /// Address the user logged in from, if it came over the network.
+    pub client_ip: Option<IpAddr>,
+}
 
 impl UserDetail for User {}
 
 impl std::fmt::Display for User {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "User")
+        write!(f, "{}", self.username)
     }
 }
 
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
+/// An external source of credentials, consulted for usernames that aren't configured locally.
+///
+/// LDAP, PAM and the login webhook are implemented this way. Embedders plug in their own user
+/// store with [`UsernamePasswordAuthenticator::with_verifier`].
+#[async_trait]
+pub trait BridgeAuthenticator: std::fmt::Debug + Send + Sync {
+    /// Short name of the backend for log messages.
+    fn name(&self) -> &'static str;
+
+    /// Returns the settings for the user if the backend accepted the credentials, `Ok(None)` if it
+    /// rejected them and `Err` if it couldn't be asked.
+    async fn verify(
+        &self,
+        username: &str,
+        password: &str,
+        source_ip: IpAddr,
+    ) -> Result<Option<UserSettings>, BackendError>;
+}
+
+/// Decides whether clients from an address may log in at all, before any credentials are checked.
+pub trait SourceFilter: std::fmt::Debug + Send + Sync {
+    /// Returns the reason for rejecting `ip`.
+    fn allows(&self, ip: IpAddr) -> Result<(), String>;
+}
+
 #[derive(Debug)]
 pub struct UsernamePasswordAuthenticator {
-    username: String,
-    password: String,
+    users: HashMap<String, UserConfig>,
+    verifiers: Vec<Box<dyn BridgeAuthenticator>>,
+    source_filter: Option<Box<dyn SourceFilter>>,
+    #[cfg(feature = "ftps")]
+    certificate_login: bool,
     paperless_health: PaperlessHealth,
+    allow_empty_password: bool,
 }
 
 impl UsernamePasswordAuthenticator {
+    #[cfg(test)]
     pub fn new(username: String, password: String, paperless_health: PaperlessHealth) -> Self {
+        let user = UserConfig::with_password(password);
+        Self::from_users(HashMap::from([(username, user)]), paperless_health)
+    }
+
+    pub fn from_users(
+        users: HashMap<String, UserConfig>,
+        paperless_health: PaperlessHealth,
+    ) -> Self {
         Self {
-            username,
-            password,
+            users,
+            verifiers: Vec::new(),
+            source_filter: None,
+            #[cfg(feature = "ftps")]
+            certificate_login: false,
             paperless_health,
+            allow_empty_password: false,
         }
     }
+
+    /// Reject clients from addresses `filter` doesn't allow.
+    #[cfg(feature = "geoip")]
+    pub fn with_source_filter(mut self, filter: Box<dyn SourceFilter>) -> Self {
+        self.source_filter = Some(filter);
+        self
+    }
+
+    /// Log in clients presenting a client certificate issued to a configured username (as CN or
+    /// SAN) as that user without checking the password. The certificate is verified against the
+    /// trust store by libunftp during the TLS handshake.
+    #[cfg(feature = "ftps")]
+    pub fn with_certificate_login(mut self, certificate_login: bool) -> Self {
+        self.certificate_login = certificate_login;
+        self
+    }
+
+    /// Check logins for unknown usernames against `verifier`. Verifiers are asked in the order
+    /// they were added.
+    pub fn with_verifier(mut self, verifier: Box<dyn BridgeAuthenticator>) -> Self {
+        self.verifiers.push(verifier);
+        self
+    }
+
+    /// Accept an empty password for scanners that can only send a username.
+    pub fn with_empty_password_allowed(mut self, allow_empty_password: bool) -> Self {
+        self.allow_empty_password = allow_empty_password;
+        self
+    }
 }
 
 #[async_trait]
@@ -39,22 +123,116 @@ impl Authenticator<User> for UsernamePasswordAuthenticator {
         username: &str,
         creds: &Credentials,
     ) -> Result<User, AuthenticationError> {
-        if let Some(ref password) = creds.password
-            && *password != self.password
+        if let Some(filter) = &self.source_filter
+            && let Err(reason) = filter.allows(creds.source_ip)
+        {
+            warn!("Rejecting login from {}: {reason}", creds.source_ip);
+            return Err(AuthenticationError::new(
+                "Connections from your location are not allowed",
+            ));
+        }
+
+        #[cfg(feature = "ftps")]
+        if self.certificate_login
+            && let Some(certificate) = creds.certificate_chain.as_ref().and_then(|c| c.first())
+        {
+            let identities = crate::tls::certificate_identities(&certificate.0);
+            if let Some((cert_user, config)) = identities
+                .iter()
+                .find_map(|identity| self.users.get_key_value(identity))
+            {
+                info!("Authenticating {cert_user} by client certificate");
+                return self.admit(cert_user, config.settings.clone(), creds);
+            }
+            debug!("Client certificate for {identities:?} matches no user");
+        }
+
+        if let Some((trusted_user, config)) = self.users.iter().find(|(_, user)| {
+            user.trusted_ips
+                .iter()
+                .any(|ip| ip.contains(creds.source_ip))
+        }) {
+            info!(
+                "Authenticating {} as {trusted_user} by trusted source IP",
+                creds.source_ip
+            );
+            return self.admit(trusted_user, config.settings.clone(), creds);
+        }
+
+        let Some(user) = self.users.get(username) else {
+            if self.verifiers.is_empty() {
+                warn!("Provided username doesn't match");
+                return Err(AuthenticationError::BadUser);
+            }
+            return self.verify_external(username, creds).await;
+        };
+        let mut password = creds.password.as_deref();
+        if let Some(secret) = &user.totp_secret {
+            match password.and_then(crate::totp::split_code) {
+                Some((static_password, code)) if secret.verify(code) => {
+                    password = Some(static_password)
+                }
+                _ => {
+                    warn!("Provided one-time code doesn't match");
+                    return Err(AuthenticationError::BadPassword);
+                }
+            }
+        }
+        if !(user.password.accepts(password) || (self.allow_empty_password && password == Some("")))
         {
             warn!("Provided password doesn't match");
             return Err(AuthenticationError::BadPassword);
         }
-        if username != self.username {
-            warn!("Provided username doesn't match");
-            return Err(AuthenticationError::BadUser);
+        self.admit(username, user.settings.clone(), creds)
+    }
+}
+
+impl UsernamePasswordAuthenticator {
+    async fn verify_external(
+        &self,
+        username: &str,
+        creds: &Credentials,
+    ) -> Result<User, AuthenticationError> {
+        // Directory servers treat a bind without password as anonymous and let it succeed.
+        let Some(password) = creds.password.as_deref().filter(|p| !p.is_empty()) else {
+            warn!("Rejecting empty password for {username}");
+            return Err(AuthenticationError::BadPassword);
+        };
+        for verifier in &self.verifiers {
+            match verifier.verify(username, password, creds.source_ip).await {
+                Ok(Some(settings)) => {
+                    info!("{} accepted credentials for {username}", verifier.name());
+                    return self.admit(username, settings, creds);
+                }
+                Ok(None) => {}
+                Err(error) => warn!("{} could not verify {username}: {error}", verifier.name()),
+            }
+        }
+        warn!("Provided credentials for {username} were rejected");
+        Err(AuthenticationError::BadPassword)
+    }
+
+    /// Complete a login whose credentials were accepted.
+    fn admit(
+        &self,
+        username: &str,
+        settings: UserSettings,
+        creds: &Credentials,
+    ) -> Result<User, AuthenticationError> {
+        if !crate::schedule::is_allowed_now(&settings.access_hours) {
+            warn!("Rejecting login of {username} outside of its access hours");
+            return Err(AuthenticationError::new("Login not allowed at this time"));
         }
         if let Err(error) = self.paperless_health.check() {
             warn!("Rejecting FTP login because Paperless is unavailable: {error}");
             return Err(AuthenticationError::new("Paperless is unavailable"));
         }
-        info!("Successfully authenticated");
-        Ok(User {})
+        info!("Successfully authenticated {username}");
+        Ok(User {
+            username: username.to_string(),
+            settings,
+            client_ip: Some(creds.source_ip),
+        })
     }
 }
 
@@ -78,6 +256,244 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn empty_password_requires_quirk() {
+        let authenticator = UsernamePasswordAuthenticator::new(
+            "scanner".to_string(),
+            "secret".to_string(),
+            PaperlessHealth::new_healthy(Duration::from_secs(60)),
+        );
+        assert!(
+            authenticator
+                .authenticate("scanner", &"".into())
+                .await
+                .is_err()
+        );
+
+        let authenticator = authenticator.with_empty_password_allowed(true);
+        assert!(
+            authenticator
+                .authenticate("scanner", &"".into())
+                .await
+                .is_ok()
+        );
+        assert!(
+            authenticator
+                .authenticate("scanner", &"wrong".into())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn password_policy_is_applied_per_user() {
+        let users = crate::users::UsersFile::parse(
+            r#"
+            [users.office]
+            password = "secret"
+
+            [users.epson]
+            password = "none"
+            "#,
+        )
+        .unwrap()
+        .users;
+        let authenticator = UsernamePasswordAuthenticator::from_users(
+            users,
+            PaperlessHealth::new_healthy(Duration::from_secs(60)),
+        );
+
+        assert!(
+            authenticator
+                .authenticate("epson", &"".into())
+                .await
+                .is_ok()
+        );
+        assert!(
+            authenticator
+                .authenticate("office", &"".into())
+                .await
+                .is_err()
+        );
+        assert!(
+            authenticator
+                .authenticate("unknown", &"".into())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn user_settings_are_attached_to_the_session() {
+        let users = crate::users::UsersFile::parse(
+            r#"
+            [users.scanner-office]
+            password = "secret"
+            tags = [2]
+            "#,
+        )
+        .unwrap()
+        .users;
+        let authenticator = UsernamePasswordAuthenticator::from_users(
+            users,
+            PaperlessHealth::new_healthy(Duration::from_secs(60)),
+        );
+
+        let user = authenticator
+            .authenticate("scanner-office", &"secret".into())
+            .await
+            .unwrap();
+        assert_eq!(user.username, "scanner-office");
+        assert_eq!(user.settings.tags, vec![2]);
+    }
+
+    #[tokio::test]
+    async fn totp_code_must_be_appended() {
+        let users = crate::users::UsersFile::parse(
+            r#"
+            [users.alice]
+            password = "secret"
+            totp_secret = "JBSWY3DPEHPK3PXP"
+            "#,
+        )
+        .unwrap()
+        .users;
+        let authenticator = UsernamePasswordAuthenticator::from_users(
+            users,
+            PaperlessHealth::new_healthy(Duration::from_secs(60)),
+        );
+
+        assert!(
+            authenticator
+                .authenticate("alice", &"secret".into())
+                .await
+                .is_err()
+        );
+        let secret = crate::totp::TotpSecret::try_from("JBSWY3DPEHPK3PXP".to_string()).unwrap();
+        let password = format!("secret{}", secret.current_code());
+        assert!(
+            authenticator
+                .authenticate("alice", &password.as_str().into())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[cfg(feature = "ftps")]
+    #[tokio::test]
+    async fn client_certificate_logs_in_matching_user() {
+        let users = crate::users::UsersFile::parse(
+            r#"
+            [users.scanner-1]
+            password = "secret"
+            "#,
+        )
+        .unwrap()
+        .users;
+        let authenticator = UsernamePasswordAuthenticator::from_users(
+            users,
+            PaperlessHealth::new_healthy(Duration::from_secs(60)),
+        )
+        .with_certificate_login(true);
+
+        let certified = rcgen::generate_simple_self_signed(vec!["scanner-1".to_string()]).unwrap();
+        let mut creds: Credentials = "".into();
+        creds.certificate_chain = Some(vec![libunftp::auth::ClientCert(
+            certified.cert.der().to_vec(),
+        )]);
+        let user = authenticator
+            .authenticate("anonymous", &creds)
+            .await
+            .unwrap();
+        assert_eq!(user.username, "scanner-1");
+
+        let other = rcgen::generate_simple_self_signed(vec!["scanner-2".to_string()]).unwrap();
+        creds.certificate_chain = Some(vec![libunftp::auth::ClientCert(other.cert.der().to_vec())]);
+        assert!(
+            authenticator
+                .authenticate("anonymous", &creds)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn trusted_ip_skips_credential_check() {
+        let users = crate::users::UsersFile::parse(
+            r#"
+            [users.scanner]
+            password = "secret"
+            trusted_ips = ["192.168.10.0/24"]
+            "#,
+        )
+        .unwrap()
+        .users;
+        let authenticator = UsernamePasswordAuthenticator::from_users(
+            users,
+            PaperlessHealth::new_healthy(Duration::from_secs(60)),
+        );
+
+        let mut creds: Credentials = "wrong".into();
+        creds.source_ip = "192.168.10.20".parse().unwrap();
+        assert!(
+            authenticator
+                .authenticate("anonymous", &creds)
+                .await
+                .is_ok()
+        );
+
+        creds.source_ip = "192.168.11.20".parse().unwrap();
+        assert!(authenticator.authenticate("scanner", &creds).await.is_err());
+    }
+
+    #[derive(Debug)]
+    struct StaticVerifier;
+
+    #[async_trait]
+    impl BridgeAuthenticator for StaticVerifier {
+        fn name(&self) -> &'static str {
+            "static"
+        }
+
+        async fn verify(
+            &self,
+            username: &str,
+            password: &str,
+            _source_ip: IpAddr,
+        ) -> Result<Option<UserSettings>, BackendError> {
+            Ok((username == "alice" && password == "directory").then(UserSettings::default))
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_users_are_checked_against_verifiers() {
+        let authenticator = UsernamePasswordAuthenticator::new(
+            "scanner".to_string(),
+            "secret".to_string(),
+            PaperlessHealth::new_healthy(Duration::from_secs(60)),
+        )
+        .with_verifier(Box::new(StaticVerifier));
+
+        assert!(
+            authenticator
+                .authenticate("alice", &"directory".into())
+                .await
+                .is_ok()
+        );
+        assert!(
+            authenticator
+                .authenticate("alice", &"wrong".into())
+                .await
+                .is_err()
+        );
+        assert!(
+            authenticator
+                .authenticate("scanner", &"directory".into())
+                .await
+                .is_err()
+        );
+    }
+
     #[tokio::test]
     async fn accepts_valid_credentials_when_paperless_is_healthy() {
         let authenticator = UsernamePasswordAuthenticator::new(
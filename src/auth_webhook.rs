@@ -0,0 +1,94 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{BackendError, BridgeAuthenticator};
+use crate::users::UserSettings;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct WebhookRequest<'a> {
+    username: &'a str,
+    password: &'a str,
+    client_ip: IpAddr,
+}
+
+#[derive(Debug, Deserialize)]
+struct WebhookResponse {
+    allow: bool,
+    #[serde(flatten)]
+    settings: UserSettings,
+}
+
+/// Delegates FTP logins to an HTTP endpoint.
+///
+/// The credentials are POSTed as JSON (`username`, `password`, `client_ip`). The endpoint answers
+/// with `{"allow": true|false}`, optionally adding `tags` and `token` for the user's uploads.
+#[derive(Debug)]
+pub struct WebhookVerifier {
+    url: String,
+    client: Client,
+}
+
+impl WebhookVerifier {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            client: Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .expect("failed to build webhook HTTP client"),
+        }
+    }
+}
+
+#[async_trait]
+impl BridgeAuthenticator for WebhookVerifier {
+    fn name(&self) -> &'static str {
+        "Authentication webhook"
+    }
+
+    async fn verify(
+        &self,
+        username: &str,
+        password: &str,
+        source_ip: IpAddr,
+    ) -> Result<Option<UserSettings>, BackendError> {
+        let response: WebhookResponse = self
+            .client
+            .post(&self.url)
+            .json(&WebhookRequest {
+                username,
+                password,
+                client_ip: source_ip.to_canonical(),
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.allow.then_some(response.settings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_responses() {
+        let response: WebhookResponse =
+            serde_json::from_str(r#"{"allow": true, "token": "abc", "tags": [3, 7]}"#).unwrap();
+        assert!(response.allow);
+        assert_eq!(response.settings.api_token.as_deref(), Some("abc"));
+        assert_eq!(response.settings.tags, vec![3, 7]);
+
+        let response: WebhookResponse = serde_json::from_str(r#"{"allow": false}"#).unwrap();
+        assert!(!response.allow);
+        assert_eq!(response.settings, UserSettings::default());
+    }
+}
@@ -0,0 +1,680 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{error, info, warn};
+use tokio::time::sleep;
+
+use crate::breaker::CircuitBreaker;
+use crate::hook::{SuccessHook, Upload};
+use crate::idle::IdleTracker;
+use crate::notify::{Event, Notifier};
+use crate::paperless::{DuplicatePolicy, PaperlessApi, PaperlessError, TaskStatus, UploadOptions};
+use crate::spool::SpoolFormat;
+use crate::storage::{
+    log_consumption, run_success_hook, upload_with_retries, wait_for_consumption,
+};
+use crate::tasks::{TaskTracker, TrackedTask};
+
+/// A page separating the documents of a batch scan, see [`SettleQueue::split`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SeparatorPage {
+    /// A blank page.
+    Blank,
+    /// A page with a QR code of this content, e.g. `PATCHT` like Paperless' own separator.
+    Barcode(String),
+}
+
+impl FromStr for SeparatorPage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "blank" => Ok(SeparatorPage::Blank),
+            Some(("barcode", content)) if !content.is_empty() => {
+                Ok(SeparatorPage::Barcode(content.to_string()))
+            }
+            _ => Err(format!(
+                "Invalid separator page '{s}', expected `blank` or `barcode:<content>`"
+            )),
+        }
+    }
+}
+
+/// A received file waiting for its batch to be submitted.
+#[derive(Debug)]
+struct PendingFile {
+    /// The name the client uploaded the file as.
+    name: String,
+    path: PathBuf,
+    options: UploadOptions,
+    /// The `client` metrics label of the scanner that sent the file.
+    client: String,
+    /// The document of the batch the file belongs to, counting separator pages.
+    segment: usize,
+}
+
+#[derive(Default)]
+struct Batch {
+    /// Incremented by every file, so a flush can tell whether another file arrived meanwhile.
+    generation: u64,
+    files: Vec<PendingFile>,
+    /// The segment files received now belong to.
+    segment: usize,
+    /// The Paperless client of the user, with their token, headers and proxy.
+    client: Option<Arc<dyn PaperlessApi>>,
+}
+
+/// The tenant a batch is uploaded to, if any, and the user who sent it.
+type BatchKey = (Option<String>, String);
+
+/// Holds received files until a user sent no more for the settle delay, then submits them
+/// together, so feeders streaming many pages finish before Paperless starts consuming.
+/// With tenants, a user has a batch per tenant, which is held and spooled in the directories of
+/// the tenant. Shared by all FTP sessions.
+#[derive(Clone)]
+pub struct SettleQueue {
+    delay: Duration,
+    /// Merge the documents of a batch into one after Paperless consumed them.
+    merge: bool,
+    dir: PathBuf,
+    breaker: CircuitBreaker,
+    spool_dir: Option<PathBuf>,
+    spool_format: SpoolFormat,
+    duplicates: DuplicatePolicy,
+    notifier: Notifier,
+    success_hook: Option<SuccessHook>,
+    tasks: TaskTracker,
+    idle: Option<IdleTracker>,
+    batches: Arc<Mutex<HashMap<BatchKey, Batch>>>,
+}
+
+impl std::fmt::Debug for SettleQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SettleQueue")
+            .field("delay", &self.delay)
+            .field("merge", &self.merge)
+            .field("dir", &self.dir)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SettleQueue {
+    pub fn new(
+        delay: Duration,
+        merge: bool,
+        breaker: CircuitBreaker,
+        spool_dir: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            delay,
+            merge,
+            dir: std::env::temp_dir().join("ftp-paperless-bridge-settle"),
+            breaker,
+            spool_dir,
+            spool_format: SpoolFormat::default(),
+            duplicates: DuplicatePolicy::default(),
+            notifier: Notifier::default(),
+            success_hook: None,
+            tasks: TaskTracker::default(),
+            idle: None,
+            batches: Arc::default(),
+        }
+    }
+
+    /// How files are stored in the spool directory.
+    pub fn with_spool_format(mut self, spool_format: SpoolFormat) -> Self {
+        self.spool_format = spool_format;
+        self
+    }
+
+    /// How to treat documents Paperless refuses as duplicates.
+    pub fn with_duplicate_policy(mut self, duplicates: DuplicatePolicy) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Where to send notifications about uploaded and failed documents.
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Run `hook` for the documents of batches that aren't merged.
+    pub fn with_success_hook(mut self, hook: Option<SuccessHook>) -> Self {
+        self.success_hook = hook;
+        self
+    }
+
+    /// Follow the consumption of the documents of batches with `tasks`.
+    pub fn with_task_tracker(mut self, tasks: TaskTracker) -> Self {
+        self.tasks = tasks;
+        self
+    }
+
+    /// Keep the bridge from counting as idle while batches wait to be submitted.
+    pub fn with_idle_tracker(mut self, idle: IdleTracker) -> Self {
+        self.idle = Some(idle);
+        self
+    }
+
+    /// Keep a copy of the staged file and (re)start the settle delay of the user's batch for
+    /// `tenant`, which is submitted with `paperless`, the client of the user or tenant.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add(
+        &self,
+        paperless: Arc<dyn PaperlessApi>,
+        tenant: Option<&str>,
+        username: &str,
+        client: &str,
+        name: &str,
+        staged: &Path,
+        options: UploadOptions,
+    ) -> std::io::Result<()> {
+        let file_name = staged
+            .file_name()
+            .ok_or_else(|| std::io::Error::other("staged file has no name"))?;
+        // A directory per file keeps the staging name, which Paperless uses as the title.
+        let id = options
+            .request_id
+            .clone()
+            .unwrap_or_else(crate::paperless::new_request_id);
+        let dir = match tenant {
+            Some(tenant) => self.dir.join(tenant).join(id),
+            None => self.dir.join(id),
+        };
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join(file_name);
+        tokio::fs::copy(staged, &path).await?;
+
+        let key = (tenant.map(str::to_string), username.to_string());
+        let generation = {
+            let mut batches = self.batches.lock().expect("settle queue lock poisoned");
+            let batch = batches.entry(key.clone()).or_default();
+            batch.generation += 1;
+            batch.client = Some(paperless);
+            batch.files.push(PendingFile {
+                name: name.to_string(),
+                path,
+                options,
+                client: client.to_string(),
+                segment: batch.segment,
+            });
+            batch.generation
+        };
+        self.flush_later(key, generation);
+        Ok(())
+    }
+
+    /// Start a new document in the user's batch for `tenant`, as marked by a separator page. With
+    /// merging, the files received after it are merged apart from those before.
+    pub fn split(&self, tenant: Option<&str>, username: &str) {
+        let key = (tenant.map(str::to_string), username.to_string());
+        let generation = {
+            let mut batches = self.batches.lock().expect("settle queue lock poisoned");
+            let Some(batch) = batches.get_mut(&key) else {
+                return;
+            };
+            // A leading or repeated separator doesn't separate anything.
+            if batch
+                .files
+                .last()
+                .is_none_or(|file| file.segment != batch.segment)
+            {
+                return;
+            }
+            batch.segment += 1;
+            batch.generation += 1;
+            batch.generation
+        };
+        self.flush_later(key, generation);
+    }
+
+    /// Submit the batch after the settle delay, unless it changed by then.
+    fn flush_later(&self, key: BatchKey, generation: u64) {
+        let busy = self.idle.as_ref().map(IdleTracker::busy);
+        let queue = self.clone();
+        tokio::spawn(async move {
+            queue.flush_after_delay(key, generation).await;
+            drop(busy);
+        });
+    }
+
+    /// Cancel the most recent pending file with this name in the user's batch for `tenant`.
+    /// Returns whether there was one.
+    pub async fn cancel(&self, tenant: Option<&str>, username: &str, name: &str) -> bool {
+        let key = (tenant.map(str::to_string), username.to_string());
+        let cancelled = {
+            let mut batches = self.batches.lock().expect("settle queue lock poisoned");
+            batches.get_mut(&key).and_then(|batch| {
+                let index = batch.files.iter().rposition(|file| file.name == name)?;
+                Some(batch.files.remove(index))
+            })
+        };
+        let Some(file) = cancelled else {
+            return false;
+        };
+        if let Some(dir) = file.path.parent() {
+            let _ = tokio::fs::remove_dir_all(dir).await;
+        }
+        true
+    }
+
+    async fn flush_after_delay(self, key: BatchKey, generation: u64) {
+        sleep(self.delay).await;
+        let batch = {
+            let mut batches = self.batches.lock().expect("settle queue lock poisoned");
+            match batches.get(&key) {
+                Some(batch) if batch.generation == generation => batches.remove(&key),
+                // Another file arrived, whose own delay flushes the batch.
+                _ => return,
+            }
+        };
+        if let Some(Batch {
+            files,
+            client: Some(client),
+            ..
+        }) = batch
+            && !files.is_empty()
+        {
+            let (tenant, username) = key;
+            self.submit(&client, tenant.as_deref(), &username, files)
+                .await;
+        }
+    }
+
+    async fn submit(
+        &self,
+        client: &Arc<dyn PaperlessApi>,
+        tenant: Option<&str>,
+        username: &str,
+        files: Vec<PendingFile>,
+    ) {
+        info!(
+            "Submitting {} files of {username} to Paperless",
+            files.len()
+        );
+        // Each tenant has a spool of its own.
+        let spool_dir = self
+            .spool_dir
+            .as_ref()
+            .map(|dir| tenant.map_or_else(|| dir.clone(), |tenant| dir.join(tenant)));
+        let batch = crate::paperless::new_request_id();
+        // The task IDs and files of each segment, which is merged into a document of its own.
+        let mut segments: Vec<Vec<(String, &PendingFile)>> = Vec::new();
+        let mut spooling = false;
+        for (position, file) in files.iter().enumerate() {
+            if position == 0 || file.segment != files[position - 1].segment {
+                segments.push(Vec::new());
+            }
+            match self
+                .upload(
+                    client.as_ref(),
+                    spool_dir.as_deref(),
+                    username,
+                    file,
+                    &batch,
+                    position,
+                    spooling,
+                )
+                .await
+            {
+                Some(task_id) => segments
+                    .last_mut()
+                    .expect("a segment was started")
+                    .push((task_id, file)),
+                // Spool the rest too, so the pages reach Paperless in order after all.
+                None => spooling = spool_dir.is_some(),
+            }
+        }
+
+        for uploads in segments {
+            if self.merge && uploads.len() > 1 {
+                let task_ids = uploads.into_iter().map(|(task_id, _)| task_id).collect();
+                tokio::spawn(merge_consumed(Arc::clone(client), task_ids));
+            } else {
+                for (task_id, file) in uploads {
+                    let hook = self.success_hook.clone().map(|hook| {
+                        let title = file.options.title.clone().unwrap_or_else(|| {
+                            Path::new(&file.name)
+                                .file_stem()
+                                .map(|stem| stem.to_string_lossy().into_owned())
+                                .unwrap_or_default()
+                        });
+                        let upload = Upload {
+                            title,
+                            user: username.to_string(),
+                            source: file.name.clone(),
+                        };
+                        (hook, upload)
+                    });
+                    let tracked = TrackedTask::new(&task_id, Some(username), &file.name, tenant);
+                    let consumption = log_consumption(Arc::clone(client), task_id, self.duplicates);
+                    self.tasks.spawn(tracked, async move {
+                        run_success_hook(hook, consumption.await.ok().flatten()).await;
+                    });
+                }
+            }
+        }
+    }
+
+    /// Upload one file of a batch, spooling it to `spool_dir` if that fails or `spool` is set.
+    /// Returns the task ID on success. The pending copy is removed unless it could neither be
+    /// uploaded nor spooled.
+    #[allow(clippy::too_many_arguments)]
+    async fn upload(
+        &self,
+        client: &dyn PaperlessApi,
+        spool_dir: Option<&Path>,
+        username: &str,
+        file: &PendingFile,
+        batch: &str,
+        position: usize,
+        spool: bool,
+    ) -> Option<String> {
+        let request_id = file.options.request_id.as_deref().unwrap_or_default();
+        let Some(path) = file.path.to_str() else {
+            error!("Pending file {} is not valid UTF-8", file.path.display());
+            return None;
+        };
+        let bytes = tokio::fs::metadata(&file.path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+        let uploaded = if spool {
+            Err(PaperlessError::Api(
+                "an earlier file of the batch was spooled".to_string(),
+            ))
+        } else if self.breaker.allows_request() {
+            upload_with_retries(client, &self.breaker, path, &file.options).await
+        } else {
+            Err(PaperlessError::Api(
+                "Paperless keeps failing, circuit breaker is open".to_string(),
+            ))
+        };
+        let task_id = match uploaded {
+            Ok(task_id) => {
+                info!("Uploaded {:?} as request {request_id}", file.name);
+                self.notifier.notify(Event::Uploaded {
+                    user: username.to_string(),
+                    name: file.name.clone(),
+                });
+                crate::metrics::UPLOADS
+                    .with_label_values(&[username, &file.client])
+                    .inc();
+                crate::metrics::UPLOAD_BYTES
+                    .with_label_values(&[username, &file.client])
+                    .inc_by(bytes);
+                Some(task_id)
+            }
+            Err(e) => {
+                error!("Upload {request_id} of {:?} failed: {e}", file.name);
+                let spooled = match spool_dir {
+                    // Paperless would reject a spooled copy again and again.
+                    _ if matches!(e, PaperlessError::Validation(_)) => false,
+                    Some(spool_dir) => crate::spool::spool_batch_file(
+                        &file.path,
+                        spool_dir,
+                        batch,
+                        position,
+                        &self.spool_format,
+                        Some(username),
+                        &file.options,
+                    )
+                    .await
+                    .map_err(|e| error!("Failed to spool file: {e}"))
+                    .is_ok(),
+                    None => false,
+                };
+                if !spooled {
+                    crate::metrics::USER_UPLOAD_FAILURES
+                        .with_label_values(&[username, &file.client])
+                        .inc();
+                    self.notifier.notify(Event::Failed {
+                        user: username.to_string(),
+                        name: file.name.clone(),
+                        reason: e.to_string(),
+                        attempts: 1,
+                    });
+                    error!(
+                        "Keeping {:?} at {} for manual recovery",
+                        file.name,
+                        file.path.display()
+                    );
+                    return None;
+                }
+                None
+            }
+        };
+        if let Some(dir) = file.path.parent() {
+            let _ = tokio::fs::remove_dir_all(dir).await;
+        }
+        task_id
+    }
+}
+
+/// Wait for Paperless to consume all documents of a batch and merge them into the first one.
+async fn merge_consumed(client: Arc<dyn PaperlessApi>, task_ids: Vec<String>) {
+    let mut document_ids = Vec::new();
+    for task_id in &task_ids {
+        match wait_for_consumption(client.as_ref(), task_id).await {
+            Ok(TaskStatus::Success {
+                document_id: Some(document_id),
+            }) => document_ids.push(document_id),
+            Ok(status) => warn!("Not merging task {task_id}, it ended in {status:?}"),
+            Err(e) => warn!("Not merging task {task_id}, its status is unknown: {e}"),
+        }
+    }
+    if document_ids.len() < 2 {
+        return;
+    }
+    match client.merge_documents(&document_ids).await {
+        Ok(()) => info!("Merging documents {document_ids:?}"),
+        Err(e) => error!("Failed to merge documents {document_ids:?}: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paperless::DryRunClient;
+
+    #[tokio::test]
+    async fn files_are_submitted_after_the_last_one_settled() {
+        let staged = tempfile::tempdir().unwrap();
+        let client = Arc::new(DryRunClient::default());
+        let queue = SettleQueue::new(
+            Duration::from_millis(200),
+            false,
+            CircuitBreaker::default(),
+            None,
+        );
+
+        for page in 0..3 {
+            let path = staged.path().join(format!("page{page}.pdf"));
+            std::fs::write(&path, format!("%PDF-{page}")).unwrap();
+            queue
+                .add(
+                    client.clone(),
+                    None,
+                    "scanner",
+                    "local",
+                    "page.pdf",
+                    &path,
+                    UploadOptions::default(),
+                )
+                .await
+                .unwrap();
+            sleep(Duration::from_millis(100)).await;
+            assert!(client.uploads().is_empty());
+        }
+
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(client.uploads().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn batches_are_submitted_with_the_client_of_their_user() {
+        let staged = tempfile::tempdir().unwrap();
+        let queue = SettleQueue::new(
+            Duration::from_millis(100),
+            false,
+            CircuitBreaker::default(),
+            None,
+        );
+
+        let clients = [
+            Arc::new(DryRunClient::default()),
+            Arc::new(DryRunClient::default()),
+        ];
+        for (username, client) in ["alice", "bob"].into_iter().zip(&clients) {
+            let path = staged.path().join(format!("{username}.pdf"));
+            std::fs::write(&path, format!("%PDF-{username}")).unwrap();
+            queue
+                .add(
+                    client.clone(),
+                    None,
+                    username,
+                    "local",
+                    "scan.pdf",
+                    &path,
+                    UploadOptions::default(),
+                )
+                .await
+                .unwrap();
+        }
+
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(clients[0].uploads(), vec![b"%PDF-alice".to_vec()]);
+        assert_eq!(clients[1].uploads(), vec![b"%PDF-bob".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn batches_of_tenants_are_submitted_apart() {
+        let staged = tempfile::tempdir().unwrap();
+        let queue = SettleQueue::new(
+            Duration::from_millis(100),
+            false,
+            CircuitBreaker::default(),
+            None,
+        );
+
+        let clients = [
+            Arc::new(DryRunClient::default()),
+            Arc::new(DryRunClient::default()),
+        ];
+        for (tenant, client) in ["smith", "jones"].into_iter().zip(&clients) {
+            let path = staged.path().join(format!("{tenant}.pdf"));
+            std::fs::write(&path, format!("%PDF-{tenant}")).unwrap();
+            queue
+                .add(
+                    client.clone(),
+                    Some(tenant),
+                    "scanner",
+                    "local",
+                    "scan.pdf",
+                    &path,
+                    UploadOptions::default(),
+                )
+                .await
+                .unwrap();
+        }
+        // Cancelling in the directory of one tenant leaves the batch of the other alone.
+        assert!(!queue.cancel(None, "scanner", "scan.pdf").await);
+
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(clients[0].uploads(), vec![b"%PDF-smith".to_vec()]);
+        assert_eq!(clients[1].uploads(), vec![b"%PDF-jones".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn cancelled_files_are_not_submitted() {
+        let staged = tempfile::tempdir().unwrap();
+        let client = Arc::new(DryRunClient::default());
+        let queue = SettleQueue::new(
+            Duration::from_millis(100),
+            false,
+            CircuitBreaker::default(),
+            None,
+        );
+
+        for name in ["keep.pdf", "misfire.pdf"] {
+            let path = staged.path().join(name);
+            std::fs::write(&path, format!("%PDF-{name}")).unwrap();
+            queue
+                .add(
+                    client.clone(),
+                    None,
+                    "scanner",
+                    "local",
+                    name,
+                    &path,
+                    UploadOptions::default(),
+                )
+                .await
+                .unwrap();
+        }
+        assert!(queue.cancel(None, "scanner", "misfire.pdf").await);
+        assert!(!queue.cancel(None, "scanner", "misfire.pdf").await);
+        assert!(!queue.cancel(None, "other", "keep.pdf").await);
+
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(client.uploads(), vec![b"%PDF-keep.pdf".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn separator_pages_split_the_batch() {
+        let staged = tempfile::tempdir().unwrap();
+        let client = Arc::new(DryRunClient::default());
+        let queue = SettleQueue::new(
+            Duration::from_millis(100),
+            true,
+            CircuitBreaker::default(),
+            None,
+        );
+
+        queue.split(None, "scanner");
+        for name in ["a1.jpg", "a2.jpg", "-", "-", "b1.jpg"] {
+            if name == "-" {
+                queue.split(None, "scanner");
+                continue;
+            }
+            let path = staged.path().join(name);
+            std::fs::write(&path, name).unwrap();
+            queue
+                .add(
+                    client.clone(),
+                    None,
+                    "scanner",
+                    "local",
+                    name,
+                    &path,
+                    UploadOptions::default(),
+                )
+                .await
+                .unwrap();
+        }
+        let segments: Vec<_> = queue.batches.lock().unwrap()[&(None, "scanner".to_string())]
+            .files
+            .iter()
+            .map(|file| file.segment)
+            .collect();
+        assert_eq!(segments, vec![0, 0, 1]);
+
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(client.uploads().len(), 3);
+    }
+
+    #[test]
+    fn parses_separator_pages() {
+        assert_eq!("blank".parse(), Ok(SeparatorPage::Blank));
+        assert_eq!(
+            "barcode:PATCHT".parse(),
+            Ok(SeparatorPage::Barcode("PATCHT".to_string()))
+        );
+        assert!("barcode:".parse::<SeparatorPage>().is_err());
+        assert!("qr".parse::<SeparatorPage>().is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+/// Stops calling Paperless for a while after repeated failures, so scanners don't each wait for
+/// the full retries and timeouts during an outage. Shared by all FTP sessions.
+///
+/// Once the cooldown is over, uploads are attempted again; the first failure reopens the circuit.
+#[derive(Clone, Debug, Default)]
+pub struct CircuitBreaker {
+    /// Consecutive failures that open the circuit; never opens if `None`.
+    threshold: Option<u32>,
+    cooldown: Duration,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold: Some(threshold),
+            cooldown,
+            state: Arc::default(),
+        }
+    }
+
+    /// Whether Paperless may be called now.
+    pub fn allows_request(&self) -> bool {
+        self.allows_request_at(Instant::now())
+    }
+
+    fn allows_request_at(&self, now: Instant) -> bool {
+        let state = self.state.lock().expect("circuit breaker lock poisoned");
+        state.open_until.is_none_or(|until| now >= until)
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().expect("circuit breaker lock poisoned");
+        if state.open_until.take().is_some() {
+            info!("Paperless responds again, closing the circuit breaker");
+        }
+        state.failures = 0;
+    }
+
+    pub fn record_failure(&self) {
+        self.record_failure_at(Instant::now());
+    }
+
+    fn record_failure_at(&self, now: Instant) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        let mut state = self.state.lock().expect("circuit breaker lock poisoned");
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= threshold {
+            warn!(
+                "Paperless failed {} times in a row, not calling it for {}s",
+                state.failures,
+                self.cooldown.as_secs()
+            );
+            state.open_until = Some(now + self.cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_consecutive_failures_until_cooldown() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+
+        breaker.record_failure_at(now);
+        assert!(breaker.allows_request_at(now));
+        breaker.record_failure_at(now);
+        assert!(!breaker.allows_request_at(now));
+        assert!(breaker.allows_request_at(now + Duration::from_secs(60)));
+
+        // A failed trial after the cooldown reopens the circuit right away.
+        breaker.record_failure_at(now + Duration::from_secs(60));
+        assert!(!breaker.allows_request_at(now + Duration::from_secs(61)));
+
+        breaker.record_success();
+        assert!(breaker.allows_request_at(now + Duration::from_secs(61)));
+        breaker.record_failure_at(now + Duration::from_secs(61));
+        assert!(breaker.allows_request_at(now + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn default_breaker_never_opens() {
+        let breaker = CircuitBreaker::default();
+        for _ in 0..100 {
+            breaker.record_failure();
+        }
+        assert!(breaker.allows_request());
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use libunftp::options::ActivePassiveMode;
+
+use crate::auth::{BridgeAuthenticator, User, UsernamePasswordAuthenticator};
+use crate::breaker::CircuitBreaker;
+use crate::health::{
+    HEALTH_CHECK_INTERVAL, HEALTH_STATUS_MAX_AGE, PaperlessHealth, monitor_paperless_health,
+};
+use crate::paperless::PaperlessApi;
+use crate::rules::RulesFile;
+use crate::storage::PaperlessStorage;
+use crate::users::UserConfig;
+
+#[derive(Debug)]
+pub enum BridgeError {
+    /// No sink was given to send the documents to.
+    NoSink,
+    Server(libunftp::ServerError),
+}
+
+impl std::fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BridgeError::NoSink => write!(f, "No sink configured"),
+            BridgeError::Server(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for BridgeError {}
+
+/// An FTP server forwarding the documents it receives, for hosting the bridge inside another
+/// Rust service instead of spawning the binary.
+pub struct Bridge {
+    server: libunftp::Server<PaperlessStorage, User>,
+    listen: String,
+    client: Arc<dyn PaperlessApi>,
+    health: PaperlessHealth,
+}
+
+impl Bridge {
+    pub fn builder() -> BridgeBuilder {
+        BridgeBuilder::default()
+    }
+
+    /// Serve FTP until the listener fails, checking the health of the sink in the background.
+    pub async fn run(self) -> Result<(), BridgeError> {
+        let monitor = tokio::spawn(monitor_paperless_health(
+            self.client,
+            self.health,
+            CircuitBreaker::default(),
+            HEALTH_CHECK_INTERVAL,
+        ));
+        let result = self.server.listen(self.listen).await;
+        monitor.abort();
+        result.map_err(BridgeError::Server)
+    }
+}
+
+pub struct BridgeBuilder {
+    listen: String,
+    passive_ports: RangeInclusive<u16>,
+    client: Option<Arc<dyn PaperlessApi>>,
+    rules: Arc<RulesFile>,
+    users: HashMap<String, UserConfig>,
+    authenticators: Vec<Box<dyn BridgeAuthenticator>>,
+    greeting: &'static str,
+}
+
+impl Default for BridgeBuilder {
+    fn default() -> Self {
+        Self {
+            listen: "0.0.0.0:2121".to_string(),
+            passive_ports: 49152..=65535,
+            client: None,
+            rules: Arc::default(),
+            users: HashMap::new(),
+            authenticators: Vec::new(),
+            greeting: "ftp-paperless-bridge",
+        }
+    }
+}
+
+impl BridgeBuilder {
+    /// Address to listen on, e.g. `127.0.0.1:2121`.
+    pub fn listen(mut self, listen: impl Into<String>) -> Self {
+        self.listen = listen.into();
+        self
+    }
+
+    pub fn passive_ports(mut self, passive_ports: RangeInclusive<u16>) -> Self {
+        self.passive_ports = passive_ports;
+        self
+    }
+
+    /// Where documents are sent, e.g. a [`crate::paperless::PaperlessClient`] or a
+    /// [`crate::consume::ConsumeDirClient`].
+    pub fn sink(mut self, client: Arc<dyn PaperlessApi>) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Routing rules assigning metadata to uploads by keyword.
+    pub fn rules(mut self, rules: RulesFile) -> Self {
+        self.rules = Arc::new(rules);
+        self
+    }
+
+    /// A user with a password or trusted addresses, like an entry of the users file.
+    pub fn user(mut self, username: impl Into<String>, config: UserConfig) -> Self {
+        self.users.insert(username.into(), config);
+        self
+    }
+
+    /// A user store asked for usernames that weren't added with [`Self::user`], in the order
+    /// they were added.
+    pub fn authenticator(mut self, authenticator: Box<dyn BridgeAuthenticator>) -> Self {
+        self.authenticators.push(authenticator);
+        self
+    }
+
+    pub fn greeting(mut self, greeting: &'static str) -> Self {
+        self.greeting = greeting;
+        self
+    }
+
+    pub fn build(self) -> Result<Bridge, BridgeError> {
+        let client = self.client.ok_or(BridgeError::NoSink)?;
+        let health = PaperlessHealth::new_healthy(HEALTH_STATUS_MAX_AGE);
+        let authenticator = self.authenticators.into_iter().fold(
+            UsernamePasswordAuthenticator::from_users(self.users, health.clone()),
+            |authenticator, verifier| authenticator.with_verifier(verifier),
+        );
+        let storage_client = Arc::clone(&client);
+        let storage_health = health.clone();
+        let rules = self.rules;
+        let server = libunftp::ServerBuilder::with_authenticator(
+            Box::new(move || {
+                PaperlessStorage::new(Arc::clone(&storage_client), storage_health.clone())
+                    .with_rules(Arc::clone(&rules))
+            }),
+            Arc::new(authenticator),
+        )
+        .greeting(self.greeting)
+        .active_passive_mode(ActivePassiveMode::ActiveAndPassive)
+        .passive_ports(self.passive_ports)
+        .build()
+        .map_err(BridgeError::Server)?;
+        Ok(Bridge {
+            server,
+            listen: self.listen,
+            client,
+            health,
+        })
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+
+use log::debug;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::Notify;
+
+/// Paused transfers check again after this long even without being woken up.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+struct BudgetState {
+    used: u64,
+    next_id: u64,
+    /// Bytes staged by each running transfer, by the order the transfers started in.
+    transfers: BTreeMap<u64, u64>,
+}
+
+/// Limits the bytes staged by all transfers together. Transfers pause reading while the budget is
+/// used up, except for the oldest one, so that the budget is eventually freed again.
+#[derive(Clone, Debug)]
+pub struct TransferBudget {
+    limit: u64,
+    state: Arc<Mutex<BudgetState>>,
+    released: Arc<Notify>,
+}
+
+impl TransferBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            state: Arc::default(),
+            released: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Register a transfer. Its bytes count against the budget until it is dropped.
+    pub fn start(&self) -> Arc<Transfer> {
+        let mut state = self.state.lock().expect("budget lock poisoned");
+        let id = state.next_id;
+        state.next_id += 1;
+        state.transfers.insert(id, 0);
+        Arc::new(Transfer {
+            budget: self.clone(),
+            id,
+        })
+    }
+}
+
+/// A transfer counted against a [`TransferBudget`].
+#[derive(Debug)]
+pub struct Transfer {
+    budget: TransferBudget,
+    id: u64,
+}
+
+impl Transfer {
+    fn try_reserve(&self, bytes: u64) -> bool {
+        let mut state = self.budget.state.lock().expect("budget lock poisoned");
+        let oldest = state.transfers.keys().next() == Some(&self.id);
+        if state.used + bytes > self.budget.limit && !oldest {
+            return false;
+        }
+        state.used += bytes;
+        *state.transfers.entry(self.id).or_default() += bytes;
+        crate::metrics::STAGED_BYTES.set(state.used as i64);
+        true
+    }
+
+    fn release(&self, bytes: u64) {
+        let mut state = self.budget.state.lock().expect("budget lock poisoned");
+        let reserved = state.transfers.entry(self.id).or_default();
+        let bytes = bytes.min(*reserved);
+        *reserved -= bytes;
+        state.used -= bytes;
+        crate::metrics::STAGED_BYTES.set(state.used as i64);
+        self.budget.released.notify_waiters();
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        let mut state = self.budget.state.lock().expect("budget lock poisoned");
+        if let Some(bytes) = state.transfers.remove(&self.id) {
+            state.used -= bytes;
+        }
+        crate::metrics::STAGED_BYTES.set(state.used as i64);
+        self.budget.released.notify_waiters();
+    }
+}
+
+/// Pauses reading while the budget of `transfer` is used up. Without a transfer it just passes
+/// reads through.
+pub struct BudgetReader<R> {
+    inner: R,
+    transfer: Option<Arc<Transfer>>,
+    waiting: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl<R> BudgetReader<R> {
+    pub fn new(inner: R, transfer: Option<Arc<Transfer>>) -> Self {
+        Self {
+            inner,
+            transfer,
+            waiting: None,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for BudgetReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let Some(transfer) = self.transfer.clone() else {
+            return Pin::new(&mut self.inner).poll_read(cx, buf);
+        };
+        let wanted = buf.remaining() as u64;
+        loop {
+            if let Some(waiting) = &mut self.waiting {
+                ready!(waiting.as_mut().poll(cx));
+                self.waiting = None;
+            }
+            if transfer.try_reserve(wanted) {
+                break;
+            }
+            debug!("Transfer budget used up, pausing reads");
+            let released = Arc::clone(&transfer.budget.released);
+            self.waiting = Some(Box::pin(async move {
+                let _ = tokio::time::timeout(RECHECK_INTERVAL, released.notified()).await;
+            }));
+        }
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = match poll {
+            Poll::Ready(Ok(())) => (buf.filled().len() - before) as u64,
+            _ => 0,
+        };
+        transfer.release(wanted - read);
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn only_the_oldest_transfer_may_exceed_the_budget() {
+        let budget = TransferBudget::new(100);
+        let first = budget.start();
+        let second = budget.start();
+        assert!(second.try_reserve(60));
+        assert!(!second.try_reserve(60));
+        assert!(first.try_reserve(200));
+
+        drop(first);
+        assert!(second.try_reserve(40));
+        second.release(100);
+        assert!(second.try_reserve(100));
+    }
+
+    #[tokio::test]
+    async fn reads_continue_once_the_budget_is_freed() {
+        let budget = TransferBudget::new(8);
+        let first = budget.start();
+        assert!(first.try_reserve(8));
+        let mut reader = BudgetReader::new(&b"content"[..], Some(budget.start()));
+
+        let read = tokio::spawn(async move {
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content).await.unwrap();
+            content
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!read.is_finished());
+        drop(first);
+        assert_eq!(read.await.unwrap(), b"content");
+    }
+}
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use tokio::time::sleep;
+
+use crate::paperless::{PaperlessApi, PaperlessError, TaskStatus, UploadOptions, wait_for_task};
+
+const CONSUMPTION_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Periodically uploads a generated PDF and waits for Paperless to consume it, so a broken
+/// pipeline is noticed even when no scans arrive.
+#[derive(Clone)]
+pub struct Canary {
+    pub client: Arc<dyn PaperlessApi>,
+    pub interval: Duration,
+    /// Tag the canary documents with this tag ID.
+    pub tag: Option<u64>,
+    /// Delete the canary document after it was consumed.
+    pub delete: bool,
+}
+
+impl Canary {
+    pub async fn run_loop(self) {
+        loop {
+            sleep(self.interval).await;
+            match self.run().await {
+                Ok(()) => {
+                    info!("Canary upload was consumed by Paperless");
+                    crate::metrics::CANARY_LAST_SUCCESS.set(chrono::Utc::now().timestamp());
+                }
+                Err(e) => {
+                    error!("Canary upload failed, the pipeline to Paperless is broken: {e}");
+                    crate::metrics::CANARY_FAILURES.inc();
+                }
+            }
+        }
+    }
+
+    /// Upload one canary document and wait until Paperless consumed it.
+    pub async fn run(&self) -> Result<(), PaperlessError> {
+        let now = chrono::Local::now();
+        // Unique content, so Paperless doesn't reject the canary as a duplicate.
+        let pdf =
+            crate::document::text_pdf(&format!("ftp-paperless-bridge canary {}", now.to_rfc3339()));
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("canary-{}.pdf", now.format("%Y%m%d-%H%M%S")));
+        tokio::fs::write(&path, pdf).await?;
+        let Some(path_str) = path.to_str() else {
+            return Err(PaperlessError::Api(
+                "Temporary directory path is not valid UTF-8".to_string(),
+            ));
+        };
+
+        let options = UploadOptions {
+            tags: self.tag.into_iter().collect(),
+            ..Default::default()
+        };
+        let uploaded = self.client.upload(path_str, &options).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        let task_id = uploaded?;
+
+        match wait_for_task(self.client.as_ref(), &task_id, CONSUMPTION_TIMEOUT).await? {
+            TaskStatus::Success { document_id } => {
+                if self.delete
+                    && let Some(document_id) = document_id
+                    && let Err(e) = self.client.delete_document(document_id).await
+                {
+                    warn!("Failed to delete canary document {document_id}: {e}");
+                }
+                Ok(())
+            }
+            TaskStatus::Failure(reason) => {
+                Err(PaperlessError::ConsumptionFailed { task_id, reason })
+            }
+            status => Err(PaperlessError::Api(format!(
+                "Canary task {task_id} ended in {status:?}"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::ObjectKind;
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingClient {
+        uploaded_tags: Mutex<Vec<u64>>,
+        deleted: Mutex<Vec<u64>>,
+    }
+
+    #[async_trait]
+    impl PaperlessApi for RecordingClient {
+        async fn health_check(&self) -> Result<(), PaperlessError> {
+            Ok(())
+        }
+
+        async fn upload(
+            &self,
+            path: &str,
+            options: &UploadOptions,
+        ) -> Result<String, PaperlessError> {
+            assert!(std::fs::read(path)?.starts_with(b"%PDF-"));
+            *self.uploaded_tags.lock().unwrap() = options.tags.clone();
+            Ok("task".to_string())
+        }
+
+        async fn upload_bytes(
+            &self,
+            _name: &str,
+            document: Bytes,
+            options: &UploadOptions,
+        ) -> Result<String, PaperlessError> {
+            assert!(document.starts_with(b"%PDF-"));
+            *self.uploaded_tags.lock().unwrap() = options.tags.clone();
+            Ok("task".to_string())
+        }
+
+        async fn task_status(&self, _task_id: &str) -> Result<TaskStatus, PaperlessError> {
+            Ok(TaskStatus::Success {
+                document_id: Some(9),
+            })
+        }
+
+        async fn document_checksum(&self, _document_id: u64) -> Result<String, PaperlessError> {
+            unimplemented!()
+        }
+
+        async fn delete_document(&self, document_id: u64) -> Result<(), PaperlessError> {
+            self.deleted.lock().unwrap().push(document_id);
+            Ok(())
+        }
+
+        async fn find_object(
+            &self,
+            _kind: ObjectKind,
+            _name: &str,
+        ) -> Result<Option<u64>, PaperlessError> {
+            unimplemented!()
+        }
+
+        async fn create_object(
+            &self,
+            _kind: ObjectKind,
+            _name: &str,
+            _parent: Option<u64>,
+        ) -> Result<u64, PaperlessError> {
+            unimplemented!()
+        }
+
+        async fn merge_documents(&self, _document_ids: &[u64]) -> Result<(), PaperlessError> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn canary_is_tagged_and_deleted() {
+        let client = Arc::new(RecordingClient::default());
+        let canary = Canary {
+            client: client.clone(),
+            interval: Duration::from_secs(3600),
+            tag: Some(4),
+            delete: true,
+        };
+        canary.run().await.unwrap();
+        assert_eq!(*client.uploaded_tags.lock().unwrap(), vec![4]);
+        assert_eq!(*client.deleted.lock().unwrap(), vec![9]);
+    }
+}
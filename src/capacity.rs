@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use log::{error, info};
+use tokio::time::{MissedTickBehavior, interval};
+
+/// How often the free space of the staging and spool filesystems is checked.
+pub const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Free space and inodes a filesystem needs to take uploads.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MinFree {
+    pub bytes: Option<u64>,
+    pub inodes: Option<u64>,
+}
+
+/// Free space and inodes of a filesystem. `inodes` is `None` for filesystems without a fixed
+/// number of them, like btrfs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Free {
+    pub bytes: u64,
+    pub inodes: Option<u64>,
+}
+
+impl MinFree {
+    pub fn is_set(&self) -> bool {
+        self.bytes.is_some() || self.inodes.is_some()
+    }
+
+    /// Why a filesystem with `free` room can't take uploads, or `None` if it can.
+    pub fn shortage(&self, free: Free) -> Option<String> {
+        if let Some(min) = self.bytes
+            && free.bytes < min
+        {
+            return Some(format!(
+                "{} bytes are free, at least {min} are needed",
+                free.bytes
+            ));
+        }
+        if let Some(min) = self.inodes
+            && let Some(inodes) = free.inodes
+            && inodes < min
+        {
+            return Some(format!(
+                "{inodes} inodes are free, at least {min} are needed"
+            ));
+        }
+        None
+    }
+}
+
+/// The free space and inodes of the filesystem `dir` is on, as available to unprivileged users.
+#[cfg(unix)]
+pub fn free(dir: &Path) -> std::io::Result<Free> {
+    let stat = nix::sys::statvfs::statvfs(dir)?;
+    Ok(Free {
+        bytes: stat.blocks_available() as u64 * stat.fragment_size() as u64,
+        inodes: (stat.files() > 0).then_some(stat.files_available() as u64),
+    })
+}
+
+#[cfg(not(unix))]
+pub fn free(_dir: &Path) -> std::io::Result<Free> {
+    Err(std::io::Error::other(
+        "free space can't be determined on this platform",
+    ))
+}
+
+/// Whether the directories uploads are written to have room, checked at startup and then
+/// periodically, so uploads are refused up front instead of failing halfway through a transfer.
+#[derive(Debug, Clone)]
+pub struct DiskCapacity {
+    dirs: Vec<PathBuf>,
+    min: MinFree,
+    shortage: Arc<RwLock<Option<String>>>,
+}
+
+impl DiskCapacity {
+    pub fn new(dirs: Vec<PathBuf>, min: MinFree) -> Self {
+        Self {
+            dirs,
+            min,
+            shortage: Arc::default(),
+        }
+    }
+
+    /// Why uploads are refused as of the last check, or `None` if there is room.
+    pub fn shortage(&self) -> Option<String> {
+        self.shortage
+            .read()
+            .expect("disk capacity lock poisoned")
+            .clone()
+    }
+
+    /// Measure the directories again, returning why uploads are refused, if they are.
+    pub fn check(&self) -> Option<String> {
+        let mut shortage = None;
+        for dir in &self.dirs {
+            let label = dir.display().to_string();
+            match free(dir) {
+                Ok(free) => {
+                    crate::metrics::DISK_FREE_BYTES
+                        .with_label_values(&[&label])
+                        .set(free.bytes as i64);
+                    if let Some(inodes) = free.inodes {
+                        crate::metrics::DISK_FREE_INODES
+                            .with_label_values(&[&label])
+                            .set(inodes as i64);
+                    }
+                    if shortage.is_none() {
+                        shortage = self
+                            .min
+                            .shortage(free)
+                            .map(|reason| format!("{}: {reason}", dir.display()));
+                    }
+                }
+                Err(e) if shortage.is_none() => {
+                    shortage = Some(format!("Can't determine the free space of {label}: {e}"));
+                }
+                Err(_) => {}
+            }
+        }
+
+        let mut current = self.shortage.write().expect("disk capacity lock poisoned");
+        match (&*current, &shortage) {
+            (None, Some(reason)) => error!("Refusing uploads, the disk is running full: {reason}"),
+            (Some(_), None) => info!("The disk has room again; uploads are accepted"),
+            _ => {}
+        }
+        crate::metrics::DISK_LOW.set(i64::from(shortage.is_some()));
+        current.clone_from(&shortage);
+        shortage
+    }
+
+    /// Check the directories every `check_interval`.
+    pub async fn monitor_loop(self, check_interval: Duration) {
+        let mut ticker = interval(check_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            self.check();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortage_of_space_or_inodes_refuses_uploads() {
+        let min = MinFree {
+            bytes: Some(1000),
+            inodes: Some(10),
+        };
+        let roomy = Free {
+            bytes: 5000,
+            inodes: Some(50),
+        };
+        assert!(min.shortage(roomy).is_none());
+        assert!(
+            min.shortage(Free {
+                bytes: 999,
+                ..roomy
+            })
+            .unwrap()
+            .contains("999 bytes")
+        );
+        assert!(
+            min.shortage(Free {
+                inodes: Some(3),
+                ..roomy
+            })
+            .unwrap()
+            .contains("3 inodes")
+        );
+        // Filesystems without a fixed number of inodes never run out of them.
+        assert!(
+            min.shortage(Free {
+                inodes: None,
+                ..roomy
+            })
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn capacity_flips_with_the_free_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let capacity = DiskCapacity::new(vec![dir.path().to_path_buf()], MinFree::default());
+        assert!(capacity.check().is_none());
+        assert!(capacity.shortage().is_none());
+
+        let capacity = DiskCapacity::new(
+            vec![dir.path().to_path_buf()],
+            MinFree {
+                bytes: Some(u64::MAX),
+                inodes: None,
+            },
+        );
+        assert!(capacity.check().is_some());
+        assert!(capacity.shortage().is_some());
+    }
+}
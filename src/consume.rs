@@ -0,0 +1,230 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::info;
+use serde_json::{Map, Value, json};
+
+use crate::metadata::ObjectKind;
+use crate::paperless::{PaperlessApi, PaperlessError, TaskStatus, UploadOptions};
+
+/// Writes documents into the consume directory of Paperless instead of uploading them via the API,
+/// for setups where only a shared volume reaches Paperless.
+#[derive(Debug)]
+pub struct ConsumeDirClient {
+    dir: PathBuf,
+    written: AtomicU64,
+}
+
+impl ConsumeDirClient {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            written: AtomicU64::new(0),
+        }
+    }
+
+    /// A path in the consume directory for `name` that doesn't exist yet, e.g. `scan_2.pdf`.
+    fn free_path(&self, name: &str) -> PathBuf {
+        let name = Path::new(name);
+        let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = name
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+        let mut path = self.dir.join(name);
+        let mut counter = 1;
+        while path.exists() || sidecar_path(&path).exists() {
+            counter += 1;
+            path = self.dir.join(format!("{stem}_{counter}{extension}"));
+        }
+        path
+    }
+
+    /// The path for `name` and the partial file to write it to first, after writing the metadata.
+    async fn reserve(
+        &self,
+        name: &str,
+        options: &UploadOptions,
+    ) -> Result<(PathBuf, PathBuf), PaperlessError> {
+        let target = self.free_path(name);
+        if let Some(metadata) = sidecar(options) {
+            tokio::fs::write(sidecar_path(&target), metadata.to_string()).await?;
+        }
+        // Paperless ignores the unsupported extension until the file is complete.
+        let mut partial = target.clone().into_os_string();
+        partial.push(".part");
+        Ok((target, partial.into()))
+    }
+
+    async fn complete(&self, partial: &Path, target: &Path) -> Result<String, PaperlessError> {
+        tokio::fs::rename(partial, target).await?;
+        let written = self.written.fetch_add(1, Ordering::Relaxed) + 1;
+        Ok(format!("consume-dir-{written}"))
+    }
+}
+
+/// Where the metadata of a document is written, e.g. `scan.pdf.json` for `scan.pdf`.
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".json");
+    path.with_file_name(name)
+}
+
+/// The metadata Paperless can't take from the file itself, with the IDs of its objects.
+fn sidecar(options: &UploadOptions) -> Option<Value> {
+    let mut metadata = Map::new();
+    if let Some(title) = &options.title {
+        metadata.insert("title".to_string(), json!(title));
+    }
+    if let Some(created) = &options.created {
+        metadata.insert("created".to_string(), json!(created));
+    }
+    if let Some(correspondent) = options.correspondent {
+        metadata.insert("correspondent".to_string(), json!(correspondent));
+    }
+    if let Some(document_type) = options.document_type {
+        metadata.insert("document_type".to_string(), json!(document_type));
+    }
+    if !options.tags.is_empty() {
+        metadata.insert("tags".to_string(), json!(options.tags));
+    }
+    if let Some(asn) = options.archive_serial_number {
+        metadata.insert("archive_serial_number".to_string(), json!(asn));
+    }
+    if !options.custom_fields.is_empty() {
+        let fields: Map<String, Value> = options
+            .custom_fields
+            .iter()
+            .map(|(id, value)| (id.to_string(), json!(value)))
+            .collect();
+        metadata.insert("custom_fields".to_string(), Value::Object(fields));
+    }
+    (!metadata.is_empty()).then_some(Value::Object(metadata))
+}
+
+#[async_trait]
+impl PaperlessApi for ConsumeDirClient {
+    async fn health_check(&self) -> Result<(), PaperlessError> {
+        let metadata = tokio::fs::metadata(&self.dir).await?;
+        if metadata.is_dir() {
+            Ok(())
+        } else {
+            Err(PaperlessError::Api(format!(
+                "{} is not a directory",
+                self.dir.display()
+            )))
+        }
+    }
+
+    async fn upload(&self, path: &str, options: &UploadOptions) -> Result<String, PaperlessError> {
+        let name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "scan".to_string());
+        let (target, partial) = self.reserve(&name, options).await?;
+        tokio::fs::copy(path, &partial).await?;
+        info!("Wrote {path:?} to {}", target.display());
+        self.complete(&partial, &target).await
+    }
+
+    async fn upload_bytes(
+        &self,
+        name: &str,
+        document: Bytes,
+        options: &UploadOptions,
+    ) -> Result<String, PaperlessError> {
+        let (target, partial) = self.reserve(name, options).await?;
+        tokio::fs::write(&partial, &document).await?;
+        info!("Wrote {name:?} from memory to {}", target.display());
+        self.complete(&partial, &target).await
+    }
+
+    /// Consumption isn't observable from outside of Paperless.
+    async fn task_status(&self, _task_id: &str) -> Result<TaskStatus, PaperlessError> {
+        Ok(TaskStatus::Success { document_id: None })
+    }
+
+    async fn document_checksum(&self, document_id: u64) -> Result<String, PaperlessError> {
+        Err(PaperlessError::Api(format!(
+            "Document {document_id} can't be checked without the Paperless API"
+        )))
+    }
+
+    async fn delete_document(&self, document_id: u64) -> Result<(), PaperlessError> {
+        Err(PaperlessError::Api(format!(
+            "Document {document_id} can't be deleted without the Paperless API"
+        )))
+    }
+
+    async fn find_object(
+        &self,
+        kind: ObjectKind,
+        name: &str,
+    ) -> Result<Option<u64>, PaperlessError> {
+        Err(PaperlessError::Api(format!(
+            "{name:?} can't be looked up in Paperless {} without the Paperless API",
+            kind.endpoint()
+        )))
+    }
+
+    async fn create_object(
+        &self,
+        kind: ObjectKind,
+        name: &str,
+        _parent: Option<u64>,
+    ) -> Result<u64, PaperlessError> {
+        Err(PaperlessError::Api(format!(
+            "{name:?} can't be created in Paperless {} without the Paperless API",
+            kind.endpoint()
+        )))
+    }
+
+    async fn merge_documents(&self, _document_ids: &[u64]) -> Result<(), PaperlessError> {
+        Err(PaperlessError::Api(
+            "Documents can't be merged without the Paperless API".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn documents_and_metadata_are_written_to_the_consume_dir() {
+        let staging = tempfile::tempdir().unwrap();
+        let consume = tempfile::tempdir().unwrap();
+        let document = staging.path().join("scan.pdf");
+        std::fs::write(&document, b"%PDF").unwrap();
+        let client = ConsumeDirClient::new(consume.path().to_path_buf());
+        client.health_check().await.unwrap();
+
+        let options = UploadOptions {
+            tags: vec![4],
+            title: Some("Q3 report".to_string()),
+            ..Default::default()
+        };
+        client
+            .upload(document.to_str().unwrap(), &options)
+            .await
+            .unwrap();
+        client
+            .upload(document.to_str().unwrap(), &UploadOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read(consume.path().join("scan.pdf")).unwrap(),
+            b"%PDF"
+        );
+        let metadata: Value =
+            serde_json::from_slice(&std::fs::read(consume.path().join("scan.pdf.json")).unwrap())
+                .unwrap();
+        assert_eq!(metadata, json!({"title": "Q3 report", "tags": [4]}));
+        // The second upload doesn't replace the first.
+        assert!(consume.path().join("scan_2.pdf").exists());
+        assert!(!consume.path().join("scan_2.pdf.json").exists());
+    }
+}
@@ -0,0 +1,86 @@
+use std::fs::OpenOptions;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Result, eyre};
+use log::warn;
+use nix::sys::signal::kill;
+use nix::sys::wait::waitpid;
+use nix::unistd::{ForkResult, Pid, chdir, dup2, fork, setsid};
+
+/// Detach from the terminal and continue in a background process, as expected by init systems
+/// like OpenRC, runit or FreeBSD rc.
+///
+/// stdin is connected to /dev/null, stdout and stderr (and thereby the log) are appended to
+/// `output`. The process ID is written to `pidfile` before the foreground process exits, so it
+/// exists once the init system checks for it.
+///
+/// Forking only copies the calling thread, so this has to run before any threads are started.
+pub fn daemonize(pidfile: Option<&Path>, output: &Path) -> Result<()> {
+    if let Some(pidfile) = pidfile {
+        check_stale_pidfile(pidfile)?;
+    }
+    let output = OpenOptions::new().create(true).append(true).open(output)?;
+    let null = OpenOptions::new().read(true).open("/dev/null")?;
+
+    // SAFETY: No other threads are running yet.
+    match unsafe { fork() }? {
+        ForkResult::Parent { child } => {
+            // Wait for the intermediate process, which writes the pidfile.
+            waitpid(child, None)?;
+            std::process::exit(0);
+        }
+        ForkResult::Child => {}
+    }
+
+    // Start a new session without a controlling terminal, then fork again so the daemon can't
+    // acquire one.
+    setsid()?;
+    // SAFETY: Still single-threaded.
+    match unsafe { fork() }? {
+        ForkResult::Parent { child } => {
+            if let Some(pidfile) = pidfile
+                && let Err(e) = std::fs::write(pidfile, format!("{child}\n"))
+            {
+                eprintln!("Failed to write pidfile {}: {e}", pidfile.display());
+                let _ = kill(child, nix::sys::signal::Signal::SIGTERM);
+                std::process::exit(1);
+            }
+            std::process::exit(0);
+        }
+        ForkResult::Child => {}
+    }
+
+    chdir("/")?;
+    dup2(null.as_raw_fd(), 0)?;
+    dup2(output.as_raw_fd(), 1)?;
+    dup2(output.as_raw_fd(), 2)?;
+    Ok(())
+}
+
+/// Refuse to start when the pidfile names a running process.
+fn check_stale_pidfile(pidfile: &Path) -> Result<()> {
+    let Ok(content) = std::fs::read_to_string(pidfile) else {
+        return Ok(());
+    };
+    if let Ok(pid) = content.trim().parse::<i32>()
+        && kill(Pid::from_raw(pid), None).is_ok()
+    {
+        return Err(eyre!(
+            "Already running with PID {pid} according to {}",
+            pidfile.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Removes the pidfile when the daemon exits.
+pub struct PidfileGuard(pub PathBuf);
+
+impl Drop for PidfileGuard {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Failed to remove pidfile {}: {e}", self.0.display());
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use crate::paperless::PaperlessApi;
+
+/// Service answering with the caller's public IP address in plain text.
+const IP_ECHO_URL: &str = "https://api.ipify.org";
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Ok,
+    Warning,
+    Failure,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Outcome::Ok => "[ ok ]",
+            Outcome::Warning => "[warn]",
+            Outcome::Failure => "[FAIL]",
+        })
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    failed: bool,
+}
+
+impl Report {
+    fn line(&mut self, outcome: Outcome, message: impl fmt::Display) {
+        self.failed |= outcome == Outcome::Failure;
+        println!("{outcome} {message}");
+    }
+}
+
+/// Diagnose the usual causes of scanners failing to connect or upload and print a report.
+/// Returns whether all checks passed without failures.
+pub async fn run(
+    listen: &str,
+    passive_ports: RangeInclusive<u16>,
+    passive_host: Option<&str>,
+    paperless: &dyn PaperlessApi,
+    version_check: bool,
+) -> bool {
+    let mut report = Report::default();
+    let listen: SocketAddr = listen.parse().expect("listen address is validated by clap");
+
+    match TcpListener::bind(listen).await {
+        Ok(_) => report.line(Outcome::Ok, format!("Can listen on {listen}")),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied && listen.port() < 1024 => {
+            report.line(
+                Outcome::Failure,
+                format!(
+                    "Can't listen on {listen}: {e}. Ports below 1024 need root or the \
+                     CAP_NET_BIND_SERVICE capability"
+                ),
+            )
+        }
+        Err(e) => report.line(Outcome::Failure, format!("Can't listen on {listen}: {e}")),
+    }
+
+    let mut unavailable = Vec::new();
+    for port in passive_ports.clone() {
+        if std::net::TcpListener::bind((listen.ip(), port)).is_err() {
+            unavailable.push(port);
+        }
+    }
+    let total = passive_ports.len();
+    match unavailable.len() {
+        0 => report.line(
+            Outcome::Ok,
+            format!(
+                "All {total} passive ports {}-{} are free",
+                passive_ports.start(),
+                passive_ports.end()
+            ),
+        ),
+        n if n == total => report.line(
+            Outcome::Failure,
+            format!(
+                "None of the passive ports {}-{} can be used",
+                passive_ports.start(),
+                passive_ports.end()
+            ),
+        ),
+        n => report.line(
+            Outcome::Warning,
+            format!(
+                "{n} of {total} passive ports are in use, e.g. {}",
+                unavailable[0]
+            ),
+        ),
+    }
+
+    let passive_host = match passive_host {
+        Some(host) => match resolve_ipv4(host).await {
+            Some(ip) => Some(ip),
+            None => {
+                report.line(
+                    Outcome::Failure,
+                    format!("--passive-host {host} has no IPv4 address"),
+                );
+                None
+            }
+        },
+        None => None,
+    };
+    let advertised = match pasv_address(listen.ip(), passive_host, outbound_ip()) {
+        Ok(ip) => {
+            report.line(
+                Outcome::Ok,
+                format!("Passive mode (PASV) advertises {ip} to IPv4 clients"),
+            );
+            Some(IpAddr::V4(ip))
+        }
+        Err(reason) => {
+            report.line(Outcome::Warning, reason);
+            None
+        }
+    };
+    if listen.is_ipv6() {
+        report.line(
+            Outcome::Ok,
+            "IPv6 clients use extended passive mode (EPSV), which only announces the port",
+        );
+    }
+
+    match public_ip().await {
+        Ok(public) => {
+            if Some(public) == advertised {
+                report.line(Outcome::Ok, format!("Public address is {public}"));
+            } else {
+                report.line(
+                    Outcome::Warning,
+                    format!(
+                        "Public address is {public}, which differs from the advertised address. \
+                         Scanners in the local network are fine, but clients connecting through \
+                         NAT get an unreachable address for passive transfers"
+                    ),
+                );
+            }
+            check_reachable(&mut report, public, passive_ports.clone()).await;
+        }
+        Err(e) => report.line(
+            Outcome::Warning,
+            format!("Can't determine the public address via {IP_ECHO_URL}: {e}"),
+        ),
+    }
+
+    match timeout(CHECK_TIMEOUT, paperless.health_check()).await {
+        Ok(Ok(())) => report.line(Outcome::Ok, "Paperless responds and accepts the API token"),
+        Ok(Err(e)) => report.line(Outcome::Failure, format!("Paperless check failed: {e}")),
+        Err(_) => report.line(Outcome::Failure, "Paperless did not respond in time"),
+    }
+
+    if version_check {
+        match timeout(CHECK_TIMEOUT, paperless.compatibility()).await {
+            Ok(Ok(compatibility)) => {
+                if let Some(version) = &compatibility.version {
+                    report.line(Outcome::Ok, format!("Paperless version is {version}"));
+                }
+                for warning in compatibility.warnings() {
+                    report.line(Outcome::Warning, warning);
+                }
+            }
+            Ok(Err(e)) => report.line(
+                Outcome::Warning,
+                format!("Can't determine the Paperless version: {e}"),
+            ),
+            Err(_) => report.line(
+                Outcome::Warning,
+                "Paperless did not tell its version in time",
+            ),
+        }
+    }
+
+    !report.failed
+}
+
+/// The address PASV advertises to IPv4 clients of a server listening on `listen`, or why there
+/// is none. PASV can only carry IPv4 addresses; IPv6 clients use EPSV, which only sends the port.
+fn pasv_address(
+    listen: IpAddr,
+    passive_host: Option<Ipv4Addr>,
+    outbound: Option<Ipv4Addr>,
+) -> Result<Ipv4Addr, String> {
+    if let Some(host) = passive_host {
+        return Ok(host);
+    }
+    // libunftp answers PASV with the address the client connected to.
+    match listen.to_canonical() {
+        IpAddr::V4(ip) if !ip.is_unspecified() => Ok(ip),
+        IpAddr::V4(_) => {
+            outbound.ok_or_else(|| "Can't determine the local address clients connect to".into())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => Err(format!(
+            "IPv4 clients connecting to [::] arrive via IPv4-mapped IPv6 addresses, which PASV \
+             can't advertise. Set --passive-host{} or listen on 0.0.0.0",
+            outbound.map(|ip| format!(" {ip}")).unwrap_or_default()
+        )),
+        IpAddr::V6(ip) => Err(format!(
+            "PASV can't advertise the IPv6 address {ip}, so only clients using EPSV can transfer \
+             files. Set --passive-host for IPv4 clients"
+        )),
+    }
+}
+
+/// The IPv4 address of a `--passive-host`, which may be a DNS name.
+async fn resolve_ipv4(host: &str) -> Option<Ipv4Addr> {
+    if let Ok(ip) = host.parse() {
+        return Some(ip);
+    }
+    tokio::net::lookup_host((host, 0))
+        .await
+        .ok()?
+        .find_map(|addr| match addr.ip() {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+}
+
+/// The local address used for outgoing connections. Connecting a UDP socket sends nothing.
+fn outbound_ip() -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(_) => None,
+    }
+}
+
+async fn public_ip() -> Result<IpAddr, Box<dyn std::error::Error + Send + Sync>> {
+    let body = reqwest::Client::builder()
+        .timeout(CHECK_TIMEOUT)
+        .build()?
+        .get(IP_ECHO_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(body.trim().parse()?)
+}
+
+/// Listen on a passive port and connect to it via the public address, which only works if the
+/// port is forwarded and the router supports connecting to its own public address.
+async fn check_reachable(report: &mut Report, public: IpAddr, passive_ports: RangeInclusive<u16>) {
+    let mut listener = None;
+    for port in passive_ports {
+        if let Ok(bound) = TcpListener::bind(("0.0.0.0", port)).await {
+            listener = Some((bound, port));
+            break;
+        }
+    }
+    let Some((listener, port)) = listener else {
+        return;
+    };
+
+    let accept = async {
+        let _ = listener.accept().await;
+    };
+    let connect = timeout(CHECK_TIMEOUT, TcpStream::connect((public, port)));
+    let (_, connected) = tokio::join!(timeout(CHECK_TIMEOUT, accept), connect);
+    match connected {
+        Ok(Ok(_)) => report.line(
+            Outcome::Ok,
+            format!("Passive port {port} is reachable via {public}"),
+        ),
+        _ => report.line(
+            Outcome::Warning,
+            format!(
+                "Passive port {port} is not reachable via {public}. This is expected if the \
+                 bridge is only used in the local network; otherwise forward the passive ports \
+                 (some routers can't connect to their own public address, so verify from outside)"
+            ),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pasv_address_depends_on_the_listen_family() {
+        let lan = Ipv4Addr::new(192, 168, 1, 5);
+        let public = Ipv4Addr::new(203, 0, 113, 7);
+        let pasv = |listen: &str, host| pasv_address(listen.parse().unwrap(), host, Some(lan));
+
+        assert_eq!(pasv("192.168.1.9", None), Ok(Ipv4Addr::new(192, 168, 1, 9)));
+        assert_eq!(pasv("0.0.0.0", None), Ok(lan));
+        assert_eq!(
+            pasv("::ffff:192.168.1.9", None),
+            Ok(Ipv4Addr::new(192, 168, 1, 9))
+        );
+        assert!(
+            pasv("::", None)
+                .unwrap_err()
+                .contains("--passive-host 192.168.1.5")
+        );
+        assert!(pasv("2001:db8::1", None).is_err());
+
+        assert_eq!(pasv("::", Some(public)), Ok(public));
+        assert_eq!(pasv("2001:db8::1", Some(public)), Ok(public));
+        assert_eq!(pasv("0.0.0.0", Some(public)), Ok(public));
+    }
+}
@@ -0,0 +1,358 @@
+use std::ffi::OsStr;
+use std::io::SeekFrom;
+use std::path::Path;
+
+use md5::{Digest, Md5};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use unicode_normalization::UnicodeNormalization;
+
+/// How many trailing bytes are inspected when looking for an end-of-document marker. The PDF spec
+/// only has readers look at the last KiB, but scanners pad files and append data after the marker.
+const TRAILER_WINDOW: u64 = 64 * 1024;
+
+const PDF_MAGIC: &[u8] = b"%PDF-";
+const PDF_EOF: &[u8] = b"%%EOF";
+const JPEG_MAGIC: &[u8] = &[0xFF, 0xD8];
+const JPEG_EOI: &[u8] = &[0xFF, 0xD9];
+const PNG_MAGIC: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const PNG_IEND: &[u8] = b"IEND";
+
+/// Decode a filename sent by the client into NFC-normalized UTF-8.
+///
+/// Older scanner firmwares send Latin-1 names regardless of `OPTS UTF8`; those are decoded as
+/// Latin-1 instead of being mangled into replacement characters. Clients on macOS tend to send
+/// decomposed umlauts, which would otherwise end up in Paperless titles as two characters.
+pub fn decode_filename(name: &OsStr) -> String {
+    let decoded = match name.to_str() {
+        Some(name) => name.to_string(),
+        None => decode_non_utf8(name),
+    };
+    decoded.nfc().collect()
+}
+
+#[cfg(unix)]
+fn decode_non_utf8(name: &OsStr) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    // Every byte is a valid Latin-1 code point with the same value.
+    name.as_bytes().iter().map(|&b| char::from(b)).collect()
+}
+
+#[cfg(not(unix))]
+fn decode_non_utf8(name: &OsStr) -> String {
+    name.to_string_lossy().into_owned()
+}
+
+/// Guess the file extension from the first bytes of a document.
+pub fn sniff_extension(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(PDF_MAGIC) {
+        Some("pdf")
+    } else if head.starts_with(JPEG_MAGIC) {
+        Some("jpg")
+    } else if head.starts_with(PNG_MAGIC) {
+        Some("png")
+    } else if head.starts_with(b"II*\0") || head.starts_with(b"MM\0*") {
+        Some("tif")
+    } else if head.starts_with(b"GIF8") {
+        Some("gif")
+    } else if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+/// Whether `name` has an extension of the file type [`sniff_extension`] detected.
+pub fn has_extension_for(name: &str, sniffed: &str) -> bool {
+    let Some(extension) = Path::new(name).extension() else {
+        return false;
+    };
+    let extension = extension.to_string_lossy().to_lowercase();
+    extension == sniffed
+        || matches!(
+            (sniffed, extension.as_str()),
+            ("jpg", "jpeg" | "jpe") | ("tif", "tiff")
+        )
+}
+
+/// The MIME type of a document, by the extension of its name.
+pub fn mime_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+    Some(match extension.as_str() {
+        "pdf" => "application/pdf",
+        "jpg" | "jpeg" | "jpe" => "image/jpeg",
+        "png" => "image/png",
+        "tif" | "tiff" => "image/tiff",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "heic" => "image/heic",
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        "eml" => "message/rfc822",
+        _ => return None,
+    })
+}
+
+/// Whether a filename was generated by the FTP server for STOU (a bare UUID).
+pub fn is_generated_name(name: &str) -> bool {
+    name.len() == 36
+        && name.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// File extensions Paperless-ngx can consume, including the office formats handled through Tika.
+const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "pdf", "png", "jpg", "jpeg", "tif", "tiff", "gif", "webp", "bmp", "heic", "txt", "csv", "eml",
+    "rtf", "doc", "docx", "odt", "xls", "xlsx", "ods", "ppt", "pptx", "odp",
+];
+
+/// Whether Paperless can consume a file with this name. Names without an extension are accepted
+/// since Paperless detects the type from the content.
+pub fn is_supported_filename(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => {
+            let ext = ext.to_string_lossy().to_lowercase();
+            SUPPORTED_EXTENSIONS.contains(&ext.as_str())
+        }
+        None => true,
+    }
+}
+
+/// Check whether a staged document carries the end-of-document marker of its format.
+///
+/// FTP stream mode signals end-of-file by closing the data connection, so a transfer that was cut
+/// short looks exactly like a complete one. For formats with a trailer (PDF, JPEG, PNG) we can tell
+/// the difference; anything else is assumed to be complete.
+pub async fn is_complete(path: &Path) -> std::io::Result<bool> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+
+    let mut head = [0u8; 8];
+    let head_len = file.read(&mut head).await?;
+    let Some(marker) = trailer_marker(&head[..head_len]) else {
+        return Ok(true);
+    };
+
+    let window = len.min(TRAILER_WINDOW);
+    file.seek(SeekFrom::Start(len - window)).await?;
+    let mut tail = Vec::with_capacity(window as usize);
+    file.read_to_end(&mut tail).await?;
+
+    Ok(tail.windows(marker.len()).any(|w| w == marker))
+}
+
+/// [`is_complete`] for a document staged in memory.
+pub fn is_complete_bytes(document: &[u8]) -> bool {
+    let Some(marker) = trailer_marker(document) else {
+        return true;
+    };
+    let tail = &document[document.len().saturating_sub(TRAILER_WINDOW as usize)..];
+    tail.windows(marker.len()).any(|w| w == marker)
+}
+
+/// The end-of-document marker of the format a document starting with `head` is in, if it has one.
+fn trailer_marker(head: &[u8]) -> Option<&'static [u8]> {
+    if head.starts_with(PDF_MAGIC) {
+        Some(PDF_EOF)
+    } else if head.starts_with(PNG_MAGIC) {
+        Some(PNG_IEND)
+    } else if head.starts_with(JPEG_MAGIC) {
+        Some(JPEG_EOI)
+    } else {
+        None
+    }
+}
+
+/// MD5 checksum of a file as lowercase hex, the format used by SITE MD5 and by Paperless.
+pub async fn md5_file(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Md5::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// [`md5_file`] for a document staged in memory.
+pub fn md5(document: &[u8]) -> String {
+    format!("{:x}", Md5::digest(document))
+}
+
+/// A single-page PDF showing `text`, used to test the pipeline without a scanner.
+pub fn text_pdf(text: &str) -> Vec<u8> {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('(', "\\(")
+        .replace(')', "\\)");
+    let content = format!("BT /F1 18 Tf 72 770 Td ({escaped}) Tj ET");
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] /Contents 4 0 R \
+         /Resources << /Font << /F1 5 0 R >> >> >>"
+            .to_string(),
+        format!(
+            "<< /Length {} >>\nstream\n{content}\nendstream",
+            content.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+    ];
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{object}\nendobj\n", i + 1).as_bytes());
+    }
+    let xref = pdf.len();
+    pdf.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{offset:010} 00000 n \n").as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1
+        )
+        .as_bytes(),
+    );
+    pdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn generated_pdf_is_complete() {
+        let pdf = text_pdf("Canary (test)");
+        assert_eq!(sniff_extension(&pdf), Some("pdf"));
+        assert!(check(&pdf).await);
+        let pdf = String::from_utf8(pdf).unwrap();
+        assert!(pdf.contains("(Canary \\(test\\)) Tj"));
+        let xref: usize = pdf
+            .split("startxref\n")
+            .nth(1)
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(pdf[xref..].starts_with("xref"));
+    }
+
+    async fn check(data: &[u8]) -> bool {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doc");
+        std::fs::write(&path, data).unwrap();
+        is_complete(&path).await.unwrap()
+    }
+
+    #[test]
+    fn decomposed_filenames_are_normalized() {
+        assert_eq!(
+            decode_filename(OsStr::new("Rechnung_Mu\u{308}ller.pdf")),
+            "Rechnung_Müller.pdf"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn latin1_filenames_are_decoded() {
+        use std::os::unix::ffi::OsStrExt;
+
+        assert_eq!(
+            decode_filename(OsStr::from_bytes(b"Rechnung_M\xfcller.pdf")),
+            "Rechnung_Müller.pdf"
+        );
+    }
+
+    #[test]
+    fn sniffs_common_scan_formats() {
+        assert_eq!(sniff_extension(b"%PDF-1.7"), Some("pdf"));
+        assert_eq!(sniff_extension(&[0xFF, 0xD8, 0xFF, 0xE0]), Some("jpg"));
+        assert_eq!(sniff_extension(b"II*\0\x08\0"), Some("tif"));
+        assert_eq!(sniff_extension(b"hello"), None);
+    }
+
+    #[test]
+    fn extensions_are_matched_to_the_content() {
+        assert!(has_extension_for("scan.PDF", "pdf"));
+        assert!(has_extension_for("photo.jpeg", "jpg"));
+        assert!(has_extension_for("fax.tiff", "tif"));
+        assert!(!has_extension_for("scan.dat", "pdf"));
+        assert!(!has_extension_for("scan", "pdf"));
+
+        assert_eq!(mime_type(Path::new("scan.pdf")), Some("application/pdf"));
+        assert_eq!(mime_type(Path::new("fax.TIF")), Some("image/tiff"));
+        assert_eq!(mime_type(Path::new("letter.docx")), None);
+    }
+
+    #[test]
+    fn detects_stou_names() {
+        assert!(is_generated_name("5f0c8a9e-3b1d-4c2e-9f7a-0123456789ab"));
+        assert!(!is_generated_name("scan_0001.pdf"));
+    }
+
+    #[test]
+    fn supported_filenames() {
+        assert!(is_supported_filename(Path::new("scan.pdf")));
+        assert!(is_supported_filename(Path::new("SCAN0001.JPG")));
+        assert!(is_supported_filename(Path::new("scan")));
+        assert!(!is_supported_filename(Path::new("setup.exe")));
+    }
+
+    #[tokio::test]
+    async fn complete_pdf_is_accepted() {
+        assert!(check(b"%PDF-1.7\n1 0 obj\nendobj\ntrailer\n%%EOF\n").await);
+    }
+
+    #[tokio::test]
+    async fn truncated_pdf_is_detected() {
+        assert!(!check(b"%PDF-1.7\n1 0 obj\nendobj\n").await);
+    }
+
+    #[tokio::test]
+    async fn data_after_the_marker_is_accepted() {
+        let mut pdf = b"%PDF-1.7\n1 0 obj\nendobj\ntrailer\n%%EOF\n".to_vec();
+        pdf.resize(pdf.len() + 4096, 0);
+        assert!(check(&pdf).await);
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0xFF, 0xD9];
+        jpeg.extend(std::iter::repeat_n(b'x', 8192));
+        assert!(check(&jpeg).await);
+    }
+
+    #[tokio::test]
+    async fn truncated_jpeg_is_detected() {
+        assert!(check(&[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0xFF, 0xD9]).await);
+        assert!(!check(&[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10]).await);
+    }
+
+    #[tokio::test]
+    async fn md5_matches_known_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doc");
+        std::fs::write(&path, b"test pdf content").unwrap();
+        assert_eq!(
+            md5_file(&path).await.unwrap(),
+            "b4813e2f48697570f3f65abc97fc32f6"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_formats_are_assumed_complete() {
+        assert!(check(b"plain text").await);
+        assert!(check(b"").await);
+    }
+}
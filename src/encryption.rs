@@ -0,0 +1,132 @@
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Plaintext bytes sealed together, so large scans don't have to fit into memory.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Random bytes at the start of every file, followed by the chunk counter in each nonce.
+const PREFIX_LEN: usize = NONCE_LEN - 4;
+const TAG_LEN: usize = 16;
+
+/// An AES-256-GCM key encrypting files at rest.
+///
+/// Files are sealed in chunks whose nonces combine a random prefix with a counter. The last chunk
+/// is marked, so a truncated file fails to decrypt instead of losing its end silently.
+#[derive(Debug, Clone)]
+pub struct FileKey(Arc<LessSafeKey>);
+
+impl FileKey {
+    /// Read a 32-byte key from `path`, encoded as base64 or hex, e.g. from
+    /// `openssl rand -base64 32`.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let encoded = std::fs::read_to_string(path)?;
+        let encoded = encoded.trim();
+        let bytes = data_encoding::BASE64
+            .decode(encoded.as_bytes())
+            .or_else(|_| data_encoding::HEXLOWER_PERMISSIVE.decode(encoded.as_bytes()))
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "key is not base64 or hex"))?;
+        Self::new(&bytes)
+    }
+
+    pub fn new(bytes: &[u8]) -> std::io::Result<Self> {
+        let key = UnboundKey::new(&AES_256_GCM, bytes).map_err(|_| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("key has {} bytes instead of 32", bytes.len()),
+            )
+        })?;
+        Ok(Self(Arc::new(LessSafeKey::new(key))))
+    }
+
+    pub fn encrypt(&self, mut input: impl Read, mut output: impl Write) -> std::io::Result<()> {
+        let mut prefix = [0; PREFIX_LEN];
+        SystemRandom::new()
+            .fill(&mut prefix)
+            .map_err(|_| std::io::Error::other("no random numbers available"))?;
+        output.write_all(&prefix)?;
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE + TAG_LEN);
+        for counter in 0u32.. {
+            chunk.clear();
+            (&mut input)
+                .take(CHUNK_SIZE as u64)
+                .read_to_end(&mut chunk)?;
+            let last = chunk.len() < CHUNK_SIZE;
+            self.0
+                .seal_in_place_append_tag(nonce(&prefix, counter), aad(last), &mut chunk)
+                .map_err(|_| std::io::Error::other("failed to encrypt"))?;
+            output.write_all(&chunk)?;
+            if last {
+                break;
+            }
+        }
+        output.flush()
+    }
+
+    pub fn decrypt(&self, mut input: impl Read, mut output: impl Write) -> std::io::Result<()> {
+        let invalid = || std::io::Error::new(ErrorKind::InvalidData, "file can't be decrypted");
+        let mut prefix = [0; PREFIX_LEN];
+        input.read_exact(&mut prefix)?;
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE + TAG_LEN);
+        for counter in 0u32.. {
+            chunk.clear();
+            (&mut input)
+                .take((CHUNK_SIZE + TAG_LEN) as u64)
+                .read_to_end(&mut chunk)?;
+            // Only the last chunk is shorter than a full one.
+            let last = chunk.len() < CHUNK_SIZE + TAG_LEN;
+            let plaintext = self
+                .0
+                .open_in_place(nonce(&prefix, counter), aad(last), &mut chunk)
+                .map_err(|_| invalid())?;
+            output.write_all(plaintext)?;
+            if last {
+                break;
+            }
+        }
+        output.flush()
+    }
+}
+
+fn nonce(prefix: &[u8; PREFIX_LEN], counter: u32) -> Nonce {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..].copy_from_slice(&counter.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn aad(last: bool) -> Aad<[u8; 1]> {
+    Aad::from([u8::from(last)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_round_trip_and_detect_truncation() {
+        let key = FileKey::new(&[7; 32]).unwrap();
+        for size in [0, 10, CHUNK_SIZE, 3 * CHUNK_SIZE + 5] {
+            let content: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let mut sealed = Vec::new();
+            key.encrypt(&content[..], &mut sealed).unwrap();
+
+            let mut opened = Vec::new();
+            key.decrypt(&sealed[..], &mut opened).unwrap();
+            assert_eq!(opened, content);
+
+            if size >= CHUNK_SIZE {
+                let truncated = &sealed[..PREFIX_LEN + CHUNK_SIZE + TAG_LEN];
+                assert!(key.decrypt(truncated, &mut Vec::new()).is_err());
+            }
+        }
+
+        let other = FileKey::new(&[8; 32]).unwrap();
+        let mut sealed = Vec::new();
+        key.encrypt(&b"%PDF"[..], &mut sealed).unwrap();
+        assert!(other.decrypt(&sealed[..], &mut Vec::new()).is_err());
+        assert!(FileKey::new(&[0; 16]).is_err());
+    }
+}
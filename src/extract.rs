@@ -0,0 +1,502 @@
+use std::io::SeekFrom;
+use std::path::Path;
+
+use clap::ValueEnum;
+use std::io::Read;
+
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// How much of the start and the end of a PDF is searched for its document info.
+const PDF_WINDOW: u64 = 1024 * 1024;
+/// How much of the start of an image is searched for its EXIF data.
+const EXIF_WINDOW: u64 = 128 * 1024;
+/// Larger PDFs are not searched for text.
+const MAX_TEXT_PDF_SIZE: u64 = 32 * 1024 * 1024;
+/// Limit on the size of a decompressed stream, against decompression bombs.
+const MAX_INFLATED_STREAM_SIZE: u64 = 16 * 1024 * 1024;
+
+const EXIF_DATE_TIME: u16 = 0x0132;
+const EXIF_IMAGE_DESCRIPTION: u16 = 0x010E;
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const EXIF_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+/// Document properties that can be taken from the metadata embedded in an upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EmbeddedField {
+    Created,
+    Title,
+}
+
+/// Metadata embedded in a PDF's document info or XMP packet, or in the EXIF data of an image.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct EmbeddedMetadata {
+    /// Creation date as `YYYY-MM-DD`.
+    pub created: Option<String>,
+    pub title: Option<String>,
+}
+
+/// Read the embedded metadata of a PDF, JPEG or TIFF file. Other files have none.
+pub async fn read_embedded(path: &Path) -> std::io::Result<EmbeddedMetadata> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    let mut head = Vec::new();
+    (&mut file).take(PDF_WINDOW).read_to_end(&mut head).await?;
+
+    if head.starts_with(b"%PDF-") {
+        // The document info is usually referenced by the trailer at the end of the file.
+        let mut content = head;
+        if len > PDF_WINDOW {
+            file.seek(SeekFrom::Start(
+                len.saturating_sub(PDF_WINDOW).max(PDF_WINDOW),
+            ))
+            .await?;
+            file.read_to_end(&mut content).await?;
+        }
+        Ok(pdf_metadata(&content))
+    } else if head.starts_with(&[0xFF, 0xD8]) {
+        head.truncate(EXIF_WINDOW as usize);
+        Ok(jpeg_exif(&head).map(tiff_metadata).unwrap_or_default())
+    } else if head.starts_with(b"II*\0") || head.starts_with(b"MM\0*") {
+        Ok(tiff_metadata(&head))
+    } else {
+        Ok(EmbeddedMetadata::default())
+    }
+}
+
+fn pdf_metadata(content: &[u8]) -> EmbeddedMetadata {
+    let created = pdf_string(content, b"/CreationDate")
+        .and_then(|date| date_from_digits(date.trim_start_matches("D:")))
+        .or_else(|| {
+            xmp_element(content, b"<xmp:CreateDate>").and_then(|date| date_from_digits(&date))
+        });
+    let title = pdf_string(content, b"/Title")
+        .or_else(|| {
+            // <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Title</rdf:li></rdf:Alt></dc:title>
+            let start = find(content, b"<dc:title>")?;
+            xmp_element(&content[start..], b"<rdf:li")
+        })
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty());
+    EmbeddedMetadata { created, title }
+}
+
+/// The last value of a document info entry, since incremental updates append newer ones.
+fn pdf_string(content: &[u8], key: &[u8]) -> Option<String> {
+    let start = rfind(content, key)? + key.len();
+    let value = &content[start..];
+    let value = &value[value.iter().position(|b| !b.is_ascii_whitespace())?..];
+    let bytes = match value.first()? {
+        b'(' => literal_string(&value[1..])?.0,
+        b'<' => hex_string(&value[1..])?.0,
+        _ => return None,
+    };
+    Some(decode_text_string(&bytes))
+}
+
+/// Bytes of a literal string like `(Q3 \(draft\))`, starting after the opening parenthesis, and
+/// the length up to and including the closing one.
+fn literal_string(value: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut bytes = Vec::new();
+    let mut depth = 0;
+    let mut chars = value.iter().copied().enumerate().peekable();
+    while let Some((i, b)) = chars.next() {
+        match b {
+            b'\\' => match chars.next()?.1 {
+                b'n' => bytes.push(b'\n'),
+                b'r' => bytes.push(b'\r'),
+                b't' => bytes.push(b'\t'),
+                b'b' => bytes.push(0x08),
+                b'f' => bytes.push(0x0C),
+                b'\r' | b'\n' => {}
+                digit @ b'0'..=b'7' => {
+                    let mut code = u32::from(digit - b'0');
+                    for _ in 0..2 {
+                        match chars.peek() {
+                            Some(&(_, next @ b'0'..=b'7')) => {
+                                code = code * 8 + u32::from(next - b'0');
+                                chars.next();
+                            }
+                            _ => break,
+                        }
+                    }
+                    bytes.push(code as u8);
+                }
+                other => bytes.push(other),
+            },
+            b'(' => {
+                depth += 1;
+                bytes.push(b);
+            }
+            b')' if depth == 0 => return Some((bytes, i + 1)),
+            b')' => {
+                depth -= 1;
+                bytes.push(b);
+            }
+            _ => bytes.push(b),
+        }
+    }
+    None
+}
+
+/// Bytes of a hex string like `<FEFF0051>`, starting after the opening angle bracket, and the
+/// length up to and including the closing one.
+fn hex_string(value: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let end = value.iter().position(|&b| b == b'>')?;
+    let mut digits: Vec<u8> = value[..end]
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    if digits.len() % 2 == 1 {
+        digits.push(b'0');
+    }
+    let bytes = digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<_>>()?;
+    Some((bytes, end + 1))
+}
+
+/// Text strings are UTF-16BE with a byte order mark, or close enough to Latin-1 otherwise.
+fn decode_text_string(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => bytes.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+/// Lowercased text shown by the content streams of a PDF, or `None` for other files.
+///
+/// This is a best effort: text in fonts with custom encodings, as produced by some OCR tools,
+/// comes out garbled, and text in compressed object streams is not found.
+pub async fn pdf_text(path: &Path) -> std::io::Result<Option<String>> {
+    if tokio::fs::metadata(path).await?.len() > MAX_TEXT_PDF_SIZE {
+        return Ok(None);
+    }
+    let content = tokio::fs::read(path).await?;
+    if !content.starts_with(b"%PDF-") {
+        return Ok(None);
+    }
+    tokio::task::spawn_blocking(move || Some(extract_pdf_text(&content)))
+        .await
+        .map_err(std::io::Error::other)
+}
+
+fn extract_pdf_text(pdf: &[u8]) -> String {
+    let mut text = String::new();
+    let mut rest = pdf;
+    while let Some(start) = find(rest, b"stream") {
+        let before = &rest[..start];
+        let after = &rest[start + b"stream".len()..];
+        let data = if let Some(data) = after.strip_prefix(b"\r\n") {
+            data
+        } else if let Some(data) = after.strip_prefix(b"\n") {
+            data
+        } else {
+            // Not the start of a stream, e.g. the end of an `endstream`.
+            rest = after;
+            continue;
+        };
+        let Some(end) = find(data, b"endstream") else {
+            break;
+        };
+        // The stream dictionary follows the `obj` keyword.
+        let dictionary = &before[rfind(before, b"obj").unwrap_or(0)..];
+        let raw = &data[..end];
+        let inflated;
+        let stream = if find(dictionary, b"/FlateDecode").is_some() {
+            inflated = inflate(raw);
+            inflated.as_deref()
+        } else if find(dictionary, b"/Filter").is_none() {
+            Some(raw)
+        } else {
+            // Images and other encodings don't contain text.
+            None
+        };
+        if let Some(stream) = stream {
+            content_text(stream, &mut text);
+        }
+        rest = &data[end + b"endstream".len()..];
+    }
+    text.to_lowercase()
+}
+
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut inflated = Vec::new();
+    flate2::read::ZlibDecoder::new(data)
+        .take(MAX_INFLATED_STREAM_SIZE)
+        .read_to_end(&mut inflated)
+        .ok()?;
+    Some(inflated)
+}
+
+/// Append the strings shown by text operators, separating those that aren't parts of one `TJ`.
+fn content_text(stream: &[u8], text: &mut String) {
+    let mut in_array = false;
+    let mut i = 0;
+    while i < stream.len() {
+        let parsed = match stream[i] {
+            b'(' => literal_string(&stream[i + 1..]),
+            b'<' if stream.get(i + 1) != Some(&b'<') => hex_string(&stream[i + 1..]),
+            b'[' => {
+                in_array = true;
+                None
+            }
+            b']' => {
+                in_array = false;
+                text.push(' ');
+                None
+            }
+            _ => None,
+        };
+        match parsed {
+            Some((bytes, len)) => {
+                text.push_str(&decode_text_string(&bytes));
+                if !in_array {
+                    text.push(' ');
+                }
+                i += 1 + len;
+            }
+            None => i += 1,
+        }
+    }
+}
+
+/// Text of the first XML element starting with `open`, e.g. `<xmp:CreateDate>`.
+fn xmp_element(content: &[u8], open: &[u8]) -> Option<String> {
+    let start = find(content, open)?;
+    let start = start + content[start..].iter().position(|&b| b == b'>')? + 1;
+    let end = start + content[start..].iter().position(|&b| b == b'<')?;
+    std::str::from_utf8(&content[start..end])
+        .ok()
+        .map(str::to_string)
+}
+
+/// `YYYY-MM-DD` from a date starting with year, month and day, with or without separators, as in
+/// `20240301120000+01'00'`, `2024:03:01 12:00:00` and `2024-03-01T12:00:00`.
+fn date_from_digits(date: &str) -> Option<String> {
+    let digits: String = date
+        .chars()
+        .take_while(|c| !matches!(c, ' ' | 'T'))
+        .filter(char::is_ascii_digit)
+        .take(8)
+        .collect();
+    if digits.len() < 8 {
+        return None;
+    }
+    let date = chrono::NaiveDate::parse_from_str(&digits, "%Y%m%d").ok()?;
+    Some(date.format("%Y-%m-%d").to_string())
+}
+
+/// The TIFF structure in the APP1 segment of a JPEG.
+fn jpeg_exif(jpeg: &[u8]) -> Option<&[u8]> {
+    let mut offset = 2;
+    while offset + 4 <= jpeg.len() && jpeg[offset] == 0xFF {
+        let marker = jpeg[offset + 1];
+        let len = usize::from(u16::from_be_bytes([jpeg[offset + 2], jpeg[offset + 3]]));
+        let segment = jpeg.get(offset + 4..offset + 2 + len)?;
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return Some(&segment[6..]);
+        }
+        // Image data follows the start of scan marker.
+        if marker == 0xDA {
+            return None;
+        }
+        offset += 2 + len;
+    }
+    None
+}
+
+fn tiff_metadata(tiff: &[u8]) -> EmbeddedMetadata {
+    let Some(tiff) = Tiff::new(tiff) else {
+        return EmbeddedMetadata::default();
+    };
+    let Some(ifd0) = tiff.u32(4) else {
+        return EmbeddedMetadata::default();
+    };
+    let original = tiff
+        .entry(ifd0, EXIF_IFD_POINTER)
+        .and_then(|(_, _, value)| tiff.u32(value))
+        .and_then(|exif| tiff.ascii(exif, EXIF_DATE_TIME_ORIGINAL));
+    let created = original
+        .or_else(|| tiff.ascii(ifd0, EXIF_DATE_TIME))
+        .and_then(|date| date_from_digits(&date));
+    let title = tiff
+        .ascii(ifd0, EXIF_IMAGE_DESCRIPTION)
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty());
+    EmbeddedMetadata { created, title }
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Self {
+            data,
+            little_endian,
+        })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<usize> {
+        let bytes = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        let value = if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        };
+        value.try_into().ok()
+    }
+
+    /// Type, count and the offset of the value field of an IFD entry.
+    fn entry(&self, ifd: usize, tag: u16) -> Option<(u16, usize, usize)> {
+        let count = self.u16(ifd)?;
+        (0..usize::from(count)).find_map(|i| {
+            let entry = ifd + 2 + i * 12;
+            (self.u16(entry)? == tag).then_some((
+                self.u16(entry + 2)?,
+                self.u32(entry + 4)?,
+                entry + 8,
+            ))
+        })
+    }
+
+    fn ascii(&self, ifd: usize, tag: u16) -> Option<String> {
+        const ASCII: u16 = 2;
+        let (kind, count, value) = self.entry(ifd, tag)?;
+        if kind != ASCII {
+            return None;
+        }
+        // Values longer than four bytes are stored elsewhere.
+        let start = if count > 4 { self.u32(value)? } else { value };
+        let bytes = self.data.get(start..start + count)?;
+        let bytes = bytes.split(|&b| b == 0).next().unwrap_or_default();
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_pdf_document_info() {
+        let pdf = b"%PDF-1.4\n1 0 obj\n<< /Title (Q3 \\(draft\\)) /CreationDate (D:20240301120000+01'00') >>\nendobj\n\
+            2 0 obj\n<< /Title <FEFF00DC0062006500720073006900630068007400> >>\nendobj\n%%EOF\n";
+        assert_eq!(
+            pdf_metadata(pdf),
+            EmbeddedMetadata {
+                created: Some("2024-03-01".to_string()),
+                title: Some("Übersicht".to_string()),
+            }
+        );
+        let pdf = b"%PDF-1.4\n1 0 obj\n<< /Title (Q3 \\(draft\\)) >>\nendobj\n%%EOF\n";
+        assert_eq!(pdf_metadata(pdf).title.as_deref(), Some("Q3 (draft)"));
+    }
+
+    #[test]
+    fn reads_pdf_xmp_packet() {
+        let pdf = b"%PDF-1.7\n<x:xmpmeta><xmp:CreateDate>2023-11-05T08:30:00Z</xmp:CreateDate>\
+            <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">Lease</rdf:li></rdf:Alt></dc:title>\
+            </x:xmpmeta>\n%%EOF\n";
+        assert_eq!(
+            pdf_metadata(pdf),
+            EmbeddedMetadata {
+                created: Some("2023-11-05".to_string()),
+                title: Some("Lease".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_jpeg_exif() {
+        // Little endian TIFF with DateTime in IFD0, pointing to an EXIF IFD with DateTimeOriginal.
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        tiff.extend_from_slice(&[2, 0]);
+        tiff.extend_from_slice(&[0x32, 0x01, 2, 0, 20, 0, 0, 0, 38, 0, 0, 0]);
+        tiff.extend_from_slice(&[0x69, 0x87, 4, 0, 1, 0, 0, 0, 58, 0, 0, 0]);
+        tiff.extend_from_slice(&[0, 0, 0, 0]);
+        tiff.extend_from_slice(b"2020:01:01 00:00:00\0");
+        tiff.extend_from_slice(&[1, 0]);
+        tiff.extend_from_slice(&[0x03, 0x90, 2, 0, 20, 0, 0, 0, 76, 0, 0, 0]);
+        tiff.extend_from_slice(&[0, 0, 0, 0]);
+        tiff.extend_from_slice(b"2024:03:01 12:00:00\0");
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 2, 0xFF, 0xD9]);
+
+        assert_eq!(
+            jpeg_exif(&jpeg).map(tiff_metadata),
+            Some(EmbeddedMetadata {
+                created: Some("2024-03-01".to_string()),
+                title: None,
+            })
+        );
+    }
+
+    #[test]
+    fn extracts_text_from_content_streams() {
+        use std::io::Write;
+
+        let content = b"BT /F1 12 Tf 72 712 Td (Rechnung) Tj [(Nr. 4)-250(2)] TJ ET";
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut pdf = b"%PDF-1.4\n4 0 obj\n<< /Length 9 /Filter /FlateDecode >>\nstream\n".to_vec();
+        pdf.extend_from_slice(&compressed);
+        pdf.extend_from_slice(b"\nendstream\nendobj\n5 0 obj\n<< /Length 12 >>\nstream\n");
+        pdf.extend_from_slice(b"BT (Vertrag) Tj ET\nendstream\nendobj\n");
+        pdf.extend_from_slice(
+            b"6 0 obj\n<< /Filter /DCTDecode >>\nstream\n(Foto)\nendstream\n%%EOF\n",
+        );
+
+        assert_eq!(extract_pdf_text(&pdf), "rechnung nr. 42 vertrag ");
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        assert_eq!(date_from_digits("20241301"), None);
+        assert_eq!(date_from_digits("0000:00:00 00:00:00"), None);
+        assert_eq!(date_from_digits("2024"), None);
+    }
+}
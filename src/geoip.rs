@@ -0,0 +1,77 @@
+use std::net::IpAddr;
+use std::path::Path;
+
+use log::debug;
+use maxminddb::{MaxMindDBError, Reader, geoip2};
+
+use crate::auth::SourceFilter;
+
+/// Rejects clients whose address a MaxMind GeoIP2/GeoLite2 country database locates outside of
+/// the allowed countries.
+pub struct GeoIpFilter {
+    reader: Reader<Vec<u8>>,
+    /// ISO 3166-1 alpha-2 codes, upper case.
+    allowed_countries: Vec<String>,
+}
+
+impl std::fmt::Debug for GeoIpFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GeoIpFilter")
+            .field("allowed_countries", &self.allowed_countries)
+            .finish()
+    }
+}
+
+impl GeoIpFilter {
+    pub fn open(database: &Path, allowed_countries: &[String]) -> Result<Self, MaxMindDBError> {
+        Ok(Self {
+            reader: Reader::open_readfile(database)?,
+            allowed_countries: allowed_countries
+                .iter()
+                .map(|c| c.trim().to_ascii_uppercase())
+                .collect(),
+        })
+    }
+
+    fn country(&self, ip: IpAddr) -> Option<&str> {
+        let country: geoip2::Country = self.reader.lookup(ip).ok()?;
+        country.country?.iso_code
+    }
+}
+
+impl SourceFilter for GeoIpFilter {
+    fn allows(&self, ip: IpAddr) -> Result<(), String> {
+        let ip = ip.to_canonical();
+        if is_local(ip) {
+            return Ok(());
+        }
+        match self.country(ip) {
+            Some(country) if self.allowed_countries.iter().any(|c| c == country) => {
+                debug!("{ip} is located in allowed country {country}");
+                Ok(())
+            }
+            Some(country) => Err(format!("{ip} is located in {country}")),
+            None => Err(format!("{ip} is not in the GeoIP database")),
+        }
+    }
+}
+
+/// Addresses of the local network, which GeoIP databases don't cover.
+fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_loopback() || ip.is_unique_local() || ip.is_unicast_link_local(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_addresses_are_recognized() {
+        assert!(is_local("192.168.1.20".parse().unwrap()));
+        assert!(is_local("fd00::1".parse().unwrap()));
+        assert!(!is_local("8.8.8.8".parse().unwrap()));
+    }
+}
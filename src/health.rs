@@ -2,11 +2,36 @@ use std::fmt;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
+use clap::ValueEnum;
 use log::{info, warn};
 use tokio::time::{MissedTickBehavior, interval};
 
+use crate::breaker::CircuitBreaker;
 use crate::paperless::PaperlessApi;
 
+/// How often the bridge checks whether Paperless is reachable.
+pub const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a health check result is trusted without a newer one.
+pub const HEALTH_STATUS_MAX_AGE: Duration = Duration::from_secs(15);
+
+/// How long the result of a health check is trusted when checking every `check_interval`, which
+/// allows for two missed checks.
+pub fn status_max_age(check_interval: Duration) -> Duration {
+    check_interval * 3
+}
+
+/// What the bridge does when Paperless can't be reached at startup.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum StartupCheck {
+    /// Exit after a few attempts.
+    #[default]
+    Fail,
+    /// Keep trying until the startup check timeout, e.g. while the host is still booting Paperless.
+    Retry,
+    /// Start right away with FTP logins disabled until Paperless is reachable.
+    Skip,
+}
+
 #[derive(Clone, Debug)]
 pub struct PaperlessHealth {
     inner: Arc<RwLock<HealthSnapshot>>,
@@ -43,6 +68,13 @@ impl PaperlessHealth {
         }
     }
 
+    /// Health of a Paperless that wasn't reached yet, so FTP logins wait for the first check.
+    pub fn new_unhealthy(max_age: Duration, error: impl fmt::Display) -> Self {
+        let health = Self::new_healthy(max_age);
+        health.mark_unhealthy(error);
+        health
+    }
+
     pub fn check(&self) -> Result<(), PaperlessUnavailable> {
         let snapshot = self.inner.read().expect("Paperless health lock poisoned");
         let age = snapshot.checked_at.elapsed();
@@ -85,9 +117,12 @@ impl PaperlessHealth {
     }
 }
 
+/// Check Paperless every `check_interval`, recording the result in `health`, the metrics and
+/// `breaker`, so uploads don't wait for a Paperless that is known to be down.
 pub async fn monitor_paperless_health(
     client: Arc<dyn PaperlessApi>,
     health: PaperlessHealth,
+    breaker: CircuitBreaker,
     check_interval: Duration,
 ) {
     let mut ticker = interval(check_interval);
@@ -95,13 +130,19 @@ pub async fn monitor_paperless_health(
 
     loop {
         ticker.tick().await;
-        match client.health_check().await {
+        let started = Instant::now();
+        let result = client.health_check().await;
+        crate::metrics::PAPERLESS_HEALTH_CHECK_SECONDS.observe(started.elapsed().as_secs_f64());
+        crate::metrics::PAPERLESS_UP.set(i64::from(result.is_ok()));
+        match result {
             Ok(()) => {
+                breaker.record_success();
                 if health.mark_healthy() {
                     info!("Paperless API is available again; FTP logins are enabled");
                 }
             }
             Err(error) => {
+                breaker.record_failure();
                 if health.mark_unhealthy(&error) {
                     warn!("Paperless API became unavailable; FTP logins are disabled: {error}");
                 }
@@ -126,6 +167,13 @@ mod tests {
         assert!(health.check().is_ok());
     }
 
+    #[test]
+    fn unreached_paperless_is_unhealthy() {
+        let health = PaperlessHealth::new_unhealthy(Duration::from_secs(60), "not reached yet");
+        assert_eq!(health.check().unwrap_err().to_string(), "not reached yet");
+        assert!(health.mark_healthy());
+    }
+
     #[test]
     fn stale_status_fails_closed() {
         let health = PaperlessHealth::new_healthy(Duration::ZERO);
@@ -0,0 +1,189 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{debug, warn};
+use reqwest::Client;
+use serde::Serialize;
+use tokio::process::Command;
+
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+type HookError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A document Paperless consumed, as told to the hooks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Consumed {
+    pub document_id: u64,
+    #[serde(flatten)]
+    pub upload: Upload,
+}
+
+/// The upload a document was made of.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Upload {
+    pub title: String,
+    pub user: String,
+    /// The path the client uploaded the file to.
+    pub source: String,
+}
+
+impl Consumed {
+    /// The environment the hook command runs with.
+    fn env(&self) -> [(&'static str, &str); 3] {
+        [
+            ("PAPERLESS_DOCUMENT_TITLE", &self.upload.title),
+            ("FTP_USER", &self.upload.user),
+            ("FTP_SOURCE_PATH", &self.upload.source),
+        ]
+    }
+}
+
+/// Runs a program and POSTs to a webhook after Paperless consumed a document, e.g. to print a
+/// confirmation slip or update a spreadsheet.
+#[derive(Debug, Clone)]
+pub struct SuccessHook {
+    command: Option<PathBuf>,
+    webhook: Option<String>,
+    client: Client,
+}
+
+impl SuccessHook {
+    /// `None` unless a command or webhook is given.
+    pub fn new(command: Option<PathBuf>, webhook: Option<String>) -> Option<Self> {
+        if command.is_none() && webhook.is_none() {
+            return None;
+        }
+        Some(Self {
+            command,
+            webhook,
+            client: Client::builder()
+                .timeout(HOOK_TIMEOUT)
+                .build()
+                .expect("failed to build hook HTTP client"),
+        })
+    }
+
+    /// Run the hooks for the document `document_id` Paperless made of `upload`, logging their
+    /// failures.
+    pub async fn run(&self, document_id: u64, upload: Upload) {
+        let document = Consumed {
+            document_id,
+            upload,
+        };
+        if let Some(command) = &self.command
+            && let Err(e) = self.run_command(command, &document).await
+        {
+            warn!(
+                "Success hook {} failed for document {}: {e}",
+                command.display(),
+                document.document_id
+            );
+        }
+        if let Some(url) = &self.webhook
+            && let Err(e) = self.post(url, &document).await
+        {
+            warn!(
+                "Success webhook failed for document {}: {e}",
+                document.document_id
+            );
+        }
+    }
+
+    async fn run_command(&self, command: &Path, document: &Consumed) -> Result<(), HookError> {
+        debug!(
+            "Running success hook {} for document {}",
+            command.display(),
+            document.document_id
+        );
+        let status = tokio::time::timeout(
+            HOOK_TIMEOUT,
+            Command::new(command)
+                .env("PAPERLESS_DOCUMENT_ID", document.document_id.to_string())
+                .envs(document.env())
+                .kill_on_drop(true)
+                .status(),
+        )
+        .await
+        .map_err(|_| format!("timed out after {}s", HOOK_TIMEOUT.as_secs()))??;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("exited with {status}").into())
+        }
+    }
+
+    async fn post(&self, url: &str, document: &Consumed) -> Result<(), HookError> {
+        self.client
+            .post(url)
+            .json(document)
+            .send()
+            .await?
+            .error_for_status()?;
+        debug!(
+            "Sent document {} to the success webhook",
+            document.document_id
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn consumed() -> Consumed {
+        Consumed {
+            document_id: 42,
+            upload: Upload {
+                title: "Invoice".to_string(),
+                user: "scanner".to_string(),
+                source: "/inbox/scan.pdf".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn webhook_gets_the_document_as_json() {
+        assert_eq!(
+            serde_json::to_value(consumed()).unwrap(),
+            serde_json::json!({
+                "document_id": 42,
+                "title": "Invoice",
+                "user": "scanner",
+                "source": "/inbox/scan.pdf",
+            })
+        );
+    }
+
+    #[test]
+    fn hooks_are_optional() {
+        assert!(SuccessHook::new(None, None).is_none());
+        assert!(SuccessHook::new(Some(PathBuf::from("/bin/true")), None).is_some());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn command_sees_the_document_in_its_environment() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let script = dir.path().join("hook.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\necho \"$PAPERLESS_DOCUMENT_ID $PAPERLESS_DOCUMENT_TITLE $FTP_USER $FTP_SOURCE_PATH\" > {}\n",
+                out.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let hook = SuccessHook::new(Some(script.clone()), None).unwrap();
+        hook.run_command(&script, &consumed()).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(out).unwrap(),
+            "42 Invoice scanner /inbox/scan.pdf\n"
+        );
+    }
+}
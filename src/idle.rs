@@ -0,0 +1,147 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use libunftp::notification::{EventMeta, PresenceEvent, PresenceListener};
+use tokio::sync::Notify;
+use tokio::time::{Instant, sleep_until};
+
+#[derive(Debug)]
+struct State {
+    /// Open FTP sessions and other work in progress, such as batches waiting to settle.
+    busy: usize,
+    /// Whether a session was ever opened.
+    sessions_seen: bool,
+    /// When the bridge last stopped being busy.
+    idle_since: Instant,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: Mutex<State>,
+    changed: Notify,
+}
+
+/// Tells when the bridge has had nothing to do for a while, so a bridge started on demand can
+/// exit once the scanner is done.
+#[derive(Debug, Clone)]
+pub struct IdleTracker(Arc<Inner>);
+
+impl Default for IdleTracker {
+    fn default() -> Self {
+        Self(Arc::new(Inner {
+            state: Mutex::new(State {
+                busy: 0,
+                sessions_seen: false,
+                idle_since: Instant::now(),
+            }),
+            changed: Notify::new(),
+        }))
+    }
+}
+
+impl IdleTracker {
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        let mut state = self.0.state.lock().expect("idle tracker lock poisoned");
+        f(&mut state);
+        if state.busy == 0 {
+            state.idle_since = Instant::now();
+        }
+        self.0.changed.notify_one();
+    }
+
+    /// Keep the bridge from being idle until the returned guard is dropped.
+    pub fn busy(&self) -> Busy {
+        self.update(|state| state.busy += 1);
+        Busy(self.clone())
+    }
+
+    /// Complete once nothing was going on for `idle`. With `after_session`, the bridge only
+    /// counts as idle after the first FTP session.
+    pub async fn wait_until_idle(&self, idle: Duration, after_session: bool) {
+        loop {
+            let changed = self.0.changed.notified();
+            let deadline = {
+                let state = self.0.state.lock().expect("idle tracker lock poisoned");
+                (state.busy == 0 && (state.sessions_seen || !after_session))
+                    .then(|| state.idle_since + idle)
+            };
+            match deadline {
+                Some(deadline) if deadline <= Instant::now() => return,
+                Some(deadline) => {
+                    tokio::select! {
+                        _ = changed => {}
+                        _ = sleep_until(deadline) => {}
+                    }
+                }
+                None => changed.await,
+            }
+        }
+    }
+}
+
+/// Work in progress, see [`IdleTracker::busy`].
+#[derive(Debug)]
+pub struct Busy(IdleTracker);
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.0
+            .update(|state| state.busy = state.busy.saturating_sub(1));
+    }
+}
+
+/// Counts the FTP sessions as busy from login to logout.
+#[async_trait]
+impl PresenceListener for IdleTracker {
+    async fn receive_presence_event(&self, e: PresenceEvent, _m: EventMeta) {
+        match e {
+            PresenceEvent::LoggedIn => self.update(|state| {
+                state.busy += 1;
+                state.sessions_seen = true;
+            }),
+            PresenceEvent::LoggedOut => {
+                self.update(|state| state.busy = state.busy.saturating_sub(1))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn idle_after_the_last_session_and_work() {
+        let tracker = IdleTracker::default();
+        let idle = Duration::from_millis(50);
+        let waiting = tokio::spawn({
+            let tracker = tracker.clone();
+            async move { tracker.wait_until_idle(idle, true).await }
+        });
+
+        // Not idle before the first session, nor while it is open.
+        tokio::time::sleep(idle * 3).await;
+        assert!(!waiting.is_finished());
+        let meta = || EventMeta {
+            username: "scanner".to_string(),
+            trace_id: String::new(),
+            sequence_number: 0,
+        };
+        tracker
+            .receive_presence_event(PresenceEvent::LoggedIn, meta())
+            .await;
+        let busy = tracker.busy();
+        tracker
+            .receive_presence_event(PresenceEvent::LoggedOut, meta())
+            .await;
+        tokio::time::sleep(idle * 3).await;
+        assert!(!waiting.is_finished());
+
+        drop(busy);
+        tokio::time::timeout(idle * 10, waiting)
+            .await
+            .expect("not idle after the work was done")
+            .unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::TryStreamExt;
+use libunftp::storage::{ErrorKind, StorageBackend};
+use log::{debug, error, info, warn};
+use mail_parser::{MessageParser, MimeHeaders};
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore, pki_types::ServerName};
+
+use crate::auth::User;
+use crate::metadata::DocumentMetadata;
+use crate::rules::RulesFile;
+use crate::storage::PaperlessStorage;
+
+type ImapError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A mailbox folder whose unread mails are fetched and whose attachments are submitted.
+#[derive(Debug, Clone)]
+pub struct ImapSource {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub folder: String,
+}
+
+impl ImapSource {
+    /// Submit the attachments of all unread mails, marking each mail as read once all its
+    /// attachments were accepted.
+    async fn poll(
+        &self,
+        user: &User,
+        rules: &RulesFile,
+        storage: &PaperlessStorage,
+    ) -> Result<(), ImapError> {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        let server_name = ServerName::try_from(self.host.clone())?;
+        let tcp = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(server_name, tcp)
+            .await?;
+
+        let client = async_imap::Client::new(stream);
+        let mut session = client
+            .login(&self.username, &self.password)
+            .await
+            .map_err(|(e, _)| e)?;
+        session.select(&self.folder).await?;
+        let uids = session.uid_search("UNSEEN").await?;
+        debug!("{} unread mail(s) in {}", uids.len(), self.folder);
+
+        for uid in uids {
+            // PEEK leaves the mail unread in case submitting it fails.
+            let fetches: Vec<_> = session
+                .uid_fetch(uid.to_string(), "BODY.PEEK[]")
+                .await?
+                .try_collect()
+                .await?;
+            let Some(body) = fetches.first().and_then(|fetch| fetch.body()) else {
+                continue;
+            };
+            if submit_attachments(body, user, rules, storage).await {
+                let _: Vec<_> = session
+                    .uid_store(uid.to_string(), "+FLAGS (\\Seen)")
+                    .await?
+                    .try_collect()
+                    .await?;
+            }
+        }
+        session.logout().await?;
+        Ok(())
+    }
+}
+
+/// Submit the attachments of a mail, returning whether all of them were accepted or rejected for
+/// good.
+async fn submit_attachments(
+    body: &[u8],
+    user: &User,
+    rules: &RulesFile,
+    storage: &PaperlessStorage,
+) -> bool {
+    let Some(message) = MessageParser::default().parse(body) else {
+        warn!("Skipping mail that can't be parsed");
+        return true;
+    };
+    let sender = message
+        .from()
+        .and_then(|from| from.first())
+        .and_then(|address| address.address())
+        .unwrap_or_default();
+    let subject = message.subject().unwrap_or_default();
+    info!("Fetched mail from {sender:?} with subject {subject:?}");
+    let metadata = rules.evaluate_mail(sender, subject);
+
+    let mut done = true;
+    for attachment in message.attachments() {
+        let Some(name) = attachment
+            .attachment_name()
+            .and_then(|name| Path::new(name).file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+        else {
+            continue;
+        };
+        if metadata != DocumentMetadata::default() {
+            storage.add_metadata(&name, metadata.clone());
+        }
+        let input = std::io::Cursor::new(attachment.contents().to_vec());
+        match storage
+            .put(user, input, Path::new("/").join(&name), 0)
+            .await
+        {
+            Ok(_) => info!("Submitted attachment {name:?}"),
+            Err(e) if e.kind() == ErrorKind::FileNameNotAllowedError => {
+                debug!("Skipping attachment {name:?}: {e}");
+            }
+            Err(e) => {
+                warn!("Failed to submit attachment {name:?}: {e}, will retry later");
+                done = false;
+            }
+        }
+    }
+    done
+}
+
+/// Background task that submits the attachments of unread mails like FTP uploads of `user`.
+pub async fn poll_loop<F>(
+    source: ImapSource,
+    user: User,
+    rules: Arc<RulesFile>,
+    storage: F,
+    interval: Duration,
+) where
+    F: Fn() -> PaperlessStorage + Send + 'static,
+{
+    loop {
+        // A new session per poll, so an attachment sent again later is submitted again.
+        let storage = storage();
+        if let Err(e) = source.poll(&user, &rules, &storage).await {
+            error!(
+                "Failed to fetch mails from {}:{}: {e}",
+                source.host, source.port
+            );
+        }
+        sleep(interval).await;
+    }
+}
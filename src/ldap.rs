@@ -0,0 +1,104 @@
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use ldap3::{LdapConnAsync, LdapError};
+use log::debug;
+
+use crate::auth::{BackendError, BridgeAuthenticator};
+use crate::users::UserSettings;
+
+/// Verifies FTP logins by binding to an LDAP or Active Directory server as the user.
+#[derive(Debug)]
+pub struct LdapVerifier {
+    url: String,
+    /// DN to bind as, with `{username}` replaced by the escaped FTP username, e.g.
+    /// `uid={username},ou=people,dc=example,dc=com` or `{username}@example.com` for AD.
+    bind_dn_template: String,
+}
+
+impl LdapVerifier {
+    pub fn new(url: String, bind_dn_template: String) -> Self {
+        Self {
+            url,
+            bind_dn_template,
+        }
+    }
+
+    fn bind_dn(&self, username: &str) -> String {
+        self.bind_dn_template
+            .replace("{username}", &escape_dn_value(username))
+    }
+}
+
+#[async_trait]
+impl BridgeAuthenticator for LdapVerifier {
+    fn name(&self) -> &'static str {
+        "LDAP"
+    }
+
+    async fn verify(
+        &self,
+        username: &str,
+        password: &str,
+        _source_ip: IpAddr,
+    ) -> Result<Option<UserSettings>, BackendError> {
+        let (conn, mut ldap) = LdapConnAsync::new(&self.url).await?;
+        ldap3::drive!(conn);
+
+        let bind_dn = self.bind_dn(username);
+        debug!("Binding to {} as {bind_dn}", self.url);
+        let result = match ldap.simple_bind(&bind_dn, password).await?.success() {
+            Ok(_) => Ok(Some(UserSettings::default())),
+            // invalidCredentials
+            Err(LdapError::LdapResult { result }) if result.rc == 49 => Ok(None),
+            Err(e) => Err(e.into()),
+        };
+        let _ = ldap.unbind().await;
+        result
+    }
+}
+
+/// Escape a value for use in a DN as described in RFC 4514.
+fn escape_dn_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '#' | ' ' if i == 0 => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\0' => escaped.push_str("\\00"),
+            _ => escaped.push(c),
+        }
+    }
+    if escaped.ends_with(' ') && !escaped.ends_with("\\ ") {
+        escaped.insert(escaped.len() - 1, '\\');
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usernames_are_escaped_in_bind_dn() {
+        let verifier = LdapVerifier::new(
+            "ldap://localhost".to_string(),
+            "uid={username},ou=people,dc=example,dc=com".to_string(),
+        );
+        assert_eq!(
+            verifier.bind_dn("scanner"),
+            "uid=scanner,ou=people,dc=example,dc=com"
+        );
+        assert_eq!(
+            verifier.bind_dn("x,ou=admins"),
+            "uid=x\\,ou\\=admins,ou=people,dc=example,dc=com"
+        );
+        assert_eq!(escape_dn_value("#a "), "\\#a\\ ");
+    }
+}
@@ -0,0 +1,78 @@
+//! Forward documents received by FTP to Paperless-ngx.
+//!
+//! The binary is a thin command line wrapper around these modules. Embedders can reuse
+//! [`storage::PaperlessStorage`] and the routing engine with their own user store by implementing
+//! [`auth::BridgeAuthenticator`].
+
+#[cfg(feature = "acme")]
+pub mod acme;
+#[cfg(feature = "admin")]
+pub mod admin;
+pub mod auth;
+pub mod auth_webhook;
+pub mod batch;
+pub mod breaker;
+pub mod bridge;
+pub mod budget;
+pub mod canary;
+pub mod capacity;
+pub mod consume;
+#[cfg(unix)]
+pub mod daemon;
+pub mod doctor;
+pub mod document;
+pub mod encryption;
+pub mod extract;
+#[cfg(feature = "geoip")]
+pub mod geoip;
+pub mod health;
+pub mod hook;
+pub mod idle;
+#[cfg(feature = "imap")]
+pub mod imap;
+#[cfg(feature = "ldap")]
+pub mod ldap;
+pub mod logging;
+pub mod metadata;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(not(feature = "metrics"))]
+#[path = "metrics_disabled.rs"]
+pub mod metrics;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod normalize;
+pub mod notify;
+#[cfg(feature = "images")]
+pub mod pages;
+#[cfg(feature = "pam")]
+pub mod pam;
+pub mod paperless;
+pub mod passive;
+pub mod privileges;
+pub mod progress;
+#[cfg(feature = "metrics")]
+pub mod pushgateway;
+pub mod quirks;
+pub mod quota;
+pub mod rules;
+pub mod sandbox;
+pub mod sanitize;
+pub mod schedule;
+pub mod selftest;
+pub mod sessions;
+pub mod spool;
+#[cfg(feature = "metrics")]
+pub mod statsd;
+pub mod storage;
+pub mod tasks;
+pub mod tcp;
+pub mod template;
+pub mod tenant;
+#[cfg(feature = "ftps")]
+pub mod tls;
+pub mod totp;
+pub mod transcript;
+pub mod users;
+pub mod version;
+pub mod watch;
@@ -0,0 +1,274 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, NaiveDate};
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Where log messages go and which ones.
+#[derive(Debug)]
+pub struct LogOutputs {
+    /// Level of messages written to stderr.
+    pub level: LevelFilter,
+    /// Per-module directives like `libunftp=warn,ftp_paperless_bridge=debug`, applied to every
+    /// output on top of its level.
+    pub filter: Option<String>,
+    pub file: Option<FileOutput>,
+    /// Level of messages sent to syslog (and thereby journald), if enabled.
+    pub syslog: Option<LevelFilter>,
+}
+
+#[derive(Debug)]
+pub struct FileOutput {
+    pub path: PathBuf,
+    pub level: LevelFilter,
+    pub rotation: Rotation,
+}
+
+/// When the log file is rotated and how many old files are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// Rotate before the file grows beyond this many bytes.
+    pub max_size: Option<u64>,
+    /// Rotate when the date changes.
+    pub daily: bool,
+    /// Number of rotated files (`<file>.1` being the newest) to keep.
+    pub keep: usize,
+}
+
+/// Install a logger writing to stderr and the configured `outputs`, each with its own level.
+pub fn init(outputs: LogOutputs) -> io::Result<()> {
+    let directives = outputs.filter.as_deref().unwrap_or_default();
+    let mut loggers: Vec<Box<dyn Log>> = vec![Box::new(
+        env_logger::Builder::new()
+            .filter_level(outputs.level)
+            .parse_filters(directives)
+            .build(),
+    )];
+    if let Some(file) = outputs.file {
+        let writer = RotatingFile::open(file.path, file.rotation)?;
+        loggers.push(Box::new(
+            env_logger::Builder::new()
+                .filter_level(file.level)
+                .parse_filters(directives)
+                .target(env_logger::Target::Pipe(Box::new(writer)))
+                .write_style(env_logger::WriteStyle::Never)
+                .build(),
+        ));
+    }
+    if let Some(level) = outputs.syslog {
+        let filter = env_filter::Builder::new()
+            .filter_level(level)
+            .parse(directives)
+            .build();
+        loggers.push(Box::new(SyslogLogger::new(filter)?));
+    }
+
+    log::set_max_level(LevelFilter::Trace);
+    log::set_boxed_logger(Box::new(Tee(loggers))).map_err(io::Error::other)
+}
+
+/// Passes each message on to every logger that accepts it.
+struct Tee(Vec<Box<dyn Log>>);
+
+impl Log for Tee {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.iter().any(|logger| logger.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        for logger in &self.0 {
+            if logger.enabled(record.metadata()) {
+                logger.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        for logger in &self.0 {
+            logger.flush();
+        }
+    }
+}
+
+/// A log file that is rotated by size or date, keeping a number of old files.
+struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    opened_on: NaiveDate,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, rotation: Rotation) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            rotation,
+            file,
+            size,
+            opened_on: Local::now().date_naive(),
+        })
+    }
+
+    fn needs_rotation(&self, incoming: usize) -> bool {
+        let too_large = self
+            .rotation
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + incoming as u64 > max);
+        let new_day = self.rotation.daily && Local::now().date_naive() != self.opened_on;
+        too_large || new_day
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.rotation.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(rotated_path(&self.path, self.rotation.keep));
+            for n in (1..self.rotation.keep).rev() {
+                let _ =
+                    std::fs::rename(rotated_path(&self.path, n), rotated_path(&self.path, n + 1));
+            }
+            std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        *self = Self::open(self.path.clone(), self.rotation)?;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.needs_rotation(buf.len()) {
+            // Keep logging to the current file rather than losing messages.
+            if let Err(e) = self.rotate() {
+                eprintln!("Failed to rotate {}: {e}", self.path.display());
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Sends messages to the local syslog daemon or journald via /dev/log.
+#[cfg(unix)]
+struct SyslogLogger {
+    filter: env_filter::Filter,
+    socket: std::os::unix::net::UnixDatagram,
+    pid: u32,
+}
+
+#[cfg(unix)]
+impl SyslogLogger {
+    /// Facility `daemon`.
+    const FACILITY: u8 = 3;
+
+    fn new(filter: env_filter::Filter) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect("/dev/log")?;
+        Ok(Self {
+            filter,
+            socket,
+            pid: std::process::id(),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Log for SyslogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let severity = match record.level() {
+            log::Level::Error => 3,
+            log::Level::Warn => 4,
+            log::Level::Info => 6,
+            log::Level::Debug | log::Level::Trace => 7,
+        };
+        let message = format!(
+            "<{}>{}[{}]: {}: {}",
+            Self::FACILITY * 8 + severity,
+            env!("CARGO_PKG_NAME"),
+            self.pid,
+            record.target(),
+            record.args()
+        );
+        // Nowhere to report a lost message to.
+        let _ = self.socket.send(message.as_bytes());
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(not(unix))]
+struct SyslogLogger;
+
+#[cfg(not(unix))]
+impl SyslogLogger {
+    fn new(_filter: env_filter::Filter) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "syslog is only supported on Unix",
+        ))
+    }
+}
+
+#[cfg(not(unix))]
+impl Log for SyslogLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        false
+    }
+
+    fn log(&self, _record: &Record) {}
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_by_size_and_keeps_old_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bridge.log");
+        let mut file = RotatingFile::open(
+            path.clone(),
+            Rotation {
+                max_size: Some(10),
+                daily: false,
+                keep: 2,
+            },
+        )
+        .unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            std::fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(rotated_path(&path, 2)).unwrap(),
+            "second\n"
+        );
+        assert!(!rotated_path(&path, 3).exists());
+    }
+}
@@ -0,0 +1,457 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+use log::warn;
+use serde::Deserialize;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+use crate::paperless::{PaperlessApi, UploadOptions};
+
+/// Largest accepted metadata sidecar file.
+pub const MAX_SIDECAR_SIZE: u64 = 64 * 1024;
+
+/// A tag, correspondent or document type, given by its Paperless ID or its name.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum ObjectRef {
+    Id(u64),
+    Name(String),
+}
+
+/// The kinds of Paperless objects documents refer to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectKind {
+    Tag,
+    Correspondent,
+    DocumentType,
+    CustomField,
+}
+
+impl ObjectKind {
+    /// Path of the API endpoint listing objects of this kind.
+    pub fn endpoint(self) -> &'static str {
+        match self {
+            ObjectKind::Tag => "tags",
+            ObjectKind::Correspondent => "correspondents",
+            ObjectKind::DocumentType => "document_types",
+            ObjectKind::CustomField => "custom_fields",
+        }
+    }
+}
+
+/// Names closer than this many edits to exactly one object name are taken to mean that object.
+const MAX_NAME_DISTANCE: usize = 2;
+
+/// The ID of the object among `objects` that `name` refers to.
+///
+/// Names match ignoring case, then ignoring accents, spaces and punctuation, so `muller gmbh`
+/// finds `Müller GmbH`. Failing both, a name at most [`MAX_NAME_DISTANCE`] typos away from a
+/// single object is taken to mean it, e.g. `Mueller GmbH`. Short names must match exactly, as
+/// they are too easy to confuse.
+pub fn match_name(name: &str, objects: &[(u64, String)]) -> Option<u64> {
+    let unique = |matches: Vec<u64>| match matches[..] {
+        [id] => Some(id),
+        _ => None,
+    };
+    let lowercase = name.to_lowercase();
+    if let Some(id) = unique(
+        objects
+            .iter()
+            .filter(|(_, object)| object.to_lowercase() == lowercase)
+            .map(|(id, _)| *id)
+            .collect(),
+    ) {
+        return Some(id);
+    }
+    let normalized = normalize_name(name);
+    if normalized.is_empty() {
+        return None;
+    }
+    let distances: Vec<_> = objects
+        .iter()
+        .map(|(id, object)| (*id, edit_distance(&normalized, &normalize_name(object))))
+        .collect();
+    let closest = distances.iter().map(|(_, distance)| *distance).min()?;
+    let max_distance = if normalized.chars().count() > 2 * MAX_NAME_DISTANCE {
+        MAX_NAME_DISTANCE
+    } else {
+        0
+    };
+    if closest > max_distance {
+        return None;
+    }
+    unique(
+        distances
+            .iter()
+            .filter(|(_, distance)| *distance == closest)
+            .map(|(id, _)| *id)
+            .collect(),
+    )
+}
+
+/// Lowercase letters and digits of `name`, without accents.
+fn normalize_name(name: &str) -> String {
+    name.nfkd()
+        .filter(|c| !is_combining_mark(*c) && c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Metadata of an uploaded document, e.g. from a `scan.pdf.json` sidecar file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DocumentMetadata {
+    pub title: Option<String>,
+    /// Creation date as accepted by Paperless, e.g. `2024-03-01`.
+    pub created: Option<String>,
+    pub correspondent: Option<ObjectRef>,
+    pub document_type: Option<ObjectRef>,
+    #[serde(default)]
+    pub tags: Vec<ObjectRef>,
+}
+
+impl DocumentMetadata {
+    /// The correspondent, if it is given by name.
+    pub fn correspondent_name(&self) -> Option<&str> {
+        match &self.correspondent {
+            Some(ObjectRef::Name(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Parse a sidecar file as JSON or YAML, depending on its extension.
+    pub fn from_sidecar(name: &str, content: &[u8]) -> Result<Self, String> {
+        if name.to_lowercase().ends_with(".json") {
+            serde_json::from_slice(content).map_err(|e| e.to_string())
+        } else {
+            serde_yaml::from_slice(content).map_err(|e| e.to_string())
+        }
+    }
+}
+
+impl ObjectRef {
+    fn parse(value: &str) -> Self {
+        value
+            .parse()
+            .map(ObjectRef::Id)
+            .unwrap_or_else(|_| ObjectRef::Name(value.to_string()))
+    }
+}
+
+/// Metadata selected by the directories of an upload path like
+/// `/tags=invoice,2024/correspondent=acme/scan.pdf`. Directories without a known `key=value` are
+/// ignored.
+pub fn from_directories(path: &Path) -> DocumentMetadata {
+    let mut metadata = DocumentMetadata::default();
+    let Some(directories) = path.parent() else {
+        return metadata;
+    };
+    for component in directories.components() {
+        let component = component.as_os_str().to_string_lossy();
+        let Some((key, value)) = component.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        match key.trim().to_lowercase().as_str() {
+            "title" => metadata.title = Some(value.to_string()),
+            "created" => metadata.created = Some(value.to_string()),
+            "correspondent" | "corr" => metadata.correspondent = Some(ObjectRef::parse(value)),
+            "document_type" | "type" => metadata.document_type = Some(ObjectRef::parse(value)),
+            "tags" | "tag" => {
+                for tag in value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                {
+                    let tag = ObjectRef::parse(tag);
+                    if !metadata.tags.contains(&tag) {
+                        metadata.tags.push(tag);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    metadata
+}
+
+/// The archive serial number of the first barcode like `ASN00042` with the given prefix, matched
+/// case-insensitively.
+pub fn archive_serial_number(barcodes: &[String], prefix: &str) -> Option<u64> {
+    barcodes.iter().find_map(|code| {
+        let number = code
+            .get(..prefix.len())
+            .filter(|start| start.eq_ignore_ascii_case(prefix))
+            .map(|_| code[prefix.len()..].trim())?;
+        number.parse().ok()
+    })
+}
+
+/// The document a sidecar file describes, e.g. `scan.pdf` for `scan.pdf.json` or `scan.pdf.yaml`.
+pub fn sidecar_target(name: &str) -> Option<&str> {
+    let lower = name.to_lowercase();
+    let extension = [".json", ".yaml", ".yml"]
+        .into_iter()
+        .find(|extension| lower.ends_with(extension))?;
+    let target = &name[..name.len() - extension.len()];
+    // Only sidecars of consumable documents, so a JSON document isn't mistaken for one.
+    (Path::new(target).extension().is_some()
+        && crate::document::is_supported_filename(Path::new(target)))
+    .then_some(target)
+}
+
+/// Separates the levels of hierarchical tag names like `finance/tax/2024`.
+const TAG_LEVEL_SEPARATOR: char = '/';
+
+/// What happens to an upload whose metadata names objects that don't exist in Paperless.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum UnresolvedPolicy {
+    /// Upload without the missing objects
+    #[default]
+    Skip,
+    /// Upload without the missing objects, with the --unresolved-tag instead
+    Tag,
+    /// Reject the upload with FTP reply 550
+    Reject,
+}
+
+/// How objects given by name are looked up in Paperless.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResolveOptions {
+    /// Take tag names like `finance/tax/2024` as a tag per level and apply all of them.
+    pub tag_hierarchy: bool,
+    /// Create the tags that don't exist yet.
+    pub create_tags: bool,
+    pub unresolved: UnresolvedPolicy,
+    /// Applied to uploads with unresolved metadata by [`UnresolvedPolicy::Tag`].
+    pub unresolved_tag: Option<u64>,
+}
+
+/// Add the metadata to `options`, looking up the IDs of objects given by name. Objects that can't
+/// be found are left out and returned, e.g. `correspondents "ACME"`.
+pub async fn apply(
+    client: &dyn PaperlessApi,
+    metadata: &DocumentMetadata,
+    options: &mut UploadOptions,
+    resolve_options: &ResolveOptions,
+) -> Vec<String> {
+    let mut unresolved = Vec::new();
+    if let Some(title) = &metadata.title {
+        options.title = Some(title.clone());
+    }
+    if let Some(created) = &metadata.created {
+        options.created = Some(created.clone());
+    }
+    if let Some(correspondent) = &metadata.correspondent {
+        match resolve(client, ObjectKind::Correspondent, correspondent).await {
+            Some(id) => options.correspondent = Some(id),
+            None => unresolved.push(describe(ObjectKind::Correspondent, correspondent)),
+        }
+    }
+    if let Some(document_type) = &metadata.document_type {
+        match resolve(client, ObjectKind::DocumentType, document_type).await {
+            Some(id) => options.document_type = Some(id),
+            None => unresolved.push(describe(ObjectKind::DocumentType, document_type)),
+        }
+    }
+    for tag in &metadata.tags {
+        for id in resolve_tag(client, tag, resolve_options, &mut unresolved).await {
+            if !options.tags.contains(&id) {
+                options.tags.push(id);
+            }
+        }
+    }
+    unresolved
+}
+
+fn describe(kind: ObjectKind, object: &ObjectRef) -> String {
+    match object {
+        ObjectRef::Id(id) => format!("{} #{id}", kind.endpoint()),
+        ObjectRef::Name(name) => format!("{} {name:?}", kind.endpoint()),
+    }
+}
+
+/// The IDs of a tag, or of each of its levels with [`ResolveOptions::tag_hierarchy`].
+async fn resolve_tag(
+    client: &dyn PaperlessApi,
+    tag: &ObjectRef,
+    resolve_options: &ResolveOptions,
+    unresolved: &mut Vec<String>,
+) -> Vec<u64> {
+    let levels: Vec<&str> = match tag {
+        ObjectRef::Name(name) if resolve_options.tag_hierarchy => name
+            .split(TAG_LEVEL_SEPARATOR)
+            .map(str::trim)
+            .filter(|level| !level.is_empty())
+            .collect(),
+        ObjectRef::Name(name) => vec![name.as_str()],
+        ObjectRef::Id(id) => return vec![*id],
+    };
+    let mut ids = Vec::new();
+    let mut parent = None;
+    for level in levels {
+        let id = match client.find_object(ObjectKind::Tag, level).await {
+            Ok(None) if resolve_options.create_tags => {
+                match client.create_object(ObjectKind::Tag, level, parent).await {
+                    Ok(id) => Some(id),
+                    Err(e) => {
+                        warn!("Failed to create the Paperless tag {level:?}: {e}");
+                        None
+                    }
+                }
+            }
+            Ok(None) => {
+                warn!("No Paperless tags is named {level:?}");
+                None
+            }
+            Ok(Some(id)) => Some(id),
+            Err(e) => {
+                warn!("Failed to look up {level:?} in Paperless tags: {e}");
+                None
+            }
+        };
+        if id.is_none() {
+            unresolved.push(describe(
+                ObjectKind::Tag,
+                &ObjectRef::Name(level.to_string()),
+            ));
+        }
+        // Without its parent, a level is still applied, just not nested below it.
+        parent = id;
+        ids.extend(id);
+    }
+    ids
+}
+
+async fn resolve(client: &dyn PaperlessApi, kind: ObjectKind, object: &ObjectRef) -> Option<u64> {
+    match object {
+        ObjectRef::Id(id) => Some(*id),
+        ObjectRef::Name(name) => match client.find_object(kind, name).await {
+            Ok(Some(id)) => Some(id),
+            Ok(None) => {
+                warn!("No Paperless {} is named {name:?}", kind.endpoint());
+                None
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to look up {name:?} in Paperless {}: {e}",
+                    kind.endpoint()
+                );
+                None
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_serial_numbers_are_read_from_barcodes() {
+        let codes = ["INV-17".to_string(), "asn00042".to_string()];
+        assert_eq!(archive_serial_number(&codes, "ASN"), Some(42));
+        assert_eq!(archive_serial_number(&codes, "TAG:"), None);
+        assert_eq!(archive_serial_number(&["ASNx".to_string()], "ASN"), None);
+    }
+
+    #[test]
+    fn names_match_loosely_but_unambiguously() {
+        let objects = vec![
+            (1, "ACME Corp.".to_string()),
+            (2, "Müller GmbH".to_string()),
+            (3, "Stadtwerke".to_string()),
+            (4, "Stadtwerk".to_string()),
+            (5, "Tax".to_string()),
+            (6, "Invoice A".to_string()),
+            (7, "Invoice B".to_string()),
+        ];
+        assert_eq!(match_name("acme corp.", &objects), Some(1));
+        assert_eq!(match_name("ACME Corp", &objects), Some(1));
+        assert_eq!(match_name("Muller GmbH", &objects), Some(2));
+        assert_eq!(match_name("Mueller GmbH", &objects), Some(2));
+        assert_eq!(match_name("Stadtwerk", &objects), Some(4));
+        assert_eq!(match_name("Stadtwerkes", &objects), Some(3));
+        // As close to one as to the other.
+        assert_eq!(match_name("Invoice C", &objects), None);
+        assert_eq!(match_name("Tux", &objects), None);
+        assert_eq!(match_name("Globex", &objects), None);
+    }
+
+    #[test]
+    fn sidecars_name_their_document() {
+        assert_eq!(sidecar_target("scan.pdf.json"), Some("scan.pdf"));
+        assert_eq!(sidecar_target("Scan.PDF.YML"), Some("Scan.PDF"));
+        assert_eq!(sidecar_target("notes.json"), None);
+        assert_eq!(sidecar_target("setup.exe.yaml"), None);
+        assert_eq!(sidecar_target("scan.pdf"), None);
+    }
+
+    #[test]
+    fn parses_metadata_directories() {
+        let metadata = from_directories(Path::new(
+            "/scans/tags=invoice,2024/correspondent=acme/type=3/scan.pdf",
+        ));
+        assert_eq!(
+            metadata,
+            DocumentMetadata {
+                correspondent: Some(ObjectRef::Name("acme".to_string())),
+                document_type: Some(ObjectRef::Id(3)),
+                tags: vec![ObjectRef::Name("invoice".to_string()), ObjectRef::Id(2024)],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            from_directories(Path::new("/tags=invoice/scan.pdf")).tags,
+            vec![ObjectRef::Name("invoice".to_string())]
+        );
+        // Only directories count, and unknown keys are plain directories.
+        assert_eq!(
+            from_directories(Path::new("/a=b/title=x.pdf")),
+            DocumentMetadata::default()
+        );
+    }
+
+    #[test]
+    fn parses_json_and_yaml_sidecars() {
+        let expected = DocumentMetadata {
+            title: Some("Q3 report".to_string()),
+            created: Some("2024-03-01".to_string()),
+            correspondent: Some(ObjectRef::Name("ACME".to_string())),
+            document_type: Some(ObjectRef::Id(3)),
+            tags: vec![ObjectRef::Id(1), ObjectRef::Name("invoice".to_string())],
+        };
+        let json = br#"{"title": "Q3 report", "created": "2024-03-01", "correspondent": "ACME",
+            "document_type": 3, "tags": [1, "invoice"]}"#;
+        assert_eq!(
+            DocumentMetadata::from_sidecar("scan.pdf.json", json),
+            Ok(expected.clone())
+        );
+        let yaml = b"title: Q3 report\ncreated: 2024-03-01\ncorrespondent: ACME\n\
+            document_type: 3\ntags: [1, invoice]\n";
+        assert_eq!(
+            DocumentMetadata::from_sidecar("scan.pdf.yaml", yaml),
+            Ok(expected)
+        );
+        assert!(DocumentMetadata::from_sidecar("scan.pdf.json", br#"{"titel": "x"}"#).is_err());
+    }
+}
@@ -0,0 +1,319 @@
+use std::net::IpAddr;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use log::error;
+use prometheus::{
+    Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    TextEncoder, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+};
+
+use crate::version::{GIT_COMMIT, enabled_features};
+
+pub static UPLOADS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "ftp_paperless_bridge_uploads_total",
+        "Documents received and forwarded to Paperless",
+        &["user", "client"]
+    )
+    .expect("failed to register uploads metric")
+});
+
+pub static UPLOAD_BYTES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "ftp_paperless_bridge_upload_bytes_total",
+        "Bytes of documents received and forwarded to Paperless",
+        &["user", "client"]
+    )
+    .expect("failed to register upload bytes metric")
+});
+
+pub static UPLOAD_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "ftp_paperless_bridge_upload_failures_total",
+        "Uploads Paperless didn't take, by the kind of error",
+        &["reason"]
+    )
+    .expect("failed to register upload failures metric")
+});
+
+/// Failed uploads by user and client, to tell which scanner is failing. [`UPLOAD_FAILURES`] has
+/// the reasons.
+pub static USER_UPLOAD_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "ftp_paperless_bridge_user_upload_failures_total",
+        "Uploads that failed, by user and client",
+        &["user", "client"]
+    )
+    .expect("failed to register user upload failures metric")
+});
+
+/// Time from the start of a transfer until Paperless took the document, to tell which scanner is
+/// slow.
+pub static UPLOAD_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "ftp_paperless_bridge_upload_seconds",
+        "Time from the start of a transfer until Paperless took the document",
+        &["user", "client"],
+        vec![
+            0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0
+        ]
+    )
+    .expect("failed to register upload duration metric")
+});
+
+/// The `client` label of uploads from `ip`: a short hash of the address, which tells scanners
+/// apart without putting their addresses into the metrics. `local` for documents that didn't come
+/// over the network, e.g. from a watch folder.
+pub fn client_label(ip: Option<IpAddr>) -> String {
+    let Some(ip) = ip else {
+        return "local".to_string();
+    };
+    let digest = ring::digest::digest(
+        &ring::digest::SHA256,
+        ip.to_canonical().to_string().as_bytes(),
+    );
+    data_encoding::HEXLOWER.encode(&digest.as_ref()[..6])
+}
+
+pub static QUOTA_REJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "ftp_paperless_bridge_quota_rejections_total",
+        "Uploads rejected because a user exceeded an upload limit",
+        &["user", "limit"]
+    )
+    .expect("failed to register quota rejections metric")
+});
+
+pub static SPOOL_FILES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "ftp_paperless_bridge_spool_files",
+        "Documents waiting in the spool directory"
+    )
+    .expect("failed to register spool files metric")
+});
+
+pub static SPOOL_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "ftp_paperless_bridge_spool_bytes",
+        "Total size of the documents waiting in the spool directory"
+    )
+    .expect("failed to register spool bytes metric")
+});
+
+pub static SPOOL_OLDEST_SECONDS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "ftp_paperless_bridge_spool_oldest_seconds",
+        "Age of the oldest document waiting in the spool directory"
+    )
+    .expect("failed to register spool age metric")
+});
+
+/// Spooled documents deleted by the retention policy, by `reason` (`age` or `size`).
+pub static SPOOL_EXPIRED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "ftp_paperless_bridge_spool_expired_total",
+        "Spooled documents deleted before they could be uploaded",
+        &["reason"]
+    )
+    .expect("failed to register spool expired metric")
+});
+
+pub static PASSIVE_PORTS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "ftp_paperless_bridge_passive_ports",
+        "Ports in the passive port range and its overflow range"
+    )
+    .expect("failed to register passive ports metric")
+});
+
+pub static PASSIVE_PORTS_IN_USE: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "ftp_paperless_bridge_passive_ports_in_use",
+        "Passive ports handed out to sessions"
+    )
+    .expect("failed to register passive ports in use metric")
+});
+
+pub static PASSIVE_PORTS_EXHAUSTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "ftp_paperless_bridge_passive_ports_exhausted_total",
+        "Transfers refused because all passive ports were in use"
+    )
+    .expect("failed to register passive ports exhausted metric")
+});
+
+pub static STAGED_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "ftp_paperless_bridge_staged_bytes",
+        "Bytes staged by running transfers, counted against --transfer-budget"
+    )
+    .expect("failed to register staged bytes metric")
+});
+
+pub static SPOOL_FULL_REJECTIONS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "ftp_paperless_bridge_spool_full_rejections_total",
+        "Uploads rejected because the spool directory reached its limits"
+    )
+    .expect("failed to register spool rejections metric")
+});
+
+pub static BLANK_PAGES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "ftp_paperless_bridge_blank_pages_total",
+        "Image uploads dropped as blank pages"
+    )
+    .expect("failed to register blank pages metric")
+});
+
+/// Consumption tasks followed, by `state` (`polling` or `queued` for a free slot).
+pub static TRACKED_TASKS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "ftp_paperless_bridge_tracked_tasks",
+        "Consumption tasks the bridge follows until Paperless finishes them",
+        &["state"]
+    )
+    .expect("failed to register tracked tasks metric")
+});
+
+pub static DISK_FREE_BYTES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "ftp_paperless_bridge_disk_free_bytes",
+        "Free space of the filesystems uploads are staged and spooled on",
+        &["dir"]
+    )
+    .expect("failed to register disk free bytes metric")
+});
+
+pub static DISK_FREE_INODES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "ftp_paperless_bridge_disk_free_inodes",
+        "Free inodes of the filesystems uploads are staged and spooled on",
+        &["dir"]
+    )
+    .expect("failed to register disk free inodes metric")
+});
+
+pub static DISK_LOW: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "ftp_paperless_bridge_disk_low",
+        "1 while uploads are refused because a filesystem is below its free space minimum"
+    )
+    .expect("failed to register disk low metric")
+});
+
+pub static DISK_LOW_REJECTIONS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "ftp_paperless_bridge_disk_low_rejections_total",
+        "Uploads rejected because a filesystem was below its free space minimum"
+    )
+    .expect("failed to register disk low rejections metric")
+});
+
+/// Durations of the phases of an upload: `transfer` from the scanner, `staging` (completeness
+/// check and checksum), the `upload` to Paperless including retries, and Paperless' `consumption`.
+pub static UPLOAD_PHASE_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "ftp_paperless_bridge_upload_phase_seconds",
+        "Time spent in each phase of an upload",
+        &["phase"],
+        vec![
+            0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0
+        ]
+    )
+    .expect("failed to register upload phase metric")
+});
+
+pub fn observe_phase(phase: &str, elapsed: Duration) {
+    UPLOAD_PHASE_SECONDS
+        .with_label_values(&[phase])
+        .observe(elapsed.as_secs_f64());
+    crate::statsd::timing("upload_phase", phase, elapsed);
+}
+
+pub static PAPERLESS_UP: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "ftp_paperless_bridge_paperless_up",
+        "1 if the last health check reached Paperless"
+    )
+    .expect("failed to register Paperless up metric")
+});
+
+pub static PAPERLESS_HEALTH_CHECK_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "ftp_paperless_bridge_paperless_health_check_seconds",
+        "Time the periodic health check of Paperless took",
+        vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]
+    )
+    .expect("failed to register Paperless health check metric")
+});
+
+pub static CANARY_LAST_SUCCESS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "ftp_paperless_bridge_canary_last_success_timestamp_seconds",
+        "When a canary upload was last consumed by Paperless"
+    )
+    .expect("failed to register canary success metric")
+});
+
+pub static CANARY_FAILURES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "ftp_paperless_bridge_canary_failures_total",
+        "Canary uploads that were not consumed by Paperless"
+    )
+    .expect("failed to register canary failures metric")
+});
+
+/// Always 1, labeled with the version, commit and features of this build.
+pub static BUILD_INFO: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    let gauge = register_int_gauge_vec!(
+        "ftp_paperless_bridge_build_info",
+        "Version, git commit and enabled features of the bridge",
+        &["version", "commit", "features"]
+    )
+    .expect("failed to register build info metric");
+    gauge
+        .with_label_values(&[
+            env!("CARGO_PKG_VERSION"),
+            GIT_COMMIT,
+            &enabled_features().join(","),
+        ])
+        .set(1);
+    gauge
+});
+
+/// Render all registered metrics in the Prometheus text format.
+pub fn render() -> String {
+    LazyLock::force(&BUILD_INFO);
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        error!("Failed to encode metrics: {e}");
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_info_is_exported() {
+        assert!(render().contains(&format!(
+            "ftp_paperless_bridge_build_info{{commit=\"{GIT_COMMIT}\""
+        )));
+    }
+
+    #[test]
+    fn client_labels_hide_the_address() {
+        let ip: IpAddr = "192.168.1.20".parse().unwrap();
+        let mapped: IpAddr = "::ffff:192.168.1.20".parse().unwrap();
+        let label = client_label(Some(ip));
+        assert_eq!(label.len(), 12);
+        assert!(!label.contains("192"));
+        assert_eq!(client_label(Some(mapped)), label);
+        assert_ne!(client_label(Some("192.168.1.21".parse().unwrap())), label);
+        assert_eq!(client_label(None), "local");
+    }
+}
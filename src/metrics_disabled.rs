@@ -0,0 +1,55 @@
+//! Stand-ins for the metrics in builds without the `metrics` feature. They record nothing, so the
+//! rest of the bridge doesn't have to tell the builds apart.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// A counter, gauge or histogram that discards what it is given.
+#[derive(Debug, Clone, Copy)]
+pub struct Metric;
+
+impl Metric {
+    pub fn with_label_values<V: AsRef<str>>(&self, _values: &[V]) -> Metric {
+        Metric
+    }
+
+    pub fn inc(&self) {}
+
+    pub fn inc_by(&self, _value: u64) {}
+
+    pub fn set(&self, _value: i64) {}
+
+    pub fn observe(&self, _value: f64) {}
+}
+
+pub static UPLOADS: Metric = Metric;
+pub static UPLOAD_BYTES: Metric = Metric;
+pub static UPLOAD_FAILURES: Metric = Metric;
+pub static USER_UPLOAD_FAILURES: Metric = Metric;
+pub static UPLOAD_SECONDS: Metric = Metric;
+pub static QUOTA_REJECTIONS: Metric = Metric;
+pub static SPOOL_FILES: Metric = Metric;
+pub static SPOOL_BYTES: Metric = Metric;
+pub static SPOOL_OLDEST_SECONDS: Metric = Metric;
+pub static SPOOL_EXPIRED: Metric = Metric;
+pub static PASSIVE_PORTS: Metric = Metric;
+pub static PASSIVE_PORTS_IN_USE: Metric = Metric;
+pub static PASSIVE_PORTS_EXHAUSTED: Metric = Metric;
+pub static STAGED_BYTES: Metric = Metric;
+pub static SPOOL_FULL_REJECTIONS: Metric = Metric;
+pub static BLANK_PAGES: Metric = Metric;
+pub static TRACKED_TASKS: Metric = Metric;
+pub static DISK_FREE_BYTES: Metric = Metric;
+pub static DISK_FREE_INODES: Metric = Metric;
+pub static DISK_LOW: Metric = Metric;
+pub static DISK_LOW_REJECTIONS: Metric = Metric;
+pub static PAPERLESS_UP: Metric = Metric;
+pub static PAPERLESS_HEALTH_CHECK_SECONDS: Metric = Metric;
+pub static CANARY_LAST_SUCCESS: Metric = Metric;
+pub static CANARY_FAILURES: Metric = Metric;
+
+pub fn client_label(_ip: Option<IpAddr>) -> String {
+    String::new()
+}
+
+pub fn observe_phase(_phase: &str, _elapsed: Duration) {}
@@ -0,0 +1,202 @@
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use rumqttc::{AsyncClient, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use serde_json::{Value, json};
+use tokio::time::sleep;
+
+use crate::health::PaperlessHealth;
+use crate::notify::Event;
+
+/// How long to wait before connecting again after the broker went away.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A broker to publish upload events and the health of the bridge to.
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Prefix of the topics of this bridge, e.g. `ftp-paperless-bridge`.
+    pub topic: String,
+    /// Prefix Home Assistant looks for discovery messages under, usually `homeassistant`.
+    pub discovery_prefix: String,
+}
+
+impl MqttConfig {
+    /// The topic prefix as a Home Assistant object ID.
+    fn node_id(&self) -> String {
+        self.topic
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect()
+    }
+
+    /// Discovery topics and configs of the entities of the bridge in Home Assistant.
+    fn discovery(&self) -> Vec<(String, Value)> {
+        let node = self.node_id();
+        let topic = &self.topic;
+        let device = json!({
+            "identifiers": [node],
+            "name": "FTP Paperless Bridge",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        let entity = |component: &str, id: &str, config: Value| {
+            let mut config = config;
+            config["unique_id"] = format!("{node}_{id}").into();
+            config["availability_topic"] = format!("{topic}/status").into();
+            config["device"] = device.clone();
+            (
+                format!("{}/{component}/{node}/{id}/config", self.discovery_prefix),
+                config,
+            )
+        };
+        vec![
+            entity(
+                "binary_sensor",
+                "paperless",
+                json!({
+                    "name": "Paperless",
+                    "device_class": "connectivity",
+                    "state_topic": format!("{topic}/paperless"),
+                }),
+            ),
+            entity(
+                "sensor",
+                "last_upload",
+                json!({
+                    "name": "Last upload",
+                    "icon": "mdi:file-document",
+                    "state_topic": format!("{topic}/last_upload"),
+                    "value_template": "{{ value_json.name }}",
+                    "json_attributes_topic": format!("{topic}/last_upload"),
+                }),
+            ),
+            entity(
+                "sensor",
+                "last_failure",
+                json!({
+                    "name": "Last failure",
+                    "icon": "mdi:file-alert",
+                    "state_topic": format!("{topic}/last_failure"),
+                    "value_template": "{{ value_json.name }}",
+                    "json_attributes_topic": format!("{topic}/last_failure"),
+                }),
+            ),
+        ]
+    }
+}
+
+/// Publishes to an MQTT broker, announcing the bridge to Home Assistant whenever it connects.
+#[derive(Debug, Clone)]
+pub struct Mqtt {
+    client: AsyncClient,
+    topic: String,
+}
+
+impl Mqtt {
+    /// Connect in the background, reconnecting whenever the connection is lost.
+    pub fn connect(config: MqttConfig) -> Self {
+        let mut options = MqttOptions::new(config.node_id(), &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(
+            format!("{}/status", config.topic),
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+        let (client, event_loop) = AsyncClient::new(options, 64);
+        let mqtt = Self {
+            client,
+            topic: config.topic.clone(),
+        };
+        tokio::spawn(mqtt.clone().run(event_loop, config));
+        mqtt
+    }
+
+    async fn run(self, mut event_loop: EventLoop, config: MqttConfig) {
+        loop {
+            match event_loop.poll().await {
+                Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to MQTT broker {}:{}", config.host, config.port);
+                    for (topic, discovery) in config.discovery() {
+                        self.publish(&topic, discovery.to_string(), true);
+                    }
+                    self.publish(&format!("{}/status", self.topic), "online", true);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(
+                        "MQTT connection to {}:{} failed: {e}",
+                        config.host, config.port
+                    );
+                    sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+
+    fn publish(&self, topic: &str, payload: impl Into<Vec<u8>>, retain: bool) {
+        if let Err(e) = self
+            .client
+            .try_publish(topic, QoS::AtLeastOnce, retain, payload)
+        {
+            debug!("Dropping MQTT message to {topic}: {e}");
+        }
+    }
+
+    /// Publish an upload event to `<topic>/event` for automations, and as the state of the
+    /// last upload or failure.
+    pub fn publish_event(&self, event: &Event) {
+        let payload = serde_json::to_string(event).unwrap_or_default();
+        let state = match event {
+            Event::Uploaded { .. } => "last_upload",
+            Event::Failed { .. } => "last_failure",
+        };
+        self.publish(&format!("{}/{state}", self.topic), payload.clone(), true);
+        self.publish(&format!("{}/event", self.topic), payload, false);
+    }
+
+    /// Background task publishing whether Paperless is reachable.
+    pub async fn health_loop(self, health: PaperlessHealth, interval: Duration) {
+        loop {
+            let state = if health.check().is_ok() { "ON" } else { "OFF" };
+            self.publish(&format!("{}/paperless", self.topic), state, true);
+            sleep(interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovery_announces_the_entities_of_the_bridge() {
+        let config = MqttConfig {
+            host: "localhost".to_string(),
+            port: 1883,
+            username: None,
+            password: None,
+            topic: "ftp-paperless-bridge".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+        };
+        let discovery = config.discovery();
+        let (topic, paperless) = &discovery[0];
+        assert_eq!(
+            topic,
+            "homeassistant/binary_sensor/ftp_paperless_bridge/paperless/config"
+        );
+        assert_eq!(paperless["state_topic"], "ftp-paperless-bridge/paperless");
+        assert_eq!(
+            paperless["availability_topic"],
+            "ftp-paperless-bridge/status"
+        );
+        assert_eq!(paperless["unique_id"], "ftp_paperless_bridge_paperless");
+        assert_eq!(discovery.len(), 3);
+    }
+}
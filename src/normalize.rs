@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use tokio::io::AsyncWriteExt;
+
+/// Magic of Fuji Xerox DocuWorks documents and binders.
+const XDW_MAGIC: &[u8] = b"%XDW";
+
+/// Why Paperless can't consume a document in a scanner-specific format, with what to do instead.
+pub fn unsupported(head: &[u8], name: Option<&str>) -> Option<&'static str> {
+    let extension = name
+        .and_then(|name| Path::new(name).extension())
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    if head.starts_with(XDW_MAGIC) || matches!(extension.as_deref(), Some("xdw" | "xbd")) {
+        Some("DocuWorks documents can't be consumed by Paperless, set the scanner to save PDF")
+    } else {
+        None
+    }
+}
+
+/// Rebuild the cross-reference table of the PDF at `path` if it is missing or empty, as written
+/// by some scanners for multi-page scans. Returns whether the file was repaired.
+pub async fn repair_pdf(path: &Path) -> std::io::Result<bool> {
+    let pdf = tokio::fs::read(path).await?;
+    if !pdf.starts_with(b"%PDF-") || xref_is_valid(&pdf) {
+        return Ok(false);
+    }
+    let Some(xref) = rebuild_xref(&pdf) else {
+        return Ok(false);
+    };
+    let mut file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(&xref).await?;
+    file.flush().await?;
+    Ok(true)
+}
+
+/// Whether the cross-reference section `startxref` points to lists any objects.
+fn xref_is_valid(pdf: &[u8]) -> bool {
+    let Some(section) = startxref(pdf).and_then(|offset| pdf.get(offset..)) else {
+        return false;
+    };
+    if let Some(rest) = section.strip_prefix(b"xref") {
+        // The first subsection starts with its first object number and the number of entries.
+        return number(skip_whitespace(rest))
+            .and_then(|(_, rest)| number(skip_whitespace(rest)))
+            .is_some_and(|(count, _)| count > 0);
+    }
+    // A cross-reference stream is an object of its own.
+    object_header(section).is_some()
+}
+
+/// A cross-reference table and trailer for every object of `pdf`, to be appended to it.
+fn rebuild_xref(pdf: &[u8]) -> Option<Vec<u8>> {
+    // Later definitions of an object replace earlier ones, as with incremental updates.
+    let mut objects = BTreeMap::new();
+    let line_starts = pdf
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| matches!(byte, b'\n' | b'\r'))
+        .map(|(end, _)| end + 1);
+    for offset in line_starts {
+        if let Some((number, generation)) = object_header(&pdf[offset..]) {
+            objects.insert(number, (generation, offset));
+        }
+    }
+    let root = reference_after(pdf, b"/Root").or_else(|| {
+        objects.iter().find_map(|(&number, &(generation, offset))| {
+            let body = &pdf[offset..];
+            let end = find(body, b"endobj").unwrap_or(body.len());
+            find(&body[..end], b"/Catalog").map(|_| (number, generation))
+        })
+    })?;
+    let size = objects.keys().next_back()? + 1;
+
+    let mut xref = String::new();
+    if !pdf.ends_with(b"\n") {
+        xref.push('\n');
+    }
+    let offset = pdf.len() + xref.len();
+    xref.push_str(&format!("xref\n0 {size}\n"));
+    for number in 0..size {
+        match objects.get(&number) {
+            Some((generation, offset)) => {
+                xref.push_str(&format!("{offset:010} {generation:05} n\r\n"));
+            }
+            None => xref.push_str("0000000000 65535 f\r\n"),
+        }
+    }
+    xref.push_str(&format!(
+        "trailer\n<< /Size {size} /Root {} {} R",
+        root.0, root.1
+    ));
+    if let Some((number, generation)) = reference_after(pdf, b"/Info") {
+        xref.push_str(&format!(" /Info {number} {generation} R"));
+    }
+    xref.push_str(&format!(" >>\nstartxref\n{offset}\n%%EOF\n"));
+    Some(xref.into_bytes())
+}
+
+/// The offset the last `startxref` of `pdf` points to.
+fn startxref(pdf: &[u8]) -> Option<usize> {
+    let at = rfind(pdf, b"startxref")?;
+    number(skip_whitespace(&pdf[at + b"startxref".len()..])).map(|(offset, _)| offset)
+}
+
+/// The object referenced after the last `key`, e.g. `/Root 1 0 R`.
+fn reference_after(pdf: &[u8], key: &[u8]) -> Option<(usize, usize)> {
+    let at = rfind(pdf, key)?;
+    let (object, rest) = number(skip_whitespace(&pdf[at + key.len()..]))?;
+    let (generation, rest) = number(skip_whitespace(rest))?;
+    skip_whitespace(rest)
+        .starts_with(b"R")
+        .then_some((object, generation))
+}
+
+/// The number and generation of the object defined at the start of `bytes`, e.g. `12 0 obj`.
+fn object_header(bytes: &[u8]) -> Option<(usize, usize)> {
+    let (object, rest) = number(bytes)?;
+    let (generation, rest) = number(skip_whitespace(rest))?;
+    skip_whitespace(rest)
+        .starts_with(b"obj")
+        .then_some((object, generation))
+}
+
+fn number(bytes: &[u8]) -> Option<(usize, &[u8])> {
+    let digits = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+    let value = std::str::from_utf8(&bytes[..digits]).ok()?.parse().ok()?;
+    Some((value, &bytes[digits..]))
+}
+
+fn skip_whitespace(bytes: &[u8]) -> &[u8] {
+    let whitespace = bytes.iter().take_while(|b| b.is_ascii_whitespace()).count();
+    &bytes[whitespace..]
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OBJECTS: &str = "%PDF-1.4\n\
+        1 0 obj\n<< /Type /Catalog /Pages 2 0 R >>\nendobj\n\
+        2 0 obj\n<< /Type /Pages /Kids [3 0 R] /Count 1 >>\nendobj\n\
+        3 0 obj\n<< /Type /Page /Parent 2 0 R >>\nendobj\n";
+
+    #[test]
+    fn empty_xref_is_rebuilt() {
+        let broken = format!(
+            "{OBJECTS}xref\n0 0\ntrailer\n<< /Size 0 /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            OBJECTS.len()
+        );
+        assert!(!xref_is_valid(broken.as_bytes()));
+
+        let mut repaired = broken.clone().into_bytes();
+        repaired.extend(rebuild_xref(broken.as_bytes()).unwrap());
+        assert!(xref_is_valid(&repaired));
+        let repaired = String::from_utf8(repaired).unwrap();
+        let xref = &repaired[startxref(repaired.as_bytes()).unwrap()..];
+        assert!(xref.starts_with("xref\n0 4\n0000000000 65535 f\r\n"));
+        let page = OBJECTS.find("3 0 obj").unwrap();
+        assert!(xref.contains(&format!("{page:010} 00000 n\r\n")));
+        assert!(xref.contains("/Size 4 /Root 1 0 R"));
+    }
+
+    #[test]
+    fn missing_trailer_finds_the_catalog() {
+        let xref = String::from_utf8(rebuild_xref(OBJECTS.as_bytes()).unwrap()).unwrap();
+        assert!(xref.contains("/Root 1 0 R"));
+        assert!(rebuild_xref(b"%PDF-1.4\n").is_none());
+    }
+
+    #[test]
+    fn valid_xref_is_kept() {
+        let xref = rebuild_xref(OBJECTS.as_bytes()).unwrap();
+        let valid = [OBJECTS.as_bytes(), &xref].concat();
+        assert!(xref_is_valid(&valid));
+    }
+
+    #[test]
+    fn docuworks_documents_are_unsupported() {
+        assert!(unsupported(b"%XDWDOC-", Some("scan.pdf")).is_some());
+        assert!(unsupported(b"", Some("scan.XDW")).is_some());
+        assert!(unsupported(b"%PDF-1.7", Some("scan.pdf")).is_none());
+    }
+}
@@ -0,0 +1,202 @@
+use std::path::Path;
+
+use image::GrayImage;
+
+use crate::batch::SeparatorPage;
+
+/// Ink percentage below which a page counts as a blank separator page when
+/// `--remove-blank-pages` doesn't set one.
+pub const DEFAULT_BLANK_THRESHOLD: f64 = 0.5;
+/// Pixels darker than this count as ink.
+const INK_LEVEL: u8 = 200;
+/// Share of each edge ignored, where scans show the shadow of the paper's edge.
+const MARGIN: f64 = 0.05;
+
+/// Percentage of the pixels of `page` that are ink, leaving out the margins.
+pub fn ink_coverage(page: &GrayImage) -> f64 {
+    let (width, height) = page.dimensions();
+    let (margin_x, margin_y) = (
+        (f64::from(width) * MARGIN) as u32,
+        (f64::from(height) * MARGIN) as u32,
+    );
+    let (mut pixels, mut ink) = (0u64, 0u64);
+    for y in margin_y..height - margin_y {
+        for x in margin_x..width - margin_x {
+            pixels += 1;
+            if page.get_pixel(x, y).0[0] < INK_LEVEL {
+                ink += 1;
+            }
+        }
+    }
+    if pixels == 0 {
+        return 0.0;
+    }
+    ink as f64 * 100.0 / pixels as f64
+}
+
+/// Whether the image in `bytes` is a blank page, with less than `threshold` percent ink. Files
+/// that aren't images are never blank.
+pub fn is_blank(bytes: &[u8], threshold: f64) -> Result<bool, image::ImageError> {
+    if image::guess_format(bytes).is_err() {
+        return Ok(false);
+    }
+    let page = image::load_from_memory(bytes)?.into_luma8();
+    Ok(ink_coverage(&page) < threshold)
+}
+
+/// The contents of the QR codes on `page`.
+pub fn qr_codes(page: &GrayImage) -> Vec<String> {
+    let (width, height) = page.dimensions();
+    let mut prepared =
+        rqrr::PreparedImage::prepare_from_greyscale(width as usize, height as usize, |x, y| {
+            page.get_pixel(x as u32, y as u32).0[0]
+        });
+    prepared
+        .detect_grids()
+        .into_iter()
+        .filter_map(|grid| grid.decode().ok())
+        .map(|(_, content)| content)
+        .collect()
+}
+
+/// The image of the first page of a scan: the image itself, or the first JPEG embedded in a PDF,
+/// which is the first page of the PDFs scanners write.
+fn first_page(bytes: &[u8]) -> Option<&[u8]> {
+    if image::guess_format(bytes).is_ok() {
+        return Some(bytes);
+    }
+    if !bytes.starts_with(b"%PDF-") {
+        return None;
+    }
+    let start = bytes.windows(3).position(|w| w == [0xFF, 0xD8, 0xFF])?;
+    let image = &bytes[start..];
+    let end = image
+        .windows(b"endstream".len())
+        .position(|w| w == b"endstream")
+        .unwrap_or(image.len());
+    Some(image[..end].trim_ascii_end())
+}
+
+/// The contents of the QR codes on the first page of the image or PDF in `bytes`.
+pub fn first_page_barcodes(bytes: &[u8]) -> Result<Vec<String>, image::ImageError> {
+    let Some(page) = first_page(bytes) else {
+        return Ok(Vec::new());
+    };
+    let page = image::load_from_memory_with_format(page, image::guess_format(page)?)?;
+    Ok(qr_codes(&page.into_luma8()))
+}
+
+/// Whether the image in `bytes` is a `separator` page. Blank pages have less than
+/// `blank_threshold` percent ink.
+pub fn is_separator(
+    bytes: &[u8],
+    separator: &SeparatorPage,
+    blank_threshold: f64,
+) -> Result<bool, image::ImageError> {
+    match separator {
+        SeparatorPage::Blank => is_blank(bytes, blank_threshold),
+        SeparatorPage::Barcode(content) => {
+            if image::guess_format(bytes).is_err() {
+                return Ok(false);
+            }
+            let page = image::load_from_memory(bytes)?.into_luma8();
+            Ok(qr_codes(&page).contains(content))
+        }
+    }
+}
+
+/// Inspect the staged file at `path` with `inspect`, decoding it off the async runtime.
+async fn inspect_file<T: Send + 'static>(
+    path: &Path,
+    inspect: impl FnOnce(&[u8]) -> Result<T, image::ImageError> + Send + 'static,
+) -> std::io::Result<T> {
+    let bytes = tokio::fs::read(path).await?;
+    tokio::task::spawn_blocking(move || inspect(&bytes))
+        .await
+        .map_err(std::io::Error::other)?
+        .map_err(std::io::Error::other)
+}
+
+/// [`is_blank`] for the staged file at `path`.
+pub async fn is_blank_file(path: &Path, threshold: f64) -> std::io::Result<bool> {
+    inspect_file(path, move |bytes| is_blank(bytes, threshold)).await
+}
+
+/// [`first_page_barcodes`] for the staged file at `path`.
+pub async fn first_page_barcodes_file(path: &Path) -> std::io::Result<Vec<String>> {
+    inspect_file(path, first_page_barcodes).await
+}
+
+/// [`is_separator`] for the staged file at `path`.
+pub async fn is_separator_file(
+    path: &Path,
+    separator: &SeparatorPage,
+    blank_threshold: f64,
+) -> std::io::Result<bool> {
+    let separator = separator.clone();
+    inspect_file(path, move |bytes| {
+        is_separator(bytes, &separator, blank_threshold)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Luma};
+
+    fn png(page: GrayImage) -> Vec<u8> {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        page.write_to(&mut bytes, ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn blank_pages_have_little_ink() {
+        let mut page = GrayImage::from_pixel(100, 100, Luma([250]));
+        // The shadow of the paper's edge doesn't count.
+        for y in 0..100 {
+            page.put_pixel(0, y, Luma([0]));
+        }
+        assert_eq!(ink_coverage(&page), 0.0);
+        assert!(is_blank(&png(page.clone()), 0.5).unwrap());
+
+        for x in 10..90 {
+            for y in 40..45 {
+                page.put_pixel(x, y, Luma([20]));
+            }
+        }
+        assert!(ink_coverage(&page) > 4.0);
+        assert!(!is_blank(&png(page), 0.5).unwrap());
+
+        assert!(!is_blank(b"%PDF-1.7", 0.5).unwrap());
+    }
+
+    #[test]
+    fn first_page_of_pdfs_is_their_first_jpeg() {
+        let mut jpeg = std::io::Cursor::new(Vec::new());
+        GrayImage::from_pixel(16, 16, Luma([250]))
+            .write_to(&mut jpeg, ImageFormat::Jpeg)
+            .unwrap();
+        let jpeg = jpeg.into_inner();
+        let pdf = [
+            b"%PDF-1.4\n4 0 obj\n<< /Filter /DCTDecode >>\nstream\n".as_slice(),
+            &jpeg,
+            b"\nendstream\nendobj\n",
+        ]
+        .concat();
+        assert_eq!(first_page(&pdf), Some(jpeg.as_slice()));
+        assert_eq!(first_page_barcodes(&pdf).unwrap(), Vec::<String>::new());
+        assert_eq!(first_page(b"%PDF-1.4\n"), None);
+        assert_eq!(first_page_barcodes(b"hello").unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn pages_without_the_barcode_are_no_separators() {
+        let page = png(GrayImage::from_pixel(100, 100, Luma([250])));
+        assert!(is_separator(&page, &SeparatorPage::Blank, DEFAULT_BLANK_THRESHOLD).unwrap());
+        let barcode = SeparatorPage::Barcode("PATCHT".to_string());
+        assert!(!is_separator(&page, &barcode, DEFAULT_BLANK_THRESHOLD).unwrap());
+        assert!(!is_separator(b"%PDF-1.7", &barcode, DEFAULT_BLANK_THRESHOLD).unwrap());
+    }
+}
@@ -0,0 +1,53 @@
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use log::debug;
+
+use crate::auth::{BackendError, BridgeAuthenticator};
+use crate::users::UserSettings;
+
+/// Verifies FTP logins against the host's PAM stack, so local Unix accounts can log in.
+#[derive(Debug)]
+pub struct PamVerifier {
+    /// PAM service whose configuration in /etc/pam.d is used, e.g. `login`.
+    service: String,
+}
+
+impl PamVerifier {
+    pub fn new(service: String) -> Self {
+        Self { service }
+    }
+}
+
+#[async_trait]
+impl BridgeAuthenticator for PamVerifier {
+    fn name(&self) -> &'static str {
+        "PAM"
+    }
+
+    async fn verify(
+        &self,
+        username: &str,
+        password: &str,
+        _source_ip: IpAddr,
+    ) -> Result<Option<UserSettings>, BackendError> {
+        let service = self.service.clone();
+        let username = username.to_string();
+        let password = password.to_string();
+        // PAM modules block, e.g. pam_unix sleeps after a failed attempt.
+        tokio::task::spawn_blocking(move || {
+            let mut client = ::pam::Client::with_password(&service)?;
+            client
+                .conversation_mut()
+                .set_credentials(username.as_str(), password.as_str());
+            match client.authenticate() {
+                Ok(()) => Ok(Some(UserSettings::default())),
+                Err(e) => {
+                    debug!("PAM service {service} rejected {username}: {e}");
+                    Ok(None)
+                }
+            }
+        })
+        .await?
+    }
+}
@@ -1,63 +1,686 @@
 use async_trait::async_trait;
-use log::info;
-use reqwest::{Client, multipart};
-use std::time::Duration;
+use bytes::Bytes;
+use clap::ValueEnum;
+use log::{debug, info};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Proxy, Response, StatusCode, multipart};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+use crate::metadata::{ObjectKind, match_name};
+use crate::users::UserSettings;
 
 const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
 const HTTP_REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+/// First wait between polls of a consumption task, doubled on each poll while it is pending.
+const TASK_POLL_INITIAL_INTERVAL: Duration = Duration::from_millis(500);
+pub const DEFAULT_MAX_TASK_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How long the names of tags, correspondents and document types are reused before they are
+/// listed again, so objects created in Paperless are found soon.
+const OBJECT_CACHE_TTL: Duration = Duration::from_secs(300);
+/// Oldest Paperless release the bridge is tested with, as (major, minor).
+const OLDEST_TESTED_VERSION: (u64, u64) = (1, 14);
+/// Newest major Paperless release the bridge is tested with.
+const NEWEST_TESTED_MAJOR_VERSION: u64 = 2;
+/// API endpoints the bridge can't work without.
+const REQUIRED_ENDPOINTS: [&str; 5] = [
+    "/api/documents/post_document/",
+    "/api/tasks/",
+    "/api/tags/",
+    "/api/correspondents/",
+    "/api/document_types/",
+];
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum PaperlessError {
-    Reqwest(reqwest::Error),
-    Io(std::io::Error),
+    /// Paperless rejected the API token (401/403).
+    #[error("Paperless rejected the API token: {0}")]
+    Auth(String),
+    /// The requested object doesn't exist (404).
+    #[error("Not found in Paperless: {0}")]
+    NotFound(String),
+    /// Paperless asks to slow down (429).
+    #[error("Paperless is rate limiting requests: {0}")]
+    RateLimited(String),
+    /// Paperless refused the request or document as invalid (other 4xx). Sending it again fails
+    /// the same way.
+    #[error("Paperless rejected the request: {0}")]
+    Validation(String),
+    /// Paperless couldn't be reached, timed out or failed internally (5xx).
+    #[error("{0}")]
+    Network(reqwest::Error),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    /// Paperless accepted an upload, but failed to turn it into a document.
+    #[error("Task {task_id} failed: {reason}")]
+    ConsumptionFailed { task_id: String, reason: String },
+    /// Paperless answered, but not in the way we expected.
+    #[error("{0}")]
+    Api(String),
 }
 
-impl std::fmt::Display for PaperlessError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl PaperlessError {
+    /// Classify a failed response by its status code, with the body Paperless sent as the reason.
+    fn from_status(status: StatusCode, reason: String) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => PaperlessError::Auth(reason),
+            StatusCode::NOT_FOUND => PaperlessError::NotFound(reason),
+            StatusCode::TOO_MANY_REQUESTS => PaperlessError::RateLimited(reason),
+            status if status.is_client_error() => PaperlessError::Validation(reason),
+            _ => PaperlessError::Api(reason),
+        }
+    }
+
+    /// Whether the same request may succeed when sent again later.
+    pub fn is_retryable(&self) -> bool {
         match self {
-            PaperlessError::Reqwest(e) => write!(f, "{e}"),
-            PaperlessError::Io(e) => write!(f, "{e}"),
+            PaperlessError::Network(_)
+            | PaperlessError::RateLimited(_)
+            | PaperlessError::Io(_)
+            | PaperlessError::Api(_) => true,
+            PaperlessError::Auth(_)
+            | PaperlessError::NotFound(_)
+            | PaperlessError::Validation(_)
+            | PaperlessError::ConsumptionFailed { .. } => false,
         }
     }
-}
 
-impl std::error::Error for PaperlessError {}
+    /// Short name of the kind of error, used as a metrics label.
+    pub fn label(&self) -> &'static str {
+        match self {
+            PaperlessError::Auth(_) => "auth",
+            PaperlessError::NotFound(_) => "not_found",
+            PaperlessError::RateLimited(_) => "rate_limited",
+            PaperlessError::Validation(_) => "validation",
+            PaperlessError::Network(_) => "network",
+            PaperlessError::Io(_) => "io",
+            PaperlessError::ConsumptionFailed { .. } => "consumption_failed",
+            PaperlessError::Api(_) => "api",
+        }
+    }
+}
 
 impl From<reqwest::Error> for PaperlessError {
     fn from(e: reqwest::Error) -> Self {
-        PaperlessError::Reqwest(e)
+        match e.status() {
+            Some(status) if !status.is_server_error() => {
+                PaperlessError::from_status(status, e.to_string())
+            }
+            _ => PaperlessError::Network(e),
+        }
+    }
+}
+
+trait CheckStatus {
+    /// Fail on error responses like [`Response::error_for_status`], keeping the reason Paperless
+    /// gave in the body, e.g. why a document was rejected.
+    async fn check_status(self) -> Result<Response, PaperlessError>;
+}
+
+impl CheckStatus for Response {
+    async fn check_status(self) -> Result<Response, PaperlessError> {
+        let status = self.status();
+        if !status.is_client_error() {
+            return Ok(self.error_for_status()?);
+        }
+        let body = self.text().await.unwrap_or_default();
+        let reason = match body.trim() {
+            "" => status.to_string(),
+            body => format!("{status}: {body}"),
+        };
+        Err(PaperlessError::from_status(status, reason))
     }
 }
 
-impl From<std::io::Error> for PaperlessError {
-    fn from(e: std::io::Error) -> Self {
-        PaperlessError::Io(e)
+/// State of a Paperless consumption task.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskStatus {
+    /// Queued or still being consumed, or not yet known to the task list.
+    Pending,
+    Success {
+        document_id: Option<u64>,
+    },
+    Failure(String),
+}
+
+/// What to make of Paperless refusing to consume a document it already has.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DuplicatePolicy {
+    /// The document is in Paperless, so the upload succeeded
+    Success,
+    /// Log a warning, but report the upload as successful
+    #[default]
+    Warn,
+    /// Treat it like any other consumption failure
+    Fail,
+}
+
+/// Whether a consumption failure is Paperless refusing a duplicate, e.g. "Not consuming scan.pdf:
+/// It is a duplicate of scan (#12)".
+pub fn is_duplicate(reason: &str) -> bool {
+    reason.to_lowercase().contains("duplicate")
+}
+
+/// Metadata and ownership for an uploaded document.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadOptions {
+    /// IDs of tags to assign.
+    pub tags: Vec<u64>,
+    /// Sent as `X-Request-Id` to correlate the bridge's logs with those of proxies and Paperless.
+    pub request_id: Option<String>,
+    pub title: Option<String>,
+    /// Creation date, e.g. `2024-03-01`.
+    pub created: Option<String>,
+    pub correspondent: Option<u64>,
+    pub document_type: Option<u64>,
+    /// Values of custom fields by field ID.
+    pub custom_fields: Vec<(u64, String)>,
+    pub archive_serial_number: Option<u64>,
+}
+
+/// Custom field values as accepted by `post_document`, a JSON object of values by field ID.
+fn custom_fields_json(fields: &[(u64, String)]) -> String {
+    let fields: serde_json::Map<String, Value> = fields
+        .iter()
+        .map(|(id, value)| (id.to_string(), Value::from(value.as_str())))
+        .collect();
+    Value::Object(fields).to_string()
+}
+
+/// A random ID for the requests belonging to one upload.
+pub fn new_request_id() -> String {
+    let mut id = [0u8; 8];
+    // The ID only correlates logs, so a failing RNG isn't worth failing the upload for.
+    let _ = ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut id);
+    data_encoding::HEXLOWER.encode(&id)
+}
+
+pub const DEFAULT_USER_AGENT: &str = concat!("ftp-paperless-bridge/", env!("CARGO_PKG_VERSION"));
+
+/// Settings of the HTTP client used to talk to Paperless.
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    pub user_agent: String,
+    /// Idle connections kept open to Paperless.
+    pub pool_max_idle: Option<usize>,
+    /// Close idle connections after this long, before a proxy silently drops them.
+    pub pool_idle_timeout: Option<Duration>,
+    pub tcp_keepalive: Option<Duration>,
+    /// Send small writes right away instead of coalescing them (Nagle's algorithm off).
+    pub tcp_nodelay: bool,
+    /// Allow HTTP/2 if the server offers it, otherwise use HTTP/1.1 only.
+    pub http2: bool,
+    /// Extra headers sent with every request, e.g. for a proxy that authenticates Paperless users.
+    pub headers: Vec<(String, String)>,
+    /// Proxy to reach Paperless through, e.g. `http://proxy.example.com:3128`.
+    pub proxy: Option<String>,
+    /// Acknowledge finished consumption tasks, which removes them from the Paperless UI.
+    pub acknowledge_tasks: bool,
+    /// Longest wait between polls of a consumption task.
+    pub max_task_poll_interval: Duration,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            pool_max_idle: None,
+            pool_idle_timeout: None,
+            tcp_keepalive: None,
+            tcp_nodelay: true,
+            http2: false,
+            headers: Vec::new(),
+            proxy: None,
+            acknowledge_tasks: false,
+            max_task_poll_interval: DEFAULT_MAX_TASK_POLL_INTERVAL,
+        }
     }
 }
 
 #[async_trait]
 pub trait PaperlessApi: Send + Sync {
     async fn health_check(&self) -> Result<(), PaperlessError>;
-    async fn upload(&self, path: &str) -> Result<String, PaperlessError>;
+    async fn upload(&self, path: &str, options: &UploadOptions) -> Result<String, PaperlessError>;
+    /// Upload a document staged in memory, named `name`.
+    async fn upload_bytes(
+        &self,
+        name: &str,
+        document: Bytes,
+        options: &UploadOptions,
+    ) -> Result<String, PaperlessError>;
+    async fn task_status(&self, task_id: &str) -> Result<TaskStatus, PaperlessError>;
+    /// MD5 checksum of the original file Paperless stored for a document.
+    async fn document_checksum(&self, document_id: u64) -> Result<String, PaperlessError>;
+    async fn delete_document(&self, document_id: u64) -> Result<(), PaperlessError>;
+    /// ID of the tag, correspondent, document type or custom field with this name, matched
+    /// loosely as described at [`match_name`].
+    async fn find_object(
+        &self,
+        kind: ObjectKind,
+        name: &str,
+    ) -> Result<Option<u64>, PaperlessError>;
+    /// Create an object with this name, nested below `parent` where Paperless supports it, and
+    /// return its ID.
+    async fn create_object(
+        &self,
+        kind: ObjectKind,
+        name: &str,
+        parent: Option<u64>,
+    ) -> Result<u64, PaperlessError>;
+    /// Merge documents into a new one with the metadata of the first, deleting the originals.
+    async fn merge_documents(&self, document_ids: &[u64]) -> Result<(), PaperlessError>;
+    /// The Paperless version and the required endpoints it lacks. Nothing is known of sinks
+    /// other than the Paperless API.
+    async fn compatibility(&self) -> Result<Compatibility, PaperlessError> {
+        Ok(Compatibility::default())
+    }
+    /// Longest wait between polls of a consumption task.
+    fn max_task_poll_interval(&self) -> Duration {
+        DEFAULT_MAX_TASK_POLL_INTERVAL
+    }
+}
+
+/// What the bridge knows about the Paperless it talks to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Compatibility {
+    /// As sent in the `X-Version` header, e.g. `2.15.3`.
+    pub version: Option<String>,
+    /// Required API endpoints answering with 404.
+    pub missing_endpoints: Vec<&'static str>,
+}
+
+impl Compatibility {
+    /// Reasons to expect trouble with this Paperless.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(version) = &self.version
+            && let Some(parsed) = parse_version(version)
+            && (parsed < OLDEST_TESTED_VERSION || parsed.0 > NEWEST_TESTED_MAJOR_VERSION)
+        {
+            warnings.push(format!(
+                "Paperless {version} is outside the tested versions {}.{} to {}.x",
+                OLDEST_TESTED_VERSION.0, OLDEST_TESTED_VERSION.1, NEWEST_TESTED_MAJOR_VERSION
+            ));
+        }
+        for endpoint in &self.missing_endpoints {
+            warnings.push(format!("Paperless has no {endpoint} endpoint (404)"));
+        }
+        warnings
+    }
+}
+
+/// Major and minor of a version like `2.15.3` or `v1.17.4-dev`.
+fn parse_version(version: &str) -> Option<(u64, u64)> {
+    let mut parts = version.trim().trim_start_matches('v').split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts
+        .next()?
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    Some((major, minor))
+}
+
+/// Poll a consumption task until Paperless reports a final state or `timeout` expires.
+pub async fn wait_for_task(
+    client: &dyn PaperlessApi,
+    task_id: &str,
+    timeout: Duration,
+) -> Result<TaskStatus, PaperlessError> {
+    let deadline = Instant::now() + timeout;
+    let mut polls = 0;
+    loop {
+        let status = client.task_status(task_id).await?;
+        if status != TaskStatus::Pending {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            return Err(PaperlessError::Api(format!(
+                "Task {task_id} did not finish within {}s",
+                timeout.as_secs()
+            )));
+        }
+        debug!("Task {task_id} still pending");
+        let interval = task_poll_interval(polls, client.max_task_poll_interval());
+        sleep(interval.min(deadline.saturating_duration_since(Instant::now()))).await;
+        polls += 1;
+    }
 }
 
+/// Wait before the next poll of a task still pending after `polls` polls: short at first, as
+/// small documents are consumed in seconds, backing off to `max` for thick ones OCR takes
+/// minutes for.
+fn task_poll_interval(polls: u32, max: Duration) -> Duration {
+    TASK_POLL_INITIAL_INTERVAL
+        .saturating_mul(2u32.saturating_pow(polls))
+        .min(max)
+}
+
+/// Status of a single task as Paperless reports it in the task list or at `/api/tasks/<id>/`.
+fn parse_task(task: &Value) -> TaskStatus {
+    match task["status"].as_str() {
+        Some("SUCCESS") => TaskStatus::Success {
+            document_id: parse_id(&task["related_document"]),
+        },
+        Some(status @ ("FAILURE" | "REVOKED")) => {
+            let result = task["result"]
+                .as_str()
+                .map(str::trim)
+                .filter(|result| !result.is_empty());
+            TaskStatus::Failure(match (result, status) {
+                (Some(result), _) => result.to_string(),
+                (None, "REVOKED") => "Consumption was cancelled".to_string(),
+                (None, _) => "Paperless gave no reason".to_string(),
+            })
+        }
+        _ => TaskStatus::Pending,
+    }
+}
+
+/// The socket of a `unix:///run/paperless.sock` URL.
+pub fn unix_socket_path(url: &str) -> Option<&str> {
+    url.strip_prefix("unix://")
+}
+
+/// Paperless has reported document IDs both as numbers and as strings over time.
+fn parse_id(value: &Value) -> Option<u64> {
+    value
+        .as_u64()
+        .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+}
+
+/// Builds the clients of users that talk to Paperless with a token, headers or proxy of their own,
+/// so their documents and lookups are attributed to the right Paperless user. The clients built are
+/// kept by username, to upload spooled files and follow tasks as the user they belong to.
+#[derive(Clone)]
+pub struct UserClients {
+    base_url: String,
+    token: String,
+    options: ClientOptions,
+    built: Arc<Mutex<HashMap<String, Arc<PaperlessClient>>>>,
+}
+
+impl UserClients {
+    pub fn new(base_url: &str, token: &str, options: ClientOptions) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            token: token.to_string(),
+            options,
+            built: Arc::default(),
+        }
+    }
+
+    /// The client of `username` with `settings`, or `None` if the client of the bridge will do.
+    pub fn client(
+        &self,
+        username: &str,
+        settings: &UserSettings,
+    ) -> Result<Option<Arc<PaperlessClient>>, String> {
+        let client = self.build(settings)?.map(Arc::new);
+        let mut built = self.built.lock().expect("user clients lock poisoned");
+        match &client {
+            Some(client) => built.insert(username.to_string(), Arc::clone(client)),
+            None => built.remove(username),
+        };
+        Ok(client)
+    }
+
+    /// The client last built for `username`, or `default` if it has none of its own.
+    pub fn get(
+        &self,
+        username: Option<&str>,
+        default: &Arc<dyn PaperlessApi>,
+    ) -> Arc<dyn PaperlessApi> {
+        let built = self.built.lock().expect("user clients lock poisoned");
+        match username.and_then(|username| built.get(username)) {
+            Some(client) => Arc::clone(client) as Arc<dyn PaperlessApi>,
+            None => Arc::clone(default),
+        }
+    }
+
+    fn build(&self, settings: &UserSettings) -> Result<Option<PaperlessClient>, String> {
+        if settings.api_token.is_none() && settings.headers.is_empty() && settings.proxy.is_none() {
+            return Ok(None);
+        }
+        let mut options = self.options.clone();
+        options.headers.extend(
+            settings
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+        if let Some(proxy) = &settings.proxy {
+            options.proxy = Some(proxy.clone());
+        }
+        let token = settings.api_token.as_deref().unwrap_or(&self.token);
+        PaperlessClient::try_new(&self.base_url, token, &options).map(Some)
+    }
+}
+
+/// IDs and names of objects by kind, with the time they were listed.
+type ObjectCache = HashMap<ObjectKind, (Instant, Arc<Vec<(u64, String)>>)>;
+
 #[derive(Clone)]
 pub struct PaperlessClient {
     base_url: String,
     token: String,
     client: Client,
+    /// IDs and names of the objects of each kind, with the time they were listed.
+    objects: Arc<Mutex<ObjectCache>>,
+    /// IDs Paperless gave the tasks of pending uploads, by their UUID.
+    task_ids: Arc<Mutex<HashMap<String, u64>>>,
+    /// Whether tasks can be fetched by ID, cleared when Paperless doesn't know `/api/tasks/<id>/`.
+    task_endpoint: Arc<AtomicBool>,
+    acknowledge_tasks: bool,
+    max_task_poll_interval: Duration,
 }
 
 impl PaperlessClient {
-    pub fn new(base_url: &str, token: &str) -> Self {
-        Self {
-            base_url: base_url.trim_end_matches('/').to_string(),
+    pub fn new(base_url: &str, token: &str, options: &ClientOptions) -> Self {
+        Self::try_new(base_url, token, options).expect("failed to build Paperless HTTP client")
+    }
+
+    /// Like [`PaperlessClient::new`], but fails on URLs, headers or proxies that reqwest rejects.
+    pub fn try_new(base_url: &str, token: &str, options: &ClientOptions) -> Result<Self, String> {
+        if unix_socket_path(base_url).is_none() {
+            reqwest::Url::parse(base_url)
+                .map_err(|e| format!("Invalid Paperless URL {base_url:?}: {e}"))?;
+        }
+        let mut builder = Client::builder()
+            .connect_timeout(HTTP_CONNECT_TIMEOUT)
+            .user_agent(&options.user_agent)
+            .tcp_keepalive(options.tcp_keepalive)
+            .tcp_nodelay(options.tcp_nodelay);
+        if let Some(max_idle) = options.pool_max_idle {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(idle_timeout) = options.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(idle_timeout);
+        }
+        if !options.http2 {
+            builder = builder.http1_only();
+        }
+        if !options.headers.is_empty() {
+            let mut headers = HeaderMap::new();
+            for (name, value) in &options.headers {
+                let name = HeaderName::try_from(name.as_str())
+                    .map_err(|e| format!("Invalid header name {name:?}: {e}"))?;
+                let value = HeaderValue::try_from(value.as_str())
+                    .map_err(|e| format!("Invalid value of header {name}: {e}"))?;
+                headers.insert(name, value);
+            }
+            builder = builder.default_headers(headers);
+        }
+        if let Some(proxy) = &options.proxy {
+            builder = builder
+                .proxy(Proxy::all(proxy).map_err(|e| format!("Invalid proxy {proxy:?}: {e}"))?);
+        }
+        let base_url = match unix_socket_path(base_url) {
+            Some(socket) => {
+                // Other platforms reject these URLs when the arguments are parsed.
+                #[cfg(unix)]
+                {
+                    builder = builder.unix_socket(socket);
+                }
+                #[cfg(not(unix))]
+                let _ = socket;
+                // The host only ends up in the Host header.
+                "http://localhost".to_string()
+            }
+            None => base_url.trim_end_matches('/').to_string(),
+        };
+        Ok(Self {
+            base_url,
             token: token.to_string(),
-            client: Client::builder()
-                .connect_timeout(HTTP_CONNECT_TIMEOUT)
-                .build()
-                .expect("failed to build Paperless HTTP client"),
+            client: builder.build().map_err(|e| e.to_string())?,
+            objects: Arc::default(),
+            task_ids: Arc::default(),
+            task_endpoint: Arc::new(AtomicBool::new(true)),
+            acknowledge_tasks: options.acknowledge_tasks,
+            max_task_poll_interval: options.max_task_poll_interval,
+        })
+    }
+
+    /// IDs and names of all objects of `kind`, from the cache while it is fresh.
+    async fn objects(&self, kind: ObjectKind) -> Result<Arc<Vec<(u64, String)>>, PaperlessError> {
+        if let Some((listed, objects)) = self
+            .objects
+            .lock()
+            .expect("object cache lock poisoned")
+            .get(&kind)
+            && listed.elapsed() < OBJECT_CACHE_TTL
+        {
+            return Ok(Arc::clone(objects));
+        }
+        let mut objects = Vec::new();
+        let mut url = Some(format!(
+            "{}/api/{}/?page_size=1000",
+            self.base_url,
+            kind.endpoint()
+        ));
+        while let Some(page_url) = url {
+            let page: Value = self
+                .client
+                .get(&page_url)
+                .header("Authorization", format!("Token {}", self.token))
+                .timeout(HTTP_REQUEST_TIMEOUT)
+                .send()
+                .await?
+                .check_status()
+                .await?
+                .json()
+                .await?;
+            objects.extend(
+                page["results"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|object| {
+                        Some((
+                            parse_id(&object["id"])?,
+                            object["name"].as_str()?.to_string(),
+                        ))
+                    }),
+            );
+            url = page["next"].as_str().map(str::to_string);
+        }
+        debug!("Listed {} Paperless {}", objects.len(), kind.endpoint());
+        let objects = Arc::new(objects);
+        self.objects
+            .lock()
+            .expect("object cache lock poisoned")
+            .insert(kind, (Instant::now(), Arc::clone(&objects)));
+        Ok(objects)
+    }
+
+    /// The task with this ID, or `None` if Paperless can't fetch tasks by ID, which is then
+    /// remembered.
+    async fn task(&self, id: u64) -> Result<Option<Value>, PaperlessError> {
+        let response = self
+            .client
+            .get(format!("{}/api/tasks/{id}/", self.base_url))
+            .header("Authorization", format!("Token {}", self.token))
+            .timeout(HTTP_REQUEST_TIMEOUT)
+            .send()
+            .await?;
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+        ) {
+            info!("Paperless can't fetch tasks by ID, filtering the task list instead");
+            self.task_endpoint.store(false, Ordering::Relaxed);
+            return Ok(None);
+        }
+        Ok(Some(response.check_status().await?.json().await?))
+    }
+
+    /// Mark a finished task as seen, so it no longer shows up in the Paperless UI.
+    async fn acknowledge_task(&self, id: u64) -> Result<(), PaperlessError> {
+        self.client
+            .post(format!("{}/api/tasks/acknowledge/", self.base_url))
+            .header("Authorization", format!("Token {}", self.token))
+            .json(&serde_json::json!({ "tasks": [id] }))
+            .timeout(HTTP_REQUEST_TIMEOUT)
+            .send()
+            .await?
+            .check_status()
+            .await?;
+        Ok(())
+    }
+
+    /// Post `document`, named like `name`, with the metadata of `options`.
+    async fn post_document(
+        &self,
+        mut document: multipart::Part,
+        name: &Path,
+        options: &UploadOptions,
+    ) -> Result<String, PaperlessError> {
+        // The extension was corrected to the content when staging, so it tells the type.
+        if let Some(mime) = crate::document::mime_type(name) {
+            document = document.mime_str(mime)?;
+        }
+        let mut form = multipart::Form::new().part("document", document);
+        for tag in &options.tags {
+            form = form.text("tags", tag.to_string());
+        }
+        if let Some(title) = &options.title {
+            form = form.text("title", title.clone());
+        }
+        if let Some(created) = &options.created {
+            form = form.text("created", created.clone());
+        }
+        if let Some(correspondent) = options.correspondent {
+            form = form.text("correspondent", correspondent.to_string());
+        }
+        if let Some(document_type) = options.document_type {
+            form = form.text("document_type", document_type.to_string());
+        }
+        if let Some(asn) = options.archive_serial_number {
+            form = form.text("archive_serial_number", asn.to_string());
+        }
+        if !options.custom_fields.is_empty() {
+            form = form.text("custom_fields", custom_fields_json(&options.custom_fields));
+        }
+        let mut request = self
+            .client
+            .post(format!("{}/api/documents/post_document/", self.base_url))
+            .header("Authorization", format!("Token {}", self.token))
+            .multipart(form);
+        if let Some(request_id) = &options.request_id {
+            request = request.header("X-Request-Id", request_id);
         }
+        let resp = request.send().await?.check_status().await?;
+
+        let uuid = resp.text().await?;
+        Ok(uuid.trim_matches('"').to_string())
     }
 }
 
@@ -70,24 +693,523 @@ impl PaperlessApi for PaperlessClient {
             .timeout(HTTP_REQUEST_TIMEOUT)
             .send()
             .await?
-            .error_for_status()?;
+            .check_status()
+            .await?;
         Ok(())
     }
 
-    async fn upload(&self, path: &str) -> Result<String, PaperlessError> {
+    async fn compatibility(&self) -> Result<Compatibility, PaperlessError> {
+        let response = self
+            .client
+            .get(format!("{}/api/ui_settings/", self.base_url))
+            .header("Authorization", format!("Token {}", self.token))
+            .timeout(HTTP_REQUEST_TIMEOUT)
+            .send()
+            .await?
+            .check_status()
+            .await?;
+        let version = response
+            .headers()
+            .get("x-version")
+            .and_then(|version| version.to_str().ok())
+            .map(str::to_string);
+        let mut missing_endpoints = Vec::new();
+        for endpoint in REQUIRED_ENDPOINTS {
+            // Only GET is sent, which upload endpoints answer with 405 if they exist.
+            let status = self
+                .client
+                .get(format!("{}{endpoint}", self.base_url))
+                .query(&[("page_size", "1")])
+                .header("Authorization", format!("Token {}", self.token))
+                .timeout(HTTP_REQUEST_TIMEOUT)
+                .send()
+                .await?
+                .status();
+            if status == StatusCode::NOT_FOUND {
+                missing_endpoints.push(endpoint);
+            }
+        }
+        Ok(Compatibility {
+            version,
+            missing_endpoints,
+        })
+    }
+
+    fn max_task_poll_interval(&self) -> Duration {
+        self.max_task_poll_interval
+    }
+
+    async fn upload(&self, path: &str, options: &UploadOptions) -> Result<String, PaperlessError> {
         info!("Uploading {path:?}");
-        let form = multipart::Form::new().file("document", path).await?;
+        let document = multipart::Part::file(path).await?;
+        self.post_document(document, Path::new(path), options).await
+    }
+
+    async fn upload_bytes(
+        &self,
+        name: &str,
+        document: Bytes,
+        options: &UploadOptions,
+    ) -> Result<String, PaperlessError> {
+        info!("Uploading {name:?} from memory");
+        let len = document.len() as u64;
+        let document = multipart::Part::stream_with_length(reqwest::Body::from(document), len)
+            .file_name(name.to_string());
+        self.post_document(document, Path::new(name), options).await
+    }
+
+    async fn task_status(&self, task_id: &str) -> Result<TaskStatus, PaperlessError> {
+        let known_id = self
+            .task_ids
+            .lock()
+            .expect("task ID lock poisoned")
+            .get(task_id)
+            .copied();
+        let task = match known_id {
+            Some(id) if self.task_endpoint.load(Ordering::Relaxed) => self.task(id).await?,
+            _ => None,
+        };
+        let task = match task {
+            Some(task) => task,
+            None => {
+                // Only filtering the task list by UUID works before the task's ID is known.
+                let tasks: Value = self
+                    .client
+                    .get(format!("{}/api/tasks/", self.base_url))
+                    .query(&[("task_id", task_id)])
+                    .header("Authorization", format!("Token {}", self.token))
+                    .timeout(HTTP_REQUEST_TIMEOUT)
+                    .send()
+                    .await?
+                    .check_status()
+                    .await?
+                    .json()
+                    .await?;
+                match tasks.as_array().and_then(|tasks| tasks.first()) {
+                    Some(task) => task.clone(),
+                    None => return Ok(TaskStatus::Pending),
+                }
+            }
+        };
+        let status = parse_task(&task);
+        let id = parse_id(&task["id"]);
+        {
+            let mut task_ids = self.task_ids.lock().expect("task ID lock poisoned");
+            if status == TaskStatus::Pending {
+                if let Some(id) = id {
+                    task_ids.insert(task_id.to_string(), id);
+                }
+                return Ok(status);
+            }
+            task_ids.remove(task_id);
+        }
+        if self.acknowledge_tasks
+            && let Some(id) = id
+            && let Err(e) = self.acknowledge_task(id).await
+        {
+            debug!("Failed to acknowledge task {task_id}: {e}");
+        }
+        Ok(status)
+    }
 
-        let resp = self
+    async fn document_checksum(&self, document_id: u64) -> Result<String, PaperlessError> {
+        let metadata: Value = self
             .client
-            .post(format!("{}/api/documents/post_document/", self.base_url))
+            .get(format!(
+                "{}/api/documents/{document_id}/metadata/",
+                self.base_url
+            ))
             .header("Authorization", format!("Token {}", self.token))
-            .multipart(form)
+            .timeout(HTTP_REQUEST_TIMEOUT)
             .send()
             .await?
-            .error_for_status()?;
+            .check_status()
+            .await?
+            .json()
+            .await?;
+        metadata["original_checksum"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| {
+                PaperlessError::Api(format!(
+                    "Document {document_id} metadata has no original_checksum"
+                ))
+            })
+    }
 
-        let uuid = resp.text().await?;
-        Ok(uuid.trim_matches('"').to_string())
+    async fn delete_document(&self, document_id: u64) -> Result<(), PaperlessError> {
+        self.client
+            .delete(format!("{}/api/documents/{document_id}/", self.base_url))
+            .header("Authorization", format!("Token {}", self.token))
+            .timeout(HTTP_REQUEST_TIMEOUT)
+            .send()
+            .await?
+            .check_status()
+            .await?;
+        Ok(())
+    }
+
+    async fn find_object(
+        &self,
+        kind: ObjectKind,
+        name: &str,
+    ) -> Result<Option<u64>, PaperlessError> {
+        let objects = self.objects(kind).await?;
+        let id = match_name(name, &objects);
+        if let Some(id) = id
+            && objects
+                .iter()
+                .any(|(other, object)| *other == id && object != name)
+        {
+            debug!(
+                "Taking {name:?} to mean Paperless {} #{id}",
+                kind.endpoint()
+            );
+        }
+        Ok(id)
+    }
+
+    async fn create_object(
+        &self,
+        kind: ObjectKind,
+        name: &str,
+        parent: Option<u64>,
+    ) -> Result<u64, PaperlessError> {
+        let mut object = serde_json::json!({ "name": name });
+        if let Some(parent) = parent {
+            // Nested tags, ignored by Paperless versions without them.
+            object["parent"] = parent.into();
+        }
+        let created: Value = self
+            .client
+            .post(format!("{}/api/{}/", self.base_url, kind.endpoint()))
+            .header("Authorization", format!("Token {}", self.token))
+            .timeout(HTTP_REQUEST_TIMEOUT)
+            .json(&object)
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .json()
+            .await?;
+        let id = parse_id(&created["id"]).ok_or_else(|| {
+            PaperlessError::Api(format!("Paperless returned no ID for the new {name:?}"))
+        })?;
+        info!("Created Paperless {} {name:?} (#{id})", kind.endpoint());
+        // List the objects again, so the new one is found.
+        self.objects
+            .lock()
+            .expect("object cache lock poisoned")
+            .remove(&kind);
+        Ok(id)
+    }
+
+    async fn merge_documents(&self, document_ids: &[u64]) -> Result<(), PaperlessError> {
+        self.client
+            .post(format!("{}/api/documents/bulk_edit/", self.base_url))
+            .header("Authorization", format!("Token {}", self.token))
+            .timeout(HTTP_REQUEST_TIMEOUT)
+            .json(&serde_json::json!({
+                "documents": document_ids,
+                "method": "merge",
+                "parameters": {
+                    "metadata_document_id": document_ids.first(),
+                    "delete_originals": true,
+                },
+            }))
+            .send()
+            .await?
+            .check_status()
+            .await?;
+        Ok(())
+    }
+}
+
+/// Accepts uploads without sending them anywhere, to test the FTP side on its own.
+#[derive(Debug, Default)]
+pub struct DryRunClient {
+    uploads: std::sync::Mutex<Vec<Vec<u8>>>,
+}
+
+impl DryRunClient {
+    /// Contents of the documents received so far.
+    pub fn uploads(&self) -> Vec<Vec<u8>> {
+        self.uploads.lock().expect("dry run lock poisoned").clone()
+    }
+}
+
+#[async_trait]
+impl PaperlessApi for DryRunClient {
+    async fn health_check(&self) -> Result<(), PaperlessError> {
+        Ok(())
+    }
+
+    async fn upload(&self, path: &str, _options: &UploadOptions) -> Result<String, PaperlessError> {
+        let content = tokio::fs::read(path).await?;
+        info!("Dry run: not uploading {path:?} ({} bytes)", content.len());
+        let mut uploads = self.uploads.lock().expect("dry run lock poisoned");
+        uploads.push(content);
+        Ok(format!("dry-run-{}", uploads.len()))
+    }
+
+    async fn upload_bytes(
+        &self,
+        name: &str,
+        document: Bytes,
+        _options: &UploadOptions,
+    ) -> Result<String, PaperlessError> {
+        info!("Dry run: not uploading {name:?} ({} bytes)", document.len());
+        let mut uploads = self.uploads.lock().expect("dry run lock poisoned");
+        uploads.push(document.to_vec());
+        Ok(format!("dry-run-{}", uploads.len()))
+    }
+
+    async fn task_status(&self, _task_id: &str) -> Result<TaskStatus, PaperlessError> {
+        Ok(TaskStatus::Success { document_id: None })
+    }
+
+    async fn document_checksum(&self, document_id: u64) -> Result<String, PaperlessError> {
+        Err(PaperlessError::Api(format!(
+            "Document {document_id} does not exist in a dry run"
+        )))
+    }
+
+    async fn delete_document(&self, _document_id: u64) -> Result<(), PaperlessError> {
+        Ok(())
+    }
+
+    async fn find_object(
+        &self,
+        _kind: ObjectKind,
+        _name: &str,
+    ) -> Result<Option<u64>, PaperlessError> {
+        Ok(None)
+    }
+
+    async fn create_object(
+        &self,
+        kind: ObjectKind,
+        name: &str,
+        _parent: Option<u64>,
+    ) -> Result<u64, PaperlessError> {
+        info!(
+            "Dry run: not creating Paperless {} {name:?}",
+            kind.endpoint()
+        );
+        Ok(0)
+    }
+
+    async fn merge_documents(&self, _document_ids: &[u64]) -> Result<(), PaperlessError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn invalid_urls_are_rejected() {
+        let options = ClientOptions::default();
+        assert!(PaperlessClient::try_new("paperless.example", "token", &options).is_err());
+        assert!(PaperlessClient::try_new("https://paperless.example", "token", &options).is_ok());
+        assert!(PaperlessClient::try_new("unix:///run/paperless.sock", "token", &options).is_ok());
+    }
+
+    /// Status of the first task of a filtered task list, as polled before the task's ID is known.
+    fn parse_task_status(tasks: &Value) -> TaskStatus {
+        match tasks.as_array().and_then(|tasks| tasks.first()) {
+            Some(task) => parse_task(task),
+            None => TaskStatus::Pending,
+        }
+    }
+
+    #[test]
+    fn users_get_clients_of_their_own_when_needed() {
+        let clients = UserClients::new(
+            "https://paperless.example.com",
+            "bridge-token",
+            ClientOptions::default(),
+        );
+        let settings = |headers: &[(&str, &str)]| UserSettings {
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        assert!(clients.client("office", &settings(&[])).unwrap().is_none());
+
+        let client = clients
+            .client("office", &settings(&[("Remote-User", "kitchen")]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(client.token, "bridge-token");
+        let client = clients
+            .client(
+                "kitchen",
+                &UserSettings {
+                    api_token: Some("kitchen-token".to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(client.token, "kitchen-token");
+
+        // Spooled files and tasks of a user are handled with the client built for it.
+        let bridge: Arc<dyn PaperlessApi> = Arc::new(PaperlessClient::new(
+            "https://paperless.example.com",
+            "bridge-token",
+            &ClientOptions::default(),
+        ));
+        let kitchen: Arc<dyn PaperlessApi> = client;
+        assert!(Arc::ptr_eq(
+            &clients.get(Some("kitchen"), &bridge),
+            &kitchen
+        ));
+        assert!(Arc::ptr_eq(&clients.get(Some("unknown"), &bridge), &bridge));
+        assert!(Arc::ptr_eq(&clients.get(None, &bridge), &bridge));
+
+        assert!(
+            clients
+                .client("office", &settings(&[("Bad Header", "x")]))
+                .is_err()
+        );
+        let proxy = UserSettings {
+            proxy: Some("not a proxy".to_string()),
+            ..Default::default()
+        };
+        assert!(clients.client("office", &proxy).is_err());
+    }
+
+    #[test]
+    fn unix_socket_urls_are_recognized() {
+        assert_eq!(
+            unix_socket_path("unix:///run/paperless.sock"),
+            Some("/run/paperless.sock")
+        );
+        assert_eq!(unix_socket_path("https://paperless.example.com"), None);
+    }
+
+    #[test]
+    fn error_responses_are_classified_by_status() {
+        let error = |status| PaperlessError::from_status(status, String::new());
+        assert!(matches!(
+            error(StatusCode::FORBIDDEN),
+            PaperlessError::Auth(_)
+        ));
+        assert!(matches!(
+            error(StatusCode::NOT_FOUND),
+            PaperlessError::NotFound(_)
+        ));
+        assert!(error(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!error(StatusCode::BAD_REQUEST).is_retryable());
+        assert_eq!(error(StatusCode::BAD_REQUEST).label(), "validation");
+    }
+
+    #[test]
+    fn versions_are_parsed_by_major_and_minor() {
+        assert_eq!(parse_version("2.15.3"), Some((2, 15)));
+        assert_eq!(parse_version("v1.17-dev"), Some((1, 17)));
+        assert_eq!(parse_version("unknown"), None);
+    }
+
+    #[test]
+    fn untested_versions_and_missing_endpoints_are_warned_about() {
+        let tested = Compatibility {
+            version: Some("2.15.3".to_string()),
+            missing_endpoints: Vec::new(),
+        };
+        assert!(tested.warnings().is_empty());
+        assert!(Compatibility::default().warnings().is_empty());
+
+        for version in ["1.10.2", "3.0.0"] {
+            let untested = Compatibility {
+                version: Some(version.to_string()),
+                missing_endpoints: Vec::new(),
+            };
+            assert_eq!(untested.warnings().len(), 1, "{version}");
+        }
+
+        let missing = Compatibility {
+            version: None,
+            missing_endpoints: vec!["/api/tasks/"],
+        };
+        assert_eq!(
+            missing.warnings(),
+            vec!["Paperless has no /api/tasks/ endpoint (404)".to_string()]
+        );
+    }
+
+    #[test]
+    fn tasks_fetched_by_id_are_parsed() {
+        assert_eq!(
+            parse_task(&json!({"id": 3, "status": "SUCCESS", "related_document": "5"})),
+            TaskStatus::Success {
+                document_id: Some(5)
+            }
+        );
+        assert_eq!(
+            parse_task(&json!({"id": 3, "status": "PENDING"})),
+            TaskStatus::Pending
+        );
+    }
+
+    #[test]
+    fn task_polls_back_off_up_to_the_cap() {
+        let max = Duration::from_secs(5);
+        let intervals: Vec<_> = (0..6).map(|polls| task_poll_interval(polls, max)).collect();
+        assert_eq!(
+            intervals,
+            [500, 1000, 2000, 4000, 5000, 5000].map(Duration::from_millis)
+        );
+        assert_eq!(task_poll_interval(u32::MAX, max), max);
+    }
+
+    #[test]
+    fn unknown_task_is_pending() {
+        assert_eq!(parse_task_status(&json!([])), TaskStatus::Pending);
+        assert_eq!(
+            parse_task_status(&json!([{"status": "STARTED"}])),
+            TaskStatus::Pending
+        );
+    }
+
+    #[test]
+    fn successful_task_reports_document_id() {
+        assert_eq!(
+            parse_task_status(&json!([{"status": "SUCCESS", "related_document": "42"}])),
+            TaskStatus::Success {
+                document_id: Some(42)
+            }
+        );
+        assert_eq!(
+            parse_task_status(&json!([{"status": "SUCCESS", "related_document": 7}])),
+            TaskStatus::Success {
+                document_id: Some(7)
+            }
+        );
+    }
+
+    #[test]
+    fn failed_task_reports_result() {
+        assert_eq!(
+            parse_task_status(&json!([{"status": "FAILURE", "result": "corrupted file"}])),
+            TaskStatus::Failure("corrupted file".to_string())
+        );
+        assert_eq!(
+            parse_task_status(&json!([{"status": "REVOKED", "result": null}])),
+            TaskStatus::Failure("Consumption was cancelled".to_string())
+        );
+    }
+
+    #[test]
+    fn duplicates_are_recognized() {
+        assert!(is_duplicate(
+            "scan.pdf: Not consuming scan.pdf: It is a duplicate of scan (#12)."
+        ));
+        assert!(!is_duplicate("scan.pdf: Error occurred while consuming"));
     }
 }
@@ -0,0 +1,181 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use libunftp::options::Binder;
+use log::warn;
+use tokio::net::TcpSocket;
+
+use crate::tcp::TcpOptions;
+
+#[derive(Debug)]
+struct Inner {
+    range: RangeInclusive<u16>,
+    /// Ports to fall back to once all ports of `range` are in use.
+    overflow: Option<RangeInclusive<u16>>,
+    tcp: TcpOptions,
+    in_use: AtomicUsize,
+    /// Offset to start looking for a free port at, so parallel transfers don't race for the same
+    /// one.
+    next: AtomicUsize,
+}
+
+/// The passive port range and the overflow range, and how many of their ports sessions hold.
+/// Sessions bind their data connections through a [`PassiveBinder`], so a PASV that finds the range
+/// exhausted takes a port from the overflow range, or is answered with 425 once both are.
+#[derive(Debug, Clone)]
+pub struct PassivePorts(Arc<Inner>);
+
+impl PassivePorts {
+    pub fn new(
+        range: RangeInclusive<u16>,
+        overflow: Option<RangeInclusive<u16>>,
+        tcp: TcpOptions,
+    ) -> Result<Self, String> {
+        if let Some(overflow) = &overflow
+            && overflow.start() <= range.end()
+            && range.start() <= overflow.end()
+        {
+            return Err(format!(
+                "The overflow range {}-{} overlaps the passive port range {}-{}",
+                overflow.start(),
+                overflow.end(),
+                range.start(),
+                range.end()
+            ));
+        }
+        let ports = range.len() + overflow.as_ref().map_or(0, ExactSizeIterator::len);
+        crate::metrics::PASSIVE_PORTS.set(ports as i64);
+        Ok(Self(Arc::new(Inner {
+            range,
+            overflow,
+            tcp,
+            in_use: AtomicUsize::new(0),
+            next: AtomicUsize::new(0),
+        })))
+    }
+
+    /// The binder for a new session.
+    pub fn binder(&self) -> PassiveBinder {
+        PassiveBinder {
+            ports: self.clone(),
+            port: None,
+        }
+    }
+
+    /// Bind a data connection socket to a free port of the range, or of the overflow range once
+    /// the range is exhausted.
+    fn bind(&self, local_addr: IpAddr) -> io::Result<(TcpSocket, PassivePort)> {
+        let range = &self.0.range;
+        if let Some(socket) = self.bind_in(range, local_addr) {
+            return Ok((socket, self.acquire()));
+        }
+        if let Some(overflow) = &self.0.overflow {
+            warn!(
+                "All {} passive ports ({}-{}) are in use, falling back to the overflow range {}-{}",
+                range.len(),
+                range.start(),
+                range.end(),
+                overflow.start(),
+                overflow.end()
+            );
+            if let Some(socket) = self.bind_in(overflow, local_addr) {
+                return Ok((socket, self.acquire()));
+            }
+        }
+        warn!("All passive ports are in use, replying 425 to the transfer");
+        crate::metrics::PASSIVE_PORTS_EXHAUSTED.inc();
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "all passive ports are in use",
+        ))
+    }
+
+    fn bind_in(&self, range: &RangeInclusive<u16>, local_addr: IpAddr) -> Option<TcpSocket> {
+        let len = range.len();
+        let start = self.0.next.fetch_add(1, Ordering::Relaxed);
+        (0..len).find_map(|offset| {
+            let port = *range.start() + ((start + offset) % len) as u16;
+            let addr = SocketAddr::new(local_addr, port);
+            // With SO_REUSEPORT the bind succeeds while another transfer listens on the port, so
+            // check with a socket without it that the port is free.
+            if self.0.tcp.reuse_port {
+                TcpOptions::default().bind(addr).ok()?;
+            }
+            self.0.tcp.bind(addr).ok()
+        })
+    }
+
+    fn acquire(&self) -> PassivePort {
+        let in_use = self.0.in_use.fetch_add(1, Ordering::Relaxed) + 1;
+        crate::metrics::PASSIVE_PORTS_IN_USE.set(in_use as i64);
+        PassivePort(self.clone())
+    }
+}
+
+/// A passive port held by a session, until its next PASV or its end.
+#[derive(Debug)]
+struct PassivePort(PassivePorts);
+
+impl Drop for PassivePort {
+    fn drop(&mut self) {
+        let in_use = self.0.0.in_use.fetch_sub(1, Ordering::Relaxed) - 1;
+        crate::metrics::PASSIVE_PORTS_IN_USE.set(in_use as i64);
+    }
+}
+
+/// Binds the data connections of a session, see [`PassivePorts`]. libunftp hands it to a single
+/// session, so every connection needs a server built with its own. EPSV doesn't use it, those data
+/// connections take a port of the range from libunftp and go uncounted.
+#[derive(Debug)]
+pub struct PassiveBinder {
+    ports: PassivePorts,
+    port: Option<PassivePort>,
+}
+
+#[async_trait]
+impl Binder for PassiveBinder {
+    async fn bind(
+        &mut self,
+        local_addr: IpAddr,
+        _passive_ports: RangeInclusive<u16>,
+    ) -> io::Result<TcpSocket> {
+        // A session transfers one file at a time, so the data connection of its previous PASV is
+        // done.
+        self.port = None;
+        let (socket, port) = self.ports.bind(local_addr)?;
+        self.port = Some(port);
+        Ok(socket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn exhausted_range_falls_back_to_the_overflow() {
+        let ports =
+            PassivePorts::new(42122..=42123, Some(42124..=42124), TcpOptions::default()).unwrap();
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        let mut binders: Vec<_> = (0..4).map(|_| ports.binder()).collect();
+        let mut listeners = Vec::new();
+        for binder in &mut binders[..3] {
+            let socket = binder.bind(localhost, 0..=0).await.unwrap();
+            listeners.push(socket.listen(1).unwrap());
+        }
+        assert_eq!(listeners[2].local_addr().unwrap().port(), 42124);
+        assert!(binders[3].bind(localhost, 0..=0).await.is_err());
+
+        drop(listeners.remove(0));
+        let socket = binders[3].bind(localhost, 0..=0).await.unwrap();
+        assert!(ports.0.range.contains(&socket.local_addr().unwrap().port()));
+
+        assert!(
+            PassivePorts::new(42122..=42123, Some(42123..=42130), TcpOptions::default()).is_err()
+        );
+    }
+}
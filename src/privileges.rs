@@ -0,0 +1,60 @@
+use std::fmt;
+
+/// Error switching to the unprivileged service account.
+#[derive(Debug)]
+pub struct PrivilegeDropError(String);
+
+impl fmt::Display for PrivilegeDropError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PrivilegeDropError {}
+
+/// Switch to `user` (and `group`, or the user's primary group) while keeping the capability to
+/// bind ports below 1024, so the listener can still be (re)bound to port 21.
+///
+/// Capabilities and credentials are per thread on Linux, so this has to run before any other
+/// threads such as the tokio runtime's are started.
+#[cfg(target_os = "linux")]
+pub fn drop_privileges(user: &str, group: Option<&str>) -> Result<(), PrivilegeDropError> {
+    use caps::{CapSet, Capability, CapsHashSet};
+    use nix::unistd::{Group, User, setgid, setgroups, setuid};
+
+    let err = |context: &str, e: &dyn fmt::Display| PrivilegeDropError(format!("{context}: {e}"));
+
+    let account = User::from_name(user)
+        .map_err(|e| err("Failed to look up user", &e))?
+        .ok_or_else(|| PrivilegeDropError(format!("Unknown user '{user}'")))?;
+    let gid = match group {
+        Some(group) => {
+            Group::from_name(group)
+                .map_err(|e| err("Failed to look up group", &e))?
+                .ok_or_else(|| PrivilegeDropError(format!("Unknown group '{group}'")))?
+                .gid
+        }
+        None => account.gid,
+    };
+
+    caps::securebits::set_keepcaps(true).map_err(|e| err("Failed to keep capabilities", &e))?;
+    setgroups(&[gid]).map_err(|e| err("Failed to set supplementary groups", &e))?;
+    setgid(gid).map_err(|e| err("Failed to set group", &e))?;
+    setuid(account.uid).map_err(|e| err("Failed to set user", &e))?;
+
+    let bind_only = CapsHashSet::from([Capability::CAP_NET_BIND_SERVICE]);
+    caps::set(None, CapSet::Permitted, &bind_only)
+        .map_err(|e| err("Failed to drop capabilities", &e))?;
+    caps::set(None, CapSet::Effective, &bind_only)
+        .map_err(|e| err("Failed to drop capabilities", &e))?;
+    caps::clear(None, CapSet::Inheritable).map_err(|e| err("Failed to drop capabilities", &e))?;
+    caps::securebits::set_keepcaps(false).map_err(|e| err("Failed to reset keepcaps", &e))?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_privileges(_user: &str, _group: Option<&str>) -> Result<(), PrivilegeDropError> {
+    Err(PrivilegeDropError(
+        "Dropping privileges is only supported on Linux".to_string(),
+    ))
+}
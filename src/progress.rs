@@ -0,0 +1,107 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use log::info;
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// How often progress of a large upload is logged.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Logs the progress of a transfer periodically once more than `threshold` bytes were read.
+///
+/// FTP doesn't announce the size of an upload, so there is no ETA.
+pub struct ProgressReader<R> {
+    inner: R,
+    name: String,
+    threshold: u64,
+    bytes: u64,
+    started: Instant,
+    last_report: Instant,
+    /// Shares the bytes read so far, e.g. with the listing of sessions.
+    counter: Option<Arc<AtomicU64>>,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, name: String, threshold: u64, started: Instant) -> Self {
+        Self {
+            inner,
+            name,
+            threshold,
+            bytes: 0,
+            started,
+            last_report: started,
+            counter: None,
+        }
+    }
+
+    pub fn with_counter(mut self, counter: Option<Arc<AtomicU64>>) -> Self {
+        self.counter = counter;
+        self
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ProgressReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = (buf.filled().len() - before) as u64;
+            self.bytes += read;
+            if let Some(counter) = &self.counter {
+                counter.fetch_add(read, Ordering::Relaxed);
+            }
+            if self.bytes > self.threshold && self.last_report.elapsed() >= REPORT_INTERVAL {
+                self.last_report = Instant::now();
+                info!(
+                    "Receiving {}: {} bytes so far at {}",
+                    self.name,
+                    self.bytes,
+                    format_rate(self.bytes, self.started.elapsed())
+                );
+            }
+        }
+        poll
+    }
+}
+
+/// Human readable transfer rate, e.g. `1.5 MB/s`.
+pub fn format_rate(bytes: u64, elapsed: Duration) -> String {
+    let per_second = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+    if per_second >= 1_000_000.0 {
+        format!("{:.1} MB/s", per_second / 1_000_000.0)
+    } else if per_second >= 1_000.0 {
+        format!("{:.1} kB/s", per_second / 1_000.0)
+    } else {
+        format!("{per_second:.0} B/s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn formats_rates() {
+        assert_eq!(format_rate(1_500_000, Duration::from_secs(1)), "1.5 MB/s");
+        assert_eq!(format_rate(30_000, Duration::from_secs(2)), "15.0 kB/s");
+        assert_eq!(format_rate(500, Duration::from_secs(1)), "500 B/s");
+    }
+
+    #[tokio::test]
+    async fn passes_data_through() {
+        let mut reader =
+            ProgressReader::new(&b"scan"[..], "scan.pdf".to_string(), 0, Instant::now());
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"scan");
+        assert_eq!(reader.bytes, 4);
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use data_encoding::BASE64URL_NOPAD;
+use log::{debug, warn};
+use reqwest::Client;
+use tokio::time::sleep;
+
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pushes all metrics to a Prometheus Pushgateway, for deployments that can't be scraped.
+#[derive(Debug, Clone)]
+pub struct Pushgateway {
+    url: String,
+    client: Client,
+}
+
+impl Pushgateway {
+    /// Push to the gateway at `base_url`, grouped by `job` and, if given, `instance`.
+    pub fn new(base_url: &str, job: &str, instance: Option<&str>) -> Self {
+        Self {
+            url: grouping_url(base_url, job, instance),
+            client: Client::builder()
+                .timeout(PUSH_TIMEOUT)
+                .build()
+                .expect("failed to build Pushgateway HTTP client"),
+        }
+    }
+
+    /// Replace the metrics of this group with the current ones.
+    pub async fn push(&self) -> Result<(), reqwest::Error> {
+        self.client
+            .put(&self.url)
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(crate::metrics::render())
+            .send()
+            .await?
+            .error_for_status()?;
+        debug!("Pushed metrics to {}", self.url);
+        Ok(())
+    }
+
+    pub async fn push_loop(self, interval: Duration) {
+        loop {
+            sleep(interval).await;
+            if let Err(e) = self.push().await {
+                warn!("Failed to push metrics to the Pushgateway: {e}");
+            }
+        }
+    }
+}
+
+/// Labels are base64 encoded in the URL so they may contain any character, including `/`.
+fn grouping_url(base_url: &str, job: &str, instance: Option<&str>) -> String {
+    let mut url = format!(
+        "{}/metrics/job@base64/{}",
+        base_url.trim_end_matches('/'),
+        BASE64URL_NOPAD.encode(job.as_bytes())
+    );
+    if let Some(instance) = instance {
+        url.push_str("/instance@base64/");
+        url.push_str(&BASE64URL_NOPAD.encode(instance.as_bytes()));
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_grouping_labels() {
+        assert_eq!(
+            grouping_url("http://gateway:9091/", "bridge", Some("office/1")),
+            "http://gateway:9091/metrics/job@base64/YnJpZGdl/instance@base64/b2ZmaWNlLzE"
+        );
+        assert_eq!(
+            grouping_url("http://gateway:9091", "bridge", None),
+            "http://gateway:9091/metrics/job@base64/YnJpZGdl"
+        );
+    }
+}
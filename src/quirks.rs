@@ -0,0 +1,78 @@
+use clap::ValueEnum;
+
+/// Suffixes scanners use for files that are renamed to their final name after the upload.
+const TEMP_SUFFIXES: &[&str] = &[".tmp", ".part", ".partial", ".temp"];
+
+/// Workarounds for scanner firmwares that don't behave like regular FTP clients.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// Accept logins that send an empty password.
+    pub empty_password: bool,
+    /// Pretend MKD succeeded for scanners that create a folder before every upload.
+    pub accept_mkd: bool,
+    /// Accept uploads under a temporary name that is renamed afterwards, uploading right away
+    /// under the final name.
+    pub temp_rename: bool,
+    /// Only advertise basic FEAT entries.
+    pub minimal_features: bool,
+    /// Rebuild the missing or empty cross-reference table of PDFs before uploading them.
+    pub repair_pdf: bool,
+}
+
+/// Named bundles of quirks for scanner families with known interop problems.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum QuirksProfile {
+    /// Brother ADS document scanners, which create dated folders and misparse FEAT
+    BrotherAds,
+    /// Epson WorkForce multifunction printers, which may send no password and upload under a
+    /// temporary name
+    EpsonWf,
+    /// Every known workaround
+    Lenient,
+}
+
+impl QuirksProfile {
+    pub fn quirks(self) -> Quirks {
+        match self {
+            QuirksProfile::BrotherAds => Quirks {
+                accept_mkd: true,
+                minimal_features: true,
+                ..Quirks::default()
+            },
+            QuirksProfile::EpsonWf => Quirks {
+                empty_password: true,
+                temp_rename: true,
+                ..Quirks::default()
+            },
+            QuirksProfile::Lenient => Quirks {
+                empty_password: true,
+                accept_mkd: true,
+                temp_rename: true,
+                minimal_features: true,
+                repair_pdf: true,
+            },
+        }
+    }
+}
+
+/// Remove a temporary upload suffix such as `.tmp` from a filename.
+pub fn strip_temp_suffix(name: &str) -> Option<&str> {
+    TEMP_SUFFIXES.iter().find_map(|suffix| {
+        let split = name.len().checked_sub(suffix.len())?;
+        (split > 0 && name.is_char_boundary(split) && name[split..].eq_ignore_ascii_case(suffix))
+            .then(|| &name[..split])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_temp_suffixes() {
+        assert_eq!(strip_temp_suffix("scan.pdf.tmp"), Some("scan.pdf"));
+        assert_eq!(strip_temp_suffix("SCAN0001.PART"), Some("SCAN0001"));
+        assert_eq!(strip_temp_suffix("scan.pdf"), None);
+        assert_eq!(strip_temp_suffix(".tmp"), None);
+    }
+}
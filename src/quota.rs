@@ -0,0 +1,198 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Upload limits of a user, counted over the last hour or 24 hours.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct UploadLimits {
+    pub max_uploads_per_hour: Option<usize>,
+    pub max_uploads_per_day: Option<usize>,
+    pub max_bytes_per_day: Option<u64>,
+}
+
+impl UploadLimits {
+    fn is_unlimited(&self) -> bool {
+        *self == UploadLimits::default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaExceeded {
+    UploadsPerHour,
+    UploadsPerDay,
+    BytesPerDay,
+}
+
+impl QuotaExceeded {
+    /// Label used in metrics.
+    pub fn label(self) -> &'static str {
+        match self {
+            QuotaExceeded::UploadsPerHour => "uploads_per_hour",
+            QuotaExceeded::UploadsPerDay => "uploads_per_day",
+            QuotaExceeded::BytesPerDay => "bytes_per_day",
+        }
+    }
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaExceeded::UploadsPerHour => write!(f, "Hourly upload limit reached"),
+            QuotaExceeded::UploadsPerDay => write!(f, "Daily upload limit reached"),
+            QuotaExceeded::BytesPerDay => write!(f, "Daily upload volume exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Time and size of the uploads of each user.
+type UploadLog = HashMap<String, VecDeque<(Instant, u64)>>;
+
+/// Uploads of every user within the last day, shared by all FTP sessions.
+#[derive(Clone, Debug, Default)]
+pub struct QuotaTracker {
+    uploads: Arc<Mutex<UploadLog>>,
+}
+
+impl QuotaTracker {
+    /// Check whether the user may start another upload.
+    pub fn check(&self, username: &str, limits: &UploadLimits) -> Result<(), QuotaExceeded> {
+        self.check_at(username, limits, 0, Instant::now())
+    }
+
+    /// Count a received upload of `bytes` against the user's limits, unless that would exceed
+    /// them.
+    pub fn record(
+        &self,
+        username: &str,
+        limits: &UploadLimits,
+        bytes: u64,
+    ) -> Result<(), QuotaExceeded> {
+        self.record_at(username, limits, bytes, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        username: &str,
+        limits: &UploadLimits,
+        bytes: u64,
+        now: Instant,
+    ) -> Result<(), QuotaExceeded> {
+        if limits.is_unlimited() {
+            return Ok(());
+        }
+        let mut uploads = self.uploads.lock().expect("quota lock poisoned");
+        let (uploads_last_hour, uploads_today, bytes_today) = match uploads.get_mut(username) {
+            Some(uploads) => {
+                while uploads
+                    .front()
+                    .is_some_and(|(at, _)| now.duration_since(*at) >= DAY)
+                {
+                    uploads.pop_front();
+                }
+                (
+                    uploads
+                        .iter()
+                        .filter(|(at, _)| now.duration_since(*at) < HOUR)
+                        .count(),
+                    uploads.len(),
+                    uploads.iter().map(|(_, bytes)| bytes).sum::<u64>(),
+                )
+            }
+            None => (0, 0, 0),
+        };
+
+        if let Some(max) = limits.max_uploads_per_hour
+            && uploads_last_hour >= max
+        {
+            return Err(QuotaExceeded::UploadsPerHour);
+        }
+        if let Some(max) = limits.max_uploads_per_day
+            && uploads_today >= max
+        {
+            return Err(QuotaExceeded::UploadsPerDay);
+        }
+        if let Some(max) = limits.max_bytes_per_day
+            && bytes_today + bytes > max
+        {
+            return Err(QuotaExceeded::BytesPerDay);
+        }
+        Ok(())
+    }
+
+    fn record_at(
+        &self,
+        username: &str,
+        limits: &UploadLimits,
+        bytes: u64,
+        now: Instant,
+    ) -> Result<(), QuotaExceeded> {
+        if limits.is_unlimited() {
+            return Ok(());
+        }
+        self.check_at(username, limits, bytes, now)?;
+        self.uploads
+            .lock()
+            .expect("quota lock poisoned")
+            .entry(username.to_string())
+            .or_default()
+            .push_back((now, bytes));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_uploads_per_hour_and_day() {
+        let tracker = QuotaTracker::default();
+        let limits = UploadLimits {
+            max_uploads_per_hour: Some(2),
+            max_uploads_per_day: Some(3),
+            ..Default::default()
+        };
+        let start = Instant::now();
+
+        assert!(tracker.record_at("scanner", &limits, 10, start).is_ok());
+        assert!(tracker.record_at("scanner", &limits, 10, start).is_ok());
+        assert_eq!(
+            tracker.check_at("scanner", &limits, 0, start),
+            Err(QuotaExceeded::UploadsPerHour)
+        );
+        assert!(tracker.check_at("other", &limits, 0, start).is_ok());
+
+        let later = start + HOUR;
+        assert!(tracker.record_at("scanner", &limits, 10, later).is_ok());
+        assert_eq!(
+            tracker.check_at("scanner", &limits, 0, later),
+            Err(QuotaExceeded::UploadsPerDay)
+        );
+        assert!(tracker.check_at("scanner", &limits, 0, start + DAY).is_ok());
+    }
+
+    #[test]
+    fn limits_bytes_per_day() {
+        let tracker = QuotaTracker::default();
+        let limits = UploadLimits {
+            max_bytes_per_day: Some(100),
+            ..Default::default()
+        };
+        let now = Instant::now();
+
+        assert!(tracker.record_at("scanner", &limits, 60, now).is_ok());
+        assert_eq!(
+            tracker.record_at("scanner", &limits, 60, now),
+            Err(QuotaExceeded::BytesPerDay)
+        );
+        assert!(tracker.record_at("scanner", &limits, 40, now).is_ok());
+    }
+}
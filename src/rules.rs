@@ -0,0 +1,265 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::metadata::{DocumentMetadata, ObjectRef};
+
+/// Assigns metadata to uploads whose filename or text contains a keyword.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Matched case-insensitively; any of them matches.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Sender addresses or domains like `@acme.com` of mails fetched via IMAP, matched
+    /// case-insensitively against the end of the address.
+    #[serde(default)]
+    pub senders: Vec<String>,
+    /// Also look for the keywords in the text layer of PDFs, not only in the filename.
+    #[serde(default)]
+    pub match_text: bool,
+    /// Prefixes of QR codes on the first page of scans, matched case-insensitively.
+    #[serde(default)]
+    pub barcodes: Vec<String>,
+    pub document_type: Option<ObjectRef>,
+    pub correspondent: Option<ObjectRef>,
+    #[serde(default)]
+    pub tags: Vec<ObjectRef>,
+}
+
+impl Rule {
+    fn matches(&self, filename: &str, text: Option<&str>) -> bool {
+        let filename = filename.to_lowercase();
+        self.keywords.iter().any(|keyword| {
+            let keyword = keyword.to_lowercase();
+            filename.contains(&keyword)
+                || (self.match_text && text.is_some_and(|text| text.contains(&keyword)))
+        })
+    }
+
+    fn matches_barcodes(&self, barcodes: &[String]) -> bool {
+        self.barcodes.iter().any(|prefix| {
+            let prefix = prefix.to_lowercase();
+            barcodes
+                .iter()
+                .any(|code| code.to_lowercase().starts_with(&prefix))
+        })
+    }
+
+    #[cfg(feature = "imap")]
+    fn matches_mail(&self, sender: &str, subject: &str) -> bool {
+        let sender = sender.to_lowercase();
+        let subject = subject.to_lowercase();
+        self.senders
+            .iter()
+            .any(|expected| sender.ends_with(&expected.to_lowercase()))
+            || self
+                .keywords
+                .iter()
+                .any(|keyword| subject.contains(&keyword.to_lowercase()))
+    }
+}
+
+/// Rules loaded from the routing rules file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct RulesFile {
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+pub enum RulesFileError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+}
+
+impl std::fmt::Display for RulesFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RulesFileError::Io(e) => write!(f, "{e}"),
+            RulesFileError::Parse(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for RulesFileError {}
+
+impl RulesFile {
+    pub fn load(path: &Path) -> Result<Self, RulesFileError> {
+        let content = std::fs::read_to_string(path).map_err(RulesFileError::Io)?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, RulesFileError> {
+        toml::from_str(content).map_err(RulesFileError::Parse)
+    }
+
+    /// Whether any rule needs the text of the uploaded documents.
+    pub fn needs_text(&self) -> bool {
+        self.rules.iter().any(|rule| rule.match_text)
+    }
+
+    /// Metadata of all rules matching an upload. The first matching rule that sets the document
+    /// type or correspondent wins, tags are combined. `text` has to be lowercase.
+    pub fn evaluate(&self, filename: &str, text: Option<&str>) -> DocumentMetadata {
+        combine(
+            self.rules
+                .iter()
+                .filter(|rule| rule.matches(filename, text)),
+        )
+    }
+
+    /// Whether any rule looks for barcodes on the first page of scans.
+    pub fn needs_barcodes(&self) -> bool {
+        self.rules.iter().any(|rule| !rule.barcodes.is_empty())
+    }
+
+    /// Like [`Self::evaluate`], also matching rules by the barcodes on the first page of a scan.
+    pub fn evaluate_scan(
+        &self,
+        filename: &str,
+        text: Option<&str>,
+        barcodes: &[String],
+    ) -> DocumentMetadata {
+        combine(
+            self.rules
+                .iter()
+                .filter(|rule| rule.matches(filename, text) || rule.matches_barcodes(barcodes)),
+        )
+    }
+
+    /// Metadata of all rules matching a mail by its sender or by a keyword in its subject.
+    #[cfg(feature = "imap")]
+    pub fn evaluate_mail(&self, sender: &str, subject: &str) -> DocumentMetadata {
+        combine(
+            self.rules
+                .iter()
+                .filter(|rule| rule.matches_mail(sender, subject)),
+        )
+    }
+}
+
+fn combine<'a>(rules: impl Iterator<Item = &'a Rule>) -> DocumentMetadata {
+    let mut metadata = DocumentMetadata::default();
+    for rule in rules {
+        if metadata.document_type.is_none() {
+            metadata.document_type = rule.document_type.clone();
+        }
+        if metadata.correspondent.is_none() {
+            metadata.correspondent = rule.correspondent.clone();
+        }
+        for tag in &rule.tags {
+            if !metadata.tags.contains(tag) {
+                metadata.tags.push(tag.clone());
+            }
+        }
+    }
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+        [[rules]]
+        keywords = ["Rechnung", "invoice"]
+        document_type = "Invoice"
+        tags = ["finance"]
+
+        [[rules]]
+        keywords = ["contract", "Vertrag"]
+        match_text = true
+        document_type = 2
+        tags = ["finance", 5]
+    "#;
+
+    #[test]
+    fn matches_keywords_in_filenames() {
+        let rules = RulesFile::parse(RULES).unwrap();
+        let metadata = rules.evaluate("Rechnung_2024-03.pdf", None);
+        assert_eq!(
+            metadata.document_type,
+            Some(ObjectRef::Name("Invoice".to_string()))
+        );
+        assert_eq!(metadata.tags, vec![ObjectRef::Name("finance".to_string())]);
+
+        assert_eq!(
+            rules.evaluate("scan_0001.pdf", None),
+            DocumentMetadata::default()
+        );
+    }
+
+    #[test]
+    fn matches_keywords_in_text_if_enabled() {
+        let rules = RulesFile::parse(RULES).unwrap();
+        assert!(rules.needs_text());
+        // The first rule only looks at filenames.
+        let metadata = rules.evaluate("scan.pdf", Some("rechnung und vertrag"));
+        assert_eq!(metadata.document_type, Some(ObjectRef::Id(2)));
+        assert_eq!(
+            metadata.tags,
+            vec![ObjectRef::Name("finance".to_string()), ObjectRef::Id(5)]
+        );
+    }
+
+    #[test]
+    fn matches_barcodes_by_prefix() {
+        let rules = RulesFile::parse(
+            r#"
+            [[rules]]
+            barcodes = ["INV-"]
+            document_type = "Invoice"
+            "#,
+        )
+        .unwrap();
+        assert!(rules.needs_barcodes());
+        let metadata = rules.evaluate_scan("scan.pdf", None, &["inv-2024-17".to_string()]);
+        assert_eq!(
+            metadata.document_type,
+            Some(ObjectRef::Name("Invoice".to_string()))
+        );
+        assert_eq!(
+            rules.evaluate_scan("scan.pdf", None, &["ASN00042".to_string()]),
+            DocumentMetadata::default()
+        );
+        assert!(!RulesFile::parse(RULES).unwrap().needs_barcodes());
+    }
+
+    #[cfg(feature = "imap")]
+    #[test]
+    fn matches_mails_by_sender_and_subject() {
+        let rules = RulesFile::parse(
+            r#"
+            [[rules]]
+            senders = ["@ACME.com"]
+            correspondent = "ACME"
+
+            [[rules]]
+            keywords = ["invoice"]
+            tags = ["finance"]
+            "#,
+        )
+        .unwrap();
+        let metadata = rules.evaluate_mail("billing@acme.com", "Your invoice 42");
+        assert_eq!(
+            metadata.correspondent,
+            Some(ObjectRef::Name("ACME".to_string()))
+        );
+        assert_eq!(metadata.tags, vec![ObjectRef::Name("finance".to_string())]);
+        assert_eq!(
+            rules.evaluate_mail("me@example.com", "Scan"),
+            DocumentMetadata::default()
+        );
+        // Senders only match mails.
+        assert_eq!(
+            rules.evaluate("acme.com.pdf", None),
+            DocumentMetadata::default()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(RulesFile::parse("[[rules]]\nkeywords = [\"x\"]\ntag = [1]\n").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Error restricting the process.
+#[derive(Debug)]
+pub struct SandboxError(String);
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for SandboxError {}
+
+/// Paths the bridge needs after startup.
+#[derive(Debug, Default)]
+pub struct SandboxPaths {
+    /// Staging, spool and ACME state directories.
+    pub writable: Vec<PathBuf>,
+    /// Users file, certificates and databases, given as the file or its directory.
+    pub readable: Vec<PathBuf>,
+}
+
+/// Syscalls the bridge never needs, which are denied to limit what an exploited parsing bug
+/// could do.
+#[cfg(target_os = "linux")]
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_personality,
+    libc::SYS_acct,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+];
+
+/// Restrict filesystem access to `paths` and system files with Landlock, and deny dangerous
+/// syscalls with seccomp. Landlock applies to the calling thread and threads started later, so
+/// this has to run before the tokio runtime starts.
+#[cfg(target_os = "linux")]
+pub fn restrict(paths: &SandboxPaths) -> Result<(), SandboxError> {
+    use landlock::{
+        ABI, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus,
+        path_beneath_rules,
+    };
+    use log::{info, warn};
+
+    let err = |context: &str, e: &dyn fmt::Display| SandboxError(format!("{context}: {e}"));
+    let existing = |paths: &[PathBuf]| -> Vec<PathBuf> {
+        paths.iter().filter(|p| p.exists()).cloned().collect()
+    };
+
+    let abi = ABI::V2;
+    let system_paths: Vec<PathBuf> = ["/etc", "/usr", "/lib", "/lib64", "/bin", "/proc/self"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))
+        .and_then(|ruleset| ruleset.create())
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(
+                existing(&system_paths),
+                AccessFs::from_read(abi),
+            ))
+        })
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(
+                existing(&paths.readable),
+                AccessFs::from_read(abi),
+            ))
+        })
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(
+                existing(&paths.writable),
+                AccessFs::from_all(abi),
+            ))
+        })
+        .and_then(|ruleset| ruleset.restrict_self())
+        .map_err(|e| err("Failed to apply Landlock rules", &e))?;
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => info!("Filesystem access restricted with Landlock"),
+        RulesetStatus::PartiallyEnforced => {
+            warn!("Filesystem access only partially restricted, the kernel lacks Landlock features")
+        }
+        RulesetStatus::NotEnforced => {
+            warn!("Filesystem access not restricted, the kernel doesn't support Landlock")
+        }
+    }
+
+    apply_seccomp().map_err(|e| err("Failed to apply seccomp filter", &e))?;
+    info!(
+        "Denied {} unneeded syscalls with seccomp",
+        DENIED_SYSCALLS.len()
+    );
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn apply_seccomp() -> Result<(), Box<dyn std::error::Error>> {
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+
+    let rules = DENIED_SYSCALLS
+        .iter()
+        .map(|syscall| (*syscall, Vec::new()))
+        .collect();
+    let filter: BpfProgram = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        std::env::consts::ARCH.try_into()?,
+    )?
+    .try_into()?;
+    seccompiler::apply_filter_all_threads(&filter)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn restrict(_paths: &SandboxPaths) -> Result<(), SandboxError> {
+    Err(SandboxError(
+        "Sandboxing is only supported on Linux".to_string(),
+    ))
+}
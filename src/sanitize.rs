@@ -0,0 +1,134 @@
+/// Characters that are replaced because they separate paths or are invalid on common filesystems.
+const REPLACED_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Extensions longer than this are treated as part of the name when truncating.
+const MAX_EXTENSION_LENGTH: usize = 10;
+
+/// Name used when nothing is left of the client's filename after sanitizing.
+const FALLBACK_NAME: &str = "scan";
+
+/// Rules applied to client-supplied filenames before they are used for the staging file and sent
+/// to Paperless.
+#[derive(Clone, Debug)]
+pub struct FilenamePolicy {
+    /// Maximum length of the sanitized name in bytes, including the extension.
+    pub max_length: usize,
+    /// Replace German umlauts and ß with their ASCII spelling (ä -> ae).
+    pub transliterate: bool,
+}
+
+impl Default for FilenamePolicy {
+    fn default() -> Self {
+        Self {
+            max_length: 200,
+            transliterate: false,
+        }
+    }
+}
+
+impl FilenamePolicy {
+    pub fn sanitize(&self, name: &str) -> String {
+        let mut sanitized = String::with_capacity(name.len());
+        for c in name.chars() {
+            match c {
+                c if c.is_control() => {}
+                c if REPLACED_CHARS.contains(&c) => sanitized.push('_'),
+                c if self.transliterate => match transliterate(c) {
+                    Some(replacement) => sanitized.push_str(replacement),
+                    None => sanitized.push(c),
+                },
+                c => sanitized.push(c),
+            }
+        }
+
+        let sanitized = sanitized.trim_matches(|c: char| c.is_whitespace() || c == '.');
+        if sanitized.is_empty() {
+            return FALLBACK_NAME.to_string();
+        }
+
+        truncate(sanitized, self.max_length)
+    }
+}
+
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'ä' => "ae",
+        'ö' => "oe",
+        'ü' => "ue",
+        'Ä' => "Ae",
+        'Ö' => "Oe",
+        'Ü' => "Ue",
+        'ß' => "ss",
+        _ => return None,
+    })
+}
+
+/// Shorten `name` to at most `max_length` bytes, keeping the extension intact.
+fn truncate(name: &str, max_length: usize) -> String {
+    if name.len() <= max_length {
+        return name.to_string();
+    }
+
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension))
+            if !stem.is_empty()
+                && extension.len() <= MAX_EXTENSION_LENGTH
+                && extension.len() < max_length =>
+        {
+            (stem, Some(extension))
+        }
+        _ => (name, None),
+    };
+
+    let stem_budget = match extension {
+        Some(extension) => max_length - extension.len() - 1,
+        None => max_length,
+    };
+    let mut end = stem_budget.min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    match extension {
+        Some(extension) => format!("{}.{extension}", &stem[..end]),
+        None => stem[..end].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_control_chars_and_separators() {
+        let policy = FilenamePolicy::default();
+        assert_eq!(policy.sanitize("scan\u{0}\t01.pdf"), "scan01.pdf");
+        assert_eq!(policy.sanitize("../etc/passwd"), "_etc_passwd");
+        assert_eq!(policy.sanitize("a\\b:c?.pdf"), "a_b_c_.pdf");
+    }
+
+    #[test]
+    fn empty_names_fall_back() {
+        let policy = FilenamePolicy::default();
+        assert_eq!(policy.sanitize(" .. "), "scan");
+    }
+
+    #[test]
+    fn transliteration_is_optional() {
+        let mut policy = FilenamePolicy::default();
+        assert_eq!(policy.sanitize("Größe.pdf"), "Größe.pdf");
+        policy.transliterate = true;
+        assert_eq!(policy.sanitize("Größe.pdf"), "Groesse.pdf");
+    }
+
+    #[test]
+    fn truncation_keeps_extension_and_char_boundaries() {
+        let policy = FilenamePolicy {
+            max_length: 10,
+            transliterate: false,
+        };
+        assert_eq!(policy.sanitize("abcdefghijkl.pdf"), "abcdef.pdf");
+        assert_eq!(policy.sanitize("ääääää.pdf"), "äää.pdf");
+        assert_eq!(policy.sanitize("abcdefghijkl"), "abcdefghij");
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use serde::Deserialize;
+
+/// Days and time of day during which a user may log in and upload, e.g. `Mon-Fri 08:00-18:00`,
+/// `Sat 09:00-12:00` or `07:00-19:00` (every day). Windows ending before they start span
+/// midnight.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeWindow {
+    /// Allowed days, indexed from Monday.
+    days: [bool; 7],
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TryFrom<String> for TimeWindow {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid =
+            || format!("Invalid time window '{value}', expected e.g. 'Mon-Fri 08:00-18:00'");
+        let (days, times) = match value.trim().split_once(' ') {
+            Some((days, times)) => (parse_days(days).ok_or_else(invalid)?, times.trim()),
+            None => ([true; 7], value.trim()),
+        };
+        let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+        let parse_time = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").ok();
+        Ok(TimeWindow {
+            days,
+            start: parse_time(start).ok_or_else(invalid)?,
+            end: parse_time(end).ok_or_else(invalid)?,
+        })
+    }
+}
+
+/// Parse `Mon-Fri`, `Sat` or `Mon,Wed,Fri`.
+fn parse_days(days: &str) -> Option<[bool; 7]> {
+    let mut allowed = [false; 7];
+    for part in days.split(',') {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let first = first.trim().parse::<Weekday>().ok()?.num_days_from_monday();
+        let last = last.trim().parse::<Weekday>().ok()?.num_days_from_monday();
+        let mut day = first;
+        loop {
+            allowed[day as usize] = true;
+            if day == last {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Some(allowed)
+}
+
+impl TimeWindow {
+    pub fn contains(&self, at: NaiveDateTime) -> bool {
+        let time = at.time();
+        let today = at.weekday().num_days_from_monday() as usize;
+        if self.start <= self.end {
+            self.days[today] && time >= self.start && time < self.end
+        } else {
+            // Spans midnight, so the early hours belong to the window opened the day before.
+            let yesterday = (today + 6) % 7;
+            (self.days[today] && time >= self.start) || (self.days[yesterday] && time < self.end)
+        }
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// Whether `at` lies in one of `windows`. No windows means no restriction.
+pub fn is_allowed(windows: &[TimeWindow], at: NaiveDateTime) -> bool {
+    windows.is_empty() || windows.iter().any(|window| window.contains(at))
+}
+
+/// Like [`is_allowed`] for the current local time.
+pub fn is_allowed_now(windows: &[TimeWindow]) -> bool {
+    is_allowed(windows, chrono::Local::now().naive_local())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn office_hours() {
+        let window = TimeWindow::try_from("Mon-Fri 08:00-18:00".to_string()).unwrap();
+        // 2026-10-16 is a Friday
+        assert!(window.contains(at("2026-10-16 08:00")));
+        assert!(!window.contains(at("2026-10-16 18:00")));
+        assert!(!window.contains(at("2026-10-17 10:00")));
+    }
+
+    #[test]
+    fn windows_can_span_midnight() {
+        let window = TimeWindow::try_from("Fri 22:00-06:00".to_string()).unwrap();
+        assert!(window.contains(at("2026-10-16 23:00")));
+        assert!(window.contains(at("2026-10-17 05:59")));
+        assert!(!window.contains(at("2026-10-16 05:00")));
+    }
+
+    #[test]
+    fn parses_day_lists_and_rejects_garbage() {
+        let window = TimeWindow::try_from("Mon,Wed 09:00-10:00".to_string()).unwrap();
+        assert!(window.contains(at("2026-10-14 09:30")));
+        assert!(!window.contains(at("2026-10-13 09:30")));
+
+        assert!(TimeWindow::try_from("weekdays 09:00-10:00".to_string()).is_err());
+        assert!(TimeWindow::try_from("9-17".to_string()).is_err());
+        assert!(is_allowed(&[], at("2026-10-17 03:00")));
+    }
+}
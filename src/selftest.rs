@@ -0,0 +1,206 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::time::timeout;
+
+use crate::bridge::Bridge;
+use crate::paperless::{DryRunClient, PaperlessApi};
+use crate::users::UserConfig;
+
+type SelftestError = Box<dyn std::error::Error + Send + Sync>;
+
+const SELFTEST_TIMEOUT: Duration = Duration::from_secs(30);
+const USERNAME: &str = "selftest";
+
+/// Start a server with a dry-run Paperless backend on the configured listen address and
+/// passive ports, upload a document to it over FTP and check that it arrived unchanged.
+pub async fn run(listen: &str, passive_ports: RangeInclusive<u16>) -> bool {
+    match timeout(SELFTEST_TIMEOUT, upload_roundtrip(listen, passive_ports)).await {
+        Ok(Ok(())) => {
+            println!("Self-test passed: commands and passive data connections work");
+            true
+        }
+        Ok(Err(e)) => {
+            println!("Self-test failed: {e}");
+            false
+        }
+        Err(_) => {
+            println!(
+                "Self-test failed: no answer within {}s, check the firewall for the passive ports",
+                SELFTEST_TIMEOUT.as_secs()
+            );
+            false
+        }
+    }
+}
+
+async fn upload_roundtrip(
+    listen: &str,
+    passive_ports: RangeInclusive<u16>,
+) -> Result<(), SelftestError> {
+    let listen: SocketAddr = listen.parse()?;
+    // Connect to the listen address, or the loopback address of its family if it listens on all.
+    let host = match listen.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    // A free port, so the self-test works next to a running bridge.
+    let port = std::net::TcpListener::bind((listen.ip(), 0))?
+        .local_addr()?
+        .port();
+    let address = SocketAddr::new(listen.ip(), port);
+
+    let client = Arc::new(DryRunClient::default());
+    let mut secret = [0u8; 16];
+    ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut secret)
+        .map_err(|_| "Failed to generate a password")?;
+    let password = data_encoding::HEXLOWER.encode(&secret);
+    let bridge = Bridge::builder()
+        .listen(address.to_string())
+        .passive_ports(passive_ports)
+        .sink(Arc::clone(&client) as Arc<dyn PaperlessApi>)
+        .user(USERNAME, UserConfig::with_password(password.clone()))
+        .build()?;
+    tokio::spawn(bridge.run());
+    println!("Started test server at {address}");
+
+    let document = crate::document::text_pdf("ftp-paperless-bridge self-test");
+    let mut control = Control::connect(SocketAddr::new(host, port)).await?;
+    control.expect(220).await?;
+    control.command(&format!("USER {USERNAME}"), 331).await?;
+    control.command(&format!("PASS {password}"), 230).await?;
+    println!("Logged in");
+    control.command("TYPE I", 200).await?;
+    let reply = control.command("EPSV", 229).await?;
+    let data_port = parse_epsv(&reply).ok_or_else(|| format!("Invalid EPSV reply: {reply}"))?;
+    let mut data = TcpStream::connect((host, data_port)).await?;
+    println!("Opened passive data connection to port {data_port}");
+    control.send("STOR selftest.pdf").await?;
+    let (code, reply) = control.reply().await?;
+    if code != 150 && code != 125 {
+        return Err(format!("STOR was refused: {reply}").into());
+    }
+    data.write_all(&document).await?;
+    data.shutdown().await?;
+    drop(data);
+    control.expect(226).await?;
+    println!("Uploaded {} bytes", document.len());
+    let _ = control.command("QUIT", 221).await;
+
+    match client.uploads().as_slice() {
+        [received] if *received == document => Ok(()),
+        [received] => Err(format!(
+            "The document was changed in transit ({} of {} bytes arrived)",
+            received.len(),
+            document.len()
+        )
+        .into()),
+        uploads => Err(format!("Expected one document, {} arrived", uploads.len()).into()),
+    }
+}
+
+/// Port from a reply like `229 Entering Extended Passive Mode (|||50001|)`.
+fn parse_epsv(reply: &str) -> Option<u16> {
+    let start = reply.find("(|||")? + 4;
+    let end = start + reply[start..].find('|')?;
+    reply[start..end].parse().ok()
+}
+
+struct Control {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Control {
+    /// Connect to the server, waiting for it to listen, which it starts doing in the background.
+    async fn connect(address: SocketAddr) -> Result<Self, SelftestError> {
+        let stream = loop {
+            match TcpStream::connect(address).await {
+                Ok(stream) => break stream,
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    async fn send(&mut self, command: &str) -> Result<(), SelftestError> {
+        self.writer
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    /// Read a reply, skipping the lines of multi-line replies.
+    async fn reply(&mut self) -> Result<(u16, String), SelftestError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err("Server closed the control connection".into());
+        }
+        let code: u16 = line
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| format!("Invalid reply: {line:?}"))?;
+        if line.as_bytes().get(3) == Some(&b'-') {
+            let last = format!("{code} ");
+            loop {
+                line.clear();
+                if self.reader.read_line(&mut line).await? == 0 {
+                    return Err("Server closed the control connection".into());
+                }
+                if line.starts_with(&last) {
+                    break;
+                }
+            }
+        }
+        Ok((code, line.trim_end().to_string()))
+    }
+
+    async fn expect(&mut self, expected: u16) -> Result<String, SelftestError> {
+        let (code, reply) = self.reply().await?;
+        if code == expected {
+            Ok(reply)
+        } else {
+            Err(format!("Expected reply {expected}, got: {reply}").into())
+        }
+    }
+
+    async fn command(&mut self, command: &str, expected: u16) -> Result<String, SelftestError> {
+        self.send(command).await?;
+        let verb = command.split(' ').next().unwrap_or(command);
+        self.expect(expected)
+            .await
+            .map_err(|e| format!("{verb} failed: {e}").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_epsv_replies() {
+        assert_eq!(
+            parse_epsv("229 Entering Extended Passive Mode (|||50001|)"),
+            Some(50001)
+        );
+        assert_eq!(parse_epsv("229 Entering Passive Mode"), None);
+    }
+
+    #[tokio::test]
+    async fn uploads_over_loopback() {
+        assert!(run("127.0.0.1:0", 49152..=49200).await);
+    }
+}
@@ -0,0 +1,109 @@
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, eyre};
+use log::error;
+use tokio::sync::oneshot;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+use crate::CliArgs;
+
+const SERVICE_NAME: &str = "ftp-paperless-bridge";
+const SERVICE_DISPLAY_NAME: &str = "FTP Paperless Bridge";
+
+/// Arguments handed from `main` to the service entry point, which the dispatcher calls without
+/// context.
+static SERVICE_ARGS: Mutex<Option<CliArgs>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Hand control to the service control manager, which runs the bridge until the service is
+/// stopped.
+pub fn run(args: CliArgs) -> Result<()> {
+    *SERVICE_ARGS.lock().expect("service args lock poisoned") = Some(args);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("Windows service failed: {e}");
+    }
+}
+
+fn run_service() -> Result<()> {
+    let args = SERVICE_ARGS
+        .lock()
+        .expect("service args lock poisoned")
+        .take()
+        .ok_or_else(|| eyre!("Service started twice"))?;
+
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let stop_tx = Mutex::new(Some(stop_tx));
+    let status_handle =
+        service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                if let Some(stop_tx) = stop_tx.lock().expect("stop lock poisoned").take() {
+                    let _ = stop_tx.send(());
+                }
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+
+    let status = |state, controls_accepted| ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(0),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    };
+    status_handle.set_service_status(status(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+    ))?;
+
+    let result = crate::start(args, async {
+        let _ = stop_rx.await;
+    });
+
+    status_handle
+        .set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty()))?;
+    result
+}
+
+/// Register a service that runs this executable with the current arguments.
+pub fn install() -> Result<()> {
+    let manager =
+        ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CREATE_SERVICE)?;
+    let mut launch_arguments: Vec<OsString> = std::env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--install-windows-service")
+        .collect();
+    launch_arguments.push("--windows-service".into());
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: SERVICE_DISPLAY_NAME.into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments,
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    manager.create_service(&info, ServiceAccess::QUERY_STATUS)?;
+    println!("Installed service {SERVICE_NAME}, start it with: sc start {SERVICE_NAME}");
+    Ok(())
+}
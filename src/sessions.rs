@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use crate::auth::User;
+
+/// A session as listed by the `/sessions` endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionInfo {
+    pub id: u64,
+    /// `None` until the client logged in.
+    pub user: Option<String>,
+    pub client_ip: Option<IpAddr>,
+    pub connected_seconds: u64,
+    /// Path of the upload in progress.
+    pub transfer: Option<String>,
+    /// Bytes of the upload in progress received so far.
+    pub bytes: u64,
+    pub transfer_seconds: Option<u64>,
+    pub terminated: bool,
+}
+
+#[derive(Debug, Default)]
+struct Activity {
+    user: Option<String>,
+    client_ip: Option<IpAddr>,
+    transfer: Option<(String, Instant)>,
+}
+
+#[derive(Debug)]
+struct State {
+    connected: Instant,
+    activity: Mutex<Activity>,
+    bytes: Arc<AtomicU64>,
+    terminated: AtomicBool,
+    terminate: Notify,
+}
+
+/// The ID of the last session opened and the open sessions by ID.
+type Registry = (u64, BTreeMap<u64, Arc<State>>);
+
+/// The FTP sessions that are open, so stuck ones can be found and terminated.
+#[derive(Debug, Clone, Default)]
+pub struct Sessions(Arc<Mutex<Registry>>);
+
+impl Sessions {
+    /// Register a new session, which is removed again when the returned handle is dropped.
+    pub fn open(&self) -> Session {
+        let state = Arc::new(State {
+            connected: Instant::now(),
+            activity: Mutex::default(),
+            bytes: Arc::default(),
+            terminated: AtomicBool::new(false),
+            terminate: Notify::new(),
+        });
+        let mut sessions = self.0.lock().expect("sessions lock poisoned");
+        sessions.0 += 1;
+        let id = sessions.0;
+        sessions.1.insert(id, Arc::clone(&state));
+        Session {
+            id,
+            sessions: self.clone(),
+            state,
+        }
+    }
+
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let sessions = self.0.lock().expect("sessions lock poisoned");
+        sessions
+            .1
+            .iter()
+            .map(|(&id, state)| {
+                let activity = state.activity.lock().expect("session lock poisoned");
+                SessionInfo {
+                    id,
+                    user: activity.user.clone(),
+                    client_ip: activity.client_ip,
+                    connected_seconds: state.connected.elapsed().as_secs(),
+                    transfer: activity.transfer.as_ref().map(|(path, _)| path.clone()),
+                    bytes: state.bytes.load(Ordering::Relaxed),
+                    transfer_seconds: activity
+                        .transfer
+                        .as_ref()
+                        .map(|(_, started)| started.elapsed().as_secs()),
+                    terminated: state.terminated.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+
+    /// Abort the transfer of session `id` and reject everything it does from now on. Returns
+    /// whether the session exists.
+    pub fn terminate(&self, id: u64) -> bool {
+        let sessions = self.0.lock().expect("sessions lock poisoned");
+        let Some(state) = sessions.1.get(&id) else {
+            return false;
+        };
+        state.terminated.store(true, Ordering::Relaxed);
+        state.terminate.notify_waiters();
+        true
+    }
+}
+
+/// An open FTP session, see [`Sessions::open`].
+#[derive(Debug)]
+pub struct Session {
+    id: u64,
+    sessions: Sessions,
+    state: Arc<State>,
+}
+
+impl Session {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Record the logged-in user of the session.
+    pub fn identify(&self, user: &User) {
+        let mut activity = self.state.activity.lock().expect("session lock poisoned");
+        if activity.user.is_none() {
+            activity.user = Some(user.username.clone());
+            activity.client_ip = user.client_ip;
+        }
+    }
+
+    /// Record the upload of `path` as in progress until the returned guard is dropped. The
+    /// counter is to be increased by the bytes received.
+    pub fn start_transfer(&self, path: &Path) -> Transfer<'_> {
+        self.state.bytes.store(0, Ordering::Relaxed);
+        self.state
+            .activity
+            .lock()
+            .expect("session lock poisoned")
+            .transfer = Some((path.display().to_string(), Instant::now()));
+        Transfer(self)
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.state.terminated.load(Ordering::Relaxed)
+    }
+
+    /// Complete once the session was terminated.
+    pub async fn terminated(&self) {
+        loop {
+            let terminate = self.state.terminate.notified();
+            if self.is_terminated() {
+                return;
+            }
+            terminate.await;
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.sessions
+            .0
+            .lock()
+            .expect("sessions lock poisoned")
+            .1
+            .remove(&self.id);
+    }
+}
+
+/// An upload in progress, see [`Session::start_transfer`].
+#[derive(Debug)]
+pub struct Transfer<'a>(&'a Session);
+
+impl Transfer<'_> {
+    /// Counter of the bytes received, for [`crate::progress::ProgressReader::with_counter`].
+    pub fn counter(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.0.state.bytes)
+    }
+}
+
+impl Drop for Transfer<'_> {
+    fn drop(&mut self) {
+        self.0
+            .state
+            .activity
+            .lock()
+            .expect("session lock poisoned")
+            .transfer = None;
+        self.0.state.bytes.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn sessions_are_listed_and_terminated() {
+        let sessions = Sessions::default();
+        let session = sessions.open();
+        let other = sessions.open();
+        session.identify(&User {
+            username: "scanner".to_string(),
+            client_ip: Some("192.168.1.20".parse().unwrap()),
+            ..Default::default()
+        });
+        let transfer = session.start_transfer(Path::new("/scan.pdf"));
+        transfer.counter().fetch_add(1000, Ordering::Relaxed);
+
+        let listed = sessions.list();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].user.as_deref(), Some("scanner"));
+        assert_eq!(listed[0].transfer.as_deref(), Some("/scan.pdf"));
+        assert_eq!(listed[0].bytes, 1000);
+        assert_eq!(listed[1].user, None);
+
+        let terminated = tokio::spawn({
+            let sessions = sessions.clone();
+            let id = session.id();
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                sessions.terminate(id)
+            }
+        });
+        tokio::time::timeout(Duration::from_secs(1), session.terminated())
+            .await
+            .expect("the session wasn't terminated");
+        assert!(terminated.await.unwrap());
+        assert!(!other.is_terminated());
+        assert!(!sessions.terminate(99));
+
+        drop(transfer);
+        drop(session);
+        assert_eq!(sessions.list().len(), 1);
+    }
+}
@@ -1,13 +1,379 @@
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
+use futures_util::StreamExt;
 use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use tokio::time::sleep;
 
-use crate::paperless::{PaperlessApi, PaperlessError};
+use crate::encryption::FileKey;
+use crate::paperless::{PaperlessApi, PaperlessError, UploadOptions, UserClients};
+use crate::tasks::{TaskTracker, TrackedTask};
+use crate::tenant::Tenants;
 
-/// Move a file into the spool directory, preserving the original filename.
-pub async fn spool_file(source: &Path, spool_dir: &Path) -> Result<PathBuf, std::io::Error> {
+/// Limits on the spool directory, so an extended Paperless outage can't fill the disk.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpoolLimits {
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<u64>,
+}
+
+impl SpoolLimits {
+    /// Why the spool directory can't take more files, or `None` if it has room.
+    pub fn exceeded(&self, spool_dir: &Path) -> Option<String> {
+        let (files, bytes, _) = measure(spool_dir);
+        if let Some(max) = self.max_files
+            && files >= max
+        {
+            return Some(format!("{files} files are spooled, the limit is {max}"));
+        }
+        if let Some(max) = self.max_bytes
+            && bytes >= max
+        {
+            return Some(format!("{bytes} bytes are spooled, the limit is {max}"));
+        }
+        None
+    }
+}
+
+/// How long spooled files are kept when Paperless doesn't take them, so an instance that can't
+/// reach Paperless for weeks doesn't slowly fill the disk. Unlike [`SpoolLimits`], which turns new
+/// uploads away, retention deletes the oldest spooled documents.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpoolRetention {
+    pub max_age: Option<Duration>,
+    pub max_bytes: Option<u64>,
+}
+
+impl SpoolRetention {
+    /// Delete the documents older than `max_age`, then the oldest documents until the spool holds
+    /// at most `max_bytes`. The files of a batch are deleted together, as of the age of its oldest
+    /// file, so a batch never reaches Paperless with pages missing. Returns the deleted files.
+    pub fn enforce(&self, spool_dir: &Path) -> Vec<PathBuf> {
+        if self.max_age.is_none() && self.max_bytes.is_none() {
+            return Vec::new();
+        }
+        let mut groups: Vec<_> = spool_groups(spool_dir)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|group| {
+                let files: Vec<_> = group
+                    .into_iter()
+                    .filter_map(|path| {
+                        let metadata = path.metadata().ok()?;
+                        Some((age(&metadata), metadata.len(), path))
+                    })
+                    .collect();
+                let oldest = files.iter().map(|(age, _, _)| *age).max();
+                (oldest.unwrap_or_default(), files)
+            })
+            .collect();
+        // Oldest first.
+        groups.sort_by_key(|(age, _)| std::cmp::Reverse(*age));
+        let mut bytes: u64 = groups
+            .iter()
+            .flat_map(|(_, files)| files.iter().map(|(_, len, _)| len))
+            .sum();
+        let mut deleted = Vec::new();
+        for (age, files) in groups {
+            let expired = self.max_age.is_some_and(|max| age > max);
+            let too_large = self.max_bytes.is_some_and(|max| bytes > max);
+            if !expired && !too_large {
+                break;
+            }
+            for (_, len, path) in files {
+                if let Err(e) = remove_spooled(&path) {
+                    warn!("Failed to delete spooled file {}: {e}", path.display());
+                    continue;
+                }
+                warn!(
+                    "Deleted spooled file {} after {}s without reaching Paperless ({})",
+                    path.display(),
+                    age.as_secs(),
+                    if expired {
+                        "too old"
+                    } else {
+                        "spool too large"
+                    }
+                );
+                crate::metrics::SPOOL_EXPIRED
+                    .with_label_values(&[if expired { "age" } else { "size" }])
+                    .inc();
+                bytes -= len;
+                remove_empty_dirs(spool_dir, &path);
+                deleted.push(path);
+            }
+        }
+        deleted
+    }
+}
+
+fn age(metadata: &std::fs::Metadata) -> Duration {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .unwrap_or_default()
+}
+
+/// Number and total size of the spooled files, which are also exported as metrics along with the
+/// age of the oldest file.
+pub fn usage(spool_dir: &Path) -> (u64, u64) {
+    let (files, bytes, oldest) = measure(spool_dir);
+    crate::metrics::SPOOL_FILES.set(files as i64);
+    crate::metrics::SPOOL_BYTES.set(bytes as i64);
+    crate::metrics::SPOOL_OLDEST_SECONDS.set(oldest.as_secs() as i64);
+    (files, bytes)
+}
+
+/// Number, total size and age of the oldest of the files below `dir`.
+fn measure(dir: &Path) -> (u64, u64, Duration) {
+    spooled_files(dir)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|path| path.metadata().ok())
+        .fold(
+            (0, 0, Duration::ZERO),
+            |(files, bytes, oldest), metadata| {
+                (
+                    files + 1,
+                    bytes + metadata.len(),
+                    oldest.max(age(&metadata)),
+                )
+            },
+        )
+}
+
+/// The files below `dir`, sorted by path.
+fn spooled_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(spooled_files(&path)?);
+        } else if path.is_file() && !is_sidecar_file(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Suffix of the files next to spooled files that count their failed upload attempts.
+const ATTEMPTS_SUFFIX: &str = ".attempts";
+/// Suffix of the files next to spooled files that keep the user and options to upload them with,
+/// stored in the format of the spooled file.
+const OPTIONS_SUFFIX: &str = ".options";
+
+/// Whether `path` belongs to a spooled file rather than being one.
+fn is_sidecar_file(path: &Path) -> bool {
+    let name = path.as_os_str().to_string_lossy();
+    name.ends_with(ATTEMPTS_SUFFIX) || name.ends_with(OPTIONS_SUFFIX)
+}
+
+fn sidecar_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn attempts_path(path: &Path) -> PathBuf {
+    sidecar_path(path, ATTEMPTS_SUFFIX)
+}
+
+fn options_path(path: &Path) -> PathBuf {
+    sidecar_path(path, OPTIONS_SUFFIX)
+}
+
+/// How often uploading the spooled file at `path` failed.
+pub fn attempts(path: &Path) -> u32 {
+    std::fs::read_to_string(attempts_path(path))
+        .ok()
+        .and_then(|attempts| attempts.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Count a failed upload of the spooled file at `path`, returning the attempts so far.
+fn record_attempt(path: &Path) -> u32 {
+    let attempts = attempts(path) + 1;
+    if let Err(e) = std::fs::write(attempts_path(path), attempts.to_string()) {
+        warn!("Failed to count the attempts of {}: {e}", path.display());
+    }
+    attempts
+}
+
+/// Remove a spooled file along with its attempt counter and options.
+pub fn remove_spooled(path: &Path) -> std::io::Result<()> {
+    std::fs::remove_file(path)?;
+    let _ = std::fs::remove_file(attempts_path(path));
+    let _ = std::fs::remove_file(options_path(path));
+    Ok(())
+}
+
+/// Suffix of spooled files that are compressed with gzip.
+const GZIP_SUFFIX: &str = ".gz";
+/// Suffix of spooled files that are encrypted with the spool key.
+const ENCRYPTED_SUFFIX: &str = ".enc";
+
+/// How files are stored in the spool directory.
+#[derive(Debug, Clone, Default)]
+pub struct SpoolFormat {
+    /// Compress spooled files with gzip, since raw scanner TIFFs fill the disk fast during long
+    /// outages.
+    pub compress: bool,
+    /// Encrypt spooled files, so documents awaiting upload aren't stored in plaintext.
+    pub key: Option<FileKey>,
+}
+
+impl SpoolFormat {
+    /// Write `source` to `dest` in this format, returning the path that was written.
+    fn encode(&self, source: &Path, dest: &Path) -> std::io::Result<PathBuf> {
+        let mut name = dest.as_os_str().to_os_string();
+        if self.compress {
+            name.push(GZIP_SUFFIX);
+        }
+        if self.key.is_some() {
+            name.push(ENCRYPTED_SUFFIX);
+        }
+        let dest = PathBuf::from(name);
+        self.write(std::fs::File::open(source)?, &dest)?;
+        Ok(dest)
+    }
+
+    /// Write the options of the file spooled as `spooled` next to it, compressed and encrypted
+    /// like the file, since titles and custom fields can tell as much as the document.
+    fn encode_options(&self, options: &SpooledOptions, spooled: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_vec(options).map_err(std::io::Error::other)?;
+        self.write(std::io::Cursor::new(json), &options_path(spooled))
+    }
+
+    fn write(&self, input: impl Read, dest: &Path) -> std::io::Result<()> {
+        let mut input: Box<dyn Read> = if self.compress {
+            Box::new(flate2::read::GzEncoder::new(
+                input,
+                flate2::Compression::default(),
+            ))
+        } else {
+            Box::new(input)
+        };
+        let mut output = std::fs::File::create(dest)?;
+        match &self.key {
+            Some(key) => key.encrypt(input, &mut output)?,
+            None => {
+                std::io::copy(&mut input, &mut output)?;
+            }
+        }
+        output.sync_all()
+    }
+
+    /// Restore a spooled file to its original content and name in a temporary directory, unless
+    /// it is stored as is. Returns the path to upload and the directory to remove afterwards.
+    fn decode(&self, path: &Path) -> std::io::Result<(PathBuf, Option<PathBuf>)> {
+        let (original, encrypted, compressed) = spooled_name(path);
+        if !encrypted && !compressed {
+            return Ok((path.to_path_buf(), None));
+        }
+        let key = self.key_for(encrypted)?;
+
+        // A directory per file keeps the original name, which Paperless uses as the title.
+        let dir = std::env::temp_dir()
+            .join("ftp-paperless-bridge-spool")
+            .join(crate::paperless::new_request_id());
+        std::fs::create_dir_all(&dir)?;
+        let decoded = dir.join(original);
+        let result = std::fs::File::create(&decoded)
+            .and_then(|output| decode_into(std::fs::File::open(path)?, output, key, compressed));
+        if let Err(e) = result {
+            let _ = std::fs::remove_dir_all(&dir);
+            return Err(e);
+        }
+        Ok((decoded, Some(dir)))
+    }
+
+    /// Read the options of the file spooled as `spooled`, or `None` if it was spooled without.
+    fn decode_options(&self, spooled: &Path) -> std::io::Result<Option<SpooledOptions>> {
+        let input = match std::fs::File::open(options_path(spooled)) {
+            Ok(input) => input,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let (_, encrypted, compressed) = spooled_name(spooled);
+        let mut json = Vec::new();
+        decode_into(input, &mut json, self.key_for(encrypted)?, compressed)?;
+        serde_json::from_slice(&json)
+            .map(Some)
+            .map_err(std::io::Error::other)
+    }
+
+    fn key_for(&self, encrypted: bool) -> std::io::Result<Option<&FileKey>> {
+        match (&self.key, encrypted) {
+            (Some(key), true) => Ok(Some(key)),
+            (None, true) => Err(std::io::Error::other(
+                "file is encrypted, but no spool key is configured",
+            )),
+            (_, false) => Ok(None),
+        }
+    }
+}
+
+/// The original name of a spooled file and whether it is encrypted and compressed.
+fn spooled_name(path: &Path) -> (String, bool, bool) {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let (name, encrypted) = match name.strip_suffix(ENCRYPTED_SUFFIX) {
+        Some(name) => (name, true),
+        None => (name.as_ref(), false),
+    };
+    let (original, compressed) = match name.strip_suffix(GZIP_SUFFIX) {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    (original.to_string(), encrypted, compressed)
+}
+
+fn decode_into(
+    mut input: std::fs::File,
+    mut output: impl Write,
+    key: Option<&FileKey>,
+    compressed: bool,
+) -> std::io::Result<()> {
+    if compressed {
+        let mut output = flate2::write::GzDecoder::new(output);
+        match key {
+            Some(key) => key.decrypt(input, &mut output)?,
+            None => {
+                std::io::copy(&mut input, &mut output)?;
+            }
+        }
+        output.try_finish()?;
+    } else if let Some(key) = key {
+        key.decrypt(input, output)?;
+    } else {
+        std::io::copy(&mut input, &mut output)?;
+    }
+    Ok(())
+}
+
+/// What a spooled file is uploaded with. Only the name of the user is kept, its client is looked up
+/// again when the file is uploaded, so its API token isn't written to disk.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SpooledOptions {
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(flatten)]
+    options: UploadOptions,
+}
+
+/// Move a file into the spool directory, preserving the original filename, along with the `user`
+/// who sent it and the `options` to upload it with.
+pub async fn spool_file(
+    source: &Path,
+    spool_dir: &Path,
+    format: &SpoolFormat,
+    user: Option<&str>,
+    options: &UploadOptions,
+) -> Result<PathBuf, std::io::Error> {
     std::fs::create_dir_all(spool_dir)?;
 
     let file_name = source
@@ -16,7 +382,7 @@ pub async fn spool_file(source: &Path, spool_dir: &Path) -> Result<PathBuf, std:
     let dest = spool_dir.join(file_name);
 
     // If a file with the same name exists, add a suffix
-    let dest = if dest.exists() {
+    let dest = if spooled_path_exists(&dest) {
         let stem = dest
             .file_stem()
             .unwrap_or_default()
@@ -35,62 +401,223 @@ pub async fn spool_file(source: &Path, spool_dir: &Path) -> Result<PathBuf, std:
         dest
     };
 
-    tokio::fs::copy(source, &dest).await?;
+    let (source, format) = (source.to_path_buf(), format.clone());
+    let options = SpooledOptions {
+        user: user.map(str::to_string),
+        options: options.clone(),
+    };
+    let dest = tokio::task::spawn_blocking(move || {
+        let dest = format.encode(&source, &dest)?;
+        if let Err(e) = format.encode_options(&options, &dest) {
+            let _ = remove_spooled(&dest);
+            return Err(e);
+        }
+        Ok(dest)
+    })
+    .await
+    .map_err(std::io::Error::other)??;
     info!("Spooled file to {}", dest.display());
     Ok(dest)
 }
 
-/// Try to upload a single file, returning Ok if it succeeds.
-async fn try_upload_file(path: &Path, client: &dyn PaperlessApi) -> Result<(), PaperlessError> {
-    let path_str = path
-        .to_str()
-        .ok_or_else(|| PaperlessError::Io(std::io::Error::other("invalid path")))?;
+/// Whether a document was spooled as `path`, in any format.
+fn spooled_path_exists(path: &Path) -> bool {
+    [
+        "",
+        GZIP_SUFFIX,
+        ENCRYPTED_SUFFIX,
+        &format!("{GZIP_SUFFIX}{ENCRYPTED_SUFFIX}"),
+    ]
+    .iter()
+    .any(|suffix| {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(suffix);
+        Path::new(&name).exists()
+    })
+}
+
+/// Spool a file of a batch whose files must reach Paperless in order. The files of a batch are
+/// kept in a directory of their own and uploaded one after the other by [`drain_spool`].
+pub async fn spool_batch_file(
+    source: &Path,
+    spool_dir: &Path,
+    batch: &str,
+    position: usize,
+    format: &SpoolFormat,
+    user: Option<&str>,
+    options: &UploadOptions,
+) -> Result<PathBuf, std::io::Error> {
+    // A directory per position keeps the original filename, which Paperless uses as the title.
+    let dir = spool_dir.join(batch).join(format!("{position:06}"));
+    spool_file(source, &dir, format, user, options).await
+}
+
+/// The spooled files in groups that are uploaded concurrently. A file spooled on its own is a
+/// group by itself, the files of a batch form one group in their original order.
+fn spool_groups(spool_dir: &Path) -> std::io::Result<Vec<Vec<PathBuf>>> {
+    let mut groups = Vec::new();
+    for entry in std::fs::read_dir(spool_dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            groups.push(spooled_files(&path)?);
+        } else if path.is_file() && !is_sidecar_file(&path) {
+            groups.push(vec![path]);
+        }
+    }
+    groups.retain(|group| !group.is_empty());
+    groups.sort();
+    Ok(groups)
+}
 
-    client.upload(path_str).await?;
+/// A spooled file that was uploaded, with the ID of its consumption task.
+pub struct Uploaded {
+    pub path: PathBuf,
+    pub task_id: String,
+    /// The user who sent the file, if it was spooled with one.
+    pub user: Option<String>,
+    /// The client the file was uploaded with, to follow its task with.
+    pub client: Arc<dyn PaperlessApi>,
+}
+
+/// Try to upload a single file with the options it was spooled with, using the client of the user
+/// who sent it if it has one of its own.
+async fn try_upload_file(
+    path: &Path,
+    client: &Arc<dyn PaperlessApi>,
+    user_clients: Option<&UserClients>,
+    format: &SpoolFormat,
+) -> Result<Uploaded, PaperlessError> {
+    let (spooled, format) = (path.to_path_buf(), format.clone());
+    let (options, (decoded, temp_dir)) = tokio::task::spawn_blocking(move || {
+        let options = format.decode_options(&spooled)?;
+        Ok::<_, std::io::Error>((options, format.decode(&spooled)?))
+    })
+    .await
+    .map_err(|e| PaperlessError::Io(std::io::Error::other(e)))??;
+    let SpooledOptions { user, options } = options.unwrap_or_else(|| {
+        debug!("{} was spooled without options", path.display());
+        SpooledOptions::default()
+    });
+    let client = match user_clients {
+        Some(user_clients) => user_clients.get(user.as_deref(), client),
+        None => Arc::clone(client),
+    };
+    let result = match decoded.to_str() {
+        Some(path_str) => client.upload(path_str, &options).await,
+        None => Err(PaperlessError::Io(std::io::Error::other("invalid path"))),
+    };
+    if let Some(dir) = temp_dir {
+        let _ = tokio::fs::remove_dir_all(dir).await;
+    }
+    let task_id = result?;
     info!("Spooled file uploaded successfully: {}", path.display());
-    Ok(())
+    Ok(Uploaded {
+        path: path.to_path_buf(),
+        task_id,
+        user,
+        client,
+    })
 }
 
-/// Drain the spool directory by uploading all files. Successfully uploaded files are removed.
+/// Drain the spool directory by uploading all files, up to `concurrency` groups at a time, with
+/// `client` or the client in `user_clients` of the user who sent them.
+/// Successfully uploaded files are removed and returned with the IDs of their consumption tasks.
+/// The files of a batch are uploaded in order, so the rest of a batch waits for the next drain
+/// when one of its files fails.
 pub async fn drain_spool(
     spool_dir: &Path,
-    client: &dyn PaperlessApi,
-) -> Result<(), std::io::Error> {
-    let entries: Vec<_> = std::fs::read_dir(spool_dir)?
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().is_file())
-        .collect();
-
-    for entry in entries {
-        let path = entry.path();
+    client: &Arc<dyn PaperlessApi>,
+    user_clients: Option<&UserClients>,
+    concurrency: usize,
+    format: &SpoolFormat,
+) -> Result<Vec<Uploaded>, std::io::Error> {
+    let groups = spool_groups(spool_dir)?;
+    let results: Vec<_> = futures_util::stream::iter(groups)
+        .map(|group| drain_group(spool_dir, group, client, user_clients, format))
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    let mut uploaded = Vec::new();
+    for result in results {
+        uploaded.extend(result?);
+    }
+    Ok(uploaded)
+}
+
+async fn drain_group(
+    spool_dir: &Path,
+    group: Vec<PathBuf>,
+    client: &Arc<dyn PaperlessApi>,
+    user_clients: Option<&UserClients>,
+    format: &SpoolFormat,
+) -> Result<Vec<Uploaded>, std::io::Error> {
+    let mut uploaded = Vec::new();
+    for path in group {
         debug!("Attempting to upload spooled file: {}", path.display());
 
-        match try_upload_file(&path, client).await {
-            Ok(()) => {
-                std::fs::remove_file(&path)?;
+        match try_upload_file(&path, client, user_clients, format).await {
+            Ok(upload) => {
+                remove_spooled(&path)?;
                 info!(
                     "Removed spooled file after successful upload: {}",
                     path.display()
                 );
+                remove_empty_dirs(spool_dir, &path);
+                uploaded.push(upload);
             }
             Err(e) => {
+                let attempts = record_attempt(&path);
                 warn!(
-                    "Failed to upload spooled file {}: {e}, will retry later",
+                    "Failed to upload spooled file {} (attempt {attempts}): {e}, will retry later",
                     path.display()
                 );
+                break;
             }
         }
     }
+    Ok(uploaded)
+}
 
-    Ok(())
+/// Clean up the directories of a batch once they are empty.
+fn remove_empty_dirs(spool_dir: &Path, path: &Path) {
+    for dir in path.ancestors().skip(1) {
+        if dir == spool_dir || std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
 }
 
-/// Background task that periodically drains the spool directory.
+/// Background task that periodically drains the spool directory, after deleting the files that
+/// outlived `retention`. Files are uploaded with the client in `user_clients` of the user who sent
+/// them, if it has one. With tenants, the spool has a directory per tenant, which is drained to
+/// the Paperless of the tenant and kept within `retention` on its own. The consumption of the
+/// uploaded files is followed by `tasks`.
+#[allow(clippy::too_many_arguments)]
 pub async fn spool_drain_loop(
     spool_dir: PathBuf,
-    client: std::sync::Arc<dyn PaperlessApi>,
+    client: Arc<dyn PaperlessApi>,
+    user_clients: Option<UserClients>,
+    tenants: Arc<Tenants>,
     interval: Duration,
+    concurrency: usize,
+    format: SpoolFormat,
+    retention: SpoolRetention,
+    tasks: TaskTracker,
 ) {
+    let spools: Vec<(PathBuf, Option<&str>, Arc<dyn PaperlessApi>)> = if tenants.is_empty() {
+        vec![(spool_dir.clone(), None, client)]
+    } else {
+        tenants
+            .iter()
+            .map(|(name, tenant)| {
+                (
+                    spool_dir.join(name),
+                    Some(name.as_str()),
+                    Arc::clone(&tenant.client),
+                )
+            })
+            .collect()
+    };
     loop {
         sleep(interval).await;
 
@@ -100,9 +627,279 @@ pub async fn spool_drain_loop(
 
         if files_exist {
             info!("Checking spool directory for pending uploads...");
-            if let Err(e) = drain_spool(&spool_dir, client.as_ref()).await {
-                error!("Error draining spool: {e}");
+            for (dir, tenant, client) in &spools {
+                if !dir.is_dir() {
+                    continue;
+                }
+                retention.enforce(dir);
+                // Tenants upload with the client of the tenant.
+                let user_clients = user_clients.as_ref().filter(|_| tenant.is_none());
+                match drain_spool(dir, client, user_clients, concurrency, &format).await {
+                    Ok(uploaded) => {
+                        for upload in uploaded {
+                            let name = upload.path.display().to_string();
+                            let tracked = TrackedTask::new(
+                                &upload.task_id,
+                                upload.user.as_deref(),
+                                &name,
+                                *tenant,
+                            );
+                            tasks.follow(upload.client, tracked);
+                        }
+                    }
+                    Err(e) => error!("Error draining spool {}: {e}", dir.display()),
+                }
             }
+            usage(&spool_dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_count_files_and_bytes() {
+        let spool_dir = tempfile::tempdir().unwrap();
+        std::fs::write(spool_dir.path().join("a.pdf"), [0u8; 100]).unwrap();
+        std::fs::write(spool_dir.path().join("b.pdf"), [0u8; 50]).unwrap();
+
+        assert_eq!(usage(spool_dir.path()), (2, 150));
+        assert!(SpoolLimits::default().exceeded(spool_dir.path()).is_none());
+        let limits = SpoolLimits {
+            max_bytes: Some(200),
+            max_files: Some(3),
+        };
+        assert!(limits.exceeded(spool_dir.path()).is_none());
+        let limits = SpoolLimits {
+            max_bytes: Some(150),
+            max_files: None,
+        };
+        assert!(limits.exceeded(spool_dir.path()).is_some());
+        let limits = SpoolLimits {
+            max_bytes: None,
+            max_files: Some(2),
+        };
+        assert!(limits.exceeded(spool_dir.path()).is_some());
+    }
+
+    #[test]
+    fn retention_deletes_the_oldest_files() {
+        let spool_dir = tempfile::tempdir().unwrap();
+        let spool = spool_dir.path();
+        let day = Duration::from_secs(86400);
+        for (name, days) in [("old.pdf", 10), ("older.pdf", 20), ("new.pdf", 0)] {
+            let path = spool.join(name);
+            std::fs::write(&path, [0u8; 100]).unwrap();
+            let modified = std::time::SystemTime::now() - day * days;
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        let batch = spool.join("batch").join("000000");
+        std::fs::create_dir_all(&batch).unwrap();
+        std::fs::write(batch.join("page.pdf"), [0u8; 100]).unwrap();
+
+        assert!(SpoolRetention::default().enforce(spool).is_empty());
+        let by_age = SpoolRetention {
+            max_age: Some(day * 15),
+            max_bytes: None,
+        };
+        assert_eq!(by_age.enforce(spool), vec![spool.join("older.pdf")]);
+        let by_size = SpoolRetention {
+            max_age: None,
+            max_bytes: Some(200),
+        };
+        assert_eq!(by_size.enforce(spool), vec![spool.join("old.pdf")]);
+        assert_eq!(usage(spool), (2, 200));
+    }
+
+    #[test]
+    fn retention_deletes_batches_as_a_whole() {
+        let spool_dir = tempfile::tempdir().unwrap();
+        let spool = spool_dir.path();
+        let day = Duration::from_secs(86400);
+        // The first page of a partially drained batch is gone, the later ones were spooled since.
+        let mut pages = Vec::new();
+        for (position, days) in [(1, 20), (2, 0)] {
+            let dir = spool.join("batch").join(format!("{position:06}"));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("page.pdf");
+            std::fs::write(&path, [0u8; 100]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(std::time::SystemTime::now() - day * days)
+                .unwrap();
+            pages.push(path);
         }
+        std::fs::write(spool.join("single.pdf"), [0u8; 100]).unwrap();
+
+        let by_age = SpoolRetention {
+            max_age: Some(day * 15),
+            max_bytes: None,
+        };
+        assert_eq!(by_age.enforce(spool), pages);
+        assert!(!spool.join("batch").exists());
+        assert_eq!(usage(spool), (1, 100));
+    }
+
+    #[tokio::test]
+    async fn batches_are_drained_in_order() {
+        let staging = tempfile::tempdir().unwrap();
+        let spool_dir = tempfile::tempdir().unwrap();
+        let consume = tempfile::tempdir().unwrap();
+        for name in ["page-b.pdf", "page-a.pdf", "single.pdf"] {
+            std::fs::write(staging.path().join(name), b"%PDF").unwrap();
+        }
+        let spool = spool_dir.path();
+        spool_batch_file(
+            &staging.path().join("page-b.pdf"),
+            spool,
+            "batch",
+            0,
+            &SpoolFormat::default(),
+            None,
+            &UploadOptions::default(),
+        )
+        .await
+        .unwrap();
+        spool_batch_file(
+            &staging.path().join("page-a.pdf"),
+            spool,
+            "batch",
+            1,
+            &SpoolFormat::default(),
+            None,
+            &UploadOptions::default(),
+        )
+        .await
+        .unwrap();
+        spool_file(
+            &staging.path().join("single.pdf"),
+            spool,
+            &SpoolFormat::default(),
+            None,
+            &UploadOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(usage(spool), (3, 12));
+        let groups = spool_groups(spool).unwrap();
+        assert_eq!(groups.len(), 2);
+        let batch: Vec<_> = groups[0]
+            .iter()
+            .map(|path| path.file_name().unwrap())
+            .collect();
+        assert_eq!(batch, ["page-b.pdf", "page-a.pdf"]);
+
+        let client: Arc<dyn PaperlessApi> = Arc::new(crate::consume::ConsumeDirClient::new(
+            consume.path().to_path_buf(),
+        ));
+        drain_spool(spool, &client, None, 4, &SpoolFormat::default())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_dir(spool).unwrap().count(), 0);
+        assert_eq!(std::fs::read_dir(consume.path()).unwrap().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn compressed_files_are_uploaded_with_their_content() {
+        let staging = tempfile::tempdir().unwrap();
+        let spool_dir = tempfile::tempdir().unwrap();
+        let consume = tempfile::tempdir().unwrap();
+        let scan = staging.path().join("scan.tiff");
+        std::fs::write(&scan, [0u8; 4096]).unwrap();
+        let format = SpoolFormat {
+            compress: true,
+            ..Default::default()
+        };
+
+        let spooled = spool_file(
+            &scan,
+            spool_dir.path(),
+            &format,
+            None,
+            &UploadOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(spooled, spool_dir.path().join("scan.tiff.gz"));
+        let (_, bytes) = usage(spool_dir.path());
+        assert!(bytes < 4096);
+        // The name stays taken while the compressed file is spooled.
+        let second = spool_file(
+            &scan,
+            spool_dir.path(),
+            &format,
+            None,
+            &UploadOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_ne!(second, spooled);
+
+        let client: Arc<dyn PaperlessApi> = Arc::new(crate::consume::ConsumeDirClient::new(
+            consume.path().to_path_buf(),
+        ));
+        drain_spool(spool_dir.path(), &client, None, 1, &format)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(consume.path().join("scan.tiff")).unwrap(),
+            [0u8; 4096]
+        );
+        assert_eq!(std::fs::read_dir(spool_dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn encrypted_files_need_the_key_to_be_uploaded() {
+        let staging = tempfile::tempdir().unwrap();
+        let spool_dir = tempfile::tempdir().unwrap();
+        let consume = tempfile::tempdir().unwrap();
+        let scan = staging.path().join("scan.pdf");
+        std::fs::write(&scan, b"%PDF-1.7 confidential").unwrap();
+        let format = SpoolFormat {
+            compress: true,
+            key: Some(FileKey::new(&[1; 32]).unwrap()),
+        };
+
+        let spooled = spool_file(
+            &scan,
+            spool_dir.path(),
+            &format,
+            None,
+            &UploadOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(spooled, spool_dir.path().join("scan.pdf.gz.enc"));
+        let client: Arc<dyn PaperlessApi> = Arc::new(crate::consume::ConsumeDirClient::new(
+            consume.path().to_path_buf(),
+        ));
+        drain_spool(spool_dir.path(), &client, None, 1, &SpoolFormat::default())
+            .await
+            .unwrap();
+        assert!(spooled.exists());
+        assert_eq!(std::fs::read_dir(consume.path()).unwrap().count(), 0);
+        // Failed attempts are counted next to the file, which isn't spooled itself.
+        assert_eq!(attempts(&spooled), 1);
+        assert_eq!(usage(spool_dir.path()).0, 1);
+
+        drain_spool(spool_dir.path(), &client, None, 1, &format)
+            .await
+            .unwrap();
+        assert!(!spooled.exists());
+        assert!(!attempts_path(&spooled).exists());
+        assert_eq!(
+            std::fs::read(consume.path().join("scan.pdf")).unwrap(),
+            b"%PDF-1.7 confidential"
+        );
     }
 }
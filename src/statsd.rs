@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::net::UdpSocket;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use log::{debug, info};
+use tokio::time::sleep;
+
+/// Prefix of the Prometheus metric names, replaced by the statsd prefix.
+const PROMETHEUS_PREFIX: &str = "ftp_paperless_bridge_";
+
+static STATSD: OnceLock<Statsd> = OnceLock::new();
+
+/// Sends the same metrics as the Prometheus endpoint to a statsd server (or Telegraf's statsd
+/// input for InfluxDB). Counters are sent as increments and gauges as values every interval,
+/// timings as they are recorded.
+#[derive(Debug)]
+struct Statsd {
+    socket: UdpSocket,
+    prefix: String,
+    /// Counter values sent last, to send increments.
+    sent: Mutex<HashMap<String, f64>>,
+}
+
+impl Statsd {
+    fn send(&self, line: &str) {
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            debug!("Failed to send metric to statsd: {e}");
+        }
+    }
+}
+
+/// Start exporting metrics to the statsd server at `addr` every `interval`.
+pub fn start(addr: &str, prefix: String, interval: Duration) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(if addr.starts_with('[') {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    })?;
+    socket.connect(addr)?;
+    socket.set_nonblocking(true)?;
+    STATSD
+        .set(Statsd {
+            socket,
+            prefix,
+            sent: Mutex::new(HashMap::new()),
+        })
+        .map_err(|_| std::io::Error::other("statsd export already started"))?;
+    info!("Sending metrics to statsd at {addr}");
+
+    tokio::spawn(async move {
+        loop {
+            sleep(interval).await;
+            flush();
+        }
+    });
+    Ok(())
+}
+
+/// Send a timing, if statsd export is enabled.
+pub fn timing(name: &str, label: &str, elapsed: Duration) {
+    if let Some(statsd) = STATSD.get() {
+        statsd.send(&format!(
+            "{}.{name}.{}:{}|ms",
+            statsd.prefix,
+            sanitize(label),
+            elapsed.as_millis()
+        ));
+    }
+}
+
+/// Send the current counters and gauges.
+pub fn flush() {
+    let Some(statsd) = STATSD.get() else {
+        return;
+    };
+    let mut sent = statsd.sent.lock().expect("statsd lock poisoned");
+    for line in lines(&crate::metrics::render(), &statsd.prefix, &mut sent) {
+        statsd.send(&line);
+    }
+}
+
+/// Convert metrics in the Prometheus text format to statsd lines. Counters are converted to
+/// increments since the values in `sent`. Histograms are skipped, as their timings are sent as
+/// they are recorded.
+fn lines(exposition: &str, prefix: &str, sent: &mut HashMap<String, f64>) -> Vec<String> {
+    let mut types = HashMap::new();
+    let mut lines = Vec::new();
+    for line in exposition.lines() {
+        if let Some(declaration) = line.strip_prefix("# TYPE ") {
+            if let Some((name, kind)) = declaration.split_once(' ') {
+                types.insert(name.to_string(), kind.to_string());
+            }
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some((series, value)) = line.rsplit_once(' ') else {
+            continue;
+        };
+        let Ok(value) = value.parse::<f64>() else {
+            continue;
+        };
+        let (name, labels) = match series.split_once('{') {
+            Some((name, labels)) => (name, labels.trim_end_matches('}')),
+            None => (series, ""),
+        };
+
+        let mut key = format!(
+            "{prefix}.{}",
+            name.strip_prefix(PROMETHEUS_PREFIX).unwrap_or(name)
+        );
+        for label in labels.split(',').filter(|label| !label.is_empty()) {
+            if let Some((_, value)) = label.split_once('=') {
+                key.push('.');
+                key.push_str(&sanitize(value.trim_matches('"')));
+            }
+        }
+
+        match types.get(name).map(String::as_str) {
+            Some("counter") => {
+                let previous = sent.insert(key.clone(), value).unwrap_or(0.0);
+                if value > previous {
+                    lines.push(format!("{key}:{}|c", value - previous));
+                }
+            }
+            Some("gauge") => lines.push(format!("{key}:{value}|g")),
+            _ => {}
+        }
+    }
+    lines
+}
+
+/// Make a label value usable as a statsd name segment.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_counters_to_increments() {
+        let exposition = "\
+# HELP ftp_paperless_bridge_uploads_total Documents received
+# TYPE ftp_paperless_bridge_uploads_total counter
+ftp_paperless_bridge_uploads_total{user=\"scan.ner\"} 3
+# TYPE ftp_paperless_bridge_upload_phase_seconds histogram
+ftp_paperless_bridge_upload_phase_seconds_count{phase=\"transfer\"} 3
+";
+        let mut sent = HashMap::new();
+        assert_eq!(
+            lines(exposition, "bridge", &mut sent),
+            vec!["bridge.uploads_total.scan_ner:3|c"]
+        );
+        assert!(lines(exposition, "bridge", &mut sent).is_empty());
+        assert_eq!(
+            lines(&exposition.replace("} 3", "} 5"), "bridge", &mut sent),
+            vec!["bridge.uploads_total.scan_ner:2|c"]
+        );
+    }
+}
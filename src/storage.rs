@@ -1,30 +1,122 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::Duration;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use async_tempfile::TempFile;
 use async_trait::async_trait;
+use bytes::Bytes;
 use libunftp::storage::{
     Error as StorageError,
-    ErrorKind::{LocalError, TransientFileNotAvailable},
-    Fileinfo, Metadata, Result as StorageResult, StorageBackend,
+    ErrorKind::{
+        ConnectionClosed, ExceededStorageAllocationError, FileNameNotAllowedError,
+        InsufficientStorageSpaceError, LocalError, PermanentFileNotAvailable, PermissionDenied,
+        TransientFileNotAvailable,
+    },
+    FEATURE_SITEMD5, Fileinfo, Metadata, Result as StorageResult, StorageBackend,
 };
 use log::{debug, error, info, warn};
-use tokio::io::AsyncSeekExt;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufWriter};
 use tokio::time::sleep;
 
 use crate::auth::User;
+use crate::batch::{SeparatorPage, SettleQueue};
+use crate::breaker::CircuitBreaker;
+use crate::budget::{BudgetReader, TransferBudget};
+use crate::capacity::DiskCapacity;
+use crate::document::{decode_filename, has_extension_for, is_generated_name, sniff_extension};
+use crate::extract::EmbeddedField;
 use crate::health::PaperlessHealth;
-use crate::paperless::PaperlessApi;
+use crate::hook::{SuccessHook, Upload};
+use crate::metadata::{
+    DocumentMetadata, MAX_SIDECAR_SIZE, ResolveOptions, UnresolvedPolicy, sidecar_target,
+};
+use crate::notify::{Event, Notifier};
+use crate::paperless::{
+    DuplicatePolicy, PaperlessApi, PaperlessError, TaskStatus, UploadOptions, UserClients,
+    is_duplicate, wait_for_task,
+};
+use crate::progress::{ProgressReader, format_rate};
+use crate::quirks::{Quirks, strip_temp_suffix};
+use crate::quota::QuotaTracker;
+use crate::rules::RulesFile;
+use crate::sanitize::FilenamePolicy;
+use crate::sessions::{Session, Transfer};
+use crate::spool::{SpoolFormat, SpoolLimits};
+use crate::tasks::{TaskTracker, TrackedTask};
+use crate::template::{TitleContext, TitleTemplate};
+use crate::tenant::Tenants;
 
 const MAX_UPLOAD_RETRIES: usize = 5;
 const INITIAL_RETRY_DELAY_MS: u64 = 500;
+/// How long checksum verification waits for Paperless to consume (and OCR) a document.
+const CONSUMPTION_TIMEOUT: Duration = Duration::from_secs(600);
+pub const DEFAULT_PROGRESS_THRESHOLD: u64 = 5_000_000;
+/// Size of the buffers the staging copy reads and writes through. Large buffers keep the number
+/// of syscalls down for multi-hundred-MB PDFs.
+pub const DEFAULT_BUFFER_SIZE: usize = 256 * 1024;
+
+/// A custom field set on every upload, with a value like `{user}/{folder}` evaluated per upload.
+#[derive(Debug, Clone)]
+pub struct SourceField {
+    /// ID of the custom field in Paperless.
+    pub id: u64,
+    pub value: TitleTemplate,
+}
 
 pub struct PaperlessStorage {
     paperless_client: Arc<dyn PaperlessApi>,
     paperless_health: PaperlessHealth,
     spool_dir: Option<PathBuf>,
+    spool_limits: SpoolLimits,
+    spool_format: SpoolFormat,
+    max_upload_size: Option<u64>,
+    /// MD5 checksums of the files received in this session, answered via SITE MD5.
+    checksums: Mutex<HashMap<PathBuf, String>>,
+    /// MD5 checksums of the files forwarded or spooled in this session, to ignore re-sends.
+    sent_checksums: Mutex<HashSet<String>>,
+    /// Metadata from sidecar files, by the name of the document they describe.
+    sidecars: Mutex<HashMap<String, DocumentMetadata>>,
+    /// Where the files received in this session were spooled, so DELE can cancel them.
+    spooled: Mutex<HashMap<PathBuf, PathBuf>>,
+    verify_checksums: bool,
+    /// How long the reply to an upload waits for Paperless to consume it.
+    consumption_wait: Option<Duration>,
+    duplicates: DuplicatePolicy,
+    notifier: Notifier,
+    /// Told about every document Paperless made of an upload.
+    success_hook: Option<SuccessHook>,
+    /// Follows the consumption of uploads the scanner isn't kept waiting for.
+    tasks: TaskTracker,
+    filename_policy: FilenamePolicy,
+    quirks: Quirks,
+    /// Drop image uploads with less than this percentage of ink.
+    blank_page_threshold: Option<f64>,
+    /// Pages that split the batch held by `settle` into separate documents.
+    separator_page: Option<SeparatorPage>,
+    /// Prefix of the QR codes on the first page that set the archive serial number, e.g. `ASN`.
+    asn_prefix: Option<String>,
+    embedded_fields: Vec<EmbeddedField>,
+    rules: Arc<RulesFile>,
+    title_template: Option<TitleTemplate>,
+    resolve_options: ResolveOptions,
+    source_field: Option<SourceField>,
+    quota: QuotaTracker,
+    progress_threshold: u64,
+    buffer_size: usize,
+    budget: Option<TransferBudget>,
+    memory_staging_threshold: Option<u64>,
+    breaker: CircuitBreaker,
+    settle: Option<SettleQueue>,
+    tenants: Arc<Tenants>,
+    user_clients: Option<UserClients>,
+    /// The client built for the user of this session by `user_clients`.
+    session_client: Mutex<Option<(String, Arc<dyn PaperlessApi>)>>,
+    session: Option<Session>,
+    disk_capacity: Option<DiskCapacity>,
 }
 
 impl std::fmt::Debug for PaperlessStorage {
@@ -39,6 +131,41 @@ impl PaperlessStorage {
             paperless_client,
             paperless_health,
             spool_dir: None,
+            spool_limits: SpoolLimits::default(),
+            spool_format: SpoolFormat::default(),
+            max_upload_size: None,
+            checksums: Mutex::new(HashMap::new()),
+            sent_checksums: Mutex::new(HashSet::new()),
+            sidecars: Mutex::new(HashMap::new()),
+            spooled: Mutex::new(HashMap::new()),
+            verify_checksums: false,
+            consumption_wait: None,
+            duplicates: DuplicatePolicy::default(),
+            notifier: Notifier::default(),
+            success_hook: None,
+            tasks: TaskTracker::default(),
+            filename_policy: FilenamePolicy::default(),
+            quirks: Quirks::default(),
+            blank_page_threshold: None,
+            separator_page: None,
+            asn_prefix: None,
+            embedded_fields: Vec::new(),
+            rules: Arc::default(),
+            title_template: None,
+            resolve_options: ResolveOptions::default(),
+            source_field: None,
+            quota: QuotaTracker::default(),
+            progress_threshold: DEFAULT_PROGRESS_THRESHOLD,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            budget: None,
+            memory_staging_threshold: None,
+            breaker: CircuitBreaker::default(),
+            settle: None,
+            tenants: Arc::default(),
+            user_clients: None,
+            session_client: Mutex::new(None),
+            session: None,
+            disk_capacity: None,
         }
     }
 
@@ -48,22 +175,498 @@ impl PaperlessStorage {
         spool_dir: PathBuf,
     ) -> Self {
         Self {
-            paperless_client,
-            paperless_health,
             spool_dir: Some(spool_dir),
+            ..Self::new(paperless_client, paperless_health)
+        }
+    }
+
+    /// Reject uploads with 452 while the spool directory exceeds `spool_limits`.
+    pub fn with_spool_limits(mut self, spool_limits: SpoolLimits) -> Self {
+        self.spool_limits = spool_limits;
+        self
+    }
+
+    /// How files are stored in the spool directory.
+    pub fn with_spool_format(mut self, spool_format: SpoolFormat) -> Self {
+        self.spool_format = spool_format;
+        self
+    }
+
+    /// Reject uploads larger than `max_upload_size` bytes with 552.
+    pub fn with_max_upload_size(mut self, max_upload_size: Option<u64>) -> Self {
+        self.max_upload_size = max_upload_size;
+        self
+    }
+
+    /// Hold the reply to each upload until Paperless consumed it, for up to `consumption_wait`, so
+    /// failed consumption is reported to the client as an FTP error.
+    pub fn with_consumption_wait(mut self, consumption_wait: Option<Duration>) -> Self {
+        self.consumption_wait = consumption_wait;
+        self
+    }
+
+    /// How to treat documents Paperless refuses as duplicates.
+    pub fn with_duplicate_policy(mut self, duplicates: DuplicatePolicy) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Where to send notifications about uploaded and failed documents.
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Run `hook` for every document Paperless consumed, which waits for the consumption of each
+    /// upload.
+    pub fn with_success_hook(mut self, hook: Option<SuccessHook>) -> Self {
+        self.success_hook = hook;
+        self
+    }
+
+    /// Follow the consumption of uploads with `tasks`, which bounds how many are polled at once.
+    pub fn with_task_tracker(mut self, tasks: TaskTracker) -> Self {
+        self.tasks = tasks;
+        self
+    }
+
+    /// Compare the checksum Paperless stored for each consumed upload with the received file.
+    pub fn with_checksum_verification(mut self, verify_checksums: bool) -> Self {
+        self.verify_checksums = verify_checksums;
+        self
+    }
+
+    pub fn with_filename_policy(mut self, filename_policy: FilenamePolicy) -> Self {
+        self.filename_policy = filename_policy;
+        self
+    }
+
+    pub fn with_quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Drop image uploads with less than `threshold` percent ink, like the blank backsides of
+    /// duplex scans.
+    pub fn with_blank_page_threshold(mut self, threshold: Option<f64>) -> Self {
+        self.blank_page_threshold = threshold;
+        self
+    }
+
+    /// Assign metadata to uploads matching the keywords of `rules`.
+    pub fn with_rules(mut self, rules: Arc<RulesFile>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Set the title of every upload from `title_template`.
+    pub fn with_title_template(mut self, title_template: Option<TitleTemplate>) -> Self {
+        self.title_template = title_template;
+        self
+    }
+
+    /// How tags and other objects given by name are looked up in Paperless.
+    pub fn with_resolve_options(mut self, resolve_options: ResolveOptions) -> Self {
+        self.resolve_options = resolve_options;
+        self
+    }
+
+    /// Fill a custom field of every upload, e.g. to tell Paperless workflows where it came from.
+    pub fn with_source_field(mut self, source_field: Option<SourceField>) -> Self {
+        self.source_field = source_field;
+        self
+    }
+
+    /// Take these fields from the metadata embedded in PDFs and images unless set otherwise.
+    pub fn with_embedded_metadata(mut self, fields: Vec<EmbeddedField>) -> Self {
+        self.embedded_fields = fields;
+        self
+    }
+
+    /// Enforce per-user upload limits with usage shared across sessions.
+    pub fn with_quota_tracker(mut self, quota: QuotaTracker) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Log the progress of uploads once they grew beyond `threshold` bytes.
+    pub fn with_progress_threshold(mut self, threshold: u64) -> Self {
+        self.progress_threshold = threshold;
+        self
+    }
+
+    /// Stage uploads through buffers of this many bytes.
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Stage uploads of at most `threshold` bytes in memory and upload them from there. They are
+    /// only written to a staging file when it is needed, e.g. to inspect pages or to spool them.
+    pub fn with_memory_staging(mut self, threshold: Option<u64>) -> Self {
+        self.memory_staging_threshold = threshold;
+        self
+    }
+
+    /// Pause reading uploads while the transfers of all sessions staged more than `budget`
+    /// allows.
+    pub fn with_transfer_budget(mut self, budget: Option<TransferBudget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Skip calling Paperless while `breaker` is open after repeated failures.
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    /// Split the batches of the settle queue into separate documents at `separator` pages.
+    pub fn with_separator_page(mut self, separator: Option<SeparatorPage>) -> Self {
+        self.separator_page = separator;
+        self
+    }
+
+    /// Set the archive serial number from a QR code like `ASN00042` on the first page.
+    pub fn with_barcode_asn_prefix(mut self, prefix: Option<String>) -> Self {
+        self.asn_prefix = prefix;
+        self
+    }
+
+    /// Hand received files to `settle` instead of uploading them right away.
+    pub fn with_settle_queue(mut self, settle: Option<SettleQueue>) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// Refuse uploads while the staging or spool filesystem is below its free space minimum.
+    pub fn with_disk_capacity(mut self, disk_capacity: Option<DiskCapacity>) -> Self {
+        self.disk_capacity = disk_capacity;
+        self
+    }
+
+    /// List the FTP session this storage serves, so it can be inspected and terminated.
+    pub fn with_session(mut self, session: Option<Session>) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Upload with a client of their own for users with a token, headers or proxy of their own.
+    pub fn with_user_clients(mut self, user_clients: Option<UserClients>) -> Self {
+        self.user_clients = user_clients;
+        self
+    }
+
+    /// Route uploads by their first directory to the Paperless instances of these tenants.
+    pub fn with_tenants(mut self, tenants: Arc<Tenants>) -> Self {
+        self.tenants = tenants;
+        self
+    }
+
+    /// The filename the client means, without a temporary upload suffix if it renames uploads.
+    fn client_name(&self, path: &Path) -> Option<String> {
+        let name = decode_filename(path.file_name()?);
+        if self.quirks.temp_rename
+            && let Some(name) = strip_temp_suffix(&name)
+        {
+            return Some(name.to_string());
+        }
+        Some(name)
+    }
+
+    /// The tenant whose directory `path` is in, if tenants are configured.
+    fn tenant_of(&self, user: &User, path: &Path) -> Option<&str> {
+        crate::tenant::select(&self.tenants, &user.username, path)
+            .ok()
+            .map(|(name, _, _)| name)
+    }
+
+    /// Name of the staging file, which Paperless uses as the initial document title.
+    fn staging_name(&self, path: &Path, sniffed_extension: Option<&str>) -> Option<String> {
+        let name = self.client_name(path)?;
+        let name = if self.quirks.temp_rename
+            && Path::new(&name).extension().is_none()
+            && let Some(extension) = sniffed_extension
+        {
+            // The final name is only sent with the rename, so the file type has to be guessed.
+            format!("{name}.{extension}")
+        } else if is_generated_name(&name) {
+            // STOU names are random UUIDs, which make for poor titles.
+            let mut name = format!("scan_{}", chrono::Local::now().format("%Y%m%d_%H%M%S"));
+            if let Some(extension) = sniffed_extension {
+                name = format!("{name}.{extension}");
+            }
+            name
+        } else if let Some(extension) = sniffed_extension
+            && !has_extension_for(&name, extension)
+        {
+            // Paperless picks its parser by the extension, which some scanners get wrong, e.g.
+            // sending PDFs as `.dat`.
+            let corrected = Path::new(&name)
+                .with_extension(extension)
+                .to_string_lossy()
+                .into_owned();
+            info!("Uploading {name:?} as {corrected:?}, which its content is");
+            corrected
+        } else {
+            name
+        };
+        Some(self.filename_policy.sanitize(&name))
+    }
+
+    /// Whether the staged upload is an image of a blank page to drop.
+    #[cfg(feature = "images")]
+    async fn is_blank_page(&self, staged: &mut Staged) -> bool {
+        let Some(threshold) = self.blank_page_threshold else {
+            return false;
+        };
+        let staged = match staged.file().await {
+            Ok(staged) => Path::new(staged),
+            Err(e) => {
+                warn!("Failed to check upload for a blank page: {e}");
+                return false;
+            }
+        };
+        match crate::pages::is_blank_file(staged, threshold).await {
+            Ok(blank) => blank,
+            Err(e) => {
+                warn!("Failed to check {} for a blank page: {e}", staged.display());
+                false
+            }
+        }
+    }
+
+    #[cfg(not(feature = "images"))]
+    async fn is_blank_page(&self, _staged: &mut Staged) -> bool {
+        // main refuses to start with a threshold in builds without the `images` feature.
+        debug_assert!(self.blank_page_threshold.is_none());
+        false
+    }
+
+    /// The contents of the QR codes on the first page of the staged upload, if they are needed.
+    #[cfg(feature = "images")]
+    async fn first_page_barcodes(&self, staged: &mut Staged) -> Vec<String> {
+        if self.asn_prefix.is_none() && !self.rules.needs_barcodes() {
+            return Vec::new();
+        }
+        let staged = match staged.file().await {
+            Ok(staged) => Path::new(staged),
+            Err(e) => {
+                warn!("Failed to read the barcodes of the upload: {e}");
+                return Vec::new();
+            }
+        };
+        crate::pages::first_page_barcodes_file(staged)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to read the barcodes of {}: {e}", staged.display());
+                Vec::new()
+            })
+    }
+
+    #[cfg(not(feature = "images"))]
+    async fn first_page_barcodes(&self, _staged: &mut Staged) -> Vec<String> {
+        debug_assert!(self.asn_prefix.is_none());
+        Vec::new()
+    }
+
+    /// Whether the staged upload is an image of a separator page.
+    #[cfg(feature = "images")]
+    async fn is_separator_page(&self, staged: &mut Staged) -> bool {
+        let Some(separator) = &self.separator_page else {
+            return false;
+        };
+        let staged = match staged.file().await {
+            Ok(staged) => Path::new(staged),
+            Err(e) => {
+                warn!("Failed to check upload for a separator page: {e}");
+                return false;
+            }
+        };
+        let threshold = self
+            .blank_page_threshold
+            .unwrap_or(crate::pages::DEFAULT_BLANK_THRESHOLD);
+        match crate::pages::is_separator_file(staged, separator, threshold).await {
+            Ok(separator) => separator,
+            Err(e) => {
+                warn!(
+                    "Failed to check {} for a separator page: {e}",
+                    staged.display()
+                );
+                false
+            }
+        }
+    }
+
+    #[cfg(not(feature = "images"))]
+    async fn is_separator_page(&self, _staged: &mut Staged) -> bool {
+        debug_assert!(self.separator_page.is_none());
+        false
+    }
+
+    /// Remember that the file received as `path` was taken care of, so a re-send is ignored.
+    fn mark_sent(&self, path: &Path) {
+        let checksum = self
+            .checksums
+            .lock()
+            .expect("checksum lock poisoned")
+            .get(path)
+            .cloned();
+        if let Some(checksum) = checksum {
+            self.sent_checksums
+                .lock()
+                .expect("checksum lock poisoned")
+                .insert(checksum);
+        }
+    }
+
+    /// Forget a cancelled upload, so sending it again uploads it.
+    fn unmark_sent(&self, path: &Path) {
+        let checksum = self
+            .checksums
+            .lock()
+            .expect("checksum lock poisoned")
+            .get(path)
+            .cloned();
+        if let Some(checksum) = checksum {
+            self.sent_checksums
+                .lock()
+                .expect("checksum lock poisoned")
+                .remove(&checksum);
+        }
+    }
+
+    /// Read a metadata sidecar file and keep it for the upload of the document `target`.
+    async fn receive_sidecar<R: tokio::io::AsyncRead + Unpin>(
+        &self,
+        name: &str,
+        target: &str,
+        input: R,
+    ) -> StorageResult<u64> {
+        let mut content = Vec::new();
+        let bytes = input
+            .take(MAX_SIDECAR_SIZE + 1)
+            .read_to_end(&mut content)
+            .await? as u64;
+        if bytes > MAX_SIDECAR_SIZE {
+            warn!("Rejecting metadata file {name:?} larger than {MAX_SIDECAR_SIZE} bytes");
+            return Err(StorageError::new(
+                ExceededStorageAllocationError,
+                "Metadata file is too large",
+            ));
+        }
+        let metadata = DocumentMetadata::from_sidecar(name, &content).map_err(|e| {
+            warn!("Rejecting invalid metadata file {name:?}: {e}");
+            StorageError::new(LocalError, format!("Invalid metadata file: {e}"))
+        })?;
+        info!("Received metadata for {target:?}");
+        self.add_metadata(target, metadata);
+        Ok(bytes)
+    }
+
+    /// Apply `metadata` to the next upload of `target` in this session, like a sidecar file.
+    pub fn add_metadata(&self, target: &str, metadata: DocumentMetadata) {
+        self.sidecars
+            .lock()
+            .expect("sidecar lock poisoned")
+            .insert(target.to_string(), metadata);
+    }
+
+    /// Record the user of the session and reject whatever a terminated session still tries.
+    fn check_session(&self, user: &User) -> StorageResult<()> {
+        let Some(ref session) = self.session else {
+            return Ok(());
+        };
+        session.identify(user);
+        if session.is_terminated() {
+            warn!(
+                "Rejecting command of {user} in terminated session {}",
+                session.id()
+            );
+            return Err(StorageError::new(
+                ConnectionClosed,
+                "The session was terminated",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Complete once the session was terminated via the `/sessions` endpoint.
+    async fn session_terminated(&self) {
+        match self.session {
+            Some(ref session) => session.terminated().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// The client of the session, built once for the token, headers and proxy of its user.
+    fn user_client(&self, user: &User) -> StorageResult<Arc<dyn PaperlessApi>> {
+        let Some(ref user_clients) = self.user_clients else {
+            return Ok(Arc::clone(&self.paperless_client));
+        };
+        let mut session_client = self
+            .session_client
+            .lock()
+            .expect("session client lock poisoned");
+        if let Some((username, client)) = &*session_client
+            && *username == user.username
+        {
+            return Ok(Arc::clone(client));
         }
+        let client: Arc<dyn PaperlessApi> =
+            match user_clients.client(&user.username, &user.settings) {
+                Ok(Some(client)) => {
+                    debug!("Using a Paperless client of its own for {user}");
+                    client
+                }
+                Ok(None) => Arc::clone(&self.paperless_client),
+                Err(e) => {
+                    error!("Failed to build the Paperless client of {user}: {e}");
+                    return Err(StorageError::new(LocalError, e));
+                }
+            };
+        *session_client = Some((user.username.clone(), Arc::clone(&client)));
+        Ok(client)
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn handle_upload_failure(
         &self,
-        temp_path: &str,
-        err: crate::paperless::PaperlessError,
+        user: &User,
+        path: &Path,
+        staged: &mut Staged,
+        options: &UploadOptions,
+        spool_dir: Option<&Path>,
+        err: PaperlessError,
         bytes_copied: u64,
     ) -> StorageResult<u64> {
-        if let Some(ref spool_dir) = self.spool_dir {
-            match crate::spool::spool_file(Path::new(temp_path), spool_dir).await {
+        // Paperless would reject a spooled copy of an invalid document again and again.
+        if let PaperlessError::Validation(_) = err {
+            self.notify_failure(user, path, &err);
+            return Err(StorageError::new(reply_kind(&err), err));
+        }
+        if let Some(spool_dir) = spool_dir {
+            let spooled = match staged.file().await {
+                Ok(temp_path) => {
+                    crate::spool::spool_file(
+                        Path::new(temp_path),
+                        spool_dir,
+                        &self.spool_format,
+                        Some(&user.username),
+                        options,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            match spooled {
                 Ok(spool_path) => {
                     info!("File spooled for later retry: {}", spool_path.display());
+                    self.mark_sent(path);
+                    self.spooled
+                        .lock()
+                        .expect("spooled files lock poisoned")
+                        .insert(path.to_path_buf(), spool_path);
                     return Ok(bytes_copied);
                 }
                 Err(spool_err) => {
@@ -71,7 +674,395 @@ impl PaperlessStorage {
                 }
             }
         }
-        Err(StorageError::new(LocalError, err))
+        self.notify_failure(user, path, &err);
+        Err(StorageError::new(reply_kind(&err), err))
+    }
+
+    fn notify_failure(&self, user: &User, path: &Path, reason: impl std::fmt::Display) {
+        crate::metrics::USER_UPLOAD_FAILURES
+            .with_label_values(&[
+                user.username.clone(),
+                crate::metrics::client_label(user.client_ip),
+            ])
+            .inc();
+        let checksum = self
+            .checksums
+            .lock()
+            .expect("checksum lock poisoned")
+            .get(path)
+            .cloned();
+        self.notifier.notify_failure(
+            checksum.as_deref(),
+            Event::Failed {
+                user: user.username.clone(),
+                name: self.client_name(path).unwrap_or_default(),
+                reason: reason.to_string(),
+                attempts: 1,
+            },
+        );
+    }
+}
+
+/// The FTP reply for an upload Paperless didn't take, so scanners only retry when it may help.
+fn reply_kind(err: &PaperlessError) -> libunftp::storage::ErrorKind {
+    match err {
+        PaperlessError::Validation(_) => PermanentFileNotAvailable,
+        PaperlessError::Auth(_) => PermissionDenied,
+        PaperlessError::RateLimited(_) | PaperlessError::Network(_) => TransientFileNotAvailable,
+        _ => LocalError,
+    }
+}
+
+/// Upload a staged file, retrying with exponential backoff unless `breaker` opens meanwhile.
+pub async fn upload_with_retries(
+    client: &dyn PaperlessApi,
+    breaker: &CircuitBreaker,
+    path: &str,
+    options: &UploadOptions,
+) -> Result<String, PaperlessError> {
+    retry_upload(breaker, || client.upload(path, options)).await
+}
+
+/// [`upload_with_retries`] for a document staged in memory.
+pub async fn upload_bytes_with_retries(
+    client: &dyn PaperlessApi,
+    breaker: &CircuitBreaker,
+    name: &str,
+    document: Bytes,
+    options: &UploadOptions,
+) -> Result<String, PaperlessError> {
+    retry_upload(breaker, || {
+        client.upload_bytes(name, document.clone(), options)
+    })
+    .await
+}
+
+async fn retry_upload<F, Fut>(
+    breaker: &CircuitBreaker,
+    mut upload: F,
+) -> Result<String, PaperlessError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<String, PaperlessError>>,
+{
+    let mut last_err = None;
+    for attempt in 0..MAX_UPLOAD_RETRIES {
+        if attempt > 0 && !breaker.allows_request() {
+            break;
+        }
+        match upload().await {
+            Ok(task_id) => {
+                breaker.record_success();
+                return Ok(task_id);
+            }
+            Err(e) if !e.is_retryable() => {
+                // Paperless answered, so it's up, but sending the file again won't help.
+                breaker.record_success();
+                crate::metrics::UPLOAD_FAILURES
+                    .with_label_values(&[e.label()])
+                    .inc();
+                return Err(e);
+            }
+            Err(e) => {
+                warn!("Upload attempt {} failed: {e}", attempt + 1);
+                breaker.record_failure();
+                last_err = Some(e);
+            }
+        }
+
+        if attempt + 1 < MAX_UPLOAD_RETRIES {
+            let delay = Duration::from_millis(INITIAL_RETRY_DELAY_MS * 2u64.pow(attempt as u32));
+            debug!("Retrying in {}ms", delay.as_millis());
+            sleep(delay).await;
+        }
+    }
+    let err = last_err.expect("at least one upload attempt was made");
+    crate::metrics::UPLOAD_FAILURES
+        .with_label_values(&[err.label()])
+        .inc();
+    Err(err)
+}
+
+/// Wait for Paperless to consume an upload and record how long it took.
+pub async fn wait_for_consumption(
+    client: &dyn PaperlessApi,
+    task_id: &str,
+) -> Result<TaskStatus, PaperlessError> {
+    let started = Instant::now();
+    let status = wait_for_task(client, task_id, CONSUMPTION_TIMEOUT).await?;
+    crate::metrics::observe_phase("consumption", started.elapsed());
+    info!(
+        "Paperless finished task {task_id} after {:.1}s",
+        started.elapsed().as_secs_f64()
+    );
+    Ok(status)
+}
+
+/// Log why Paperless failed to consume task `task_id`, returning whether that counts as a failure
+/// of the upload, which duplicates don't unless `duplicates` says so.
+fn consumption_failed(task_id: &str, reason: &str, duplicates: DuplicatePolicy) -> bool {
+    if !is_duplicate(reason) {
+        error!("Paperless failed to consume task {task_id}: {reason}");
+        crate::metrics::UPLOAD_FAILURES
+            .with_label_values(&["consumption_failed"])
+            .inc();
+        return true;
+    }
+    match duplicates {
+        DuplicatePolicy::Success => {
+            info!("Paperless already has the document of task {task_id}: {reason}");
+            return false;
+        }
+        DuplicatePolicy::Warn => {
+            warn!("Paperless already has the document of task {task_id}: {reason}")
+        }
+        DuplicatePolicy::Fail => {
+            error!("Paperless refused the duplicate of task {task_id}: {reason}")
+        }
+    }
+    crate::metrics::UPLOAD_FAILURES
+        .with_label_values(&["duplicate"])
+        .inc();
+    duplicates == DuplicatePolicy::Fail
+}
+
+/// Follow the consumption of an upload, returning the ID of the document Paperless made of it if
+/// that's known, or why it failed.
+pub async fn log_consumption(
+    client: Arc<dyn PaperlessApi>,
+    task_id: String,
+    duplicates: DuplicatePolicy,
+) -> Result<Option<u64>, String> {
+    match wait_for_consumption(client.as_ref(), &task_id).await {
+        Ok(TaskStatus::Failure(reason)) if consumption_failed(&task_id, &reason, duplicates) => {
+            Err(reason)
+        }
+        Ok(TaskStatus::Success { document_id }) => Ok(document_id),
+        Ok(_) => Ok(None),
+        Err(e) => {
+            warn!("Could not follow consumption of task {task_id}: {e}");
+            Ok(None)
+        }
+    }
+}
+
+/// Run the success hook for the document Paperless made of an upload, if both are known.
+pub async fn run_success_hook(hook: Option<(SuccessHook, Upload)>, document_id: Option<u64>) {
+    if let Some((hook, upload)) = hook
+        && let Some(document_id) = document_id
+    {
+        hook.run(document_id, upload).await;
+    }
+}
+
+/// Wait for Paperless to consume an upload and compare its stored checksum with ours.
+///
+/// Returns the ID of the document, and `false` if Paperless stored a different file than we
+/// received.
+async fn verify_consumed_checksum(
+    client: &dyn PaperlessApi,
+    task_id: &str,
+    expected: &str,
+) -> Result<(u64, bool), PaperlessError> {
+    match wait_for_consumption(client, task_id).await? {
+        TaskStatus::Success {
+            document_id: Some(document_id),
+        } => {
+            let stored = client.document_checksum(document_id).await?;
+            Ok((document_id, stored.eq_ignore_ascii_case(expected)))
+        }
+        TaskStatus::Failure(reason) => Err(PaperlessError::ConsumptionFailed {
+            task_id: task_id.to_string(),
+            reason,
+        }),
+        status => Err(PaperlessError::Api(format!(
+            "Task {task_id} finished without a document: {status:?}"
+        ))),
+    }
+}
+
+/// Verify the checksum of a consumed upload, returning the ID of its document.
+async fn log_checksum_verification(
+    client: Arc<dyn PaperlessApi>,
+    task_id: String,
+    expected: String,
+) -> Option<u64> {
+    match verify_consumed_checksum(client.as_ref(), &task_id, &expected).await {
+        Ok((document_id, true)) => {
+            info!("Verified checksum of consumed document (task {task_id})");
+            Some(document_id)
+        }
+        Ok((document_id, false)) => {
+            error!(
+                "Checksum mismatch: Paperless stored a different file than was received (task {task_id}, md5 {expected})"
+            );
+            Some(document_id)
+        }
+        Err(e) => {
+            warn!("Could not verify checksum of consumed document: {e}");
+            None
+        }
+    }
+}
+
+/// Whether `path` lies within the user's virtual root. Paths are resolved by libunftp against the
+/// working directory, so anything containing `..` is refused rather than normalized.
+fn within_root(user: &User, path: &Path) -> bool {
+    let Some(root) = &user.settings.root else {
+        return true;
+    };
+    if path
+        .components()
+        .any(|c| matches!(c, std::path::Component::ParentDir))
+    {
+        return false;
+    }
+    Path::new("/")
+        .join(path)
+        .starts_with(Path::new("/").join(root))
+}
+
+/// Map an I/O error on the staging file to 452 when the local disk is the problem.
+fn staging_error(e: std::io::Error) -> StorageError {
+    if e.kind() == std::io::ErrorKind::StorageFull {
+        StorageError::new(InsufficientStorageSpaceError, e)
+    } else {
+        e.into()
+    }
+}
+
+/// Map a failure to create the staging file to 553 when its name is the problem, e.g. too long
+/// for the file system, and like [`staging_error`] otherwise.
+fn staging_file_error(e: async_tempfile::Error) -> StorageError {
+    match e {
+        async_tempfile::Error::Io(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::InvalidFilename | std::io::ErrorKind::InvalidInput
+            ) =>
+        {
+            StorageError::new(FileNameNotAllowedError, e)
+        }
+        async_tempfile::Error::Io(e) => staging_error(e),
+        async_tempfile::Error::InvalidFile => StorageError::new(FileNameNotAllowedError, e),
+        e => StorageError::new(LocalError, e),
+    }
+}
+
+/// Remove the staging file of an aborted or incomplete transfer so it is never submitted.
+async fn discard_partial(writer: BufWriter<TempFile>, temp_path: &str) {
+    // Dropping the last handle already removes the file; this also covers a failed removal there.
+    drop(writer);
+    if let Err(e) = tokio::fs::remove_file(temp_path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!("Failed to remove partial upload {temp_path}: {e}");
+    }
+}
+
+/// An upload while and after it is received.
+enum Staged {
+    /// Small uploads are kept in memory and only written to a file in `dir` if one is needed.
+    Memory {
+        name: String,
+        dir: PathBuf,
+        document: Vec<u8>,
+    },
+    File {
+        writer: BufWriter<TempFile>,
+        path: String,
+    },
+}
+
+impl Staged {
+    /// Path of the staging file, which is written first if the upload is still in memory.
+    async fn file(&mut self) -> std::io::Result<&str> {
+        if let Staged::Memory {
+            name,
+            dir,
+            document,
+        } = self
+        {
+            let tempfile = TempFile::new_with_name_in(name.as_str(), dir.as_path())
+                .await
+                .map_err(|e| match e {
+                    async_tempfile::Error::Io(e) => e,
+                    e => std::io::Error::other(e),
+                })?;
+            let path = tempfile
+                .file_path()
+                .to_str()
+                .ok_or_else(|| std::io::Error::other("Staging path is not valid UTF-8"))?
+                .to_owned();
+            debug!("Writing upload staged in memory to {path}");
+            let mut writer = BufWriter::new(tempfile);
+            tokio::io::AsyncWriteExt::write_all(&mut writer, document).await?;
+            tokio::io::AsyncWriteExt::flush(&mut writer).await?;
+            *self = Staged::File { writer, path };
+        }
+        match self {
+            Staged::File { path, .. } => Ok(path),
+            Staged::Memory { .. } => unreachable!("the upload was just written to a file"),
+        }
+    }
+
+    /// File name of the upload, which Paperless uses as the initial document title.
+    fn name(&self) -> &str {
+        match self {
+            Staged::Memory { name, .. } => name,
+            Staged::File { path, .. } => Path::new(path)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default(),
+        }
+    }
+
+    async fn is_complete(&self) -> std::io::Result<bool> {
+        match self {
+            Staged::Memory { document, .. } => Ok(crate::document::is_complete_bytes(document)),
+            Staged::File { path, .. } => crate::document::is_complete(Path::new(path)).await,
+        }
+    }
+
+    async fn md5(&self) -> std::io::Result<String> {
+        match self {
+            Staged::Memory { document, .. } => Ok(crate::document::md5(document)),
+            Staged::File { path, .. } => crate::document::md5_file(Path::new(path)).await,
+        }
+    }
+
+    /// Drop an aborted or incomplete transfer so it is never submitted.
+    async fn discard(self) {
+        if let Staged::File { writer, path } = self {
+            discard_partial(writer, &path).await;
+        }
+    }
+}
+
+impl tokio::io::AsyncWrite for Staged {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Staged::Memory { document, .. } => Pin::new(document).poll_write(cx, buf),
+            Staged::File { writer, .. } => Pin::new(writer).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Staged::Memory { document, .. } => Pin::new(document).poll_flush(cx),
+            Staged::File { writer, .. } => Pin::new(writer).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Staged::Memory { document, .. } => Pin::new(document).poll_shutdown(cx),
+            Staged::File { writer, .. } => Pin::new(writer).poll_shutdown(cx),
+        }
     }
 }
 
@@ -112,6 +1103,14 @@ impl Metadata for Meta {
 impl StorageBackend<User> for PaperlessStorage {
     type Metadata = Meta;
 
+    fn supported_features(&self) -> u32 {
+        if self.quirks.minimal_features {
+            0
+        } else {
+            FEATURE_SITEMD5
+        }
+    }
+
     async fn metadata<P: AsRef<Path> + Send + Debug>(
         &self,
         _user: &User,
@@ -123,13 +1122,14 @@ impl StorageBackend<User> for PaperlessStorage {
 
     async fn list<P: AsRef<Path> + Send + Debug>(
         &self,
-        _user: &User,
+        user: &User,
         path: P,
     ) -> StorageResult<Vec<Fileinfo<PathBuf, Self::Metadata>>>
     where
         <Self as StorageBackend<User>>::Metadata: Metadata,
     {
         debug!("LIST called for path: {:?}", path.as_ref());
+        self.check_session(user)?;
         Ok(vec![])
     }
 
@@ -147,12 +1147,17 @@ impl StorageBackend<User> for PaperlessStorage {
         R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static,
     >(
         &self,
-        _user: &User,
+        user: &User,
         input: R,
         path: P,
         start_pos: u64,
     ) -> StorageResult<u64> {
-        info!("Received upload request");
+        let request_id = crate::paperless::new_request_id();
+        info!(
+            "Received upload request {request_id} for {:?}",
+            path.as_ref()
+        );
+        self.check_session(user)?;
 
         // A login may have been admitted just before the monitor detected an outage.
         // Reject before reading document bytes so the scanner gets prompt feedback.
@@ -161,89 +1166,838 @@ impl StorageBackend<User> for PaperlessStorage {
             return Err(StorageError::new(TransientFileNotAvailable, error));
         }
 
-        // Save to temp file first
-        let mut tempfile =
-            if let Some(file_name) = path.as_ref().file_name().map(|x| x.to_string_lossy()) {
-                TempFile::new_with_name(file_name).await.unwrap()
-            } else {
-                TempFile::new().await.unwrap()
-            };
-        let temp_path = tempfile.file_path().to_str().unwrap().to_owned();
-        debug!("Saving upload to {temp_path}");
+        if !within_root(user, path.as_ref()) {
+            warn!("Rejecting upload outside of the virtual root of {user}");
+            return Err(StorageError::new(
+                PermissionDenied,
+                "Uploads outside of your directory are not allowed",
+            ));
+        }
 
-        tempfile.set_len(start_pos).await.unwrap();
-        tempfile
-            .seek(std::io::SeekFrom::Start(start_pos))
-            .await
-            .unwrap();
+        // The directory of a tenant selects its Paperless, the rest of the path is routed as usual.
+        let (client, routing_path, tenant) = if self.tenants.is_empty() {
+            (self.user_client(user)?, path.as_ref().to_path_buf(), None)
+        } else {
+            match crate::tenant::select(&self.tenants, &user.username, path.as_ref()) {
+                Ok((name, tenant, rest)) => (Arc::clone(&tenant.client), rest, Some(name)),
+                Err(reason) => {
+                    warn!("Rejecting upload {request_id}: {reason}");
+                    return Err(StorageError::new(PermissionDenied, reason));
+                }
+            }
+        };
+        if let Some(tenant) = tenant {
+            debug!("Routing upload {request_id} to tenant {tenant}");
+        }
+        // Each tenant has a spool of its own, so its backlog can't fill the spool of the others.
+        let spool_dir = self
+            .spool_dir
+            .as_ref()
+            .map(|dir| tenant.map_or_else(|| dir.clone(), |tenant| dir.join(tenant)));
+
+        // Sessions may outlast the end of a time window.
+        if !crate::schedule::is_allowed_now(&user.settings.access_hours) {
+            warn!("Rejecting upload of {user} outside of its access hours");
+            return Err(StorageError::new(
+                PermissionDenied,
+                "Uploads are not allowed at this time",
+            ));
+        }
 
-        let mut reader = tokio::io::BufReader::with_capacity(4096, input);
-        let mut writer = tokio::io::BufWriter::with_capacity(4096, tempfile);
-        let bytes_copied = tokio::io::copy(&mut reader, &mut writer).await?;
-        // Flush to ensure all data is written before we might spool the file
-        tokio::io::AsyncWriteExt::flush(&mut writer).await?;
+        if let Err(exceeded) = self.quota.check(&user.username, &user.settings.limits) {
+            warn!("Rejecting upload of {user}: {exceeded}");
+            crate::metrics::QUOTA_REJECTIONS
+                .with_label_values(&[&user.username, exceeded.label()])
+                .inc();
+            return Err(StorageError::new(ExceededStorageAllocationError, exceeded));
+        }
 
-        // Pre-upload health check
-        if let Err(e) = self.paperless_client.health_check().await {
-            self.paperless_health.mark_unhealthy(&e);
-            warn!("Pre-upload health check failed: {e}");
-            return self
-                .handle_upload_failure(&temp_path, e, bytes_copied)
-                .await;
+        if let Some(reason) = self.disk_capacity.as_ref().and_then(DiskCapacity::shortage) {
+            error!("Rejecting upload, the disk is running full: {reason}");
+            crate::metrics::DISK_LOW_REJECTIONS.inc();
+            return Err(StorageError::new(
+                InsufficientStorageSpaceError,
+                "The bridge is running out of disk space, try again later",
+            ));
         }
-        self.paperless_health.mark_healthy();
 
-        // Upload with retry
-        let mut last_err = None;
-        for attempt in 0..MAX_UPLOAD_RETRIES {
-            match self.paperless_client.upload(&temp_path).await {
-                Ok(_task_id) => {
-                    info!("File uploaded successfully");
-                    return Ok(bytes_copied);
-                }
-                Err(e) => {
-                    warn!("Upload attempt {} failed: {e}", attempt + 1);
-                    last_err = Some(e);
-                }
-            }
+        if let Some(ref spool_dir) = spool_dir
+            && let Some(reason) = self.spool_limits.exceeded(spool_dir)
+        {
+            error!("Rejecting upload, the spool is full: {reason}. Is Paperless down?");
+            crate::metrics::SPOOL_FULL_REJECTIONS.inc();
+            return Err(StorageError::new(
+                InsufficientStorageSpaceError,
+                "Too many documents are waiting for Paperless, try again later",
+            ));
+        }
+
+        // A resumed transfer would need the partial file of the aborted one, which we discard.
+        if start_pos != 0 {
+            warn!("Rejecting upload resumed at offset {start_pos}; partial uploads are not kept");
+            return Err(StorageError::new(
+                PermanentFileNotAvailable,
+                "Resuming uploads is not supported",
+            ));
+        }
 
-            if attempt + 1 < MAX_UPLOAD_RETRIES {
-                let delay =
-                    Duration::from_millis(INITIAL_RETRY_DELAY_MS * 2u64.pow(attempt as u32));
-                debug!("Retrying in {}ms", delay.as_millis());
-                sleep(delay).await;
+        if let Some(name) = self.client_name(path.as_ref())
+            && let Some(target) = sidecar_target(&name)
+        {
+            return self.receive_sidecar(&name, target, input).await;
+        }
+
+        let started = Instant::now();
+        let mut reader = tokio::io::BufReader::with_capacity(self.buffer_size, input);
+        let (sniffed_extension, unsupported) = match reader.fill_buf().await {
+            Ok(head) => (
+                sniff_extension(head),
+                crate::normalize::unsupported(head, self.client_name(path.as_ref()).as_deref()),
+            ),
+            Err(e) => {
+                warn!("Transfer aborted before any data was received: {e}");
+                return Err(e.into());
             }
+        };
+        if let Some(guidance) = unsupported {
+            warn!("Rejecting upload {request_id}: {guidance}");
+            return Err(StorageError::new(FileNameNotAllowedError, guidance));
         }
 
-        let err = last_err.unwrap();
-        error!("Upload failed after {MAX_UPLOAD_RETRIES} attempts: {err}");
-        self.handle_upload_failure(&temp_path, err, bytes_copied)
-            .await
-    }
+        // An unknown extension is fine if the content is a known type, the name is corrected then.
+        // Temporary names are still rejected, their upload is complete only with the rename.
+        if let Some(name) = self.client_name(path.as_ref())
+            && !crate::document::is_supported_filename(Path::new(&name))
+            && (sniffed_extension.is_none() || strip_temp_suffix(&name).is_some())
+        {
+            warn!(
+                "Rejecting upload of unsupported file type: {:?}",
+                path.as_ref()
+            );
+            return Err(StorageError::new(
+                FileNameNotAllowedError,
+                "Paperless can't consume this file type",
+            ));
+        }
 
-    async fn del<P: AsRef<Path> + Send + Debug>(
-        &self,
-        _user: &User,
-        _path: P,
-    ) -> StorageResult<()> {
-        unimplemented!()
+        // Small files are staged in memory, which spares the flash storage of embedded devices.
+        let mut prefix = Vec::new();
+        let mut in_memory = false;
+        if let Some(threshold) = self.memory_staging_threshold {
+            if let Err(e) = (&mut reader)
+                .take(threshold + 1)
+                .read_to_end(&mut prefix)
+                .await
+            {
+                warn!("Transfer aborted, discarding partial upload: {e}");
+                return Err(e.into());
+            }
+            in_memory = prefix.len() as u64 <= threshold;
+        }
+
+        let mut staging_dir = std::env::temp_dir();
+        if let Some(tenant) = tenant {
+            staging_dir.push(tenant);
+            if let Err(e) = tokio::fs::create_dir_all(&staging_dir).await {
+                error!("Failed to create staging directory: {e}");
+                return Err(staging_error(e));
+            }
+        }
+
+        let staging_name = self.staging_name(path.as_ref(), sniffed_extension);
+        let mut staged = match staging_name {
+            Some(name) if in_memory => {
+                debug!("Staging upload of {} bytes in memory", prefix.len());
+                Staged::Memory {
+                    name,
+                    dir: staging_dir,
+                    document: Vec::with_capacity(prefix.len()),
+                }
+            }
+            staging_name => {
+                // Save to temp file first. Dropping the TempFile deletes it, so an ABOR that
+                // cancels this future mid-transfer never leaves a partial document behind.
+                let tempfile = if let Some(file_name) = staging_name {
+                    TempFile::new_with_name_in(file_name, staging_dir).await
+                } else {
+                    TempFile::new_in(staging_dir).await
+                }
+                .map_err(|e| {
+                    error!("Failed to create staging file: {e}");
+                    staging_file_error(e)
+                })?;
+                let Some(temp_path) = tempfile.file_path().to_str().map(str::to_owned) else {
+                    error!(
+                        "Staging path {} is not valid UTF-8",
+                        tempfile.file_path().display()
+                    );
+                    return Err(StorageError::new(
+                        LocalError,
+                        "Staging path is not valid UTF-8",
+                    ));
+                };
+                debug!("Saving upload to {temp_path}");
+                Staged::File {
+                    writer: BufWriter::with_capacity(self.buffer_size, tempfile),
+                    path: temp_path,
+                }
+            }
+        };
+
+        // Read one byte past the limit so an oversized upload can be told apart from one that is
+        // exactly at the limit.
+        let read_limit = self.max_upload_size.map_or(u64::MAX, |max| max + 1);
+        // Counts against the budget until the staged file was taken care of.
+        let transfer = self.budget.as_ref().map(TransferBudget::start);
+        // Listed with the session until the transfer is over.
+        let session_transfer = self
+            .session
+            .as_ref()
+            .map(|session| session.start_transfer(path.as_ref()));
+        // Once the sniffed head is consumed, reads of a whole buffer bypass the inner BufReader
+        // and copy_buf hands the outer buffer straight to the writer.
+        let mut reader = tokio::io::BufReader::with_capacity(
+            self.buffer_size,
+            BudgetReader::new(
+                ProgressReader::new(
+                    std::io::Cursor::new(prefix).chain(reader).take(read_limit),
+                    format!("{:?}", path.as_ref()),
+                    self.progress_threshold,
+                    started,
+                )
+                .with_counter(session_transfer.as_ref().map(Transfer::counter)),
+                transfer.clone(),
+            ),
+        );
+        let transfer_started = Instant::now();
+        // Terminating a stuck session drops the data connection, which frees its passive port.
+        let copied = tokio::select! {
+            copied = tokio::io::copy_buf(&mut reader, &mut staged) => Some(copied),
+            () = self.session_terminated() => None,
+        };
+        // Closing the data connection right away frees its passive port for the next scanner while
+        // the document is staged and uploaded.
+        drop((reader, session_transfer));
+        let bytes_copied = match copied {
+            Some(Ok(bytes_copied)) => bytes_copied,
+            Some(Err(e)) => {
+                warn!("Transfer aborted, discarding partial upload: {e}");
+                staged.discard().await;
+                return Err(staging_error(e));
+            }
+            None => {
+                warn!("Session terminated during upload {request_id}, discarding partial upload");
+                staged.discard().await;
+                return Err(StorageError::new(
+                    ConnectionClosed,
+                    "The session was terminated",
+                ));
+            }
+        };
+        let transfer_time = transfer_started.elapsed();
+        let staging_started = Instant::now();
+        // Flush to ensure all data is written before we might spool the file
+        if let Err(e) = tokio::io::AsyncWriteExt::flush(&mut staged).await {
+            staged.discard().await;
+            return Err(staging_error(e));
+        }
+
+        if let Some(max) = self.max_upload_size
+            && bytes_copied > max
+        {
+            warn!("Rejecting upload exceeding the maximum size of {max} bytes");
+            staged.discard().await;
+            return Err(StorageError::new(
+                ExceededStorageAllocationError,
+                "Upload exceeds the maximum size",
+            ));
+        }
+
+        if bytes_copied == 0 {
+            // Some scanners probe the destination with an empty file before the real scan.
+            warn!(
+                "Ignoring empty upload {request_id} of {:?}, nothing is sent to Paperless",
+                path.as_ref()
+            );
+            staged.discard().await;
+            return Ok(0);
+        }
+
+        match staged.is_complete().await {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("Discarding truncated upload after {bytes_copied} bytes");
+                staged.discard().await;
+                return Err(StorageError::new(
+                    LocalError,
+                    "Transfer incomplete, document is truncated",
+                ));
+            }
+            Err(e) => {
+                staged.discard().await;
+                return Err(staging_error(e));
+            }
+        }
+
+        // Repaired before the checksum, which has to match the document Paperless stores.
+        if self.quirks.repair_pdf {
+            let repaired = match staged.file().await {
+                Ok(temp_path) => crate::normalize::repair_pdf(Path::new(temp_path)).await,
+                Err(e) => Err(e),
+            };
+            match repaired {
+                Ok(true) => info!("Rebuilt the cross-reference table of upload {request_id}"),
+                Ok(false) => {}
+                Err(e) => {
+                    staged.discard().await;
+                    return Err(staging_error(e));
+                }
+            }
+        }
+
+        if let Some(ref settle) = self.settle
+            && self.is_separator_page(&mut staged).await
+        {
+            info!(
+                "Starting a new document after separator page {:?}",
+                path.as_ref()
+            );
+            settle.split(tenant, &user.username);
+            return Ok(bytes_copied);
+        }
+
+        if self.is_blank_page(&mut staged).await {
+            info!("Dropping blank page {:?}", path.as_ref());
+            crate::metrics::BLANK_PAGES.inc();
+            return Ok(bytes_copied);
+        }
+
+        let checksum = match staged.md5().await {
+            Ok(checksum) => {
+                info!(
+                    "Received {:?}: {bytes_copied} bytes at {}, md5 {checksum}",
+                    path.as_ref(),
+                    format_rate(bytes_copied, started.elapsed())
+                );
+                self.checksums
+                    .lock()
+                    .expect("checksum lock poisoned")
+                    .insert(path.as_ref().to_path_buf(), checksum.clone());
+                Some(checksum)
+            }
+            Err(e) => {
+                warn!("Failed to compute checksum of staged upload: {e}");
+                None
+            }
+        };
+
+        if let Some(ref checksum) = checksum
+            && self
+                .sent_checksums
+                .lock()
+                .expect("checksum lock poisoned")
+                .contains(checksum)
+        {
+            // Some scanners send a file again when they missed the reply to the first transfer.
+            info!(
+                "Ignoring {:?}, the same file was already received in this session",
+                path.as_ref()
+            );
+            return Ok(bytes_copied);
+        }
+
+        if let Err(exceeded) =
+            self.quota
+                .record(&user.username, &user.settings.limits, bytes_copied)
+        {
+            warn!("Rejecting upload of {user}: {exceeded}");
+            crate::metrics::QUOTA_REJECTIONS
+                .with_label_values(&[&user.username, exceeded.label()])
+                .inc();
+            staged.discard().await;
+            return Err(StorageError::new(ExceededStorageAllocationError, exceeded));
+        }
+
+        let staging_time = staging_started.elapsed();
+        crate::metrics::observe_phase("transfer", transfer_time);
+        crate::metrics::observe_phase("staging", staging_time);
+
+        let mut options = UploadOptions {
+            tags: user.settings.tags.clone(),
+            request_id: Some(request_id.clone()),
+            ..Default::default()
+        };
+        // Named in the title template.
+        let mut correspondent_name = None;
+        // Objects named by the metadata that Paperless doesn't have.
+        let mut unresolved = Vec::new();
+        let barcodes = self.first_page_barcodes(&mut staged).await;
+        if !barcodes.is_empty() {
+            debug!("Barcodes on the first page of upload {request_id}: {barcodes:?}");
+        }
+        if let Some(prefix) = &self.asn_prefix {
+            options.archive_serial_number =
+                crate::metadata::archive_serial_number(&barcodes, prefix);
+        }
+        if !self.rules.rules.is_empty() {
+            let text = if self.rules.needs_text() {
+                let text = match staged.file().await {
+                    Ok(temp_path) => crate::extract::pdf_text(Path::new(temp_path)).await,
+                    Err(e) => Err(e),
+                };
+                text.unwrap_or_else(|e| {
+                    warn!("Failed to read the text of upload {request_id}: {e}");
+                    None
+                })
+            } else {
+                None
+            };
+            let name = self.client_name(path.as_ref()).unwrap_or_default();
+            let matched = self.rules.evaluate_scan(&name, text.as_deref(), &barcodes);
+            if matched != DocumentMetadata::default() {
+                debug!("Rules matched upload {request_id}: {matched:?}");
+                correspondent_name = matched.correspondent_name().map(str::to_string);
+                unresolved.extend(
+                    crate::metadata::apply(
+                        client.as_ref(),
+                        &matched,
+                        &mut options,
+                        &self.resolve_options,
+                    )
+                    .await,
+                );
+            }
+        }
+        // Metadata directories like `/tags=invoice/` take precedence over the rules.
+        let directories = crate::metadata::from_directories(&routing_path);
+        if directories != DocumentMetadata::default() {
+            debug!("Applying directory metadata to upload {request_id}: {directories:?}");
+            if let Some(name) = directories.correspondent_name() {
+                correspondent_name = Some(name.to_string());
+            }
+            unresolved.extend(
+                crate::metadata::apply(
+                    client.as_ref(),
+                    &directories,
+                    &mut options,
+                    &self.resolve_options,
+                )
+                .await,
+            );
+        }
+        // Sidecar files take precedence over the rules and directories.
+        let sidecar = self.client_name(path.as_ref()).and_then(|name| {
+            self.sidecars
+                .lock()
+                .expect("sidecar lock poisoned")
+                .remove(&name)
+        });
+        if let Some(metadata) = sidecar {
+            debug!("Applying sidecar metadata to upload {request_id}: {metadata:?}");
+            if let Some(name) = metadata.correspondent_name() {
+                correspondent_name = Some(name.to_string());
+            }
+            unresolved.extend(
+                crate::metadata::apply(
+                    client.as_ref(),
+                    &metadata,
+                    &mut options,
+                    &self.resolve_options,
+                )
+                .await,
+            );
+        }
+        if !unresolved.is_empty() {
+            let unresolved = unresolved.join(", ");
+            match self.resolve_options.unresolved {
+                UnresolvedPolicy::Skip => {}
+                UnresolvedPolicy::Tag => {
+                    warn!("Tagging upload {request_id}, Paperless has no {unresolved}");
+                    if let Some(tag) = self.resolve_options.unresolved_tag
+                        && !options.tags.contains(&tag)
+                    {
+                        options.tags.push(tag);
+                    }
+                }
+                UnresolvedPolicy::Reject => {
+                    let reason = format!("Paperless has no {unresolved}");
+                    error!("Rejecting upload {request_id}: {reason}");
+                    crate::metrics::UPLOAD_FAILURES
+                        .with_label_values(&["unresolved_metadata"])
+                        .inc();
+                    staged.discard().await;
+                    self.notify_failure(user, path.as_ref(), &reason);
+                    return Err(StorageError::new(PermanentFileNotAvailable, reason));
+                }
+            }
+        }
+        if !self.embedded_fields.is_empty() {
+            let embedded = match staged.file().await {
+                Ok(temp_path) => crate::extract::read_embedded(Path::new(temp_path)).await,
+                Err(e) => Err(e),
+            };
+            match embedded {
+                Ok(embedded) => {
+                    debug!("Metadata embedded in upload {request_id}: {embedded:?}");
+                    if self.embedded_fields.contains(&EmbeddedField::Created)
+                        && options.created.is_none()
+                    {
+                        options.created = embedded.created;
+                    }
+                    if self.embedded_fields.contains(&EmbeddedField::Title)
+                        && options.title.is_none()
+                    {
+                        options.title = embedded.title;
+                    }
+                }
+                Err(e) => warn!("Failed to read the metadata embedded in upload {request_id}: {e}"),
+            }
+        }
+        let filename = self.client_name(path.as_ref()).unwrap_or_default();
+        let context = TitleContext {
+            path: &routing_path,
+            filename: &filename,
+            user: &user.username,
+            title: options.title.as_deref(),
+            correspondent: correspondent_name.as_deref(),
+            created: options.created.as_deref(),
+            received: chrono::Local::now(),
+        };
+        let source = self
+            .source_field
+            .as_ref()
+            .map(|field| (field.id, field.value.render(&context)));
+        if let Some(ref template) = self.title_template {
+            let title = template.render(&context);
+            debug!("Title of upload {request_id}: {title:?}");
+            options.title = Some(title);
+        }
+        if let Some(source) = source {
+            debug!("Source of upload {request_id}: {:?}", source.1);
+            options.custom_fields.push(source);
+        }
+
+        if let Some(ref settle) = self.settle {
+            let name = self
+                .client_name(path.as_ref())
+                .unwrap_or_else(|| "scan".to_string());
+            let temp_path = match staged.file().await {
+                Ok(temp_path) => temp_path,
+                Err(e) => {
+                    error!("Failed to queue upload {request_id}: {e}");
+                    return Err(staging_error(e));
+                }
+            };
+            return match settle
+                .add(
+                    client,
+                    tenant,
+                    &user.username,
+                    &crate::metrics::client_label(user.client_ip),
+                    &name,
+                    Path::new(temp_path),
+                    options,
+                )
+                .await
+            {
+                Ok(()) => {
+                    info!("Holding upload {request_id} until no more files arrive");
+                    self.mark_sent(path.as_ref());
+                    Ok(bytes_copied)
+                }
+                Err(e) => {
+                    error!("Failed to queue upload {request_id}: {e}");
+                    Err(staging_error(e))
+                }
+            };
+        }
+
+        if !self.breaker.allows_request() {
+            warn!(
+                "Not calling Paperless for upload {request_id} while the circuit breaker is open"
+            );
+            return self
+                .handle_upload_failure(
+                    user,
+                    path.as_ref(),
+                    &mut staged,
+                    &options,
+                    spool_dir.as_deref(),
+                    PaperlessError::Api("Paperless keeps failing, try again later".to_string()),
+                    bytes_copied,
+                )
+                .await;
+        }
+
+        // Pre-upload health check
+        if let Err(e) = client.health_check().await {
+            self.breaker.record_failure();
+            // The health of the bridge is that of its own Paperless.
+            if tenant.is_none() {
+                self.paperless_health.mark_unhealthy(&e);
+            }
+            warn!("Pre-upload health check failed: {e}");
+            return self
+                .handle_upload_failure(
+                    user,
+                    path.as_ref(),
+                    &mut staged,
+                    &options,
+                    spool_dir.as_deref(),
+                    e,
+                    bytes_copied,
+                )
+                .await;
+        }
+        if tenant.is_none() {
+            self.paperless_health.mark_healthy();
+        }
+
+        let upload_started = Instant::now();
+        let uploaded = match &staged {
+            Staged::Memory { name, document, .. } => {
+                let document = Bytes::copy_from_slice(document);
+                upload_bytes_with_retries(client.as_ref(), &self.breaker, name, document, &options)
+                    .await
+            }
+            Staged::File { path, .. } => {
+                upload_with_retries(client.as_ref(), &self.breaker, path, &options).await
+            }
+        };
+        match uploaded {
+            Ok(task_id) => {
+                let upload_time = upload_started.elapsed();
+                crate::metrics::observe_phase("upload", upload_time);
+                info!(
+                    "File uploaded successfully as request {request_id} (transfer {:.1}s, staging {:.1}s, upload {:.1}s)",
+                    transfer_time.as_secs_f64(),
+                    staging_time.as_secs_f64(),
+                    upload_time.as_secs_f64()
+                );
+                let labels = [
+                    user.username.clone(),
+                    crate::metrics::client_label(user.client_ip),
+                ];
+                crate::metrics::UPLOADS.with_label_values(&labels).inc();
+                crate::metrics::UPLOAD_BYTES
+                    .with_label_values(&labels)
+                    .inc_by(bytes_copied);
+                crate::metrics::UPLOAD_SECONDS
+                    .with_label_values(&labels)
+                    .observe(started.elapsed().as_secs_f64());
+                let mut consumed = false;
+                let mut duplicate = false;
+                let mut document_id = None;
+                if let Some(timeout) = self.consumption_wait {
+                    let started = Instant::now();
+                    match wait_for_task(client.as_ref(), &task_id, timeout).await {
+                        Ok(TaskStatus::Failure(reason)) => {
+                            if consumption_failed(&task_id, &reason, self.duplicates) {
+                                self.notify_failure(user, path.as_ref(), &reason);
+                                return Err(StorageError::new(
+                                    PermanentFileNotAvailable,
+                                    PaperlessError::ConsumptionFailed { task_id, reason },
+                                ));
+                            }
+                            // An accepted duplicate is already in Paperless: the transfer
+                            // succeeds, a re-send is ignored and there is no new document to
+                            // verify or follow.
+                            consumed = true;
+                            duplicate = true;
+                        }
+                        Ok(status) => {
+                            crate::metrics::observe_phase("consumption", started.elapsed());
+                            info!("Paperless consumed upload {request_id}");
+                            consumed = true;
+                            if let TaskStatus::Success { document_id: id } = status {
+                                document_id = id;
+                            }
+                        }
+                        Err(e) => debug!("Not waiting any longer for upload {request_id}: {e}"),
+                    }
+                }
+                // Only now, so the scanner's retry of a document Paperless failed to consume is
+                // uploaded again instead of being ignored as a re-send.
+                self.mark_sent(path.as_ref());
+                // Consumption can take minutes, so don't hold the scanner's transfer any longer.
+                let client = Arc::clone(&client);
+                let hook = self.success_hook.clone().map(|hook| {
+                    let title = options.title.clone().unwrap_or_else(|| {
+                        Path::new(staged.name())
+                            .file_stem()
+                            .map(|stem| stem.to_string_lossy().into_owned())
+                            .unwrap_or_default()
+                    });
+                    let upload = Upload {
+                        title,
+                        user: user.username.clone(),
+                        source: path.as_ref().display().to_string(),
+                    };
+                    (hook, upload)
+                });
+                let tracked = TrackedTask::new(
+                    &task_id,
+                    Some(user.username.as_str()),
+                    &path.as_ref().display().to_string(),
+                    tenant,
+                );
+                let user = user.username.clone();
+                let name = self.client_name(path.as_ref()).unwrap_or_default();
+                let document = checksum.clone();
+                match checksum {
+                    Some(checksum) if self.verify_checksums && !duplicate => {
+                        self.notifier.resolve(&checksum);
+                        self.notifier.notify(Event::Uploaded { user, name });
+                        self.tasks.spawn(tracked, async move {
+                            let document_id =
+                                log_checksum_verification(client, task_id, checksum).await;
+                            run_success_hook(hook, document_id).await;
+                        });
+                    }
+                    _ if consumed => {
+                        if let Some(document) = &document {
+                            self.notifier.resolve(document);
+                        }
+                        self.notifier.notify(Event::Uploaded { user, name });
+                        tokio::spawn(run_success_hook(hook, document_id));
+                    }
+                    _ => {
+                        let (notifier, duplicates) = (self.notifier.clone(), self.duplicates);
+                        self.tasks.spawn(tracked, async move {
+                            match log_consumption(client, task_id, duplicates).await {
+                                Ok(document_id) => {
+                                    if let Some(document) = &document {
+                                        notifier.resolve(document);
+                                    }
+                                    notifier.notify(Event::Uploaded { user, name });
+                                    run_success_hook(hook, document_id).await;
+                                }
+                                Err(reason) => notifier.notify_failure(
+                                    document.as_deref(),
+                                    Event::Failed {
+                                        user,
+                                        name,
+                                        reason,
+                                        attempts: 1,
+                                    },
+                                ),
+                            }
+                        });
+                    }
+                }
+                Ok(bytes_copied)
+            }
+            Err(err) => {
+                error!("Upload {request_id} failed: {err}");
+                self.handle_upload_failure(
+                    user,
+                    path.as_ref(),
+                    &mut staged,
+                    &options,
+                    spool_dir.as_deref(),
+                    err,
+                    bytes_copied,
+                )
+                .await
+            }
+        }
     }
 
-    async fn mkd<P: AsRef<Path> + Send + Debug>(
+    async fn md5<P: AsRef<Path> + Send + Debug>(
         &self,
         _user: &User,
-        _path: P,
-    ) -> StorageResult<()> {
-        unimplemented!()
+        path: P,
+    ) -> StorageResult<String> {
+        debug!("MD5 called for path: {:?}", path.as_ref());
+        self.checksums
+            .lock()
+            .expect("checksum lock poisoned")
+            .get(path.as_ref())
+            .cloned()
+            .ok_or_else(|| {
+                StorageError::new(
+                    PermanentFileNotAvailable,
+                    "No file with this name was received in this session",
+                )
+            })
+    }
+
+    async fn del<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> StorageResult<()> {
+        debug!("DELE called for path: {:?}", path.as_ref());
+        if let Some(ref settle) = self.settle
+            && let Some(name) = self.client_name(path.as_ref())
+            && settle
+                .cancel(self.tenant_of(user, path.as_ref()), &user.username, &name)
+                .await
+        {
+            info!("Cancelled pending upload of {name:?}");
+            self.unmark_sent(path.as_ref());
+            return Ok(());
+        }
+
+        let spooled = self
+            .spooled
+            .lock()
+            .expect("spooled files lock poisoned")
+            .remove(path.as_ref());
+        if let Some(spool_path) = spooled {
+            return match crate::spool::remove_spooled(&spool_path) {
+                Ok(()) => {
+                    info!("Cancelled spooled upload {}", spool_path.display());
+                    self.unmark_sent(path.as_ref());
+                    Ok(())
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to cancel spooled upload {}: {e}",
+                        spool_path.display()
+                    );
+                    Err(StorageError::new(
+                        PermanentFileNotAvailable,
+                        "The file was already submitted to Paperless",
+                    ))
+                }
+            };
+        }
+
+        Err(StorageError::new(
+            PermanentFileNotAvailable,
+            "No pending upload with this name",
+        ))
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> StorageResult<()> {
+        debug!("MKD called for path: {:?}", path.as_ref());
+        if self.quirks.accept_mkd {
+            Ok(())
+        } else {
+            Err(StorageError::new(
+                PermissionDenied,
+                "Directories can't be created",
+            ))
+        }
     }
 
     async fn rename<P: AsRef<Path> + Send + Debug>(
         &self,
         _user: &User,
-        _from: P,
-        _to: P,
+        from: P,
+        to: P,
     ) -> StorageResult<()> {
-        unimplemented!()
+        debug!(
+            "RENAME called from {:?} to {:?}",
+            from.as_ref(),
+            to.as_ref()
+        );
+        if self.quirks.temp_rename {
+            // The upload was already forwarded under its final name.
+            Ok(())
+        } else {
+            Err(StorageError::new(
+                PermissionDenied,
+                "Uploaded files can't be renamed",
+            ))
+        }
     }
 
     async fn rmd<P: AsRef<Path> + Send + Debug>(
@@ -254,102 +2008,194 @@ impl StorageBackend<User> for PaperlessStorage {
         unimplemented!()
     }
 
-    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, _user: &User, path: P) -> StorageResult<()> {
+    async fn cwd<P: AsRef<Path> + Send + Debug>(&self, user: &User, path: P) -> StorageResult<()> {
         debug!("CWD called for path: {:?}", path.as_ref());
-        Ok(())
+        self.check_session(user)?;
+        if within_root(user, path.as_ref()) {
+            Ok(())
+        } else {
+            Err(StorageError::new(
+                PermissionDenied,
+                "Directories outside of your directory are not accessible",
+            ))
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::paperless::PaperlessError;
-    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::metadata::ObjectKind;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
     fn healthy_status() -> PaperlessHealth {
         PaperlessHealth::new_healthy(Duration::from_secs(60))
     }
 
-    /// Mock that fails N times then succeeds on upload, always succeeds on health_check
-    struct RetryMockClient {
-        fail_count: AtomicUsize,
-        failures_remaining: AtomicUsize,
+    fn unreachable_error() -> PaperlessError {
+        PaperlessError::Io(std::io::Error::other("dns error: Name does not resolve"))
+    }
+
+    /// Configurable mock of Paperless that records what it was asked. By default every request
+    /// succeeds and consumption tasks end with document 1.
+    #[derive(Default)]
+    struct MockClient {
+        /// Uploads that fail like an unreachable Paperless before they succeed.
+        failures: AtomicUsize,
+        /// Every request fails like an unreachable Paperless.
+        unreachable: bool,
+        health_check_fails: bool,
+        /// Uploads are refused as invalid documents.
+        rejects: bool,
+        /// Paperless fails to consume uploads for this reason.
+        consumption_failure: Option<&'static str>,
+        /// Whether the data connection was closed, recorded at each upload in `closed_at_upload`.
+        connection: Option<Arc<AtomicBool>>,
+        closed_at_upload: AtomicBool,
+        uploads: AtomicUsize,
+        /// Uploads of documents staged in memory, also counted in `uploads`.
+        uploads_from_memory: AtomicUsize,
+        health_checks: AtomicUsize,
+        options: Mutex<Vec<UploadOptions>>,
+        paths: Mutex<Vec<String>>,
+        created: Mutex<Vec<(ObjectKind, String, Option<u64>)>>,
     }
 
-    impl RetryMockClient {
-        fn new(fail_n_times: usize) -> Self {
+    impl MockClient {
+        fn failing(failures: usize) -> Self {
             Self {
-                fail_count: AtomicUsize::new(0),
-                failures_remaining: AtomicUsize::new(fail_n_times),
+                failures: AtomicUsize::new(failures),
+                ..Default::default()
             }
         }
-    }
 
-    #[async_trait]
-    impl PaperlessApi for RetryMockClient {
-        async fn health_check(&self) -> Result<(), PaperlessError> {
-            Ok(())
+        fn unreachable() -> Self {
+            Self {
+                unreachable: true,
+                ..Default::default()
+            }
         }
 
-        async fn upload(&self, _path: &str) -> Result<String, PaperlessError> {
-            self.fail_count.fetch_add(1, Ordering::SeqCst);
-            let remaining = self.failures_remaining.fetch_sub(1, Ordering::SeqCst);
-            if remaining > 0 {
-                Err(PaperlessError::Io(std::io::Error::other(
-                    "dns error: Name does not resolve",
-                )))
-            } else {
-                Ok("test-task-id".to_string())
+        fn rejecting() -> Self {
+            Self {
+                rejects: true,
+                ..Default::default()
             }
         }
-    }
-
-    /// Mock that always fails upload (for spool testing)
-    struct AlwaysFailClient;
 
-    #[async_trait]
-    impl PaperlessApi for AlwaysFailClient {
-        async fn health_check(&self) -> Result<(), PaperlessError> {
-            Err(PaperlessError::Io(std::io::Error::other(
-                "dns error: Name does not resolve",
-            )))
+        fn failing_consumption(reason: &'static str) -> Self {
+            Self {
+                consumption_failure: Some(reason),
+                ..Default::default()
+            }
         }
 
-        async fn upload(&self, _path: &str) -> Result<String, PaperlessError> {
-            Err(PaperlessError::Io(std::io::Error::other(
-                "dns error: Name does not resolve",
-            )))
+        fn accept(&self, path: &str, options: &UploadOptions) -> Result<String, PaperlessError> {
+            self.uploads.fetch_add(1, Ordering::SeqCst);
+            if let Some(connection) = &self.connection {
+                let closed = connection.load(Ordering::SeqCst);
+                self.closed_at_upload.store(closed, Ordering::SeqCst);
+            }
+            self.check_reachable()?;
+            let failed = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            if failed {
+                return Err(unreachable_error());
+            }
+            if self.rejects {
+                return Err(PaperlessError::Validation(
+                    "400 Bad Request: File type not supported".to_string(),
+                ));
+            }
+            self.options.lock().unwrap().push(options.clone());
+            self.paths.lock().unwrap().push(path.to_string());
+            Ok("test-task-id".to_string())
         }
-    }
-
-    /// Mock that tracks health_check calls, fails health_check but would succeed upload
-    struct HealthCheckTrackingClient {
-        health_check_count: AtomicUsize,
-        health_check_fails: bool,
-    }
 
-    impl HealthCheckTrackingClient {
-        fn new(health_check_fails: bool) -> Self {
-            Self {
-                health_check_count: AtomicUsize::new(0),
-                health_check_fails,
+        fn check_reachable(&self) -> Result<(), PaperlessError> {
+            match self.unreachable {
+                true => Err(unreachable_error()),
+                false => Ok(()),
             }
         }
     }
 
     #[async_trait]
-    impl PaperlessApi for HealthCheckTrackingClient {
+    impl PaperlessApi for MockClient {
         async fn health_check(&self) -> Result<(), PaperlessError> {
-            self.health_check_count.fetch_add(1, Ordering::SeqCst);
+            self.health_checks.fetch_add(1, Ordering::SeqCst);
             if self.health_check_fails {
-                Err(PaperlessError::Io(std::io::Error::other("dns error")))
-            } else {
-                Ok(())
+                return Err(unreachable_error());
             }
+            self.check_reachable()
         }
 
-        async fn upload(&self, _path: &str) -> Result<String, PaperlessError> {
-            Ok("test-task-id".to_string())
+        async fn upload(
+            &self,
+            path: &str,
+            options: &UploadOptions,
+        ) -> Result<String, PaperlessError> {
+            self.accept(path, options)
+        }
+
+        async fn upload_bytes(
+            &self,
+            name: &str,
+            _document: Bytes,
+            options: &UploadOptions,
+        ) -> Result<String, PaperlessError> {
+            self.uploads_from_memory.fetch_add(1, Ordering::SeqCst);
+            self.accept(name, options)
+        }
+
+        async fn task_status(&self, _task_id: &str) -> Result<TaskStatus, PaperlessError> {
+            self.check_reachable()?;
+            Ok(match self.consumption_failure {
+                Some(reason) => TaskStatus::Failure(reason.to_string()),
+                None => TaskStatus::Success {
+                    document_id: Some(1),
+                },
+            })
+        }
+
+        async fn document_checksum(&self, _document_id: u64) -> Result<String, PaperlessError> {
+            self.check_reachable()?;
+            Ok("b4813e2f48697570f3f65abc97fc32f6".to_string())
+        }
+
+        async fn delete_document(&self, _document_id: u64) -> Result<(), PaperlessError> {
+            self.check_reachable()
+        }
+
+        async fn find_object(
+            &self,
+            kind: ObjectKind,
+            name: &str,
+        ) -> Result<Option<u64>, PaperlessError> {
+            self.check_reachable()?;
+            Ok(match (kind, name) {
+                (ObjectKind::Correspondent, "ACME") => Some(7),
+                (ObjectKind::Tag, "invoice") => Some(4),
+                _ => None,
+            })
+        }
+
+        async fn create_object(
+            &self,
+            kind: ObjectKind,
+            name: &str,
+            parent: Option<u64>,
+        ) -> Result<u64, PaperlessError> {
+            self.check_reachable()?;
+            let mut created = self.created.lock().unwrap();
+            created.push((kind, name.to_string(), parent));
+            Ok(100 + created.len() as u64)
+        }
+
+        async fn merge_documents(&self, _document_ids: &[u64]) -> Result<(), PaperlessError> {
+            self.check_reachable()
         }
     }
 
@@ -357,33 +2203,61 @@ mod tests {
         tokio::io::BufReader::new(std::io::Cursor::new(data.to_vec()))
     }
 
+    /// Reader that yields some data and then fails like a dropped data connection
+    struct DroppedConnectionReader {
+        data: Option<Vec<u8>>,
+    }
+
+    impl tokio::io::AsyncRead for DroppedConnectionReader {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            match self.data.take() {
+                Some(data) => {
+                    buf.put_slice(&data);
+                    std::task::Poll::Ready(Ok(()))
+                }
+                None => std::task::Poll::Ready(Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionReset,
+                    "data connection dropped",
+                ))),
+            }
+        }
+    }
+
     // === Feature 1: Retry with backoff ===
 
     #[tokio::test]
     async fn test_upload_retries_on_transient_error_then_succeeds() {
         // Upload fails twice then succeeds on third attempt
-        let client = Arc::new(RetryMockClient::new(2));
+        let client = Arc::new(MockClient::failing(2));
         let storage = PaperlessStorage::new(client.clone(), healthy_status());
 
         let input = make_input(b"test pdf content");
-        let result = storage.put(&User, input, Path::new("/test.pdf"), 0).await;
+        let result = storage
+            .put(&User::default(), input, Path::new("/test.pdf"), 0)
+            .await;
 
         assert!(result.is_ok(), "Upload should succeed after retries");
         // Should have been called 3 times (2 failures + 1 success)
-        assert_eq!(client.fail_count.load(Ordering::SeqCst), 3);
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 3);
     }
 
     #[tokio::test]
     async fn test_upload_gives_up_after_max_retries() {
         // Upload always fails - should give up after max retries, not retry forever
-        let client = Arc::new(RetryMockClient::new(100));
+        let client = Arc::new(MockClient::failing(100));
         let storage = PaperlessStorage::new(client.clone(), healthy_status());
 
         let input = make_input(b"test pdf content");
-        let result = storage.put(&User, input, Path::new("/test.pdf"), 0).await;
+        let result = storage
+            .put(&User::default(), input, Path::new("/test.pdf"), 0)
+            .await;
 
         assert!(result.is_err(), "Upload should fail after max retries");
-        let attempts = client.fail_count.load(Ordering::SeqCst);
+        let attempts = client.uploads.load(Ordering::SeqCst);
         // Should have retried a bounded number of times (e.g. 3-5), not 100
         assert!(
             attempts <= 6,
@@ -391,125 +2265,1156 @@ mod tests {
         );
     }
 
-    // === Feature 2: Pre-upload health check ===
+    // === Aborted and partial transfers ===
 
     #[tokio::test]
-    async fn test_health_check_called_before_upload() {
-        // Health check passes - upload should proceed
-        let client = Arc::new(HealthCheckTrackingClient::new(false));
+    async fn test_aborted_transfer_is_not_uploaded() {
+        let client = Arc::new(MockClient::failing(0));
         let storage = PaperlessStorage::new(client.clone(), healthy_status());
 
-        let input = make_input(b"test pdf content");
-        let result = storage.put(&User, input, Path::new("/test.pdf"), 0).await;
+        let input = DroppedConnectionReader {
+            data: Some(b"%PDF-1.7 partial".to_vec()),
+        };
+        let result = storage
+            .put(&User::default(), input, Path::new("/test.pdf"), 0)
+            .await;
 
-        assert!(result.is_ok());
-        assert!(
-            client.health_check_count.load(Ordering::SeqCst) >= 1,
-            "health_check should be called before upload"
-        );
+        assert!(result.is_err(), "aborted transfer should fail");
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 0);
     }
 
     #[tokio::test]
-    async fn test_upload_rejected_when_health_check_fails() {
-        // Health check fails - upload should be rejected early without attempting upload
-        let client = Arc::new(HealthCheckTrackingClient::new(true));
+    async fn test_truncated_document_is_not_uploaded() {
+        let client = Arc::new(MockClient::failing(0));
         let storage = PaperlessStorage::new(client.clone(), healthy_status());
 
-        let input = make_input(b"test pdf content");
-        let result = storage.put(&User, input, Path::new("/test.pdf"), 0).await;
+        let input = make_input(b"%PDF-1.7\n1 0 obj\nendobj\n");
+        let result = storage
+            .put(&User::default(), input, Path::new("/test.pdf"), 0)
+            .await;
 
-        // Should fail because health check failed (after retries)
-        assert!(
-            result.is_err(),
-            "Upload should be rejected when health check fails"
-        );
+        assert!(result.is_err(), "truncated PDF should be rejected");
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 0);
     }
 
     #[tokio::test]
-    async fn test_upload_rejected_immediately_when_cached_health_is_unhealthy() {
-        let client = Arc::new(HealthCheckTrackingClient::new(false));
-        let health = healthy_status();
-        health.mark_unhealthy("Paperless is unreachable");
-        let storage = PaperlessStorage::new(client.clone(), health);
+    async fn test_empty_upload_is_not_uploaded() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+
+        let result = storage
+            .put(&User::default(), make_input(b""), Path::new("/test.pdf"), 0)
+            .await;
+
+        assert_eq!(result.unwrap(), 0);
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_resumed_upload_is_rejected() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
 
         let result = storage
             .put(
-                &User,
-                make_input(b"test pdf content"),
+                &User::default(),
+                make_input(b"rest"),
                 Path::new("/test.pdf"),
-                0,
+                10,
             )
             .await;
 
-        let error = result.expect_err("upload should be rejected");
-        assert_eq!(error.kind(), TransientFileNotAvailable);
-        assert_eq!(client.health_check_count.load(Ordering::SeqCst), 0);
+        assert!(result.is_err());
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 0);
     }
 
-    // === Feature 3: Spool to disk on failure ===
-
     #[tokio::test]
-    async fn test_file_spooled_to_disk_on_upload_failure() {
-        let spool_dir = tempfile::tempdir().unwrap();
-        let client = Arc::new(AlwaysFailClient);
-        let storage = PaperlessStorage::new_with_spool(
-            client,
-            healthy_status(),
-            spool_dir.path().to_path_buf(),
+    async fn test_overlong_filename_fails_without_panicking() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status()).with_filename_policy(
+            FilenamePolicy {
+                max_length: 1000,
+                transliterate: false,
+            },
         );
 
-        let input = make_input(b"test pdf content");
-        // put should succeed (from FTP client's perspective) because file is spooled
+        let name = format!("/{}.pdf", "a".repeat(300));
         let result = storage
-            .put(&User, input, Path::new("/spool_test_1.pdf"), 0)
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new(&name),
+                0,
+            )
             .await;
 
-        assert!(
-            result.is_ok(),
-            "put should succeed when file is spooled to disk, got: {:?}",
-            result
-        );
+        let error = result.expect_err("staging file cannot be created");
+        assert_eq!(error.kind(), FileNameNotAllowedError);
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_stou_names_are_replaced() {
+        let storage = PaperlessStorage::new(Arc::new(MockClient::failing(0)), healthy_status());
+
+        let name = storage
+            .staging_name(
+                Path::new("/5f0c8a9e-3b1d-4c2e-9f7a-0123456789ab"),
+                Some("pdf"),
+            )
+            .unwrap();
+        assert!(name.starts_with("scan_"), "got {name}");
+        assert!(name.ends_with(".pdf"), "got {name}");
 
-        // Verify the file was saved to the spool directory
-        let spool_files: Vec<_> = std::fs::read_dir(spool_dir.path()).unwrap().collect();
         assert_eq!(
-            spool_files.len(),
-            1,
-            "Exactly one file should be spooled to disk"
+            storage
+                .staging_name(Path::new("/scan_0001.pdf"), Some("pdf"))
+                .unwrap(),
+            "scan_0001.pdf"
         );
     }
 
     #[tokio::test]
-    async fn test_spooled_file_retried_when_api_recovers() {
-        let spool_dir = tempfile::tempdir().unwrap();
-
-        // First, spool a file with a failing client
-        let client = Arc::new(AlwaysFailClient);
-        let storage = PaperlessStorage::new_with_spool(
-            client,
-            healthy_status(),
-            spool_dir.path().to_path_buf(),
+    async fn test_wrong_extensions_are_corrected_by_content() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+        assert_eq!(
+            storage
+                .staging_name(Path::new("/SCAN0001.DAT"), Some("pdf"))
+                .unwrap(),
+            "SCAN0001.pdf"
+        );
+        assert_eq!(
+            storage
+                .staging_name(Path::new("/photo.jpeg"), Some("jpg"))
+                .unwrap(),
+            "photo.jpeg"
         );
 
-        let input = make_input(b"test pdf content");
         storage
-            .put(&User, input, Path::new("/spool_test_2.pdf"), 0)
+            .put(
+                &User::default(),
+                make_input(b"%PDF-1.7 %%EOF"),
+                Path::new("/SCAN0001.DAT"),
+                0,
+            )
             .await
-            .unwrap();
+            .expect("a PDF sent as .dat is uploaded");
+    }
 
-        // Verify file is in spool
-        let spool_files: Vec<_> = std::fs::read_dir(spool_dir.path())
-            .unwrap()
-            .filter_map(|e| e.ok())
-            .collect();
-        assert_eq!(spool_files.len(), 1);
+    #[tokio::test]
+    async fn test_temp_rename_quirk_uploads_under_final_name() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+        let result = storage
+            .put(
+                &User::default(),
+                make_input(b"%PDF-1.7 %%EOF"),
+                Path::new("/scan.tmp"),
+                0,
+            )
+            .await;
+        assert_eq!(
+            result
+                .expect_err("temp names are rejected by default")
+                .kind(),
+            FileNameNotAllowedError
+        );
+
+        let storage = storage.with_quirks(Quirks {
+            temp_rename: true,
+            ..Quirks::default()
+        });
+        assert_eq!(
+            storage
+                .staging_name(Path::new("/scan.tmp"), Some("pdf"))
+                .unwrap(),
+            "scan.pdf"
+        );
+        storage
+            .put(
+                &User::default(),
+                make_input(b"%PDF-1.7 %%EOF"),
+                Path::new("/scan.tmp"),
+                0,
+            )
+            .await
+            .unwrap();
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 1);
+        storage
+            .rename(
+                &User::default(),
+                Path::new("/scan.tmp"),
+                Path::new("/scan.pdf"),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mkd_requires_quirk() {
+        let storage = PaperlessStorage::new(Arc::new(MockClient::failing(0)), healthy_status());
+        assert!(
+            storage
+                .mkd(&User::default(), Path::new("/2024-01-01"))
+                .await
+                .is_err()
+        );
+
+        let storage = storage.with_quirks(Quirks {
+            accept_mkd: true,
+            ..Quirks::default()
+        });
+        assert!(
+            storage
+                .mkd(&User::default(), Path::new("/2024-01-01"))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_virtual_root_confines_user() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+        let user = User {
+            username: "scanner-kitchen".to_string(),
+            settings: crate::users::UserSettings {
+                root: Some(PathBuf::from("/kitchen")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        for path in ["/office/scan.pdf", "/kitchen/../office/scan.pdf"] {
+            let result = storage
+                .put(&user, make_input(b"test pdf content"), Path::new(path), 0)
+                .await;
+            assert!(result.is_err(), "{path} was accepted");
+        }
+        assert!(storage.cwd(&user, Path::new("/office")).await.is_err());
+        assert!(storage.cwd(&user, Path::new("/kitchen/2024")).await.is_ok());
+
+        let result = storage
+            .put(
+                &user,
+                make_input(b"test pdf content"),
+                Path::new("/kitchen/scan.pdf"),
+                0,
+            )
+            .await;
+        assert!(result.is_ok(), "got: {result:?}");
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_quota_is_shared_across_sessions() {
+        let client = Arc::new(MockClient::failing(0));
+        let quota = QuotaTracker::default();
+        let user = User {
+            username: "scanner".to_string(),
+            settings: crate::users::UserSettings {
+                limits: crate::quota::UploadLimits {
+                    max_uploads_per_day: Some(1),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        for expect_ok in [true, false] {
+            let storage = PaperlessStorage::new(client.clone(), healthy_status())
+                .with_quota_tracker(quota.clone());
+            let result = storage
+                .put(
+                    &user,
+                    make_input(b"test pdf content"),
+                    Path::new("/scan.pdf"),
+                    0,
+                )
+                .await;
+            assert_eq!(result.is_ok(), expect_ok, "got: {result:?}");
+        }
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_overlong_filename_is_shortened_by_default() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+
+        let name = format!("/{}.pdf", "a".repeat(300));
+        let result = storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new(&name),
+                0,
+            )
+            .await;
+
+        assert!(result.is_ok(), "got: {result:?}");
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_non_utf8_filename_is_uploaded() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+
+        let name = std::ffi::OsStr::from_bytes(b"/scan\xff.pdf");
+        let result = storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new(name),
+                0,
+            )
+            .await;
+
+        assert!(result.is_ok(), "got: {result:?}");
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 1);
+        assert!(storage.md5(&User::default(), Path::new(name)).await.is_ok());
+    }
+
+    // === Reply codes ===
+
+    #[tokio::test]
+    async fn test_unsupported_file_type_is_rejected_with_553() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+
+        let result = storage
+            .put(
+                &User::default(),
+                make_input(b"MZ"),
+                Path::new("/setup.exe"),
+                0,
+            )
+            .await;
+
+        let error = result.expect_err("upload should be rejected");
+        assert_eq!(error.kind(), FileNameNotAllowedError);
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_docuworks_upload_is_rejected_with_guidance() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+
+        let error = storage
+            .put(
+                &User::default(),
+                make_input(b"%XDWDOC-0400"),
+                Path::new("/scan.pdf"),
+                0,
+            )
+            .await
+            .expect_err("upload should be rejected");
+        assert_eq!(error.kind(), FileNameNotAllowedError);
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_upload_is_rejected_with_552() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage =
+            PaperlessStorage::new(client.clone(), healthy_status()).with_max_upload_size(Some(4));
+
+        let result = storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/test.pdf"),
+                0,
+            )
+            .await;
+
+        let error = result.expect_err("upload should be rejected");
+        assert_eq!(error.kind(), ExceededStorageAllocationError);
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_paperless_failure_is_reported_as_451() {
+        let client = Arc::new(MockClient::failing(100));
+        let storage = PaperlessStorage::new(client, healthy_status());
+
+        let result = storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/test.pdf"),
+                0,
+            )
+            .await;
+
+        assert_eq!(result.expect_err("upload should fail").kind(), LocalError);
+    }
+
+    #[tokio::test]
+    async fn test_md5_of_received_file() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client, healthy_status());
+
+        storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/test.pdf"),
+                0,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage
+                .md5(&User::default(), Path::new("/test.pdf"))
+                .await
+                .unwrap(),
+            "b4813e2f48697570f3f65abc97fc32f6"
+        );
+        let error = storage
+            .md5(&User::default(), Path::new("/other.pdf"))
+            .await
+            .expect_err("unknown file has no checksum");
+        assert_eq!(error.kind(), PermanentFileNotAvailable);
+    }
+
+    #[tokio::test]
+    async fn test_consumed_checksum_is_verified() {
+        let client = MockClient::failing(0);
+
+        assert!(
+            verify_consumed_checksum(&client, "task", "B4813E2F48697570F3F65ABC97FC32F6")
+                .await
+                .unwrap()
+                .1
+        );
+        assert!(
+            !verify_consumed_checksum(&client, "task", "00000000000000000000000000000000")
+                .await
+                .unwrap()
+                .1
+        );
+        assert!(
+            verify_consumed_checksum(&MockClient::unreachable(), "task", "")
+                .await
+                .is_err()
+        );
+    }
+
+    // === Feature 2: Pre-upload health check ===
+
+    #[tokio::test]
+    async fn test_health_check_called_before_upload() {
+        // Health check passes - upload should proceed
+        let client = Arc::new(MockClient {
+            health_check_fails: false,
+            ..Default::default()
+        });
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+
+        let input = make_input(b"test pdf content");
+        let result = storage
+            .put(&User::default(), input, Path::new("/test.pdf"), 0)
+            .await;
+
+        assert!(result.is_ok());
+        assert!(
+            client.health_checks.load(Ordering::SeqCst) >= 1,
+            "health_check should be called before upload"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejected_when_health_check_fails() {
+        // Health check fails - upload should be rejected early without attempting upload
+        let client = Arc::new(MockClient {
+            health_check_fails: true,
+            ..Default::default()
+        });
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+
+        let input = make_input(b"test pdf content");
+        let result = storage
+            .put(&User::default(), input, Path::new("/test.pdf"), 0)
+            .await;
+
+        // Should fail because health check failed (after retries)
+        assert!(
+            result.is_err(),
+            "Upload should be rejected when health check fails"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejected_immediately_when_cached_health_is_unhealthy() {
+        let client = Arc::new(MockClient {
+            health_check_fails: false,
+            ..Default::default()
+        });
+        let health = healthy_status();
+        health.mark_unhealthy("Paperless is unreachable");
+        let storage = PaperlessStorage::new(client.clone(), health);
+
+        let result = storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/test.pdf"),
+                0,
+            )
+            .await;
+
+        let error = result.expect_err("upload should be rejected");
+        assert_eq!(error.kind(), TransientFileNotAvailable);
+        assert_eq!(client.health_checks.load(Ordering::SeqCst), 0);
+    }
+
+    // === Feature 3: Spool to disk on failure ===
+
+    #[tokio::test]
+    async fn test_file_spooled_to_disk_on_upload_failure() {
+        let spool_dir = tempfile::tempdir().unwrap();
+        let client = Arc::new(MockClient::unreachable());
+        let storage = PaperlessStorage::new_with_spool(
+            client,
+            healthy_status(),
+            spool_dir.path().to_path_buf(),
+        );
+
+        let input = make_input(b"test pdf content");
+        // put should succeed (from FTP client's perspective) because file is spooled
+        let result = storage
+            .put(&User::default(), input, Path::new("/spool_test_1.pdf"), 0)
+            .await;
+
+        assert!(
+            result.is_ok(),
+            "put should succeed when file is spooled to disk, got: {:?}",
+            result
+        );
+
+        // Verify the file was saved to the spool directory
+        assert_eq!(
+            crate::spool::usage(spool_dir.path()).0,
+            1,
+            "Exactly one file should be spooled to disk"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sidecar_metadata_applied_to_its_document() {
+        let client = Arc::new(MockClient::default());
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+
+        let sidecar = br#"{"title": "Q3 report", "correspondent": "ACME", "tags": ["invoice", 9, "unknown"]}"#;
+        storage
+            .put(
+                &User::default(),
+                make_input(sidecar),
+                Path::new("/report.pdf.json"),
+                0,
+            )
+            .await
+            .unwrap();
+        assert!(client.options.lock().unwrap().is_empty());
+
+        for name in ["/report.pdf", "/other.pdf"] {
+            storage
+                .put(
+                    &User::default(),
+                    make_input(name.as_bytes()),
+                    Path::new(name),
+                    0,
+                )
+                .await
+                .unwrap();
+        }
+        let options = client.options.lock().unwrap();
+        assert_eq!(options[0].title.as_deref(), Some("Q3 report"));
+        assert_eq!(options[0].correspondent, Some(7));
+        assert_eq!(options[0].tags, vec![4, 9]);
+        assert_eq!(options[1].title, None);
+        assert!(options[1].tags.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rules_assign_metadata_by_keyword() {
+        let client = Arc::new(MockClient::default());
+        let rules = RulesFile::parse(
+            r#"
+            [[rules]]
+            keywords = ["invoice"]
+            correspondent = "ACME"
+            tags = [2]
+            "#,
+        )
+        .unwrap();
+        let storage =
+            PaperlessStorage::new(client.clone(), healthy_status()).with_rules(Arc::new(rules));
+
+        for name in ["/Invoice_0042.pdf", "/letter.pdf"] {
+            storage
+                .put(
+                    &User::default(),
+                    make_input(name.as_bytes()),
+                    Path::new(name),
+                    0,
+                )
+                .await
+                .unwrap();
+        }
+        let options = client.options.lock().unwrap();
+        assert_eq!(options[0].correspondent, Some(7));
+        assert_eq!(options[0].tags, vec![2]);
+        assert_eq!(options[1].correspondent, None);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_directories_applied_to_uploads() {
+        let client = Arc::new(MockClient::default());
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+        let path = Path::new("/tags=invoice,9/correspondent=ACME");
+        storage.cwd(&User::default(), path).await.unwrap();
+
+        storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                path.join("scan.pdf"),
+                0,
+            )
+            .await
+            .unwrap();
+        let options = client.options.lock().unwrap();
+        assert_eq!(options[0].tags, vec![4, 9]);
+        assert_eq!(options[0].correspondent, Some(7));
+    }
+
+    /// Data connection that records when it was closed.
+    struct DataConnection {
+        data: std::io::Cursor<Vec<u8>>,
+        closed: Arc<AtomicBool>,
+    }
+
+    impl tokio::io::AsyncRead for DataConnection {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::pin::Pin::new(&mut self.data).poll_read(cx, buf)
+        }
+    }
+
+    impl Drop for DataConnection {
+        fn drop(&mut self) {
+            self.closed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_data_connection_closed_before_uploading() {
+        let closed = Arc::new(AtomicBool::new(false));
+        let client = Arc::new(MockClient {
+            connection: Some(Arc::clone(&closed)),
+            ..Default::default()
+        });
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+        let input = DataConnection {
+            data: std::io::Cursor::new(b"%PDF-1.7 %%EOF".to_vec()),
+            closed,
+        };
+        storage
+            .put(&User::default(), input, Path::new("/scan.pdf"), 0)
+            .await
+            .unwrap();
+        assert!(client.closed_at_upload.load(Ordering::SeqCst));
+    }
 
-        // Now create a working client and run the spool drain
-        let working_client: Arc<dyn PaperlessApi> = Arc::new(RetryMockClient::new(0));
-        crate::spool::drain_spool(spool_dir.path(), working_client.as_ref())
+    #[tokio::test]
+    async fn test_small_uploads_staged_in_memory() {
+        let client = Arc::new(MockClient::default());
+        let storage =
+            PaperlessStorage::new(client.clone(), healthy_status()).with_memory_staging(Some(20));
+
+        for (name, content) in [
+            ("/small.pdf", &b"small content"[..]),
+            ("/large.pdf", &b"content larger than the threshold"[..]),
+        ] {
+            storage
+                .put(&User::default(), make_input(content), Path::new(name), 0)
+                .await
+                .unwrap();
+        }
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 2);
+        assert_eq!(client.uploads_from_memory.load(Ordering::SeqCst), 1);
+        assert_eq!(client.paths.lock().unwrap()[0], "small.pdf");
+    }
+
+    #[tokio::test]
+    async fn test_failed_upload_staged_in_memory_is_spooled() {
+        let spool_dir = tempfile::tempdir().unwrap();
+        let client = Arc::new(MockClient::failing(usize::MAX));
+        let storage = PaperlessStorage::new_with_spool(
+            client.clone(),
+            healthy_status(),
+            spool_dir.path().to_path_buf(),
+        )
+        .with_memory_staging(Some(1024));
+
+        storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/scan.pdf"),
+                0,
+            )
+            .await
+            .unwrap();
+        assert!(client.uploads_from_memory.load(Ordering::SeqCst) > 0);
+        assert_eq!(crate::spool::usage(spool_dir.path()).0, 1);
+    }
+
+    #[tokio::test]
+    async fn test_title_template_sets_title() {
+        let client = Arc::new(MockClient::default());
+        let storage = PaperlessStorage::new(client.clone(), healthy_status()).with_title_template(
+            Some("{correspondent} {basename} ({folder})".parse().unwrap()),
+        );
+
+        let sidecar = br#"{"correspondent": "ACME"}"#;
+        storage
+            .put(
+                &User::default(),
+                make_input(sidecar),
+                Path::new("/bills/invoice.pdf.json"),
+                0,
+            )
+            .await
+            .unwrap();
+        storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/bills/invoice.pdf"),
+                0,
+            )
+            .await
+            .unwrap();
+        let options = client.options.lock().unwrap();
+        assert_eq!(options[0].title.as_deref(), Some("ACME invoice (bills)"));
+    }
+
+    #[tokio::test]
+    async fn test_tag_hierarchy_creates_missing_levels() {
+        let client = Arc::new(MockClient::default());
+        let storage = PaperlessStorage::new(client.clone(), healthy_status()).with_resolve_options(
+            ResolveOptions {
+                tag_hierarchy: true,
+                create_tags: true,
+                ..Default::default()
+            },
+        );
+
+        let sidecar = br#"{"tags": ["invoice/2024"]}"#;
+        storage
+            .put(
+                &User::default(),
+                make_input(sidecar),
+                Path::new("/scan.pdf.json"),
+                0,
+            )
+            .await
+            .unwrap();
+        storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/scan.pdf"),
+                0,
+            )
+            .await
+            .unwrap();
+        assert_eq!(client.options.lock().unwrap()[0].tags, vec![4, 101]);
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            vec![(ObjectKind::Tag, "2024".to_string(), Some(4))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unresolved_metadata_follows_the_policy() {
+        let sidecar = br#"{"correspondent": "Globex", "tags": ["invoice"]}"#;
+        for (unresolved, expected_tags) in [
+            (UnresolvedPolicy::Skip, Some(vec![4])),
+            (UnresolvedPolicy::Tag, Some(vec![4, 9])),
+            (UnresolvedPolicy::Reject, None),
+        ] {
+            let client = Arc::new(MockClient::default());
+            let storage = PaperlessStorage::new(client.clone(), healthy_status())
+                .with_resolve_options(ResolveOptions {
+                    unresolved,
+                    unresolved_tag: Some(9),
+                    ..Default::default()
+                });
+            storage
+                .put(
+                    &User::default(),
+                    make_input(sidecar),
+                    Path::new("/scan.pdf.json"),
+                    0,
+                )
+                .await
+                .unwrap();
+            let result = storage
+                .put(
+                    &User::default(),
+                    make_input(b"test pdf content"),
+                    Path::new("/scan.pdf"),
+                    0,
+                )
+                .await;
+            let options = client.options.lock().unwrap();
+            match expected_tags {
+                Some(tags) => {
+                    assert!(result.is_ok());
+                    assert_eq!(options[0].tags, tags);
+                    assert_eq!(options[0].correspondent, None);
+                }
+                None => {
+                    assert_eq!(result.unwrap_err().kind(), PermanentFileNotAvailable);
+                    assert!(options.is_empty());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tenant_directory_selects_the_paperless_instance() {
+        let own = Arc::new(MockClient::default());
+        let smith = Arc::new(MockClient::default());
+        let tenants = crate::tenant::Tenants::from([(
+            "smith".to_string(),
+            crate::tenant::Tenant {
+                client: smith.clone(),
+                users: Vec::new(),
+            },
+        )]);
+        let storage = PaperlessStorage::new(own.clone(), healthy_status())
+            .with_tenants(Arc::new(tenants))
+            .with_title_template(Some("{folder} {basename}".parse().unwrap()));
+
+        storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/smith/bills/invoice.pdf"),
+                0,
+            )
+            .await
+            .unwrap();
+        let error = storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/jones/invoice.pdf"),
+                0,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), PermissionDenied);
+
+        assert!(own.options.lock().unwrap().is_empty());
+        let options = smith.options.lock().unwrap();
+        assert_eq!(options.len(), 1);
+        assert_eq!(options[0].title.as_deref(), Some("bills invoice"));
+    }
+
+    #[tokio::test]
+    async fn test_terminated_session_aborts_the_transfer() {
+        use tokio::io::AsyncWriteExt;
+
+        let sessions = crate::sessions::Sessions::default();
+        let session = sessions.open();
+        let id = session.id();
+        let storage = PaperlessStorage::new(Arc::new(MockClient::failing(0)), healthy_status())
+            .with_session(Some(session));
+
+        // A scanner that stopped sending in the middle of the document.
+        let (mut scanner, input) = tokio::io::duplex(64);
+        scanner.write_all(b"%PDF-1.4").await.unwrap();
+        let terminate = async {
+            sleep(Duration::from_millis(50)).await;
+            assert_eq!(sessions.list()[0].bytes, 8);
+            assert!(sessions.terminate(id));
+        };
+        let user = User::default();
+        let (result, ()) = tokio::join!(
+            storage.put(&user, input, Path::new("/scan.pdf"), 0),
+            terminate
+        );
+        assert_eq!(result.unwrap_err().kind(), ConnectionClosed);
+        assert_eq!(sessions.list()[0].transfer, None);
+
+        let result = storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/scan.pdf"),
+                0,
+            )
+            .await;
+        assert_eq!(result.unwrap_err().kind(), ConnectionClosed);
+    }
+
+    #[tokio::test]
+    async fn test_tenants_have_spools_of_their_own() {
+        let spool_dir = tempfile::tempdir().unwrap();
+        let tenants = crate::tenant::Tenants::from([(
+            "smith".to_string(),
+            crate::tenant::Tenant {
+                client: Arc::new(MockClient::unreachable()),
+                users: Vec::new(),
+            },
+        )]);
+        let storage = PaperlessStorage::new_with_spool(
+            Arc::new(MockClient::default()),
+            healthy_status(),
+            spool_dir.path().to_path_buf(),
+        )
+        .with_tenants(Arc::new(tenants))
+        .with_spool_limits(SpoolLimits {
+            max_bytes: None,
+            max_files: Some(1),
+        });
+
+        storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/smith/invoice.pdf"),
+                0,
+            )
+            .await
+            .unwrap();
+        assert!(spool_dir.path().join("smith/invoice.pdf").exists());
+
+        // The limit counts the files of the tenant.
+        let error = storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/smith/receipt.pdf"),
+                0,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), InsufficientStorageSpaceError);
+    }
+
+    #[tokio::test]
+    async fn test_source_field_is_set_per_upload() {
+        let client = Arc::new(MockClient::default());
+        let storage = PaperlessStorage::new(client.clone(), healthy_status()).with_source_field(
+            Some(SourceField {
+                id: 3,
+                value: "FTP {user} {folder}".parse().unwrap(),
+            }),
+        );
+        let user = User {
+            username: "scanner-office".to_string(),
+            ..Default::default()
+        };
+
+        storage
+            .put(
+                &user,
+                make_input(b"test pdf content"),
+                Path::new("/bills/invoice.pdf"),
+                0,
+            )
+            .await
+            .unwrap();
+        let options = client.options.lock().unwrap();
+        assert_eq!(
+            options[0].custom_fields,
+            vec![(3, "FTP scanner-office bills".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_embedded_metadata_fills_missing_fields() {
+        let client = Arc::new(MockClient::default());
+        let storage = PaperlessStorage::new(client.clone(), healthy_status())
+            .with_embedded_metadata(vec![EmbeddedField::Created]);
+
+        let pdf =
+            b"%PDF-1.4\n1 0 obj\n<< /Title (Scan) /CreationDate (D:20240301) >>\nendobj\n%%EOF\n";
+        storage
+            .put(&User::default(), make_input(pdf), Path::new("/scan.pdf"), 0)
+            .await
+            .unwrap();
+        let options = client.options.lock().unwrap();
+        assert_eq!(options[0].created.as_deref(), Some("2024-03-01"));
+        assert_eq!(options[0].title, None);
+    }
+
+    #[tokio::test]
+    async fn test_resent_file_is_uploaded_once() {
+        let client = Arc::new(MockClient::failing(0));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+
+        for name in ["/scan.pdf", "/scan.pdf", "/scan_retry.pdf"] {
+            let result = storage
+                .put(
+                    &User::default(),
+                    make_input(b"test pdf content"),
+                    Path::new(name),
+                    0,
+                )
+                .await;
+            assert_eq!(result.unwrap(), 16);
+        }
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 1);
+
+        storage
+            .put(
+                &User::default(),
+                make_input(b"other pdf content"),
+                Path::new("/scan.pdf"),
+                0,
+            )
+            .await
+            .unwrap();
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_dele_cancels_spooled_upload() {
+        let spool_dir = tempfile::tempdir().unwrap();
+        let storage = PaperlessStorage::new_with_spool(
+            Arc::new(MockClient::unreachable()),
+            healthy_status(),
+            spool_dir.path().to_path_buf(),
+        );
+        storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/misfire.pdf"),
+                0,
+            )
+            .await
+            .unwrap();
+        assert_eq!(crate::spool::usage(spool_dir.path()).0, 1);
+
+        storage
+            .del(&User::default(), Path::new("/misfire.pdf"))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_dir(spool_dir.path()).unwrap().count(), 0);
+        let error = storage
+            .del(&User::default(), Path::new("/misfire.pdf"))
+            .await
+            .expect_err("the upload was already cancelled");
+        assert_eq!(error.kind(), PermanentFileNotAvailable);
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejected_when_spool_is_full() {
+        let spool_dir = tempfile::tempdir().unwrap();
+        std::fs::write(spool_dir.path().join("waiting.pdf"), b"%PDF-").unwrap();
+        let storage = PaperlessStorage::new_with_spool(
+            Arc::new(MockClient::unreachable()),
+            healthy_status(),
+            spool_dir.path().to_path_buf(),
+        )
+        .with_spool_limits(SpoolLimits {
+            max_bytes: None,
+            max_files: Some(1),
+        });
+
+        let error = storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/spool_full.pdf"),
+                0,
+            )
+            .await
+            .expect_err("upload should be rejected while the spool is full");
+        assert_eq!(error.kind(), InsufficientStorageSpaceError);
+        assert_eq!(std::fs::read_dir(spool_dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejected_when_disk_is_low() {
+        let client = Arc::new(MockClient::failing(0));
+        let capacity = DiskCapacity::new(
+            vec![std::env::temp_dir()],
+            crate::capacity::MinFree {
+                bytes: Some(u64::MAX),
+                inodes: None,
+            },
+        );
+        capacity.check();
+        let storage = PaperlessStorage::new(client.clone(), healthy_status())
+            .with_disk_capacity(Some(capacity));
+
+        let error = storage
+            .put(
+                &User::default(),
+                make_input(b"test pdf content"),
+                Path::new("/low_disk.pdf"),
+                0,
+            )
+            .await
+            .expect_err("upload should be rejected while the disk is low");
+        assert_eq!(error.kind(), InsufficientStorageSpaceError);
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_spooled_file_retried_when_api_recovers() {
+        let spool_dir = tempfile::tempdir().unwrap();
+
+        // First, spool a file with a failing client
+        let client = Arc::new(MockClient::unreachable());
+        let storage = PaperlessStorage::new_with_spool(
+            client,
+            healthy_status(),
+            spool_dir.path().to_path_buf(),
+        );
+
+        let input = make_input(b"test pdf content");
+        storage
+            .put(&User::default(), input, Path::new("/spool_test_2.pdf"), 0)
             .await
             .unwrap();
 
+        // Verify file is in spool
+        assert_eq!(crate::spool::usage(spool_dir.path()).0, 1);
+
+        // Now create a working client and run the spool drain
+        let working_client: Arc<dyn PaperlessApi> = Arc::new(MockClient::failing(0));
+        crate::spool::drain_spool(
+            spool_dir.path(),
+            &working_client,
+            None,
+            1,
+            &Default::default(),
+        )
+        .await
+        .unwrap();
+
         // Spool directory should be empty after successful drain
         let remaining: Vec<_> = std::fs::read_dir(spool_dir.path())
             .unwrap()
@@ -521,4 +3426,145 @@ mod tests {
             "Spool directory should be empty after drain"
         );
     }
+
+    #[tokio::test]
+    async fn test_spooled_file_keeps_its_options() {
+        let spool_dir = tempfile::tempdir().unwrap();
+        let storage = PaperlessStorage::new_with_spool(
+            Arc::new(MockClient::unreachable()),
+            healthy_status(),
+            spool_dir.path().to_path_buf(),
+        )
+        .with_spool_format(SpoolFormat {
+            compress: true,
+            key: Some(crate::encryption::FileKey::new(&[1; 32]).unwrap()),
+        });
+        let user = User {
+            username: "alice".to_string(),
+            settings: crate::users::UserSettings {
+                tags: vec![3, 5],
+                api_token: Some("alice-token".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        storage
+            .put(
+                &user,
+                make_input(b"test pdf content"),
+                Path::new("/scan.pdf"),
+                0,
+            )
+            .await
+            .unwrap();
+
+        let client = Arc::new(MockClient::default());
+        let api: Arc<dyn PaperlessApi> = client.clone();
+        let uploaded =
+            crate::spool::drain_spool(spool_dir.path(), &api, None, 1, &storage.spool_format)
+                .await
+                .unwrap();
+        // Only the user is kept, its client and token are looked up again when draining.
+        assert_eq!(uploaded[0].user.as_deref(), Some("alice"));
+        let options = client.options.lock().unwrap();
+        assert_eq!(options.len(), 1);
+        assert_eq!(options[0].tags, [3, 5]);
+        assert!(options[0].request_id.is_some());
+        assert_eq!(std::fs::read_dir(spool_dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_upload_neither_retried_nor_spooled() {
+        let spool_dir = tempfile::tempdir().unwrap();
+        let client = Arc::new(MockClient::rejecting());
+        let storage = PaperlessStorage::new_with_spool(
+            client.clone(),
+            healthy_status(),
+            spool_dir.path().to_path_buf(),
+        );
+
+        let error = storage
+            .put(
+                &User::default(),
+                make_input(b"not a document"),
+                Path::new("/scan.pdf"),
+                0,
+            )
+            .await
+            .expect_err("upload should be rejected");
+        assert_eq!(error.kind(), PermanentFileNotAvailable);
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 1);
+        assert_eq!(std::fs::read_dir(spool_dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_consumption_reported_when_waiting() {
+        let client = Arc::new(MockClient::failing_consumption("scan.pdf: corrupted file"));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status());
+        let input = make_input(b"%PDF-1.7 %%EOF");
+        storage
+            .put(&User::default(), input, Path::new("/scan.pdf"), 0)
+            .await
+            .expect("upload should succeed without waiting");
+
+        let storage = PaperlessStorage::new(client, healthy_status())
+            .with_consumption_wait(Some(Duration::from_secs(5)));
+        let input = make_input(b"%PDF-1.7 %%EOF");
+        let error = storage
+            .put(&User::default(), input, Path::new("/scan.pdf"), 0)
+            .await
+            .expect_err("consumption failure should be reported");
+        assert_eq!(error.kind(), PermanentFileNotAvailable);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_failed_consumption_is_uploaded() {
+        let client = Arc::new(MockClient::failing_consumption("scan.pdf: corrupted file"));
+        let storage = PaperlessStorage::new(client.clone(), healthy_status())
+            .with_consumption_wait(Some(Duration::from_secs(5)));
+        for _ in 0..2 {
+            let error = storage
+                .put(
+                    &User::default(),
+                    make_input(b"%PDF-1.7 %%EOF"),
+                    Path::new("/scan.pdf"),
+                    0,
+                )
+                .await
+                .expect_err("consumption failure should be reported");
+            assert_eq!(error.kind(), PermanentFileNotAvailable);
+        }
+        assert_eq!(client.uploads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_follow_the_duplicate_policy() {
+        for (policy, accepted) in [
+            (DuplicatePolicy::Success, true),
+            (DuplicatePolicy::Warn, true),
+            (DuplicatePolicy::Fail, false),
+        ] {
+            let client = Arc::new(MockClient::failing_consumption(
+                "scan.pdf: Not consuming scan.pdf: It is a duplicate of scan (#12).",
+            ));
+            let storage = PaperlessStorage::new(client.clone(), healthy_status())
+                .with_consumption_wait(Some(Duration::from_secs(5)))
+                .with_duplicate_policy(policy);
+            for _ in 0..2 {
+                let result = storage
+                    .put(
+                        &User::default(),
+                        make_input(b"%PDF-1.7 %%EOF"),
+                        Path::new("/scan.pdf"),
+                        0,
+                    )
+                    .await;
+                assert_eq!(result.is_ok(), accepted, "{policy:?}");
+            }
+            // An accepted duplicate counts as sent, so the retry isn't uploaded again, while a
+            // refused one is.
+            let uploads = if accepted { 1 } else { 2 };
+            assert_eq!(client.uploads.load(Ordering::SeqCst), uploads, "{policy:?}");
+        }
+    }
 }
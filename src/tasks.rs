@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::paperless::{DuplicatePolicy, PaperlessApi, UserClients};
+use crate::storage::log_consumption;
+use crate::tenant::Tenants;
+
+/// How many consumption tasks are polled at once by default.
+pub const DEFAULT_MAX_POLLED_TASKS: usize = 32;
+
+/// A consumption task of a document handed to Paperless, as persisted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackedTask {
+    pub task_id: String,
+    /// `None` for documents spooled before the spool kept their user.
+    pub user: Option<String>,
+    /// Path the document was uploaded or spooled as.
+    pub name: String,
+    /// The tenant whose Paperless consumes the document.
+    pub tenant: Option<String>,
+    /// Unix time the document was handed to Paperless.
+    pub since: u64,
+}
+
+impl TrackedTask {
+    pub fn new(task_id: &str, user: Option<&str>, name: &str, tenant: Option<&str>) -> Self {
+        Self {
+            task_id: task_id.to_string(),
+            user: user.map(str::to_string),
+            name: name.to_string(),
+            tenant: tenant.map(str::to_string),
+            since: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+/// A task as listed by the `/tasks` endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskInfo {
+    #[serde(flatten)]
+    pub task: TrackedTask,
+    /// Whether the task is polled, or waits until fewer tasks are.
+    pub polling: bool,
+}
+
+struct State {
+    tasks: Mutex<BTreeMap<String, TaskInfo>>,
+    polls: Semaphore,
+    state_file: Option<PathBuf>,
+    duplicates: DuplicatePolicy,
+}
+
+/// Follows the consumption tasks of uploads Paperless hasn't finished yet, polling a limited number
+/// of them at once. With a state file, the tasks are followed again after a restart.
+#[derive(Clone)]
+pub struct TaskTracker(Arc<State>);
+
+impl Default for TaskTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_POLLED_TASKS, None)
+    }
+}
+
+impl TaskTracker {
+    pub fn new(max_polled: usize, state_file: Option<PathBuf>) -> Self {
+        Self::with_duplicate_policy(max_polled, state_file, DuplicatePolicy::default())
+    }
+
+    /// Like [`TaskTracker::new`], treating documents Paperless refuses as duplicates by
+    /// `duplicates` when only logging how a task ended.
+    pub fn with_duplicate_policy(
+        max_polled: usize,
+        state_file: Option<PathBuf>,
+        duplicates: DuplicatePolicy,
+    ) -> Self {
+        Self(Arc::new(State {
+            tasks: Mutex::default(),
+            polls: Semaphore::new(max_polled.max(1)),
+            state_file,
+            duplicates,
+        }))
+    }
+
+    /// Run `follow`, which polls `task`, once fewer than the maximum number of tasks are polled.
+    pub fn spawn(&self, task: TrackedTask, follow: impl Future<Output = ()> + Send + 'static) {
+        let task_id = task.task_id.clone();
+        self.update(|tasks| {
+            tasks.insert(
+                task_id.clone(),
+                TaskInfo {
+                    task,
+                    polling: false,
+                },
+            );
+        });
+        let tracker = self.clone();
+        tokio::spawn(async move {
+            let _permit = tracker
+                .0
+                .polls
+                .acquire()
+                .await
+                .expect("task tracker semaphore closed");
+            tracker.update(|tasks| {
+                if let Some(info) = tasks.get_mut(&task_id) {
+                    info.polling = true;
+                }
+            });
+            follow.await;
+            tracker.update(|tasks| {
+                tasks.remove(&task_id);
+            });
+        });
+    }
+
+    /// Follow `task` on `client`, only logging how it ends.
+    pub fn follow(&self, client: Arc<dyn PaperlessApi>, task: TrackedTask) {
+        let (task_id, duplicates) = (task.task_id.clone(), self.0.duplicates);
+        self.spawn(task, async move {
+            let _ = log_consumption(client, task_id, duplicates).await;
+        });
+    }
+
+    /// The tasks followed, oldest first.
+    pub fn list(&self) -> Vec<TaskInfo> {
+        let mut tasks: Vec<_> = self
+            .0
+            .tasks
+            .lock()
+            .expect("task tracker lock poisoned")
+            .values()
+            .cloned()
+            .collect();
+        tasks.sort_by_key(|info| info.task.since);
+        tasks
+    }
+
+    /// Follow the tasks outstanding when the bridge stopped, on the client of their tenant or user.
+    /// How they end is only logged, as the uploads they belong to are gone, with them what
+    /// notifications and hooks need.
+    pub fn resume(
+        &self,
+        client: &Arc<dyn PaperlessApi>,
+        user_clients: Option<&UserClients>,
+        tenants: &Tenants,
+    ) {
+        let Some(state_file) = &self.0.state_file else {
+            return;
+        };
+        let tasks = match load(state_file) {
+            Ok(tasks) => tasks,
+            Err(e) => {
+                warn!(
+                    "Failed to read outstanding tasks from {}: {e}",
+                    state_file.display()
+                );
+                return;
+            }
+        };
+        if !tasks.is_empty() {
+            info!("Following {} tasks outstanding before restart", tasks.len());
+        }
+        for task in tasks {
+            let client = match task.tenant.as_deref().map(|name| tenants.get(name)) {
+                Some(Some(tenant)) => Arc::clone(&tenant.client),
+                Some(None) => {
+                    warn!(
+                        "Not following task {} of unknown tenant {:?}",
+                        task.task_id, task.tenant
+                    );
+                    continue;
+                }
+                None => match user_clients {
+                    Some(user_clients) => user_clients.get(task.user.as_deref(), client),
+                    None => Arc::clone(client),
+                },
+            };
+            self.follow(client, task);
+        }
+    }
+
+    /// Change the tasks, then persist them and update the metrics.
+    fn update(&self, change: impl FnOnce(&mut BTreeMap<String, TaskInfo>)) {
+        let mut tasks = self.0.tasks.lock().expect("task tracker lock poisoned");
+        let before = tasks.len();
+        change(&mut tasks);
+        let polling = tasks.values().filter(|info| info.polling).count();
+        crate::metrics::TRACKED_TASKS
+            .with_label_values(&["polling"])
+            .set(polling as i64);
+        crate::metrics::TRACKED_TASKS
+            .with_label_values(&["queued"])
+            .set((tasks.len() - polling) as i64);
+        // Whether a task is polled isn't worth a write.
+        if tasks.len() != before
+            && let Some(state_file) = &self.0.state_file
+            && let Err(e) = save(state_file, tasks.values().map(|info| &info.task))
+        {
+            warn!(
+                "Failed to save outstanding tasks to {}: {e}",
+                state_file.display()
+            );
+        }
+    }
+}
+
+fn load(path: &Path) -> std::io::Result<Vec<TrackedTask>> {
+    match std::fs::read(path) {
+        Ok(content) => Ok(serde_json::from_slice(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replace the state file, so a crash while writing leaves the previous one.
+fn save<'a>(path: &Path, tasks: impl Iterator<Item = &'a TrackedTask>) -> std::io::Result<()> {
+    let temp = path.with_extension("tmp");
+    std::fs::write(&temp, serde_json::to_vec(&tasks.collect::<Vec<_>>())?)?;
+    std::fs::rename(temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn polls_are_bounded() {
+        let tracker = TaskTracker::new(1, None);
+        let (first_done, first) = oneshot::channel::<()>();
+        tracker.spawn(
+            TrackedTask::new("a", Some("scanner"), "/a.pdf", None),
+            async {
+                let _ = first.await;
+            },
+        );
+        tracker.spawn(TrackedTask::new("b", None, "/b.pdf", None), async {});
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let polling: Vec<_> = tracker
+            .list()
+            .into_iter()
+            .map(|info| (info.task.task_id, info.polling))
+            .collect();
+        assert!(polling.contains(&("a".to_string(), true)));
+        assert!(polling.contains(&("b".to_string(), false)));
+
+        first_done.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(tracker.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn outstanding_tasks_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("tasks.json");
+        let tracker = TaskTracker::new(1, Some(state_file.clone()));
+        let task = TrackedTask::new("a", Some("scanner"), "/a.pdf", Some("smith"));
+        tracker.spawn(task.clone(), std::future::pending());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(load(&state_file).unwrap(), vec![task]);
+        assert!(load(&dir.path().join("missing.json")).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+/// Socket options for FTP control and data connections. SO_REUSEADDR is always set, like tokio and
+/// libunftp do.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpOptions {
+    /// Send keep-alive probes after a connection was idle this long.
+    pub keepalive: Option<Duration>,
+    pub nodelay: bool,
+    /// Set SO_REUSEPORT, so a second bridge can take over the ports while the first one finishes
+    /// its sessions.
+    pub reuse_port: bool,
+}
+
+impl TcpOptions {
+    /// A socket for `addr` with the options set, bound but not yet listening.
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<TcpSocket> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        socket.set_reuseaddr(true)?;
+        #[cfg(unix)]
+        socket.set_reuseport(self.reuse_port)?;
+        // Connections accepted on the socket inherit these.
+        socket.set_nodelay(self.nodelay)?;
+        if let Some(keepalive) = self.keepalive {
+            SockRef::from(&socket).set_tcp_keepalive(&TcpKeepalive::new().with_time(keepalive))?;
+        }
+        socket.bind(addr)?;
+        Ok(socket)
+    }
+
+    /// Listen for FTP control connections on `addr`.
+    pub fn listen(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        self.bind(addr)?.listen(1024)
+    }
+
+    /// Set the options on an accepted connection, for platforms where it doesn't inherit them
+    /// from the listener.
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(keepalive) = self.keepalive {
+            SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(keepalive))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+
+/// Values of the variables of a title template for one upload.
+#[derive(Debug, Clone)]
+pub struct TitleContext<'a> {
+    /// Path of the upload as sent by the client.
+    pub path: &'a Path,
+    /// The filename the client means, e.g. without a temporary upload suffix.
+    pub filename: &'a str,
+    pub user: &'a str,
+    /// Title from a sidecar file or embedded in the document.
+    pub title: Option<&'a str>,
+    pub correspondent: Option<&'a str>,
+    /// Creation date of the document, if known.
+    pub created: Option<&'a str>,
+    pub received: DateTime<Local>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    Basename,
+    Filename,
+    Extension,
+    Folder,
+    User,
+    Title,
+    Correspondent,
+    Created,
+    Date,
+    Time,
+}
+
+impl Variable {
+    const ALL: [(&'static str, Variable); 10] = [
+        ("basename", Variable::Basename),
+        ("filename", Variable::Filename),
+        ("extension", Variable::Extension),
+        ("folder", Variable::Folder),
+        ("user", Variable::User),
+        ("title", Variable::Title),
+        ("correspondent", Variable::Correspondent),
+        ("created", Variable::Created),
+        ("date", Variable::Date),
+        ("time", Variable::Time),
+    ];
+
+    fn value(self, context: &TitleContext) -> String {
+        let basename = Path::new(context.filename)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match self {
+            Variable::Basename => basename,
+            Variable::Filename => context.filename.to_string(),
+            Variable::Extension => Path::new(context.filename)
+                .extension()
+                .map(|extension| extension.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            Variable::Folder => context
+                .path
+                .parent()
+                .map(|folder| folder.to_string_lossy().trim_matches('/').to_string())
+                .unwrap_or_default(),
+            Variable::User => context.user.to_string(),
+            Variable::Title => context.title.map(str::to_string).unwrap_or(basename),
+            Variable::Correspondent => context.correspondent.unwrap_or_default().to_string(),
+            Variable::Created => context.created.unwrap_or_default().to_string(),
+            Variable::Date => context
+                .created
+                .map(str::to_string)
+                .unwrap_or_else(|| context.received.format("%Y-%m-%d").to_string()),
+            Variable::Time => context.received.format("%H:%M").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Variable(Variable),
+}
+
+/// A document title like `{date} {correspondent} {basename}`, evaluated per upload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitleTemplate {
+    parts: Vec<Part>,
+}
+
+impl FromStr for TitleTemplate {
+    type Err = String;
+
+    /// Variables are written as `{name}` or `{{name}}`.
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format!("Unclosed '{{' in title template '{template}'"))?;
+            let name = rest[start..end].trim_start_matches('{').trim();
+            let variable = Variable::ALL
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|(_, variable)| *variable)
+                .ok_or_else(|| {
+                    let known: Vec<_> = Variable::ALL.iter().map(|(name, _)| *name).collect();
+                    format!(
+                        "Unknown variable '{name}' in title template, known are: {}",
+                        known.join(", ")
+                    )
+                })?;
+            parts.push(Part::Variable(variable));
+            rest = rest[end..].trim_start_matches('}');
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(Self { parts })
+    }
+}
+
+impl TitleTemplate {
+    /// The title for an upload. Whitespace left by empty variables is collapsed.
+    pub fn render(&self, context: &TitleContext) -> String {
+        let title: String = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Variable(variable) => variable.value(context),
+            })
+            .collect();
+        title.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn context<'a>() -> TitleContext<'a> {
+        TitleContext {
+            path: Path::new("/office/bills/Rechnung 42.pdf"),
+            filename: "Rechnung 42.pdf",
+            user: "scanner",
+            title: None,
+            correspondent: Some("ACME"),
+            created: None,
+            received: Local.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn renders_variables() {
+        let template: TitleTemplate = "{date} {correspondent} {basename}".parse().unwrap();
+        assert_eq!(template.render(&context()), "2024-03-01 ACME Rechnung 42");
+
+        let template: TitleTemplate = "{{folder}}/{{title}} ({user}, {time}).{extension}"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            template.render(&context()),
+            "office/bills/Rechnung 42 (scanner, 09:30).pdf"
+        );
+    }
+
+    #[test]
+    fn prefers_the_creation_date_and_collapses_empty_variables() {
+        let template: TitleTemplate = "{date} {created} {correspondent} {basename}"
+            .parse()
+            .unwrap();
+        let mut context = context();
+        context.correspondent = None;
+        assert_eq!(template.render(&context), "2024-03-01 Rechnung 42");
+        context.created = Some("2023-12-24");
+        assert_eq!(
+            template.render(&context),
+            "2023-12-24 2023-12-24 Rechnung 42"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!("{date".parse::<TitleTemplate>().is_err());
+        assert!("{author} {basename}".parse::<TitleTemplate>().is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use crate::paperless::PaperlessApi;
+
+/// A Paperless instance selected by the first directory of an upload path, e.g. `/smith/` in
+/// `/smith/bills/scan.pdf`, so one bridge can serve several households or companies.
+#[derive(Clone)]
+pub struct Tenant {
+    pub client: Arc<dyn PaperlessApi>,
+    /// Users allowed to upload to the tenant. Empty allows every user.
+    pub users: Vec<String>,
+}
+
+impl std::fmt::Debug for Tenant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tenant")
+            .field("users", &self.users)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The tenants by the name of their directory.
+pub type Tenants = HashMap<String, Tenant>;
+
+/// The tenant of an upload and the path below its directory, which is used for routing.
+pub fn select<'a>(
+    tenants: &'a Tenants,
+    username: &str,
+    path: &Path,
+) -> Result<(&'a str, &'a Tenant, PathBuf), String> {
+    let components: Vec<_> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+    let (name, rest) = match &components[..] {
+        [name, rest @ ..] if !rest.is_empty() => (name, rest),
+        _ => return Err("Upload into the directory of a tenant".to_string()),
+    };
+    let name = name.to_string_lossy();
+    let (name, tenant) = tenants
+        .get_key_value(name.as_ref())
+        .ok_or_else(|| format!("There is no tenant {name:?}"))?;
+    if !tenant.users.is_empty() && !tenant.users.iter().any(|user| user == username) {
+        return Err(format!("{username} may not upload to tenant {name:?}"));
+    }
+    let rest = Path::new("/").join(rest.iter().collect::<PathBuf>());
+    Ok((name, tenant, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paperless::DryRunClient;
+
+    #[test]
+    fn the_first_directory_selects_the_tenant() {
+        let tenant = |users: &[&str]| Tenant {
+            client: Arc::new(DryRunClient::default()),
+            users: users.iter().map(|user| user.to_string()).collect(),
+        };
+        let tenants = Tenants::from([
+            ("smith".to_string(), tenant(&[])),
+            ("acme".to_string(), tenant(&["acme-scanner"])),
+        ]);
+
+        let (name, _, rest) =
+            select(&tenants, "office", Path::new("/smith/bills/scan.pdf")).unwrap();
+        assert_eq!(name, "smith");
+        assert_eq!(rest, Path::new("/bills/scan.pdf"));
+        let (_, _, rest) = select(&tenants, "acme-scanner", Path::new("acme/scan.pdf")).unwrap();
+        assert_eq!(rest, Path::new("/scan.pdf"));
+
+        assert!(select(&tenants, "office", Path::new("/acme/scan.pdf")).is_err());
+        assert!(select(&tenants, "office", Path::new("/jones/scan.pdf")).is_err());
+        assert!(select(&tenants, "office", Path::new("/scan.pdf")).is_err());
+    }
+}
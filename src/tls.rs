@@ -0,0 +1,286 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use arc_swap::ArcSwap;
+use clap::ValueEnum;
+use libunftp::options::TlsFlags;
+use log::{debug, error, info, warn};
+use rustls::crypto::aws_lc_rs;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{
+    ClientHello, NoServerSessionStorage, ResolvesServerCert, ServerSessionMemoryCache,
+    StoresServerSessions, WebPkiClientVerifier,
+};
+use rustls::sign::CertifiedKey;
+use rustls::version::{TLS12, TLS13};
+use rustls::{RootCertStore, ServerConfig};
+use tokio::time::sleep;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+const CERTIFICATE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const SESSION_CACHE_SIZE: usize = 1024;
+
+pub type TlsError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Oldest TLS version FTPS clients may negotiate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum MinTlsVersion {
+    #[default]
+    #[value(name = "1.2")]
+    Tls12,
+    #[value(name = "1.3")]
+    Tls13,
+}
+
+impl MinTlsVersion {
+    pub fn tls_flags(self) -> TlsFlags {
+        let mut flags = TlsFlags::default();
+        if self == MinTlsVersion::Tls13 {
+            // libunftp leaves TLS 1.3 off by default.
+            flags.remove(TlsFlags::V1_2);
+            flags.insert(TlsFlags::V1_3);
+        }
+        flags
+    }
+}
+
+/// Hands FTPS clients the current certificate. A renewed certificate is swapped in, so new
+/// connections use it without restarting the server.
+#[derive(Debug)]
+pub struct CertificateResolver {
+    certified: ArcSwap<CertifiedKey>,
+}
+
+impl CertificateResolver {
+    pub fn load(cert: &Path, key: &Path) -> Result<Arc<Self>, TlsError> {
+        Ok(Arc::new(Self {
+            certified: ArcSwap::from_pointee(certified_key(cert, key)?),
+        }))
+    }
+
+    /// Swap in the certificate and key from these files, keeping the current ones if they can't be
+    /// loaded.
+    pub fn reload(&self, cert: &Path, key: &Path) {
+        match certified_key(cert, key) {
+            Ok(certified) => {
+                self.certified.store(Arc::new(certified));
+                info!("Loaded certificate {}", cert.display());
+            }
+            Err(e) => error!(
+                "Failed to load certificate {}, keeping the current one: {e}",
+                cert.display()
+            ),
+        }
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified.load_full())
+    }
+}
+
+fn certified_key(cert: &Path, key: &Path) -> Result<CertifiedKey, TlsError> {
+    let chain = CertificateDer::pem_file_iter(cert)?.collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(key)?;
+    // Also checks that the key belongs to the certificate.
+    Ok(CertifiedKey::from_der(
+        chain,
+        key,
+        &aws_lc_rs::default_provider(),
+    )?)
+}
+
+/// The TLS configuration libunftp builds from certificate files, with the certificate from
+/// `resolver` instead. Clients need a certificate issued by `client_ca` if it is set.
+pub fn server_config(
+    resolver: Arc<CertificateResolver>,
+    flags: TlsFlags,
+    client_ca: Option<&Path>,
+) -> Result<Arc<ServerConfig>, TlsError> {
+    let mut versions = Vec::new();
+    if flags.contains(TlsFlags::V1_2) {
+        versions.push(&TLS12);
+    }
+    if flags.contains(TlsFlags::V1_3) {
+        versions.push(&TLS13);
+    }
+    let provider = Arc::new(aws_lc_rs::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(&versions)?;
+    let builder = match client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(ca)? {
+                roots.add(cert?)?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(resolver);
+    config.session_storage = if flags.contains(TlsFlags::RESUMPTION_SESS_ID) {
+        Arc::new(SessionCache(ServerSessionMemoryCache::new(
+            SESSION_CACHE_SIZE,
+        )))
+    } else {
+        Arc::new(NoServerSessionStorage {})
+    };
+    if flags.contains(TlsFlags::RESUMPTION_TICKETS) {
+        config.ticketer = aws_lc_rs::Ticketer::new()?;
+    }
+    Ok(Arc::new(config))
+}
+
+/// Keeps sessions when they are resumed, like libunftp does: FileZilla resumes the session of
+/// the control connection for every data connection.
+#[derive(Debug)]
+struct SessionCache(Arc<ServerSessionMemoryCache>);
+
+impl StoresServerSessions for SessionCache {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.0.put(key, value)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.get(key)
+    }
+
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.get(key)
+    }
+
+    fn can_cache(&self) -> bool {
+        true
+    }
+}
+
+/// Modification time and size of a file, to notice when it was replaced.
+type FileStamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> FileStamp {
+    // Follows symlinks, so swapping the target like Kubernetes secret mounts do is noticed too.
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Whether the certificate parses, so a half-written renewal isn't loaded.
+fn is_loadable(cert: &Path, key: &Path) -> bool {
+    let Ok(pem) = std::fs::read(cert) else {
+        return false;
+    };
+    let cert_ok = x509_parser::pem::parse_x509_pem(&pem)
+        .map(|(_, pem)| pem.parse_x509().is_ok())
+        .unwrap_or(false);
+    let key_ok = std::fs::read(key).is_ok_and(|key| !key.is_empty());
+    cert_ok && key_ok
+}
+
+/// Watch certificate and key files managed by another tool (certbot, cert-manager) and swap them
+/// into `resolver` when they change.
+pub async fn watch_certificate_files(
+    cert: PathBuf,
+    key: PathBuf,
+    resolver: Arc<CertificateResolver>,
+) {
+    let mut loaded = (stamp(&cert), stamp(&key));
+    loop {
+        sleep(CERTIFICATE_POLL_INTERVAL).await;
+        let current = (stamp(&cert), stamp(&key));
+        if current == loaded {
+            continue;
+        }
+        if !is_loadable(&cert, &key) {
+            debug!("Certificate files changed but are not complete yet");
+            continue;
+        }
+        // Wait for the key to be written as well when both are replaced.
+        sleep(Duration::from_secs(1)).await;
+        if (stamp(&cert), stamp(&key)) != current {
+            continue;
+        }
+        if current.0.is_none() || current.1.is_none() {
+            warn!("Certificate or key file disappeared, keeping the loaded certificate");
+            continue;
+        }
+        info!("Certificate {} changed", cert.display());
+        loaded = current;
+        resolver.reload(&cert, &key);
+    }
+}
+
+/// Names a client certificate was issued to: the subject CNs followed by DNS and email SANs.
+pub fn certificate_identities(der: &[u8]) -> Vec<String> {
+    let Ok((_, certificate)) = X509Certificate::from_der(der) else {
+        return Vec::new();
+    };
+    let mut identities: Vec<String> = certificate
+        .subject()
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok())
+        .map(str::to_string)
+        .collect();
+    if let Ok(Some(san)) = certificate.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(name) | GeneralName::RFC822Name(name) => {
+                    identities.push(name.to_string())
+                }
+                _ => {}
+            }
+        }
+    }
+    identities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tls_flags_per_minimum_version() {
+        assert!(MinTlsVersion::Tls12.tls_flags().contains(TlsFlags::V1_2));
+        let flags = MinTlsVersion::Tls13.tls_flags();
+        assert!(!flags.contains(TlsFlags::V1_2));
+        assert!(flags.contains(TlsFlags::V1_3));
+    }
+
+    #[test]
+    fn reload_swaps_the_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        let issue = |name: &str| {
+            let certified = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+            std::fs::write(&cert, certified.cert.pem()).unwrap();
+            std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+            certified.cert.der().to_vec()
+        };
+        let loaded = |resolver: &CertificateResolver| resolver.certified.load().cert[0].to_vec();
+
+        let first = issue("first");
+        let resolver = CertificateResolver::load(&cert, &key).unwrap();
+        assert_eq!(loaded(&resolver), first);
+        server_config(Arc::clone(&resolver), TlsFlags::default(), None).unwrap();
+
+        let second = issue("second");
+        resolver.reload(&cert, &key);
+        assert_eq!(loaded(&resolver), second);
+
+        std::fs::write(&key, "not a key").unwrap();
+        resolver.reload(&cert, &key);
+        assert_eq!(loaded(&resolver), second);
+    }
+
+    #[test]
+    fn extracts_cn_and_san() {
+        let certified = rcgen::generate_simple_self_signed(vec!["scanner-1".to_string()]).unwrap();
+        let identities = certificate_identities(certified.cert.der());
+        assert!(identities.contains(&"scanner-1".to_string()));
+        assert!(certificate_identities(b"garbage").is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use data_encoding::BASE32_NOPAD;
+use ring::hmac;
+use serde::Deserialize;
+
+/// Number of digits in a code.
+pub const CODE_LENGTH: usize = 6;
+
+/// Length of a time step in seconds.
+const TIME_STEP: u64 = 30;
+
+/// Codes from this many steps before or after the current one are accepted to allow for clock
+/// drift and slow typing.
+const ALLOWED_DRIFT: u64 = 1;
+
+/// Shared secret for time-based one-time passwords (RFC 6238), given in base32 like authenticator
+/// apps show it.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TotpSecret(Vec<u8>);
+
+impl std::fmt::Debug for TotpSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TotpSecret(..)")
+    }
+}
+
+impl TryFrom<String> for TotpSecret {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let normalized: String = value
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '=')
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        match BASE32_NOPAD.decode(normalized.as_bytes()) {
+            Ok(secret) if !secret.is_empty() => Ok(TotpSecret(secret)),
+            _ => Err("Invalid base32 TOTP secret".to_string()),
+        }
+    }
+}
+
+impl TotpSecret {
+    /// Check a code against the current time.
+    pub fn verify(&self, code: &str) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.verify_at(code, now)
+    }
+
+    fn verify_at(&self, code: &str, unix_time: u64) -> bool {
+        if code.len() != CODE_LENGTH || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return false;
+        }
+        let counter = unix_time / TIME_STEP;
+        (counter.saturating_sub(ALLOWED_DRIFT)..=counter + ALLOWED_DRIFT)
+            .any(|counter| format!("{:06}", self.code(counter)) == code)
+    }
+
+    #[cfg(test)]
+    pub fn current_code(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        format!("{:06}", self.code(now / TIME_STEP))
+    }
+
+    /// HOTP value (RFC 4226) for `counter`.
+    fn code(&self, counter: u64) -> u32 {
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &self.0);
+        let digest = hmac::sign(&key, &counter.to_be_bytes());
+        let digest = digest.as_ref();
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let value = u32::from_be_bytes([
+            digest[offset],
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]) & 0x7fff_ffff;
+        value % 10u32.pow(CODE_LENGTH as u32)
+    }
+}
+
+/// Split a password with an appended one-time code into the password and the code.
+pub fn split_code(password: &str) -> Option<(&str, &str)> {
+    let split = password.len().checked_sub(CODE_LENGTH)?;
+    password
+        .is_char_boundary(split)
+        .then(|| password.split_at(split))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_rfc6238_test_vectors() {
+        // "12345678901234567890" in base32
+        let secret = TotpSecret::try_from("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".to_string()).unwrap();
+        assert!(secret.verify_at("287082", 59));
+        assert!(secret.verify_at("081804", 1111111109));
+        assert!(secret.verify_at("081804", 1111111109 + TIME_STEP));
+        assert!(!secret.verify_at("081804", 1111111109 + 3 * TIME_STEP));
+        assert!(!secret.verify_at("81804", 1111111109));
+    }
+
+    #[test]
+    fn splits_code_from_password() {
+        assert_eq!(split_code("secret123456"), Some(("secret", "123456")));
+        assert_eq!(split_code("12345"), None);
+    }
+}
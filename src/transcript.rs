@@ -0,0 +1,140 @@
+use std::fmt::{self, Write as _};
+use std::fs::OpenOptions;
+use std::io::Write as _;
+use std::path::PathBuf;
+
+use slog::{Drain, KV, Key, Never, OwnedKVList, Record, Serializer};
+
+/// Writes everything libunftp logs about a session, including the commands it received and the
+/// replies it sent, to `<dir>/<trace-id>.log`.
+///
+/// Passwords are masked. Messages without a session are dropped.
+#[derive(Debug)]
+pub struct TranscriptDrain {
+    dir: PathBuf,
+}
+
+impl TranscriptDrain {
+    pub fn new(dir: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// A logger for libunftp that writes transcripts and passes everything on to the `log` crate.
+    pub fn into_logger(self) -> slog::Logger {
+        slog::Logger::root(
+            slog::Duplicate::new(self, slog_stdlog::StdLog).fuse(),
+            slog::o!(),
+        )
+    }
+}
+
+impl Drain for TranscriptDrain {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+        let mut fields = Fields::default();
+        let _ = record.kv().serialize(record, &mut fields);
+        let _ = values.serialize(record, &mut fields);
+        let Some(trace_id) = fields.trace_id else {
+            return Ok(());
+        };
+        // Trace ids are generated by libunftp, but keep them from escaping the directory anyway.
+        if trace_id.is_empty()
+            || !trace_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Ok(());
+        }
+
+        let line = format!(
+            "{} {} {}{}\n",
+            chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"),
+            record.level().as_short_str(),
+            redact(&record.msg().to_string()),
+            redact(&fields.rest)
+        );
+        let path = self.dir.join(format!("{trace_id}.log"));
+        if let Err(e) = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+        {
+            log::warn!("Failed to write session transcript {}: {e}", path.display());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    trace_id: Option<String>,
+    rest: String,
+}
+
+impl Serializer for Fields {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        if key == "trace-id" {
+            self.trace_id = Some(val.to_string());
+        } else {
+            let _ = write!(self.rest, " {key}={val}");
+        }
+        Ok(())
+    }
+}
+
+/// Mask the password of a `PASS` command, however libunftp happens to format it.
+fn redact(text: &str) -> String {
+    let lowercase = text.to_ascii_lowercase();
+    let position = ["pass ", "password"]
+        .iter()
+        .filter_map(|needle| lowercase.find(needle))
+        .min();
+    match position {
+        Some(position) => format!("{}PASS ********", &text[..position]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_session_messages_without_passwords() {
+        let dir = tempfile::tempdir().unwrap();
+        let logger = slog::Logger::root(
+            TranscriptDrain::new(dir.path().to_path_buf())
+                .unwrap()
+                .fuse(),
+            slog::o!(),
+        );
+        let session = logger.new(slog::o!("trace-id" => "0a1b2c"));
+
+        slog::debug!(session, "Received command: USER scanner");
+        slog::debug!(session, "Received command: PASS hunter2");
+        slog::debug!(session, "Sending reply"; "code" => 230);
+        slog::info!(logger, "Listening");
+
+        let transcript = std::fs::read_to_string(dir.path().join("0a1b2c.log")).unwrap();
+        let lines: Vec<&str> = transcript.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("Received command: USER scanner"));
+        assert!(lines[1].ends_with("Received command: PASS ********"));
+        assert!(lines[2].ends_with("Sending reply code=230"));
+        assert!(!transcript.contains("hunter2"));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn redacts_debug_formatted_commands() {
+        assert_eq!(
+            redact("Processing command Pass { password: \"secret\" }"),
+            "Processing command PASS ********"
+        );
+        assert_eq!(redact("Sending reply 331"), "Sending reply 331");
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use ipnet::IpNet;
+use serde::Deserialize;
+
+use crate::quota::UploadLimits;
+use crate::schedule::TimeWindow;
+use crate::totp::TotpSecret;
+
+/// How a user's password is checked.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(from = "String")]
+pub enum PasswordPolicy {
+    /// The password must match exactly.
+    Required(String),
+    /// No password may be sent (`password = "none"`), for scanners that can only send a username.
+    Empty,
+    /// Any password is accepted (`password = "any"`).
+    Any,
+}
+
+impl From<String> for PasswordPolicy {
+    fn from(password: String) -> Self {
+        match password.as_str() {
+            "none" => PasswordPolicy::Empty,
+            "any" => PasswordPolicy::Any,
+            _ => PasswordPolicy::Required(password),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn accepts(&self, password: Option<&str>) -> bool {
+        match self {
+            PasswordPolicy::Required(expected) => password.is_none_or(|p| p == expected),
+            PasswordPolicy::Empty => password.is_none_or(str::is_empty),
+            PasswordPolicy::Any => true,
+        }
+    }
+}
+
+/// A single address or a CIDR network, e.g. `192.168.1.20` or `10.0.5.0/24`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpMatcher(IpNet);
+
+impl TryFrom<String> for IpMatcher {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value
+            .parse::<IpNet>()
+            .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+            .map(IpMatcher)
+            .map_err(|_| format!("Invalid IP address or network '{value}'"))
+    }
+}
+
+impl IpMatcher {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Listening on [::] reports IPv4 clients as IPv4-mapped IPv6 addresses.
+        self.0.contains(&ip.to_canonical())
+    }
+}
+
+/// Settings applied to the uploads of a logged-in user.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct UserSettings {
+    /// IDs of Paperless tags added to every document.
+    #[serde(default)]
+    pub tags: Vec<u64>,
+    /// Paperless API token to upload with instead of the bridge's own, so the documents are
+    /// owned by that Paperless user.
+    #[serde(default, alias = "token")]
+    pub api_token: Option<String>,
+    /// Extra HTTP headers sent to Paperless for the user, e.g. `Remote-User` for a proxy that
+    /// authenticates Paperless users.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Proxy to reach Paperless through for the user.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Directory the user is confined to, e.g. `/kitchen`. Uploads and CWD outside of it are
+    /// rejected.
+    #[serde(default)]
+    pub root: Option<PathBuf>,
+    #[serde(flatten)]
+    pub limits: UploadLimits,
+    /// Times during which the user may log in and upload. Empty means any time.
+    #[serde(default)]
+    pub access_hours: Vec<TimeWindow>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UserConfig {
+    pub password: PasswordPolicy,
+    /// Clients connecting from these addresses are logged in as this user without checking the
+    /// username or password.
+    #[serde(default)]
+    pub trusted_ips: Vec<IpMatcher>,
+    /// Require a one-time code from an authenticator app, appended to the password.
+    #[serde(default)]
+    pub totp_secret: Option<TotpSecret>,
+    #[serde(flatten)]
+    pub settings: UserSettings,
+}
+
+impl UserConfig {
+    pub fn with_password(password: String) -> Self {
+        Self {
+            password: PasswordPolicy::Required(password),
+            trusted_ips: Vec::new(),
+            totp_secret: None,
+            settings: UserSettings::default(),
+        }
+    }
+}
+
+/// A Paperless instance uploads are routed to by the first directory of their path.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TenantConfig {
+    pub paperless_url: String,
+    #[serde(alias = "token")]
+    pub api_token: String,
+    /// Users allowed to upload to the tenant. Empty allows every user.
+    #[serde(default)]
+    pub users: Vec<String>,
+}
+
+/// Accounts loaded from the users file, keyed by username.
+#[derive(Debug, Default, Deserialize)]
+pub struct UsersFile {
+    #[serde(default)]
+    pub users: HashMap<String, UserConfig>,
+    /// Tenants keyed by the name of their directory.
+    #[serde(default)]
+    pub tenants: HashMap<String, TenantConfig>,
+}
+
+#[derive(Debug)]
+pub enum UsersFileError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+}
+
+impl std::fmt::Display for UsersFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UsersFileError::Io(e) => write!(f, "{e}"),
+            UsersFileError::Parse(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for UsersFileError {}
+
+impl UsersFile {
+    pub fn load(path: &Path) -> Result<Self, UsersFileError> {
+        let content = std::fs::read_to_string(path).map_err(UsersFileError::Io)?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, UsersFileError> {
+        toml::from_str(content).map_err(UsersFileError::Parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_password_policies() {
+        let file = UsersFile::parse(
+            r#"
+            [users.office]
+            password = "secret"
+
+            [users.epson]
+            password = "none"
+
+            [users.kiosk]
+            password = "any"
+            "#,
+        )
+        .unwrap();
+
+        let office = &file.users["office"].password;
+        assert_eq!(*office, PasswordPolicy::Required("secret".to_string()));
+        assert!(office.accepts(Some("secret")));
+        assert!(!office.accepts(Some("")));
+
+        let epson = &file.users["epson"].password;
+        assert!(epson.accepts(Some("")));
+        assert!(!epson.accepts(Some("guess")));
+
+        let kiosk = &file.users["kiosk"].password;
+        assert!(kiosk.accepts(Some("guess")));
+    }
+
+    #[test]
+    fn parses_user_settings() {
+        let file = UsersFile::parse(
+            r#"
+            [users.scanner-kitchen]
+            password = "secret"
+            tags = [4]
+            root = "/kitchen"
+            max_uploads_per_day = 50
+            access_hours = ["Mon-Fri 08:00-18:00"]
+            headers = { Remote-User = "kitchen" }
+            "#,
+        )
+        .unwrap();
+        let settings = &file.users["scanner-kitchen"].settings;
+        assert_eq!(settings.tags, vec![4]);
+        assert_eq!(settings.root.as_deref(), Some(Path::new("/kitchen")));
+        assert_eq!(settings.limits.max_uploads_per_day, Some(50));
+        assert_eq!(settings.access_hours.len(), 1);
+        assert_eq!(settings.headers["Remote-User"], "kitchen");
+    }
+
+    #[test]
+    fn parses_tenants() {
+        let file = UsersFile::parse(
+            r#"
+            [users.office]
+            password = "secret"
+
+            [tenants.smith]
+            paperless_url = "https://paperless.smith.example"
+            api_token = "abc"
+            users = ["office"]
+            "#,
+        )
+        .unwrap();
+        let smith = &file.tenants["smith"];
+        assert_eq!(smith.paperless_url, "https://paperless.smith.example");
+        assert_eq!(smith.users, vec!["office".to_string()]);
+    }
+
+    #[test]
+    fn parses_totp_secrets() {
+        let file = UsersFile::parse(
+            r#"
+            [users.alice]
+            password = "secret"
+            totp_secret = "JBSW Y3DP EHPK 3PXP"
+            "#,
+        )
+        .unwrap();
+        assert!(file.users["alice"].totp_secret.is_some());
+
+        assert!(
+            UsersFile::parse(
+                r#"
+                [users.alice]
+                password = "secret"
+                totp_secret = "not base32!"
+                "#,
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn matches_trusted_ips() {
+        let network = IpMatcher::try_from("10.0.5.0/24".to_string()).unwrap();
+        assert!(network.contains("10.0.5.17".parse().unwrap()));
+        assert!(network.contains("::ffff:10.0.5.17".parse().unwrap()));
+        assert!(!network.contains("10.0.6.1".parse().unwrap()));
+
+        let single = IpMatcher::try_from("192.168.1.20".to_string()).unwrap();
+        assert!(single.contains("192.168.1.20".parse().unwrap()));
+        assert!(!single.contains("192.168.1.21".parse().unwrap()));
+
+        assert!(IpMatcher::try_from("scanner".to_string()).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+pub const GIT_COMMIT: &str = env!("FTP_PAPERLESS_BRIDGE_GIT_COMMIT");
+
+/// Cargo features the bridge was built with.
+pub fn enabled_features() -> Vec<&'static str> {
+    [
+        ("acme", cfg!(feature = "acme")),
+        ("admin", cfg!(feature = "admin")),
+        ("ftps", cfg!(feature = "ftps")),
+        ("geoip", cfg!(feature = "geoip")),
+        ("images", cfg!(feature = "images")),
+        ("imap", cfg!(feature = "imap")),
+        ("ldap", cfg!(feature = "ldap")),
+        ("metrics", cfg!(feature = "metrics")),
+        ("mqtt", cfg!(feature = "mqtt")),
+        ("pam", cfg!(feature = "pam")),
+        ("smtp", cfg!(feature = "smtp")),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect()
+}
+
+/// The build info as served at `/version`.
+pub fn version_json() -> String {
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "commit": GIT_COMMIT,
+        "features": enabled_features(),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_lists_the_build() {
+        let version: serde_json::Value = serde_json::from_str(&version_json()).unwrap();
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(version["commit"], GIT_COMMIT);
+    }
+}
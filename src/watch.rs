@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use libunftp::storage::{ErrorKind, StorageBackend};
+use log::{debug, error, info, warn};
+use tokio::time::sleep;
+
+use crate::auth::User;
+use crate::storage::PaperlessStorage;
+
+/// Size and modification time of a file, compared between polls to tell whether it is complete.
+type Stamp = (u64, SystemTime);
+
+/// Files seen in a watched directory, by path.
+#[derive(Debug, Default)]
+pub struct WatchState {
+    seen: HashMap<PathBuf, Stamp>,
+    /// Files that were rejected and are only tried again once they change.
+    rejected: HashMap<PathBuf, Stamp>,
+}
+
+impl WatchState {
+    /// Files that didn't change since the previous poll, sidecar files first so their metadata is
+    /// known when the document follows.
+    fn settled_files(&mut self, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let mut current = HashMap::new();
+        collect_files(dir, &mut current)?;
+        let mut settled: Vec<_> = current
+            .iter()
+            .filter(|(path, stamp)| self.seen.get(*path) == Some(*stamp))
+            .filter(|(path, stamp)| self.rejected.get(*path) != Some(*stamp))
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.rejected.retain(|path, _| current.contains_key(path));
+        self.seen = current;
+        settled.sort_by_key(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            (
+                crate::metadata::sidecar_target(&name).is_none(),
+                path.clone(),
+            )
+        });
+        Ok(settled)
+    }
+}
+
+/// Files below `dir`, skipping hidden ones such as the temporary files of SMB clients.
+fn collect_files(dir: &Path, files: &mut HashMap<PathBuf, Stamp>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if metadata.is_file() {
+            files.insert(entry.path(), (metadata.len(), metadata.modified()?));
+        }
+    }
+    Ok(())
+}
+
+/// Background task that submits the files dropped into `dir` like FTP uploads of `user` and
+/// removes them afterwards. Subdirectories become the directories of the upload, so metadata
+/// directories like `tags=invoice` work as well.
+pub async fn watch_loop<F>(dir: PathBuf, user: User, storage: F, interval: Duration)
+where
+    F: Fn() -> PaperlessStorage + Send + 'static,
+{
+    let mut state = WatchState::default();
+    loop {
+        sleep(interval).await;
+
+        let files = match state.settled_files(&dir) {
+            Ok(files) => files,
+            Err(e) => {
+                error!("Failed to read watched directory {}: {e}", dir.display());
+                continue;
+            }
+        };
+        if files.is_empty() {
+            continue;
+        }
+        // A new session per poll, so a file dropped again later is submitted again.
+        let storage = storage();
+        for path in files {
+            let relative = path.strip_prefix(&dir).unwrap_or(&path);
+            let virtual_path = Path::new("/").join(relative);
+            debug!("Submitting watched file {}", path.display());
+            let result = match tokio::fs::File::open(&path).await {
+                Ok(file) => storage
+                    .put(&user, file, &virtual_path, 0)
+                    .await
+                    .map_err(|e| (e.kind(), e.to_string())),
+                Err(e) => Err((ErrorKind::LocalError, e.to_string())),
+            };
+            match result {
+                Ok(_) => {
+                    info!("Submitted watched file {}", path.display());
+                    if let Err(e) = tokio::fs::remove_file(&path).await {
+                        error!("Failed to remove watched file {}: {e}", path.display());
+                    }
+                }
+                Err((ErrorKind::FileNameNotAllowedError, e)) => {
+                    warn!(
+                        "Ignoring watched file {} until it changes: {e}",
+                        path.display()
+                    );
+                    if let Some(stamp) = state.seen.get(&path) {
+                        state.rejected.insert(path.clone(), *stamp);
+                    }
+                }
+                Err((_, e)) => {
+                    warn!(
+                        "Failed to submit watched file {}: {e}, will retry later",
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_settled_once_unchanged_between_polls() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("tags=invoice")).unwrap();
+        let document = dir.path().join("tags=invoice/scan.pdf");
+        let sidecar = dir.path().join("tags=invoice/scan.pdf.json");
+        std::fs::write(&document, b"%PDF").unwrap();
+        std::fs::write(&sidecar, b"{}").unwrap();
+        std::fs::write(dir.path().join(".~lock.scan.pdf"), b"").unwrap();
+
+        let mut state = WatchState::default();
+        assert!(state.settled_files(dir.path()).unwrap().is_empty());
+        assert_eq!(
+            state.settled_files(dir.path()).unwrap(),
+            vec![sidecar.clone(), document.clone()]
+        );
+
+        // Still being written.
+        std::fs::write(&document, b"%PDF-1.7").unwrap();
+        assert_eq!(state.settled_files(dir.path()).unwrap(), vec![sidecar]);
+        assert_eq!(state.settled_files(dir.path()).unwrap().len(), 2);
+    }
+}